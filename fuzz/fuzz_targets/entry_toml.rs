@@ -0,0 +1,10 @@
+//! entry.toml written by users is rejected without panics
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(toml_str) = std::str::from_utf8(data) {
+        let _ = llvmenv::entry::load_entry_toml(toml_str);
+    }
+});
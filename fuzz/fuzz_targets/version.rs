@@ -0,0 +1,13 @@
+//! Names of entries, builds, and release tags are parsed without panics, and printed versions are parsed back
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use llvmenv::version::{Version, VersionReq};
+
+fuzz_target!(|name: &str| {
+    if let Ok(version) = name.parse::<Version>() {
+        assert_eq!(version.to_string().parse::<Version>().unwrap(), version);
+    }
+    let _ = name.parse::<VersionReq>();
+    let _ = Version::find(name);
+});
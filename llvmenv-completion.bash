@@ -0,0 +1,118 @@
+# bash completion for llvmenv
+#
+# Entry, build, and tag names are queried by `llvmenv __complete` at completion time
+
+_llvmenv_commands="init builds tag entries build-entry rebuild source builddir build-log cmake-cache sync current prefix version env exec llvm-sys-env hostinfo report doctor man global local uninstall lock unlock rehash snapshot gc verify archive expand bazel meson conan vcpkg modulefile spack-register homebrew package serve push pull auth install releases audit-log analyze analyzer-wrappers state asdf-shim plugins config edit zsh bash-completion completion help"
+
+_llvmenv_compgen() {
+  COMPREPLY=($(compgen -W "$1" -- "$2"))
+}
+
+_llvmenv() {
+  local cur prev cmd
+  cur="${COMP_WORDS[COMP_CWORD]}"
+  prev="${COMP_WORDS[COMP_CWORD-1]}"
+  if [[ $COMP_CWORD -eq 1 ]]; then
+    _llvmenv_compgen "$_llvmenv_commands $(llvmenv __complete plugins 2>/dev/null)" "$cur"
+    return
+  fi
+  cmd="${COMP_WORDS[1]}"
+
+  case "$prev" in
+    --tag)
+      _llvmenv_compgen "$(llvmenv __complete tags 2>/dev/null)" "$cur"
+      return
+      ;;
+    --group)
+      _llvmenv_compgen "$(llvmenv __complete groups 2>/dev/null)" "$cur"
+      return
+      ;;
+    --format)
+      [[ $cmd == package ]] && _llvmenv_compgen "deb rpm zip msi" "$cur"
+      return
+      ;;
+    --build-type)
+      _llvmenv_compgen "Debug Release RelWithDebInfo MinSizeRel" "$cur"
+      return
+      ;;
+    --only|--skip)
+      _llvmenv_compgen "download configure build install" "$cur"
+      return
+      ;;
+    -n|--name|--build)
+      _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      return
+      ;;
+  esac
+
+  case "$cmd" in
+    build-entry|rebuild|report|source|builddir|build-log|log|cmake-cache)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "$(llvmenv __complete entries 2>/dev/null)" "$cur"
+      ;;
+    env|doctor|global|local|archive|bazel|meson|conan|vcpkg|modulefile|homebrew|package|analyzer-wrappers)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      ;;
+    spack-register)
+      _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      ;;
+    tag)
+      if [[ $COMP_CWORD -eq 2 ]]; then
+        _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      else
+        _llvmenv_compgen "$(llvmenv __complete tags 2>/dev/null)" "$cur"
+      fi
+      ;;
+    push)
+      [[ $COMP_CWORD -eq 3 ]] && _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      ;;
+    hostinfo)
+      _llvmenv_compgen "--json" "$cur"
+      ;;
+    gc)
+      _llvmenv_compgen "--yes" "$cur"
+      ;;
+    verify)
+      if [[ $cur == -* ]]; then
+        _llvmenv_compgen "--deep --record" "$cur"
+      else
+        _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      fi
+      ;;
+    uninstall)
+      if [[ $cur == -* ]]; then
+        _llvmenv_compgen "--purge-cache" "$cur"
+      else
+        _llvmenv_compgen "$(llvmenv __complete builds 2>/dev/null)" "$cur"
+      fi
+      ;;
+    audit-log)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "show clear" "$cur"
+      ;;
+    auth)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "login logout" "$cur"
+      ;;
+    config)
+      if [[ $COMP_CWORD -eq 2 ]]; then
+        _llvmenv_compgen "get set unset list backup restore" "$cur"
+      elif [[ ${COMP_WORDS[2]} == restore ]]; then
+        _llvmenv_compgen "$(llvmenv config restore --list 2>/dev/null)" "$cur"
+      elif [[ $COMP_CWORD -eq 3 ]]; then
+        _llvmenv_compgen "paths.tmp paths.build audit.enabled build.jobs build.builder build.ccache download.retries download.backoff" "$cur"
+      fi
+      ;;
+    state)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "export import" "$cur"
+      ;;
+    asdf-shim)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "list-all install bin-path" "$cur"
+      ;;
+    expand)
+      COMPREPLY=($(compgen -f -- "$cur"))
+      ;;
+    completion)
+      [[ $COMP_CWORD -eq 2 ]] && _llvmenv_compgen "bash zsh fish powershell elvish" "$cur"
+      ;;
+  esac
+}
+
+complete -F _llvmenv llvmenv
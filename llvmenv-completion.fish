@@ -0,0 +1,10 @@
+# Dynamic values of llvmenv for fish, appended to the completion generated from the arguments
+#
+# Entry, build, and tag names are queried by `llvmenv __complete` at completion time
+
+complete -c llvmenv -n '__fish_use_subcommand' -f -a '(llvmenv __complete plugins 2>/dev/null)'
+complete -c llvmenv -n '__fish_seen_subcommand_from build-entry rebuild report source builddir build-log cmake-cache' -f -a '(llvmenv __complete entries 2>/dev/null)'
+complete -c llvmenv -n '__fish_seen_subcommand_from tag env doctor global local uninstall verify archive bazel meson conan vcpkg modulefile spack-register homebrew package push analyzer-wrappers' -f -a '(llvmenv __complete builds 2>/dev/null)'
+complete -c llvmenv -l tag -x -a '(llvmenv __complete tags 2>/dev/null)'
+complete -c llvmenv -l group -x -a '(llvmenv __complete groups 2>/dev/null)'
+complete -c llvmenv -s n -l name -l build -x -a '(llvmenv __complete builds 2>/dev/null)'
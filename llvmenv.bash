@@ -0,0 +1,45 @@
+# bash integration for llvmenv
+#
+# Add `eval "$(llvmenv init bash)"` into ~/.bashrc
+
+llvmenv_remove_path() {
+  local path_base="${XDG_DATA_HOME:-$HOME/.local/share}/llvmenv"
+  local dirs dir new_path=""
+  IFS=: read -ra dirs <<< "$PATH"
+  for dir in "${dirs[@]}"; do
+    case "$dir" in
+      "$path_base"/*) ;;
+      *) new_path="${new_path:+$new_path:}$dir" ;;
+    esac
+  done
+  PATH="$new_path"
+}
+
+llvmenv_append_path() {
+  local prefix
+  prefix="$(llvmenv prefix)"
+  if [[ -n "$prefix" && "$prefix" != "/usr" && -d "$prefix/bin" ]]; then
+    # To avoid /usr/bin and /bin become the top of $PATH
+    PATH="$prefix/bin${PATH:+:$PATH}"
+  fi
+}
+
+llvmenv_env_llvm_sys() {
+  export "LLVM_SYS_$(llvmenv version --major --minor)_PREFIX=$(llvmenv prefix)"
+}
+
+llvmenv_update() {
+  llvmenv_remove_path
+  llvmenv_append_path
+  if [[ -n "$LLVMENV_RUST_BINDING" ]]; then
+    llvmenv_env_llvm_sys
+  fi
+}
+
+llvmenv_cd_source() {
+  eval "$(llvmenv source --cd "$@")"
+}
+
+if [[ ";${PROMPT_COMMAND:-};" != *";llvmenv_update;"* ]]; then
+  PROMPT_COMMAND="llvmenv_update${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+fi
@@ -0,0 +1,41 @@
+# fish integration for llvmenv
+#
+# Add `llvmenv init fish | source` into ~/.config/fish/config.fish
+
+function llvmenv_remove_path
+  set -l path_base $HOME/.local/share/llvmenv
+  if set -q XDG_DATA_HOME
+    set path_base $XDG_DATA_HOME/llvmenv
+  end
+  set -l new_path
+  for dir in $PATH
+    if not string match -q -- "$path_base/*" $dir
+      set -a new_path $dir
+    end
+  end
+  set -gx PATH $new_path
+end
+
+function llvmenv_append_path
+  set -l prefix (llvmenv prefix)
+  # To avoid /usr/bin and /bin become the top of $PATH
+  if test -n "$prefix" -a "$prefix" != /usr -a -d "$prefix/bin"
+    set -gx PATH $prefix/bin $PATH
+  end
+end
+
+function llvmenv_env_llvm_sys
+  set -gx LLVM_SYS_(llvmenv version --major --minor)_PREFIX (llvmenv prefix)
+end
+
+function llvmenv_update --on-event fish_prompt
+  llvmenv_remove_path
+  llvmenv_append_path
+  if test -n "$LLVMENV_RUST_BINDING"
+    llvmenv_env_llvm_sys
+  end
+end
+
+function llvmenv_cd_source
+  cd (llvmenv source $argv)
+end
@@ -0,0 +1,33 @@
+# PowerShell integration for llvmenv
+#
+# Add `Invoke-Expression (& llvmenv init powershell | Out-String)` into $PROFILE
+
+function global:Update-Llvmenv {
+  $sep = [IO.Path]::PathSeparator
+  # Remove the bin directory added by the previous update
+  $paths = $env:PATH -split [regex]::Escape($sep) | Where-Object { $_ -and $_ -ne $global:LlvmenvBin }
+  $global:LlvmenvBin = $null
+  $prefix = llvmenv prefix
+  $bin = Join-Path $prefix "bin"
+  if ($prefix -and $prefix -ne "/usr" -and (llvmenv current) -ne "system" -and (Test-Path $bin)) {
+    $global:LlvmenvBin = $bin
+    $paths = @($bin) + $paths
+  }
+  $env:PATH = $paths -join $sep
+  if ($env:LLVMENV_RUST_BINDING) {
+    $version = llvmenv version --major --minor
+    Set-Item "env:LLVM_SYS_${version}_PREFIX" $prefix
+  }
+}
+
+function global:llvmenv_cd_source {
+  Set-Location (llvmenv source @args)
+}
+
+if (-not $global:LlvmenvPrompt) {
+  $global:LlvmenvPrompt = $function:prompt
+  function global:prompt {
+    Update-Llvmenv
+    & $global:LlvmenvPrompt
+  }
+}
@@ -0,0 +1,153 @@
+//! Clang static analyzer with a build of llvmenv
+//!
+//! `llvmenv analyze -- make -j4` runs the build command under `scan-build` of the current build,
+//! where clang, clang++, and the analyzer are also taken from the build.
+//! HTML reports are saved into `${data_dir}/reports/${project}`, where `${project}` is
+//! the name of the current directory (or `-o` option).
+//!
+//! `llvmenv analyzer-wrappers -o bin/` generates `scan-build` and `analyze-build` scripts
+//! bound to the build, for IDEs or CI scripts which expect them in `$PATH`.
+
+use log::info;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build::Build;
+use crate::config::data_dir;
+use crate::error::*;
+
+/// Wrapper scripts generated by [generate_wrappers](fn.generate_wrappers.html)
+pub const WRAPPERS: [&str; 2] = ["scan-build", "analyze-build"];
+
+/// Default directory of reports for the current directory
+pub fn default_report_dir() -> Result<PathBuf> {
+    let cwd = env::current_dir()?;
+    let project = cwd
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "root".into());
+    Ok(data_dir()?.join("reports").join(project))
+}
+
+fn bin(build: &Build, tool: &str) -> Result<PathBuf> {
+    let path = build.prefix().join("bin").join(tool);
+    if !path.is_file() {
+        bail!(
+            "{} is not found in build '{}'. Install clang with its tools (e.g. scan-build component).",
+            tool,
+            build.name()
+        );
+    }
+    Ok(path)
+}
+
+/// Options passed to `tool` to use the compilers and the analyzer of the build
+fn tool_options(build: &Build, tool: &str) -> Result<Vec<String>> {
+    let clang = bin(build, "clang")?.display().to_string();
+    let mut opts = Vec::new();
+    if tool == "scan-build" {
+        opts.push("--use-cc".to_string());
+        opts.push(clang.clone());
+        opts.push("--use-c++".to_string());
+        opts.push(bin(build, "clang++")?.display().to_string());
+    }
+    opts.push("--use-analyzer".to_string());
+    opts.push(clang);
+    Ok(opts)
+}
+
+/// Run `cmd` under `scan-build` of the build, saving reports into `report_dir`
+pub fn run(build: &Build, report_dir: &Path, cmd: &[String]) -> Result<()> {
+    if cmd.is_empty() {
+        bail!("No build command is given, e.g. `llvmenv analyze -- make`");
+    }
+    let scan_build = bin(build, "scan-build")?;
+    fs::create_dir_all(report_dir)?;
+    info!("Save reports into {}", report_dir.display());
+    Command::new(scan_build)
+        .args(tool_options(build, "scan-build")?)
+        .arg("-o")
+        .arg(report_dir)
+        .args(cmd)
+        .check_run()?;
+    Ok(())
+}
+
+/// POSIX shell script executing `tool` of the build with its compilers and analyzer
+pub fn wrapper(build: &Build, tool: &str) -> Result<String> {
+    let exe = bin(build, tool)?;
+    let opts: Vec<String> = tool_options(build, tool)?
+        .iter()
+        .map(|opt| shell_quote(opt))
+        .collect();
+    Ok(format!(
+        "#!/bin/sh\n# Generated by llvmenv for build '{}'\nexec {} {} \"$@\"\n",
+        build.name(),
+        shell_quote(&exe.display().to_string()),
+        opts.join(" ")
+    ))
+}
+
+/// Write [WRAPPERS](constant.WRAPPERS.html) into `out` for the tools existing in the build
+pub fn generate_wrappers(build: &Build, out: &Path) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(out)?;
+    let mut paths = Vec::new();
+    for tool in WRAPPERS.iter() {
+        if !build.prefix().join("bin").join(tool).is_file() {
+            info!("{} is not found in build '{}', skipped", tool, build.name());
+            continue;
+        }
+        let path = out.join(tool);
+        fs::write(&path, wrapper(build, tool)?)?;
+        set_executable(&path)?;
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        bail!(
+            "Neither scan-build nor analyze-build is found in build '{}'",
+            build.name()
+        );
+    }
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrappers() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("prefix");
+        fs::create_dir_all(prefix.join("bin"))?;
+        for tool in &["clang", "clang++", "scan-build"] {
+            fs::write(prefix.join("bin").join(tool), "")?;
+        }
+        let build = Build::from_path(&prefix);
+        let script = wrapper(&build, "scan-build")?;
+        let bin = prefix.join("bin");
+        assert!(script.contains(&format!(
+            "exec '{0}/scan-build' '--use-cc' '{0}/clang' '--use-c++' '{0}/clang++' '--use-analyzer' '{0}/clang' \"$@\"",
+            bin.display()
+        )));
+
+        let paths = generate_wrappers(&build, &tmp_dir.path().join("out"))?;
+        assert_eq!(paths, vec![tmp_dir.path().join("out/scan-build")]);
+        assert!(wrapper(&build, "analyze-build").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Engine of [asdf](https://asdf-vm.com/) / [mise](https://mise.jdx.dev/) plugin
+//!
+//! `llvmenv asdf-shim` implements the commands of the asdf plugin protocol
+//! using entries and builds of llvmenv. A plugin is a directory whose scripts call them:
+//!
+//! ```text
+//! asdf-llvmenv/bin/list-all        # exec llvmenv asdf-shim list-all
+//! asdf-llvmenv/bin/install         # exec llvmenv asdf-shim install
+//! asdf-llvmenv/bin/list-bin-paths  # exec llvmenv asdf-shim bin-path
+//! ```
+//!
+//! The build is placed in the prefix of llvmenv as usual,
+//! and `$ASDF_INSTALL_PATH` is linked to it.
+
+use log::info;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::build::Build;
+use crate::entry;
+use crate::error::*;
+
+/// Directories of executables relative to the install path
+pub const BIN_PATHS: [&str; 1] = ["bin"];
+
+/// Names of all entries, which are the versions installable by asdf
+pub fn list_all() -> Result<Vec<String>> {
+    Ok(entry::load_entries()?
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect())
+}
+
+/// Install the version specified by `$ASDF_INSTALL_VERSION` into `$ASDF_INSTALL_PATH`
+pub fn install_from_env(nproc: usize) -> Result<()> {
+    let var = |key: &str| env::var(key).map_err(|_| format_err!("{} is not set", key));
+    if let Ok(ty) = env::var("ASDF_INSTALL_TYPE") {
+        if ty != "version" {
+            bail!("Unsupported install type: {}", ty);
+        }
+    }
+    install(
+        &var("ASDF_INSTALL_VERSION")?,
+        Path::new(&var("ASDF_INSTALL_PATH")?),
+        nproc,
+    )
+}
+
+/// Build the entry `version` if not installed, and link `install_path` to its prefix
+pub fn install(version: &str, install_path: &Path, nproc: usize) -> Result<()> {
+    let build = Build::from_name(version)?;
+    if build.exists() {
+        info!("Build '{}' already exists", version);
+    } else {
+        let entry = entry::load_entry(version)?;
+        entry.checkout()?;
+        entry.build(nproc, false)?;
+    }
+    if install_path.exists() {
+        if fs::read_dir(install_path)?.next().is_some() {
+            bail!("Install path is not empty: {}", install_path.display());
+        }
+        fs::remove_dir(install_path)?;
+    }
+    info!(
+        "Link {} to {}",
+        install_path.display(),
+        build.prefix().display()
+    );
+    link_dir(build.prefix(), install_path)
+}
+
+#[cfg(unix)]
+fn link_dir(src: &Path, dst: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(src, dst)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn link_dir(src: &Path, dst: &Path) -> Result<()> {
+    let mut opt = fs_extra::dir::CopyOptions::new();
+    opt.content_only = true;
+    fs::create_dir_all(dst)?;
+    fs_extra::dir::copy(src, dst, &opt)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_path_not_empty() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        fs::write(tmp_dir.path().join("file"), "")?;
+        assert!(install("system", tmp_dir.path(), 1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! Append-only log of llvmenv usage
+//!
+//! If enabled in config.toml,
+//!
+//! ```toml
+//! [audit]
+//! enabled = true
+//! ```
+//!
+//! every invocation of llvmenv (and every tool execution resolved through it) is appended into
+//! `${data_dir}/audit.log` as a tab-separated line of the time (UTC), kind, build, its version,
+//! the current directory, and the command, e.g.
+//!
+//! ```text
+//! 2019-01-15T09:30:00Z  llvmenv  7.0.0  7.0.0  /home/me/project  llvmenv build-entry 7.0.0
+//! ```
+//!
+//! to answer "which compiler built this artifact last Tuesday" later.
+//! `llvmenv audit-log show` prints it, and `llvmenv audit-log clear` removes it.
+
+use log::warn;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::build::{self, Build};
+use crate::config::{data_dir, load_config};
+use crate::error::Result;
+
+pub const AUDIT_LOG_FN: &str = "audit.log";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// `YYYY-MM-DDTHH:MM:SSZ`
+    pub time: String,
+    /// `llvmenv` for invocations of llvmenv, or the name of the executed tool
+    pub kind: String,
+    pub build: String,
+    pub version: String,
+    pub cwd: String,
+    pub command: String,
+}
+
+fn field(s: &str) -> String {
+    if s.is_empty() {
+        "-".into()
+    } else {
+        s.replace(['\t', '\n', '\r'], " ")
+    }
+}
+
+impl Record {
+    /// Record of now in the current directory
+    pub fn new(kind: &str, build: Option<&Build>, command: &[OsString]) -> Self {
+        let version = build
+            .and_then(|b| b.version().ok())
+            .map(|(major, minor, patch)| format!("{}.{}.{}", major, minor, patch))
+            .unwrap_or_default();
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Record {
+            time: format_utc(secs),
+            kind: kind.into(),
+            build: build.map(|b| b.name().to_string()).unwrap_or_default(),
+            version,
+            cwd: env::current_dir()
+                .map(|d| d.display().to_string())
+                .unwrap_or_default(),
+            command: command
+                .iter()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        [
+            &self.time,
+            &self.kind,
+            &self.build,
+            &self.version,
+            &self.cwd,
+            &self.command,
+        ]
+        .iter()
+        .map(|s| field(s))
+        .collect::<Vec<_>>()
+        .join("\t")
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<String> = line
+            .splitn(6, '\t')
+            .map(|s| {
+                if s == "-" {
+                    String::new()
+                } else {
+                    s.to_string()
+                }
+            })
+            .collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        Some(Record {
+            time: fields[0].clone(),
+            kind: fields[1].clone(),
+            build: fields[2].clone(),
+            version: fields[3].clone(),
+            cwd: fields[4].clone(),
+            command: fields[5].clone(),
+        })
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` of UNIX time
+///
+/// ```
+/// # use llvmenv::audit::format_utc;
+/// assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+/// assert_eq!(format_utc(1547544600), "2019-01-15T09:30:00Z");
+/// ```
+pub fn format_utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil date from days since 1970-01-01 (H. Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+pub fn audit_log_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join(AUDIT_LOG_FN))
+}
+
+/// Whether `[audit].enabled` is set in config.toml
+pub fn is_enabled() -> bool {
+    load_config().map(|c| c.audit.enabled).unwrap_or(false)
+}
+
+/// Append the record if enabled. Errors are only warned not to break the command.
+pub fn record(record: &Record) {
+    if is_enabled() {
+        append(record);
+    }
+}
+
+fn append(record: &Record) {
+    let res = audit_log_path().and_then(|path| {
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        writeln!(f, "{}", record.to_line())?;
+        Ok(())
+    });
+    if let Err(e) = res {
+        warn!("Failed to write audit log: {}", e);
+    }
+}
+
+/// Record the invocation of llvmenv with the current build
+pub fn log_invocation(args: &[OsString]) {
+    if !is_enabled() {
+        return;
+    }
+    let build = build::seek_build().ok();
+    append(&Record::new("llvmenv", build.as_ref(), args));
+}
+
+/// Record the execution of `tool` in the build
+pub fn log_tool(tool: &str, build: &Build, args: &[OsString]) {
+    if !is_enabled() {
+        return;
+    }
+    append(&Record::new(tool, Some(build), args));
+}
+
+/// Records in the log, oldest first
+pub fn records() -> Result<Vec<Record>> {
+    let path = audit_log_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(&path)?
+        .lines()
+        .filter_map(Record::parse)
+        .collect())
+}
+
+pub fn clear() -> Result<()> {
+    let path = audit_log_path()?;
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_line() {
+        let record = Record {
+            time: "2019-01-15T09:30:00Z".into(),
+            kind: "llvmenv".into(),
+            build: "7.0.0".into(),
+            version: String::new(),
+            cwd: "/home/me/project".into(),
+            command: "llvmenv build-entry\t7.0.0".into(),
+        };
+        let line = record.to_line();
+        assert_eq!(
+            line,
+            "2019-01-15T09:30:00Z\tllvmenv\t7.0.0\t-\t/home/me/project\tllvmenv build-entry 7.0.0"
+        );
+        let parsed = Record::parse(&line).unwrap();
+        assert_eq!(parsed.version, "");
+        assert_eq!(parsed.command, "llvmenv build-entry 7.0.0");
+        assert!(Record::parse("broken line").is_none());
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc(4_102_444_799), "2099-12-31T23:59:59Z");
+    }
+}
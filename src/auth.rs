@@ -0,0 +1,301 @@
+//! Tokens for authenticated resources in the keychain of OS
+//!
+//! `llvmenv auth login <host>` reads a token from stdin and stores it in
+//!
+//! - Secret Service (GNOME Keyring, KWallet) by `secret-tool` on Linux
+//! - Keychain by `security` on macOS
+//! - `${config_dir}/credentials/<host>.dpapi` encrypted by DPAPI (using PowerShell) on Windows
+//!
+//! instead of plaintext config files. The token is sent as `Authorization: Bearer <token>`
+//! on downloading Tar archives from the host, only over `https://`.
+//! It is not sent on redirects to other hosts, e.g. CDNs of release assets.
+//! `llvmenv auth logout <host>` removes it.
+
+use log::{info, warn};
+#[cfg(windows)]
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use url::Url;
+
+use crate::config::{config_dir, APP_NAME};
+use crate::error::*;
+
+/// `host[:port]` in lower case of a URL or a bare host name
+///
+/// ```
+/// # use llvmenv::auth::normalize_host;
+/// assert_eq!(normalize_host("https://Mirror.example.com:8080/llvm.tar.xz").unwrap(), "mirror.example.com:8080");
+/// assert_eq!(normalize_host("github.com").unwrap(), "github.com");
+/// assert!(normalize_host("file:///tmp/a").is_err());
+/// ```
+pub fn normalize_host(host: &str) -> Result<String> {
+    let url = if host.contains("://") {
+        Url::parse(host)?
+    } else {
+        Url::parse(&format!("https://{}", host))?
+    };
+    let name = url
+        .host_str()
+        .ok_or_else(|| format_err!("No host in {}", host))?
+        .to_lowercase();
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", name, port),
+        None => name,
+    })
+}
+
+/// Store `token` for `host`
+pub fn login(host: &str, token: &str) -> Result<()> {
+    let host = normalize_host(host)?;
+    if token.is_empty() {
+        bail!("Token is empty");
+    }
+    store(&host, token)?;
+    info!("Stored a token for {}", host);
+    Ok(())
+}
+
+/// Remove the token for `host`
+pub fn logout(host: &str) -> Result<()> {
+    let host = normalize_host(host)?;
+    delete(&host)?;
+    info!("Removed the token for {}", host);
+    Ok(())
+}
+
+/// Token for the host of `url`, None if not logged in, the keychain is unavailable, or `url` is not `https://`
+pub fn token_for_url(url: &str) -> Option<String> {
+    if Url::parse(url).ok()?.scheme() != "https" {
+        info!("No token is sent to {}, which is not https://", url);
+        return None;
+    }
+    let host = normalize_host(url).ok()?;
+    match lookup(&host) {
+        Ok(token) => token,
+        Err(e) => {
+            warn!("Cannot read the token for {}: {}", host, e);
+            None
+        }
+    }
+}
+
+fn label(host: &str) -> String {
+    format!("{}: {}", APP_NAME, host)
+}
+
+/// Run `cmd` with writing `input` into its stdin
+fn run_with_input(cmd: &mut Command, input: &str) -> Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())?;
+    Ok(child.wait_with_output()?)
+}
+
+fn check_output(tool: &str, output: &Output) -> Result<()> {
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn store(host: &str, token: &str) -> Result<()> {
+    check_tool("secret-tool")?;
+    let output = run_with_input(
+        Command::new("secret-tool")
+            .arg("store")
+            .arg(format!("--label={}", label(host)))
+            .args(["service", APP_NAME, "host", host]),
+        token,
+    )?;
+    check_output("secret-tool", &output)
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn lookup(host: &str) -> Result<Option<String>> {
+    // Exit with 1 without any messages if not found
+    let output = match Command::new("secret-tool")
+        .args(["lookup", "service", APP_NAME, "host", host])
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return Ok(None), // secret-tool is not installed
+    };
+    if !output.status.success() || output.stdout.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn delete(host: &str) -> Result<()> {
+    check_tool("secret-tool")?;
+    let output = Command::new("secret-tool")
+        .args(["clear", "service", APP_NAME, "host", host])
+        .output()?;
+    check_output("secret-tool", &output)
+}
+
+#[cfg(target_os = "macos")]
+fn store(host: &str, token: &str) -> Result<()> {
+    // `-w` at the last without the value prompts the password twice, which is given through stdin
+    // instead of the arguments visible to the other users
+    let output = run_with_input(
+        Command::new("security")
+            .args([
+                "add-generic-password",
+                "-U",
+                "-s",
+                APP_NAME,
+                "-a",
+                host,
+                "-l",
+            ])
+            .arg(label(host))
+            .arg("-w"),
+        &format!("{0}\n{0}\n", token),
+    )?;
+    check_output("security", &output)
+}
+
+#[cfg(target_os = "macos")]
+fn lookup(host: &str) -> Result<Option<String>> {
+    let output = Command::new("security")
+        .args(["find-generic-password", "-s", APP_NAME, "-a", host, "-w"])
+        .output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+#[cfg(target_os = "macos")]
+fn delete(host: &str) -> Result<()> {
+    let output = Command::new("security")
+        .args(["delete-generic-password", "-s", APP_NAME, "-a", host])
+        .output()?;
+    check_output("security", &output)
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+fn credential_path(host: &str) -> Result<PathBuf> {
+    Ok(config_dir()?
+        .join("credentials")
+        .join(format!("{}.dpapi", host.replace(':', "_"))))
+}
+
+#[cfg(windows)]
+fn powershell(script: &str, input: &str) -> Result<String> {
+    let output = run_with_input(
+        Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", script]),
+        input,
+    )?;
+    check_output("powershell", &output)?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(windows)]
+fn store(host: &str, token: &str) -> Result<()> {
+    let encrypted = powershell(
+        "ConvertTo-SecureString ([Console]::In.ReadToEnd().Trim()) -AsPlainText -Force | ConvertFrom-SecureString",
+        token,
+    )?;
+    let path = credential_path(host)?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, encrypted)?;
+    info!("{} is stored in {}", label(host), path.display());
+    Ok(())
+}
+
+#[cfg(windows)]
+fn lookup(host: &str) -> Result<Option<String>> {
+    let path = credential_path(host)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let token = powershell(
+        "$s = ConvertTo-SecureString ([Console]::In.ReadToEnd().Trim()); [Runtime.InteropServices.Marshal]::PtrToStringAuto([Runtime.InteropServices.Marshal]::SecureStringToBSTR($s))",
+        &fs::read_to_string(&path)?,
+    )?;
+    Ok(Some(token))
+}
+
+#[cfg(windows)]
+fn delete(host: &str) -> Result<()> {
+    let path = credential_path(host)?;
+    if !path.exists() {
+        bail!("Not logged in to {}", host);
+    }
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+/// Read a token from stdin, without echo if it is a terminal
+pub fn read_token(host: &str) -> Result<String> {
+    let tty = is_tty();
+    if tty {
+        eprint!("Token for {}: ", host);
+        set_echo(false);
+    }
+    let mut token = String::new();
+    let res = std::io::stdin().read_line(&mut token);
+    if tty {
+        set_echo(true);
+        eprintln!();
+    }
+    res?;
+    Ok(token.trim().to_string())
+}
+
+fn set_echo(echo: bool) {
+    let _ = Command::new("stty")
+        .arg(if echo { "echo" } else { "-echo" })
+        .stdin(Stdio::inherit())
+        .status();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_host() -> Result<()> {
+        assert_eq!(
+            normalize_host("HTTPS://GitHub.com/llvm/llvm-project")?,
+            "github.com"
+        );
+        assert_eq!(normalize_host("localhost:8080")?, "localhost:8080");
+        assert_eq!(normalize_host("https://example.com:443/a")?, "example.com");
+        Ok(())
+    }
+
+    #[test]
+    fn test_token_for_http() {
+        assert_eq!(token_for_url("http://localhost:8080/llvm.tar.xz"), None);
+        assert_eq!(token_for_url("file:///tmp/llvm.tar.xz"), None);
+    }
+
+    #[test]
+    fn test_credential_path() -> Result<()> {
+        let path = credential_path("localhost:8080")?;
+        assert_eq!(path.file_name().unwrap(), "localhost_8080.dpapi");
+        Ok(())
+    }
+}
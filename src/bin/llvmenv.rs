@@ -1,12 +1,19 @@
-use llvmenv::error::CommandExt;
+use llvmenv::error::{err_msg, shell_quote, CommandExt};
 use llvmenv::*;
 
-use failure::bail;
-use std::env;
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 use std::process::{exit, Command};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{env, fs, io};
 use structopt::StructOpt;
 
+/// Entries downloaded ahead while building another one by `build-entry --group`
+const PREFETCH_JOBS: usize = 2;
+
 #[derive(StructOpt, Debug)]
 #[structopt(
     name = "llvmenv",
@@ -15,22 +22,73 @@ use structopt::StructOpt;
 )]
 enum LLVMEnv {
     #[structopt(name = "init", about = "Initialize llvmenv")]
-    Init {},
+    Init {
+        #[structopt(long = "defaults", help = "set up without questions")]
+        defaults: bool,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "replace entry.toml after backing it up"
+        )]
+        force: bool,
+        #[structopt(
+            help = "print the hook of the shell integration instead",
+            raw(possible_values = "&shell::SHELLS"),
+            parse(try_from_str)
+        )]
+        shell: Option<shell::Shell>,
+    },
 
     #[structopt(name = "builds", about = "List usable build")]
-    Builds {},
+    Builds {
+        #[structopt(
+            long = "tag",
+            help = "show only builds with the tag (key=value)",
+            parse(try_from_str = "build::parse_tag")
+        )]
+        tag: Vec<(String, String)>,
+    },
+
+    #[structopt(name = "tag", about = "Show or set tags (key=value) of build")]
+    Tag {
+        name: String,
+        #[structopt(parse(try_from_str = "build::parse_tag"))]
+        tags: Vec<(String, String)>,
+        #[structopt(short = "d", long = "delete", help = "remove the tag of the key")]
+        delete: Vec<String>,
+    },
 
     #[structopt(name = "entries", about = "List entries to be built")]
-    Entries {},
+    Entries {
+        #[structopt(long = "group", help = "show only entries in the group")]
+        group: Option<String>,
+    },
     #[structopt(name = "build-entry", about = "Build LLVM/Clang")]
     BuildEntry {
-        name: String,
+        #[structopt(raw(required_unless = r#""group""#))]
+        name: Option<String>,
+        #[structopt(
+            long = "group",
+            help = "build all entries in the group",
+            raw(conflicts_with = r#""name""#)
+        )]
+        group: Option<String>,
         #[structopt(short = "u", long = "update")]
         update: bool,
         #[structopt(short = "c", long = "clean", help = "clean build directory")]
         clean: bool,
         #[structopt(long = "ccache", help = "use ccache to cache builds")]
         ccache: bool,
+        #[structopt(
+            long = "nice",
+            help = "build with low CPU and I/O priority, and half of the default jobs"
+        )]
+        nice: bool,
+        #[structopt(
+            long = "offline",
+            help = "use the archives and git bundles placed in the download cache"
+        )]
+        offline: bool,
         #[structopt(
             short = "G",
             long = "builder",
@@ -45,6 +103,129 @@ enum LLVMEnv {
         discard: bool,
         #[structopt(short = "j", long = "nproc")]
         nproc: Option<usize>,
+        #[structopt(
+            long = "compile-commands",
+            help = "export compile_commands.json into the source directory"
+        )]
+        compile_commands: bool,
+        #[structopt(
+            long = "build-type",
+            help = "CMAKE_BUILD_TYPE: Debug, Release, RelWithDebInfo, or MinSizeRel, installed into e.g. ${name}-debug"
+        )]
+        build_type: Option<entry::BuildType>,
+        #[structopt(
+            long = "timeout",
+            help = "kill the build after the duration, e.g. 90m or 2h",
+            parse(try_from_str = "error::parse_duration")
+        )]
+        timeout: Option<Duration>,
+        #[structopt(
+            long = "hashed-prefix",
+            help = "install into ${hash}-${name} computed from sources, options, and host"
+        )]
+        hashed_prefix: bool,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "rebuild even if the build is up to date"
+        )]
+        force: bool,
+        #[structopt(
+            long = "only",
+            help = "run only the phases (download, configure, build, or install)",
+            parse(try_from_str),
+            raw(number_of_values = "1")
+        )]
+        only: Vec<progress::Phase>,
+        #[structopt(
+            long = "skip",
+            help = "skip the phases",
+            parse(try_from_str),
+            raw(number_of_values = "1")
+        )]
+        skip: Vec<progress::Phase>,
+        #[structopt(
+            long = "components",
+            help = "install only the component sets of the entry, e.g. compilers,analysis"
+        )]
+        components: Option<String>,
+    },
+
+    #[structopt(
+        name = "rebuild",
+        about = "Update the sources and build incrementally, re-running cmake only if the options changed"
+    )]
+    Rebuild {
+        name: String,
+        #[structopt(long = "ccache", help = "use ccache to cache builds")]
+        ccache: bool,
+        #[structopt(
+            long = "nice",
+            help = "build with low CPU and I/O priority, and half of the default jobs"
+        )]
+        nice: bool,
+        #[structopt(short = "j", long = "nproc")]
+        nproc: Option<usize>,
+        #[structopt(
+            long = "build-type",
+            help = "Build profile to rebuild, e.g. Debug for ${name}-debug"
+        )]
+        build_type: Option<entry::BuildType>,
+    },
+
+    #[structopt(name = "source", about = "Show the source directory of entry")]
+    Source {
+        name: String,
+        #[structopt(long = "open", help = "open in $EDITOR, or file manager if unset")]
+        open: bool,
+        #[structopt(long = "cd", help = "print cd command to be evaluated by shell")]
+        cd: bool,
+    },
+
+    #[structopt(name = "builddir", about = "Show the build directory of entry")]
+    Builddir { name: String },
+
+    #[structopt(
+        name = "build-log",
+        about = "Show the output of configure, build, and install of entry",
+        raw(alias = r#""log""#)
+    )]
+    Log {
+        name: String,
+        #[structopt(long = "errors-only", help = "show only lines about errors")]
+        errors_only: bool,
+        #[structopt(short = "n", long = "tail", help = "show only the last lines")]
+        tail: Option<usize>,
+        #[structopt(
+            short = "f",
+            long = "follow",
+            help = "keep showing the lines of the running build"
+        )]
+        follow: bool,
+        #[structopt(long = "previous", help = "show the log of the previous build")]
+        previous: bool,
+    },
+
+    #[structopt(
+        name = "cmake-cache",
+        about = "Show variables in CMakeCache.txt of entry"
+    )]
+    CMakeCache {
+        name: String,
+        /// Variables to be shown, all if empty
+        vars: Vec<String>,
+    },
+
+    #[structopt(
+        name = "sync",
+        about = "Build and set the build pinned by llvmenv.lock"
+    )]
+    Sync {
+        #[structopt(short = "j", long = "nproc")]
+        nproc: Option<usize>,
+        /// Record the commits and checksums of the sources into the lockfile
+        #[structopt(long = "pin")]
+        pin: bool,
     },
 
     #[structopt(name = "current", about = "Show the name of current build")]
@@ -67,18 +248,160 @@ enum LLVMEnv {
         minor: bool,
         #[structopt(long = "patch")]
         patch: bool,
+        #[structopt(
+            short = "v",
+            long = "verbose",
+            help = "Show which setting selects the build"
+        )]
+        verbose: bool,
+    },
+
+    #[structopt(
+        name = "env",
+        about = "Print shell exports to use the build, including sanitizer runtimes"
+    )]
+    Env { name: Option<String> },
+
+    #[structopt(
+        name = "llvm-sys-env",
+        about = "Print LLVM_SYS_<version>_PREFIX of the build for llvm-sys"
+    )]
+    LlvmSysEnv {
+        #[structopt(help = "build, e.g. 17.0.6 [default: the current build]")]
+        name: Option<String>,
+        #[structopt(long = "export", help = "print as a shell export")]
+        export: bool,
+    },
+
+    #[structopt(
+        name = "exec",
+        about = "Run a command with the build without switching to it"
+    )]
+    Exec {
+        name: String,
+        #[structopt(raw(last = "true"))]
+        cmd: Vec<String>,
+    },
+
+    #[structopt(
+        name = "hostinfo",
+        about = "Show CPUs, memory, disk, and tools of the host"
+    )]
+    Hostinfo {
+        #[structopt(long = "json", help = "print in JSON")]
+        json: bool,
+    },
+
+    #[structopt(
+        name = "report",
+        about = "Print a sanitized bug report of the host, config, and entry to be pasted into an issue"
+    )]
+    Report {
+        name: Option<String>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            parse(from_os_str),
+            help = "write into the file instead of stdout"
+        )]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "doctor", about = "Check the environment using the build")]
+    Doctor { name: Option<String> },
+
+    #[structopt(name = "man", about = "Show the man page of the tool in the build")]
+    Man {
+        tool: String,
+        #[structopt(short = "n", long = "name")]
+        name: Option<String>,
     },
 
     #[structopt(name = "global", about = "Set the build to use (global)")]
-    Global { name: String },
+    Global {
+        #[structopt(raw(required_unless = r#""unset""#))]
+        name: Option<String>,
+        #[structopt(long = "unset", help = "remove the global setting")]
+        unset: bool,
+    },
     #[structopt(name = "local", about = "Set the build to use (local)")]
     Local {
-        name: String,
+        #[structopt(raw(required_unless = r#""unset""#))]
+        name: Option<String>,
+        #[structopt(
+            long = "unset",
+            help = "remove .llvmenv and the llvm line of .tool-versions"
+        )]
+        unset: bool,
         #[structopt(short = "p", long = "path", parse(from_os_str))]
         path: Option<PathBuf>,
+        /// Write into `.tool-versions` of asdf instead of `.llvmenv`
+        #[structopt(long = "tool-versions")]
+        tool_versions: bool,
+    },
+    #[structopt(
+        name = "uninstall",
+        alias = "remove",
+        about = "Remove the build and the global/local settings using it"
+    )]
+    Uninstall {
+        name: String,
+        #[structopt(
+            long = "purge-cache",
+            help = "remove also the source and build directories of its entry"
+        )]
+        purge_cache: bool,
     },
 
-    #[structopt(name = "archive", about = "archive build into *.tar.xz (require pixz)")]
+    #[structopt(name = "lock", about = "Make the build read-only")]
+    Lock {
+        #[structopt(help = "prefix or name of the build")]
+        prefix: String,
+    },
+
+    #[structopt(name = "unlock", about = "Make the build writable for editing it")]
+    Unlock {
+        #[structopt(help = "prefix or name of the build")]
+        prefix: String,
+    },
+
+    #[structopt(
+        name = "rehash",
+        about = "Regenerate the shims of the tools in the builds"
+    )]
+    Rehash {},
+
+    #[structopt(
+        name = "snapshot",
+        about = "List, take, or restore snapshots of the builds"
+    )]
+    Snapshot(SnapshotCommand),
+
+    #[structopt(
+        name = "gc",
+        about = "Remove unused sources, build trees, and downloads"
+    )]
+    Gc {
+        #[structopt(short = "y", long = "yes", help = "remove them instead of listing")]
+        yes: bool,
+    },
+
+    #[structopt(
+        name = "verify",
+        about = "Check files of the build against the manifest recorded on installation"
+    )]
+    Verify {
+        name: Option<String>,
+        #[structopt(long = "deep", help = "hash all files instead of a sample")]
+        deep: bool,
+        #[structopt(long = "record", help = "record the manifest of the current files")]
+        record: bool,
+    },
+
+    #[structopt(
+        name = "archive",
+        about = "archive build into *.tar.xz (compressed in parallel by pixz if found)"
+    )]
     Archive {
         name: String,
         #[structopt(short = "v", long = "verbose")]
@@ -92,22 +415,579 @@ enum LLVMEnv {
         verbose: bool,
     },
 
+    #[structopt(name = "bazel", about = "Generate Bazel cc_toolchain using the build")]
+    Bazel {
+        name: Option<String>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "toolchain",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(
+        name = "meson",
+        about = "Print Meson native file (or cross file) using the build"
+    )]
+    Meson {
+        name: Option<String>,
+        #[structopt(long = "cross", help = "print cross file for the target triple")]
+        cross: Option<String>,
+    },
+
+    #[structopt(name = "conan", about = "Generate Conan recipe packaging the build")]
+    Conan {
+        name: Option<String>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "llvm-conan",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(name = "vcpkg", about = "Generate vcpkg overlay port of the build")]
+    Vcpkg {
+        name: Option<String>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "ports",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(
+        name = "modulefile",
+        about = "Generate modulefile of Environment Modules/Lmod"
+    )]
+    Modulefile {
+        name: Option<String>,
+        #[structopt(long = "lua", help = "generate Lua modulefile for Lmod")]
+        lua: bool,
+        #[structopt(long = "all", help = "regenerate modulefiles for all builds")]
+        all: bool,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "modulefiles",
+            parse(from_os_str),
+            help = "output directory for --all"
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(
+        name = "spack-register",
+        about = "Register builds as externals of Spack"
+    )]
+    SpackRegister {
+        /// Builds to be registered, all builds if empty
+        names: Vec<String>,
+        #[structopt(
+            long = "file",
+            parse(from_os_str),
+            help = "packages.yaml to be updated [default: ~/.spack/packages.yaml]"
+        )]
+        file: Option<PathBuf>,
+    },
+
+    #[structopt(
+        name = "homebrew",
+        about = "Generate Homebrew formula of the archived build"
+    )]
+    Homebrew {
+        name: String,
+        #[structopt(long = "url", help = "URL where the archive is served")]
+        url: String,
+        #[structopt(
+            long = "archive",
+            parse(from_os_str),
+            help = "archive of the build [default: created by `llvmenv archive`]"
+        )]
+        archive: Option<PathBuf>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "Formula",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(
+        name = "package",
+        about = "Create package (deb, rpm, zip, or msi) of the build"
+    )]
+    Package {
+        name: String,
+        #[structopt(long = "format", help = "deb, rpm, zip, or msi", parse(try_from_str))]
+        format: package::Format,
+        #[structopt(
+            long = "install-prefix",
+            parse(from_os_str),
+            help = "where the package installs the build [default: /opt/llvmenv/<name>]"
+        )]
+        install_prefix: Option<PathBuf>,
+        #[structopt(short = "o", long = "output", default_value = ".", parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    #[structopt(name = "serve", about = "Serve download cache and archives over HTTP")]
+    Serve {
+        #[structopt(long = "addr", default_value = "127.0.0.1:8080")]
+        addr: String,
+    },
+
+    #[structopt(name = "push", about = "Copy the build to another llvmenv over SSH")]
+    Push {
+        #[structopt(help = "ssh://[user@]host[:port]", parse(try_from_str))]
+        remote: remote::Remote,
+        name: Option<String>,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Replace the existing build without confirmation"
+        )]
+        force: bool,
+    },
+
+    #[structopt(name = "pull", about = "Copy the build from another llvmenv over SSH")]
+    Pull {
+        #[structopt(help = "ssh://[user@]host[:port]", parse(try_from_str))]
+        remote: remote::Remote,
+        name: String,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "Replace the existing build without confirmation"
+        )]
+        force: bool,
+    },
+
+    #[structopt(
+        name = "auth",
+        about = "Manage tokens for authenticated resources in the keychain"
+    )]
+    Auth(AuthCommand),
+
+    #[structopt(
+        name = "install",
+        about = "Install an official prebuilt release of LLVM/Clang"
+    )]
+    Install {
+        /// Version of LLVM, e.g. 17.0.6, or 17 for the latest 17.x.y
+        version: String,
+        #[structopt(long = "name", help = "name of the build (the version by default)")]
+        name: Option<String>,
+        #[structopt(short = "f", long = "force", help = "replace the existing build")]
+        force: bool,
+        #[structopt(
+            long = "no-verify",
+            help = "skip the signature verification by gpg, which is required otherwise"
+        )]
+        no_verify: bool,
+    },
+
+    #[structopt(name = "releases", about = "List releases of LLVM on GitHub")]
+    Releases {
+        #[structopt(long = "assets", help = "Show prebuilt assets of each release")]
+        assets: bool,
+        #[structopt(long = "prerelease", help = "Include release candidates")]
+        prerelease: bool,
+    },
+
+    #[structopt(
+        name = "analyze",
+        about = "Run the build command under scan-build of the build"
+    )]
+    Analyze {
+        #[structopt(short = "n", long = "name")]
+        name: Option<String>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            parse(from_os_str),
+            help = "directory of HTML reports [default: ${data_dir}/reports/${current directory}]"
+        )]
+        output: Option<PathBuf>,
+        #[structopt(raw(last = "true"))]
+        cmd: Vec<String>,
+    },
+
+    #[structopt(
+        name = "analyzer-wrappers",
+        about = "Generate scan-build and analyze-build scripts bound to the build"
+    )]
+    AnalyzerWrappers {
+        name: Option<String>,
+        #[structopt(
+            short = "o",
+            long = "output",
+            default_value = "bin",
+            parse(from_os_str)
+        )]
+        output: PathBuf,
+    },
+
+    #[structopt(name = "audit-log", about = "Show or clear the log of llvmenv usage")]
+    AuditLog(AuditCommand),
+
+    #[structopt(name = "state", about = "Export/import config, entries, and builds")]
+    State(StateCommand),
+
+    #[structopt(
+        name = "asdf-shim",
+        about = "Commands of asdf plugin protocol backed by llvmenv"
+    )]
+    AsdfShim(AsdfCommand),
+
+    #[structopt(
+        name = "plugins",
+        about = "List subcommand plugins (llvmenv-* in $PATH)"
+    )]
+    Plugins {},
+
+    #[structopt(name = "config", about = "Get or set values in config.toml")]
+    Config(ConfigCommand),
+
     #[structopt(name = "edit", about = "Edit llvmenv configure in your editor")]
     Edit {},
 
     #[structopt(name = "zsh", about = "Setup Zsh integration")]
     Zsh {},
+
+    #[structopt(name = "bash-completion", about = "Setup bash completion")]
+    BashCompletion {},
+
+    #[structopt(
+        name = "completion",
+        about = "Print the completion script of the shell"
+    )]
+    Completion {
+        #[structopt(raw(possible_values = r#"&["bash", "zsh", "fish", "powershell", "elvish"]"#))]
+        shell: String,
+    },
+
+    #[structopt(
+        name = "__complete",
+        about = "List candidates for shell completion",
+        raw(setting = "structopt::clap::AppSettings::Hidden")
+    )]
+    Complete {
+        #[structopt(raw(
+            possible_values = r#"&["entries", "builds", "tags", "groups", "plugins"]"#
+        ))]
+        kind: String,
+    },
+
+    #[structopt(
+        name = "__remote",
+        about = "Commands called by push/pull over SSH",
+        raw(setting = "structopt::clap::AppSettings::Hidden")
+    )]
+    Remote(RemoteCommand),
+}
+
+#[derive(StructOpt, Debug)]
+enum StateCommand {
+    #[structopt(
+        name = "export",
+        about = "Export the state of llvmenv into tar archive"
+    )]
+    Export {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        #[structopt(long = "builds", help = "include builds themselves")]
+        builds: bool,
+    },
+    #[structopt(name = "import", about = "Import the state exported by `state export`")]
+    Import {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        #[structopt(
+            short = "f",
+            long = "force",
+            help = "overwrite existing config and builds"
+        )]
+        force: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum AsdfCommand {
+    #[structopt(name = "list-all", about = "List all installable versions")]
+    ListAll {},
+    #[structopt(
+        name = "install",
+        about = "Install $ASDF_INSTALL_VERSION into $ASDF_INSTALL_PATH"
+    )]
+    Install {
+        #[structopt(short = "j", long = "nproc")]
+        nproc: Option<usize>,
+    },
+    #[structopt(name = "bin-path", about = "List directories of executables")]
+    BinPath {},
+}
+
+#[derive(StructOpt, Debug)]
+enum AuthCommand {
+    #[structopt(name = "login", about = "Store a token for the host read from stdin")]
+    Login { host: String },
+    #[structopt(name = "logout", about = "Remove the token for the host")]
+    Logout { host: String },
+}
+
+#[derive(StructOpt, Debug)]
+enum AuditCommand {
+    #[structopt(name = "show", about = "Show the audit log")]
+    Show {
+        #[structopt(short = "n", long = "lines", help = "show only the last lines")]
+        lines: Option<usize>,
+        #[structopt(long = "build", help = "show only the records of the build")]
+        build: Option<String>,
+    },
+    #[structopt(name = "clear", about = "Remove the audit log")]
+    Clear {},
+}
+
+#[derive(StructOpt, Debug)]
+enum ConfigCommand {
+    #[structopt(name = "get", about = "Show the value of the key, e.g. build.jobs")]
+    Get { key: String },
+    #[structopt(name = "set", about = "Set the value of the key")]
+    Set { key: String, value: String },
+    #[structopt(name = "unset", about = "Remove the key")]
+    Unset { key: String },
+    #[structopt(name = "list", about = "Show all keys and their values")]
+    List {},
+    #[structopt(name = "backup", about = "Backup entry.toml and config.toml")]
+    Backup {},
+    #[structopt(
+        name = "restore",
+        about = "Restore a backup, the latest one by default"
+    )]
+    Restore {
+        name: Option<String>,
+        #[structopt(long = "list", help = "list backups")]
+        list: bool,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum SnapshotCommand {
+    #[structopt(name = "list", about = "Show the snapshots, oldest first")]
+    List {},
+    #[structopt(name = "create", about = "Take a snapshot now")]
+    Create {},
+    #[structopt(name = "restore", about = "Roll the builds back to the snapshot")]
+    Restore { name: String },
+}
+
+#[derive(StructOpt, Debug)]
+enum RemoteCommand {
+    #[structopt(name = "data-dir", about = "Show the data directory")]
+    DataDir {},
+    #[structopt(name = "prefix", about = "Show the prefix of the build")]
+    Prefix { name: String },
+    #[structopt(name = "exists", about = "Show whether the build exists")]
+    Exists { name: String },
+    #[structopt(name = "relocate", about = "Replace the old prefix in the build")]
+    Relocate { name: String, old_prefix: String },
+}
+
+/// Output of commands, `--output json` before the subcommand for build systems and editors
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Output {
+    Text,
+    Json,
+}
+
+/// Completion script of bash querying dynamic values, also used by `completion bash`
+const BASH_COMPLETION: &str = include_str!("../../llvmenv-completion.bash");
+
+fn print_json(value: &serde_json::Value) -> error::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
 }
 
-fn main() -> error::Result<()> {
+fn main() {
     env_logger::init();
-    let opt = LLVMEnv::from_args();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        // e.g. `llvmenv entries | head`
+        if info.to_string().contains("Broken pipe") {
+            return;
+        }
+        report::record_panic(&info.to_string());
+        eprintln!(
+            "This is a bug of llvmenv. Please run `llvmenv report` and paste it into an issue:"
+        );
+        eprintln!("  {}/issues", env!("CARGO_PKG_REPOSITORY"));
+    }));
+    if let Err(e) = try_main() {
+        if e.is_timeout() {
+            eprintln!("Interrupted: {}", e);
+        } else {
+            eprintln!("Error: {}", e);
+        }
+        exit(e.exit_code());
+    }
+}
+
+/// Arguments of llvmenv including the global options
+fn app() -> structopt::clap::App<'static, 'static> {
+    // Reject arguments which are not UTF-8 by an error, instead of panicking in the accessors of clap
+    LLVMEnv::clap()
+        .global_setting(structopt::clap::AppSettings::StrictUtf8)
+        .arg(
+            structopt::clap::Arg::with_name("output")
+                .long("output")
+                .env("LLVMENV_OUTPUT")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("output format of prefix, current, version, builds, entries, and hostinfo"),
+        )
+        .arg(
+            structopt::clap::Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("print the commands of build-entry and rebuild as a shell script instead of running them"),
+        )
+}
+
+fn try_main() -> error::Result<()> {
+    // Arguments may not be UTF-8, e.g. file names
+    let args: Vec<OsString> = env::args_os().collect();
+    let subcommand = args.get(1).and_then(|arg| arg.to_str());
+    // Arguments of the tool are not parsed, and the execution is logged as the tool
+    if subcommand == Some(shim::SHIM_COMMAND) {
+        let tool = args.get(2).and_then(|tool| tool.to_str()).ok_or_else(|| {
+            err_msg(format!(
+                "Usage: llvmenv {} <tool> [args]...",
+                shim::SHIM_COMMAND
+            ))
+        })?;
+        exit(shim::exec(tool, &args[3..])?);
+    }
+    shared::init();
+    if subcommand != Some("__complete") {
+        audit::log_invocation(&args);
+    }
+    if let Some(res) = run_prompt(&args) {
+        return res;
+    }
+    let matches = match app().get_matches_from_safe(&args) {
+        Ok(matches) => matches,
+        Err(e) => {
+            use structopt::clap::ErrorKind;
+            let unknown = matches!(
+                e.kind,
+                ErrorKind::InvalidSubcommand
+                    | ErrorKind::UnrecognizedSubcommand
+                    | ErrorKind::UnknownArgument
+            );
+            if unknown {
+                if let Some(exe) = subcommand.and_then(plugin::find) {
+                    exit(plugin::run(&exe, &args[2..])?);
+                }
+            }
+            e.exit()
+        }
+    };
+    let opt = LLVMEnv::from_clap(&matches);
+    // Other commands would run as usual, e.g. remove builds
+    if matches.is_present("dry-run")
+        && !matches!(opt, LLVMEnv::BuildEntry { .. } | LLVMEnv::Rebuild { .. })
+    {
+        return Err(err_msg(
+            "--dry-run is only supported by build-entry and rebuild",
+        ));
+    }
+    error::set_dry_run(matches.is_present("dry-run"));
+    let output = match matches.value_of("output") {
+        Some("json") => Output::Json,
+        _ => Output::Text,
+    };
+    if io::stderr().is_terminal() {
+        progress::set_observer(progress::TerminalObserver::new());
+    }
+    let setup_free = matches!(
+        opt,
+        LLVMEnv::Init { .. }
+            | LLVMEnv::Zsh {}
+            | LLVMEnv::BashCompletion {}
+            | LLVMEnv::Completion { .. }
+            | LLVMEnv::Complete { .. }
+            | LLVMEnv::Remote(_)
+            // Used in prompts and scripts, which should not be interrupted
+            | LLVMEnv::Prefix { .. }
+            | LLVMEnv::Current { .. }
+            | LLVMEnv::Version { .. }
+            | LLVMEnv::LlvmSysEnv { .. }
+            | LLVMEnv::Env { .. }
+            | LLVMEnv::Exec { .. }
+    );
+    if !setup_free {
+        setup::offer()?;
+    }
+    run(opt, output)
+}
+
+/// `llvmenv prefix` and `llvmenv current` without options, which are called on every prompt or by shims.
+/// They are run before building the parser of all the subcommands.
+fn run_prompt(args: &[OsString]) -> Option<error::Result<()>> {
+    if args.len() != 2 || env::var_os("LLVMENV_OUTPUT").is_some() {
+        return None;
+    }
+    let prefix = match args[1].to_str()? {
+        "prefix" => true,
+        "current" => false,
+        _ => return None,
+    };
+    Some(build::current_build().map(|build| {
+        if prefix {
+            println!("{}", build.prefix().display());
+        } else {
+            println!("{}", build.name());
+        }
+    }))
+}
+
+fn run(opt: LLVMEnv, output: Output) -> error::Result<()> {
     match opt {
-        LLVMEnv::Init {} => config::init_config()?,
+        LLVMEnv::Init {
+            shell: Some(shell), ..
+        } => print!("{}", shell.hook()),
+        LLVMEnv::Init {
+            defaults, force, ..
+        } => setup::run(defaults, force)?,
 
-        LLVMEnv::Builds {} => {
-            let builds = build::builds()?;
-            let max = builds.iter().map(|b| b.name().len()).max().unwrap();
+        LLVMEnv::Builds { tag } => {
+            let mut builds = Vec::new();
+            for b in build::builds()? {
+                if b.has_tags(&tag)? {
+                    builds.push(b);
+                }
+            }
+            if output == Output::Json {
+                let builds = builds
+                    .iter()
+                    .map(|b| {
+                        Ok(serde_json::json!({
+                            "name": b.name(),
+                            "prefix": b.prefix(),
+                            "tags": b.metadata()?.tags,
+                        }))
+                    })
+                    .collect::<error::Result<Vec<_>>>()?;
+                return print_json(&builds.into());
+            }
+            let max = builds.iter().map(|b| b.name().len()).max().unwrap_or(0);
             for b in &builds {
                 println!(
                     "{name:<width$}: {prefix}",
@@ -118,50 +998,415 @@ fn main() -> error::Result<()> {
             }
         }
 
-        LLVMEnv::Entries {} => {
-            match entry::load_entries() {
-                Ok(ref entries) if entries.len() > 0 => {
-                    for entry in entries {
-                        println!("{}", entry.name());
-                    }
-                }
-                Ok(_) => {
-                    bail!("No entries. Please define entries in $XDG_CONFIG_HOME/llvmenv/entry.toml");
+        LLVMEnv::Tag { name, tags, delete } => {
+            let build = build::Build::existing(&name)?;
+            let mut metadata = build.metadata()?;
+            if tags.is_empty() && delete.is_empty() {
+                for (k, v) in &metadata.tags {
+                    println!("{}={}", k, v);
                 }
-                Err(reason) => {
-                    bail!(reason.to_string())
+            } else {
+                metadata.tags.extend(tags);
+                for key in &delete {
+                    metadata.tags.remove(key);
                 }
+                build.save_metadata(&metadata)?;
             }
         }
+
+        LLVMEnv::Entries { group: Some(group) } if output == Output::Json => {
+            print_json(&entry::entries_json(&entry::load_group(&group)?))?;
+        }
+        LLVMEnv::Entries { group: Some(group) } => {
+            for entry in entry::load_group(&group)? {
+                println!("{}", entry.name());
+            }
+        }
+        LLVMEnv::Entries { group: None } => match entry::load_entries() {
+            Ok(mut entries) if !entries.is_empty() => {
+                entries.sort_by(|a, b| version::cmp_names(a.name(), b.name()));
+                if output == Output::Json {
+                    return print_json(&entry::entries_json(&entries));
+                }
+                for entry in entries {
+                    if entry.deprecation().is_some() {
+                        println!("{} (deprecated)", entry.name());
+                    } else {
+                        println!("{}", entry.name());
+                    }
+                }
+            }
+            Ok(_) => {
+                return Err(err_msg(
+                    "No entries. Please define entries in $XDG_CONFIG_HOME/llvmenv/entry.toml",
+                ));
+            }
+            Err(reason) => {
+                return Err(reason);
+            }
+        },
         LLVMEnv::BuildEntry {
             name,
+            group,
             update,
             clean,
             ccache,
+            nice,
+            offline,
             discard,
             builder,
             nproc,
+            compile_commands,
+            build_type,
+            timeout,
+            hashed_prefix,
+            force,
+            only,
+            skip,
+            components,
+        } => {
+            if let Some(timeout) = timeout {
+                error::set_timeout(timeout);
+            }
+            let entries = match (name, group) {
+                (Some(name), _) => vec![entry::load_entry(&name)?],
+                (None, Some(group)) => entry::load_group(&group)?,
+                (None, None) => unreachable!("name or group is required"),
+            };
+            let config = config::load_config()?;
+            let (defaults, power) = (config.build, config.power);
+            priority::set_nice(nice || defaults.nice);
+            priority::lower_current_process();
+            network::set_offline(offline);
+            let nproc = nproc.or(defaults.jobs).unwrap_or_else(|| {
+                power::default_jobs(&power, priority::default_jobs(hostinfo::default_jobs()))
+            });
+            let ccache = ccache || defaults.ccache;
+            let default_builder = defaults.builder.clone();
+            let phases: Vec<progress::Phase> = if only.is_empty() {
+                progress::ALL_PHASES.to_vec()
+            } else {
+                only.clone()
+            };
+            let phases: Vec<_> = phases.into_iter().filter(|p| !skip.contains(p)).collect();
+            // An up-to-date build is skipped if it would be reinstalled, but not e.g. by `--only configure`
+            let installs = phases.contains(&progress::Phase::Install);
+            // Hits of the compiler cache, summed up over the group
+            let savings: Mutex<Option<cachestats::Savings>> = Mutex::new(None);
+            // Apply the options, and download the sources
+            let prepare = |entry: &mut entry::Entry| -> error::Result<()> {
+                if let Some(deprecation) = entry.deprecation() {
+                    progress::message(&deprecation.warning(entry.name()));
+                }
+                if cfg!(windows) {
+                    if let Some(warning) = config::path_length_warning(&entry.build_dir()?) {
+                        progress::message(&warning);
+                    }
+                }
+                if let Some(builder) = &builder {
+                    entry.set_builder(builder)?;
+                } else if let Some(builder) = &default_builder {
+                    entry.set_default_builder(builder)?;
+                }
+                if compile_commands {
+                    entry.set_compile_commands(true);
+                }
+                if let Some(build_type) = build_type {
+                    entry.set_build_type(build_type);
+                }
+                if hashed_prefix {
+                    entry.set_hashed_prefix(true);
+                }
+                if let Some(components) = &components {
+                    let sets: Vec<String> = components
+                        .split(',')
+                        .map(|s| s.trim().to_string())
+                        .collect();
+                    entry.set_components(&sets)?;
+                }
+                if discard {
+                    entry.clean_cache_dir()?;
+                }
+                if phases.contains(&progress::Phase::Download) {
+                    entry.checkout()?;
+                    if update {
+                        entry.update()?;
+                    }
+                }
+                Ok(())
+            };
+            let build_entry = |entry: &entry::Entry| -> error::Result<()> {
+                if installs && !force && entry.is_up_to_date()? {
+                    progress::message(&format!(
+                        "Build '{}' is up to date. Use --force to rebuild.",
+                        entry.name()
+                    ));
+                    return Ok(());
+                }
+                if clean {
+                    entry.clean_build_dir()?;
+                }
+                let cache = entry.compiler_cache(ccache);
+                let before = cache.and_then(cachestats::CacheStats::read);
+                let watch = power::watch(&power);
+                let res = entry.build_phases(nproc, ccache, &phases);
+                drop(watch);
+                if let Ok(diagnostics) = entry.diagnostics() {
+                    if !diagnostics.is_empty() {
+                        progress::message(&diagnostics.to_string());
+                        progress::message(&format!(
+                            "See `llvmenv log {} --errors-only` for details",
+                            entry.name()
+                        ));
+                    }
+                }
+                if res.is_err() {
+                    progress::message(&format!(
+                        "To report the failure, run `llvmenv report {}`",
+                        entry.name()
+                    ));
+                }
+                let summary = res?;
+                progress::message(&summary.to_string());
+                if phases.contains(&progress::Phase::Install) && !error::is_dry_run() {
+                    shim::refresh()?;
+                }
+                if let (Some(cache), Some(before)) = (cache, before) {
+                    if let Some(after) = cachestats::CacheStats::read(cache) {
+                        let mut savings = savings.lock().unwrap();
+                        let savings =
+                            savings.get_or_insert_with(|| cachestats::Savings::new(cache));
+                        savings.add(entry.name(), after.since(&before));
+                        if let Some(message) = savings.entry_message(entry.name()) {
+                            progress::message(&message);
+                        }
+                    }
+                }
+                Ok(())
+            };
+            if entries.len() == 1 {
+                let mut entry = entries.into_iter().next().unwrap();
+                prepare(&mut entry)?;
+                build_entry(&entry)?;
+                return Ok(());
+            }
+            // Sources of the next entries are downloaded while building one, and the builds run one by one
+            let names: Vec<String> = entries.iter().map(|e| e.name().to_string()).collect();
+            let slots: Vec<Mutex<entry::Entry>> = entries.into_iter().map(Mutex::new).collect();
+            let (prepare, build_entry) = (&prepare, &build_entry);
+            let mut scheduler = jobs::Scheduler::new(PREFETCH_JOBS + 1);
+            let mut builds = Vec::new();
+            for (slot, name) in slots.iter().zip(&names) {
+                let download = scheduler.add(&format!("download {}", name), move || {
+                    prepare(&mut slot.lock().unwrap())
+                });
+                let build = scheduler.add(&format!("build {}", name), move || {
+                    progress::message(&format!("Build '{}'", name));
+                    build_entry(&slot.lock().unwrap())
+                });
+                scheduler.requires(build, download);
+                if let Some(&previous) = builds.last() {
+                    scheduler.after(build, previous);
+                }
+                builds.push(build);
+            }
+            let mut outcomes = scheduler.run().into_iter();
+            let mut failed = Vec::new();
+            for name in &names {
+                let error = match (outcomes.next().unwrap(), outcomes.next().unwrap()) {
+                    (_, jobs::Outcome::Done) => continue,
+                    (jobs::Outcome::Failed(e), _) | (_, jobs::Outcome::Failed(e)) => e,
+                    (_, jobs::Outcome::Skipped(reason)) => {
+                        err_msg(format!("Skipped since {}", reason))
+                    }
+                };
+                // The deadline is shared by all entries
+                if error.is_timeout() {
+                    return Err(error);
+                }
+                eprintln!("Failed to build '{}': {}", name, error);
+                failed.push(name.clone());
+            }
+            match savings.lock().unwrap().as_ref() {
+                Some(savings) => progress::message(&savings.to_string()),
+                None if !error::is_dry_run() && failed.len() < names.len() => progress::message(
+                    "Install sccache or ccache to reuse identical compile jobs between the entries of a group",
+                ),
+                None => {}
+            }
+            if !failed.is_empty() {
+                return Err(err_msg(format!(
+                    "{} of {} entries failed: {}",
+                    failed.len(),
+                    names.len(),
+                    failed.join(", ")
+                )));
+            }
+        }
+
+        LLVMEnv::Rebuild {
+            name,
+            ccache,
+            nice,
+            nproc,
+            build_type,
         } => {
             let mut entry = entry::load_entry(&name)?;
-            let nproc = nproc.unwrap_or(num_cpus::get());
-            if let Some(builder) = builder {
-                entry.set_builder(&builder)?;
+            let config = config::load_config()?;
+            let (defaults, power) = (config.build, config.power);
+            priority::set_nice(nice || defaults.nice);
+            priority::lower_current_process();
+            let nproc = nproc.or(defaults.jobs).unwrap_or_else(|| {
+                power::default_jobs(&power, priority::default_jobs(hostinfo::default_jobs()))
+            });
+            if let Some(builder) = &defaults.builder {
+                entry.set_default_builder(builder)?;
+            }
+            if let Some(build_type) = build_type {
+                entry.set_build_type(build_type);
             }
-            if discard {
-                entry.clean_cache_dir().unwrap();
+            let watch = power::watch(&power);
+            let res = entry.rebuild(nproc, ccache || defaults.ccache);
+            drop(watch);
+            if let Ok(diagnostics) = entry.diagnostics() {
+                if !diagnostics.is_empty() {
+                    eprintln!("{}", diagnostics);
+                    eprintln!(
+                        "See `llvmenv log {} --errors-only` for details",
+                        entry.name()
+                    );
+                }
+            }
+            if res.is_err() {
+                eprintln!(
+                    "To report the failure, run `llvmenv report {}`",
+                    entry.name()
+                );
             }
-            entry.checkout().unwrap();
-            if update {
-                entry.update().unwrap();
+            eprintln!("{}", res?);
+        }
+
+        LLVMEnv::Source { name, open, cd } => {
+            let entry = entry::load_entry(&name)?;
+            let src = entry.src_dir()?;
+            if !src.is_dir() {
+                return Err(err_msg(format!(
+                    "Source of '{}' is not checked out yet: {}",
+                    name,
+                    src.display()
+                )));
             }
-            if clean {
-                entry.clean_build_dir().unwrap();
+            if open {
+                let opener = env::var("EDITOR").unwrap_or_else(|_| file_manager().into());
+                Command::new(opener).arg(&src).check_run()?;
+            } else if cd {
+                println!("cd {}", shell_quote(&src.display().to_string()));
+            } else {
+                println!("{}", src.display());
             }
-            entry.build(nproc, ccache).unwrap();
         }
 
+        LLVMEnv::Builddir { name } => {
+            let entry = entry::load_entry(&name)?;
+            println!("{}", entry.build_dir()?.display());
+        }
+
+        LLVMEnv::Log {
+            name,
+            errors_only,
+            tail,
+            follow,
+            previous,
+        } => {
+            let entry = entry::load_entry(&name)?;
+            if errors_only {
+                for line in entry.error_lines()? {
+                    println!("{}", line);
+                }
+                return Ok(());
+            }
+            let mut log = entry.build_log()?;
+            if previous {
+                log.set_file_name(diagnostics::PREVIOUS_BUILD_LOG_FN);
+            }
+            if !log.exists() {
+                return Err(err_msg(format!(
+                    "No build log of '{}': {}",
+                    name,
+                    log.display()
+                )));
+            }
+            let mut file = fs::File::open(&log)?;
+            match tail {
+                Some(n) => {
+                    // Compiler output may not be UTF-8, e.g. in the locale of the system
+                    let mut bytes = Vec::new();
+                    io::Read::read_to_end(&mut file, &mut bytes)?;
+                    print!("{}", diagnostics::tail(&String::from_utf8_lossy(&bytes), n));
+                }
+                None => {
+                    io::copy(&mut file, &mut io::stdout())?;
+                }
+            }
+            // Until Ctrl-C, since the log does not tell whether the build is finished
+            if follow {
+                loop {
+                    io::Write::flush(&mut io::stdout())?;
+                    std::thread::sleep(Duration::from_millis(500));
+                    io::copy(&mut file, &mut io::stdout())?;
+                }
+            }
+        }
+
+        LLVMEnv::CMakeCache { name, vars } => {
+            let entry = entry::load_entry(&name)?;
+            let cache = entry.cmake_cache()?;
+            if vars.is_empty() {
+                for (k, v) in &cache {
+                    println!("{}={}", k, v);
+                }
+            } else {
+                let mut missing = Vec::new();
+                for var in &vars {
+                    match cache.get(var) {
+                        Some(v) => println!("{}={}", var, v),
+                        None => missing.push(var.as_str()),
+                    }
+                }
+                if !missing.is_empty() {
+                    return Err(err_msg(format!(
+                        "Not found in CMakeCache.txt: {}",
+                        missing.join(", ")
+                    )));
+                }
+            }
+        }
+
+        LLVMEnv::Sync { nproc, pin } => {
+            let mut lock = lock::Lock::seek(&env::current_dir()?)?.ok_or_else(|| {
+                err_msg("No lockfile found. Please create llvmenv.lock or .llvmenv.toml")
+            })?;
+            if pin {
+                for pin in lock.write_pins()? {
+                    let id = pin.revision.as_ref().or(pin.sha256.as_ref()).unwrap();
+                    eprintln!("Pin {} at {} ({})", pin.path, id, pin.url);
+                }
+            }
+            let nproc = nproc.unwrap_or_else(hostinfo::default_jobs);
+            let build = lock.sync(nproc)?;
+            eprintln!("Use '{}' in {}", build.name(), lock.dir().display());
+        }
+
+        LLVMEnv::Current { .. } | LLVMEnv::Prefix { .. } if output == Output::Json => {
+            let build = build::current_build()?;
+            print_json(&serde_json::json!({
+                "name": build.name(),
+                "prefix": build.prefix(),
+                "set_by": build.env_path(),
+            }))?;
+        }
         LLVMEnv::Current { verbose } => {
-            let build = build::seek_build()?;
+            let build = build::current_build()?;
             println!("{}", build.name());
             if verbose {
                 if let Some(env) = build.env_path() {
@@ -170,7 +1415,7 @@ fn main() -> error::Result<()> {
             }
         }
         LLVMEnv::Prefix { verbose } => {
-            let build = build::seek_build()?;
+            let build = build::current_build()?;
             println!("{}", build.prefix().display());
             if verbose {
                 if let Some(env) = build.env_path() {
@@ -183,13 +1428,30 @@ fn main() -> error::Result<()> {
             major,
             minor,
             patch,
+            verbose,
         } => {
             let build = if let Some(name) = name {
-                get_existing_build(&name)
+                if verbose {
+                    eprintln!("'{}' given by --name", name);
+                }
+                build::Build::existing(&name)?
+            } else if verbose {
+                let resolution = build::resolve(&env::current_dir()?)?;
+                eprintln!("{}", resolution);
+                resolution.build
             } else {
-                build::seek_build()?
+                build::current_build()?
             };
             let (ma, mi, pa) = build.version()?;
+            if output == Output::Json {
+                return print_json(&serde_json::json!({
+                    "name": build.name(),
+                    "version": format!("{}.{}.{}", ma, mi, pa),
+                    "major": ma,
+                    "minor": mi,
+                    "patch": pa,
+                }));
+            }
             if !(major || minor || patch) {
                 println!("{}.{}.{}", ma, mi, pa);
             } else {
@@ -202,28 +1464,479 @@ fn main() -> error::Result<()> {
                 if patch {
                     print!("{}", pa);
                 }
-                println!("");
+                println!();
             }
         }
 
-        LLVMEnv::Global { name } => {
-            let build = get_existing_build(&name);
+        LLVMEnv::Global { unset: true, .. } => unset_settings(&config::config_dir()?)?,
+        LLVMEnv::Global { name, .. } => {
+            let build = build::Build::existing(&name.unwrap())?;
             build.set_global()?;
         }
-        LLVMEnv::Local { name, path } => {
-            let build = get_existing_build(&name);
+        LLVMEnv::Local {
+            unset: true, path, ..
+        } => unset_settings(&path.unwrap_or(env::current_dir()?))?,
+        LLVMEnv::Local {
+            name,
+            path,
+            tool_versions,
+            ..
+        } => {
+            let build = build::Build::existing(&name.unwrap())?;
             let path = path.unwrap_or(env::current_dir()?);
-            build.set_local(&path)?;
+            if tool_versions {
+                build.set_tool_versions(&path)?;
+            } else {
+                build.set_local(&path)?;
+            }
+        }
+        LLVMEnv::Lock { prefix } => {
+            let prefix = lockdown::prefix_of(&prefix)?;
+            lockdown::lock(&prefix)?;
+            eprintln!("Locked {}", prefix.display());
+        }
+        LLVMEnv::Unlock { prefix } => {
+            let prefix = lockdown::prefix_of(&prefix)?;
+            lockdown::unlock(&prefix)?;
+            eprintln!("Unlocked {}", prefix.display());
+        }
+        LLVMEnv::Rehash {} => {
+            let tools = shim::rehash()?;
+            let dir = shim::shim_dir()?;
+            eprintln!("Generated {} shims in {}", tools.len(), dir.display());
+            let paths = env::var_os("PATH").unwrap_or_default();
+            if !env::split_paths(&paths).any(|path| path == dir) {
+                eprintln!(
+                    "Add it to PATH to use them, e.g. export PATH=\"{}:$PATH\"",
+                    dir.display()
+                );
+            }
+        }
+        LLVMEnv::Snapshot(SnapshotCommand::List {}) => {
+            let (dir, backend) = snapshot::backend()?;
+            for name in backend.list(&dir)? {
+                println!("{}", name);
+            }
+        }
+        LLVMEnv::Snapshot(SnapshotCommand::Create {}) => {
+            let (dir, backend) = snapshot::backend()?;
+            let name = backend.create(&dir, "manual")?;
+            eprintln!(
+                "Snapshot '{}' of {} ({})",
+                name,
+                dir.display(),
+                backend.name()
+            );
+        }
+        LLVMEnv::Snapshot(SnapshotCommand::Restore { name }) => {
+            let (dir, backend) = snapshot::backend()?;
+            backend.restore(&dir, &name)?;
+            eprintln!("Restored the builds in {} to '{}'", dir.display(), name);
+        }
+        LLVMEnv::Gc { yes } => {
+            let garbage = gc::scan()?;
+            for g in &garbage {
+                println!(
+                    "{:>10}  {:<12}  {}",
+                    gc::human_size(g.size),
+                    g.kind,
+                    g.path.display()
+                );
+            }
+            let total = gc::human_size(garbage.iter().map(|g| g.size).sum());
+            if garbage.is_empty() {
+                eprintln!("Nothing to remove");
+            } else if yes {
+                gc::remove(&garbage)?;
+                eprintln!("Removed {}", total);
+            } else {
+                eprintln!("{} can be reclaimed by `llvmenv gc --yes`", total);
+            }
+        }
+        LLVMEnv::Verify { name, deep, record } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            if record {
+                let files = verify::record(&build)?;
+                eprintln!("Recorded {} files of '{}'", files, build.name());
+                return Ok(());
+            }
+            let report = verify::verify(&build, deep)?;
+            for path in &report.missing {
+                println!("missing   {}", path);
+            }
+            for path in &report.modified {
+                println!("modified  {}", path);
+            }
+            eprintln!(
+                "Checked {} files of '{}', hashed {} files ({})",
+                report.files,
+                build.name(),
+                report.hashed,
+                gc::human_size(report.hashed_bytes)
+            );
+            if !report.is_ok() {
+                return Err(err_msg(format!(
+                    "'{}' has {} missing and {} modified files. Reinstall it to repair.",
+                    build.name(),
+                    report.missing.len(),
+                    report.modified.len()
+                )));
+            }
+        }
+        LLVMEnv::Uninstall { name, purge_cache } => {
+            let build = build::Build::existing(&name)?;
+            for path in build.uninstall(purge_cache)? {
+                eprintln!("Removed {}", path.display());
+            }
+            shim::refresh()?;
+        }
+
+        LLVMEnv::Env { name } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            print!("{}", environment::export_script(&environment::vars(&build)));
+        }
+        LLVMEnv::LlvmSysEnv { name, export } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            let (var, prefix) = environment::llvm_sys_env(&build)?;
+            if let Some(current) =
+                env::var_os(&var).filter(|current| *current != *prefix.as_os_str())
+            {
+                eprintln!(
+                    "{} is {} in the current environment, not the build '{}'",
+                    var,
+                    Path::new(&current).display(),
+                    build.name()
+                );
+            }
+            if export {
+                println!("export {}={}", var, shell_quote(&prefix.to_string_lossy()));
+            } else {
+                println!("{}={}", var, prefix.display());
+            }
+        }
+        LLVMEnv::Exec { name, cmd } => {
+            let build = build::Build::existing(&name)?;
+            exit(environment::exec(&build, &cmd)?);
+        }
+
+        LLVMEnv::Hostinfo { json } => {
+            let info = hostinfo::HostInfo::detect();
+            if json || output == Output::Json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+            } else {
+                print!("{}", info);
+            }
+        }
+
+        LLVMEnv::Report { name, output } => {
+            let entry = match name {
+                Some(name) => Some(entry::load_entry(&name)?),
+                None => None,
+            };
+            let bundle = report::bundle(entry.as_ref())?;
+            match output {
+                Some(path) => {
+                    fs::write(&path, bundle)?;
+                    eprintln!("Wrote the report into {}", path.display());
+                }
+                None => print!("{}", bundle),
+            }
+        }
+
+        LLVMEnv::Doctor { name } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            let checks = doctor::run(&build);
+            for check in &checks {
+                println!("{}", check);
+            }
+            if checks.iter().any(|c| c.status == doctor::Status::Error) {
+                exit(1);
+            }
+        }
+
+        LLVMEnv::Man { tool, name } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            if let (Some(dir), Some(_)) = (
+                environment::man_dir(&build),
+                environment::man_page(&build, &tool),
+            ) {
+                Command::new("man")
+                    .arg("-M")
+                    .arg(dir)
+                    .arg(&tool)
+                    .check_run()?;
+            } else {
+                // LLVM installs man pages only if LLVM_BUILD_DOCS is enabled
+                let exe = build.prefix().join("bin").join(&tool);
+                if !exe.is_file() {
+                    return Err(err_msg(format!(
+                        "'{}' is not found in build '{}'",
+                        tool,
+                        build.name()
+                    )));
+                }
+                eprintln!(
+                    "No man page of '{}' in build '{}', show --help instead",
+                    tool,
+                    build.name()
+                );
+                Command::new(exe).arg("--help").check_run()?;
+            }
         }
 
         LLVMEnv::Archive { name, verbose } => {
-            let build = get_existing_build(&name);
-            build.archive(verbose)?;
+            let build = build::Build::existing(&name)?;
+            println!("{}", build.archive(verbose)?.display());
         }
         LLVMEnv::Expand { path, verbose } => {
             build::expand(&path, verbose)?;
         }
 
+        LLVMEnv::Bazel { name, output } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            toolchain::bazel::generate(&build, &output)?;
+        }
+
+        LLVMEnv::Meson { name, cross } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            print!("{}", toolchain::meson::generate(&build, cross.as_deref())?);
+        }
+
+        LLVMEnv::Conan { name, output } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            toolchain::conan::generate(&build, &output)?;
+            eprintln!("Register by `conan export-pkg {}`", output.display());
+        }
+
+        LLVMEnv::Vcpkg { name, output } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            toolchain::vcpkg::generate(&build, &output)?;
+            eprintln!(
+                "Use by `vcpkg install llvm --overlay-ports={}`",
+                output.display()
+            );
+        }
+
+        LLVMEnv::Modulefile {
+            name,
+            lua,
+            all,
+            output,
+        } => {
+            use toolchain::modulefile::{self, Format};
+            let format = if lua { Format::Lua } else { Format::Tcl };
+            if all {
+                for path in modulefile::generate_all(&output, format)? {
+                    println!("{}", path.display());
+                }
+            } else {
+                let build = match name {
+                    Some(name) => build::Build::existing(&name)?,
+                    None => build::current_build()?,
+                };
+                print!("{}", modulefile::generate(&build, format));
+            }
+        }
+
+        LLVMEnv::SpackRegister { names, file } => {
+            let builds: Vec<_> = if names.is_empty() {
+                build::builds()?
+                    .into_iter()
+                    .filter(|b| b.name() != "system")
+                    .collect()
+            } else {
+                names
+                    .iter()
+                    .map(|name| build::Build::existing(name))
+                    .collect::<error::Result<_>>()?
+            };
+            let file = match file {
+                Some(file) => file,
+                None => toolchain::spack::default_packages_yaml()?,
+            };
+            toolchain::spack::register(&builds, &file)?;
+            eprintln!("Registered {} builds into {}", builds.len(), file.display());
+        }
+
+        LLVMEnv::Homebrew {
+            name,
+            url,
+            archive,
+            output,
+        } => {
+            let build = build::Build::existing(&name)?;
+            let archive = match archive {
+                Some(archive) => archive,
+                None => toolchain::homebrew::default_archive(&build)?,
+            };
+            let path = toolchain::homebrew::generate(&build, &url, &archive, &output)?;
+            println!("{}", path.display());
+        }
+
+        LLVMEnv::Package {
+            name,
+            format,
+            install_prefix,
+            output,
+        } => {
+            let build = build::Build::existing(&name)?;
+            let path = package::package(&build, format, install_prefix, &output)?;
+            println!("{}", path.display());
+        }
+
+        LLVMEnv::Serve { addr } => {
+            serve::Server::default_roots()?.serve(addr.as_str())?;
+        }
+
+        LLVMEnv::Push {
+            remote,
+            name,
+            force,
+        } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            remote.push(&build, force)?;
+        }
+        LLVMEnv::Pull {
+            remote,
+            name,
+            force,
+        } => {
+            let build = remote.pull(&name, force)?;
+            println!("{}", build.prefix().display());
+        }
+
+        LLVMEnv::Auth(AuthCommand::Login { host }) => {
+            let token = auth::read_token(&host)?;
+            auth::login(&host, &token)?;
+        }
+        LLVMEnv::Auth(AuthCommand::Logout { host }) => {
+            auth::logout(&host)?;
+        }
+
+        LLVMEnv::Install {
+            version,
+            name,
+            force,
+            no_verify,
+        } => {
+            let build = prebuilt::install(&version, name.as_deref(), force, !no_verify)?;
+            shim::refresh()?;
+            eprintln!(
+                "Installed '{}' into {}",
+                build.name(),
+                build.prefix().display()
+            );
+        }
+
+        LLVMEnv::Releases { assets, prerelease } => {
+            let client = github::Client::new()?;
+            let mut releases = client.releases(github::LLVM_REPO)?;
+            releases.sort_by(|a, b| version::cmp_names(&a.tag_name, &b.tag_name));
+            for release in releases {
+                if release.prerelease && !prerelease {
+                    continue;
+                }
+                println!("{}", release.tag_name);
+                if assets {
+                    for asset in &release.assets {
+                        println!("  {}", asset.name);
+                    }
+                }
+            }
+        }
+
+        LLVMEnv::Analyze { name, output, cmd } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            let output = match output {
+                Some(output) => output,
+                None => analyze::default_report_dir()?,
+            };
+            analyze::run(&build, &output, &cmd)?;
+        }
+        LLVMEnv::AnalyzerWrappers { name, output } => {
+            let build = match name {
+                Some(name) => build::Build::existing(&name)?,
+                None => build::current_build()?,
+            };
+            for path in analyze::generate_wrappers(&build, &output)? {
+                println!("{}", path.display());
+            }
+        }
+
+        LLVMEnv::AuditLog(AuditCommand::Show { lines, build }) => {
+            if !audit::is_enabled() {
+                eprintln!("Audit log is disabled. Set `enabled = true` in [audit] section of config.toml.");
+            }
+            let records: Vec<_> = audit::records()?
+                .into_iter()
+                .filter(|r| build.as_ref().is_none_or(|b| &r.build == b))
+                .collect();
+            let skip = lines.map_or(0, |n| records.len().saturating_sub(n));
+            for record in &records[skip..] {
+                println!("{}", record.to_line());
+            }
+        }
+        LLVMEnv::AuditLog(AuditCommand::Clear {}) => {
+            audit::clear()?;
+        }
+
+        LLVMEnv::State(StateCommand::Export { path, builds }) => {
+            state::export(&path, builds)?;
+        }
+        LLVMEnv::State(StateCommand::Import { path, force }) => {
+            state::import(&path, force)?;
+        }
+
+        LLVMEnv::AsdfShim(AsdfCommand::ListAll {}) => {
+            println!("{}", asdf::list_all()?.join(" "));
+        }
+        LLVMEnv::AsdfShim(AsdfCommand::Install { nproc }) => {
+            let nproc = nproc
+                .or_else(|| {
+                    env::var("ASDF_CONCURRENCY")
+                        .ok()
+                        .and_then(|n| n.parse().ok())
+                })
+                .unwrap_or_else(hostinfo::default_jobs);
+            asdf::install_from_env(nproc)?;
+        }
+        LLVMEnv::AsdfShim(AsdfCommand::BinPath {}) => {
+            println!("{}", asdf::BIN_PATHS.join(" "));
+        }
+
         LLVMEnv::Edit {} => {
             let editor = env::var("EDITOR").expect("EDITOR environmental value is not set");
             Command::new(editor)
@@ -231,20 +1944,139 @@ fn main() -> error::Result<()> {
                 .check_run()?;
         }
 
-        LLVMEnv::Zsh {} => {
-            let src = include_str!("../../llvmenv.zsh");
-            println!("{}", src);
+        LLVMEnv::Zsh {} => print!("{}", shell::Shell::Zsh.hook()),
+
+        LLVMEnv::Config(ConfigCommand::Get { key }) => match config::config_get(&key)? {
+            Some(value) => println!("{}", value),
+            None => exit(1),
+        },
+        LLVMEnv::Config(ConfigCommand::Set { key, value }) => config::config_set(&key, &value)?,
+        LLVMEnv::Config(ConfigCommand::Unset { key }) => config::config_unset(&key)?,
+        LLVMEnv::Config(ConfigCommand::List {}) => {
+            for (key, _) in config::CONFIG_KEYS.iter() {
+                if let Some(value) = config::config_get(key)? {
+                    println!("{}={}", key, value);
+                }
+            }
+        }
+
+        LLVMEnv::Config(ConfigCommand::Backup {}) => {
+            let backup = config::backup_config()?;
+            eprintln!("Backup into {}", backup.display());
+        }
+        LLVMEnv::Config(ConfigCommand::Restore {
+            name: _,
+            list: true,
+        }) => {
+            for name in config::backups()? {
+                println!("{}", name);
+            }
+        }
+        LLVMEnv::Config(ConfigCommand::Restore { name, list: false }) => {
+            let name = config::restore_config(name.as_deref())?;
+            eprintln!("Restored backup '{}'", name);
+        }
+
+        LLVMEnv::Plugins {} => {
+            let plugins = plugin::plugins();
+            let max = plugins.keys().map(|name| name.len()).max().unwrap_or(0);
+            for (name, path) in &plugins {
+                println!("{:<width$}: {}", name, path.display(), width = max);
+            }
+        }
+
+        LLVMEnv::BashCompletion {} => {
+            println!("{}", BASH_COMPLETION);
+        }
+        LLVMEnv::Completion { shell } => {
+            if shell == "bash" {
+                println!("{}", BASH_COMPLETION);
+                return Ok(());
+            }
+            let clap_shell: structopt::clap::Shell = shell.parse().map_err(err_msg)?;
+            app().gen_completions_to("llvmenv", clap_shell, &mut io::stdout());
+            if shell == "fish" {
+                print!("{}", include_str!("../../llvmenv-completion.fish"));
+            }
+        }
+
+        LLVMEnv::Complete { kind } => match kind.as_str() {
+            "entries" => {
+                for entry in entry::load_entries()? {
+                    println!("{}", entry.name());
+                }
+            }
+            "builds" => {
+                for b in build::builds()? {
+                    println!("{}", b.name());
+                }
+            }
+            "tags" => {
+                let mut tags = BTreeSet::new();
+                for b in build::builds()? {
+                    for (k, v) in b.metadata()?.tags {
+                        tags.insert(format!("{}={}", k, v));
+                    }
+                }
+                for tag in tags {
+                    println!("{}", tag);
+                }
+            }
+            "groups" => {
+                let groups: BTreeSet<String> = entry::load_entries()?
+                    .iter()
+                    .filter_map(|entry| entry.group().map(|g| g.to_string()))
+                    .collect();
+                for group in groups {
+                    println!("{}", group);
+                }
+            }
+            "plugins" => {
+                for name in plugin::plugins().keys() {
+                    println!("{}", name);
+                }
+            }
+            _ => unreachable!(),
+        },
+
+        LLVMEnv::Remote(RemoteCommand::DataDir {}) => {
+            println!("{}", config::data_dir()?.display());
+        }
+        LLVMEnv::Remote(RemoteCommand::Prefix { name }) => {
+            let build = build::Build::existing(&name)?;
+            println!("{}", build.prefix().display());
+        }
+        LLVMEnv::Remote(RemoteCommand::Exists { name }) => {
+            let build = build::Build::from_path(&config::data_dir()?.join(&name));
+            println!("{}", build.exists());
+        }
+        LLVMEnv::Remote(RemoteCommand::Relocate { name, old_prefix }) => {
+            let build = build::Build::existing(&name)?;
+            lockdown::unlocked(build.prefix(), || {
+                remote::relocate(build.prefix(), &old_prefix)
+            })?;
         }
     }
     Ok(())
 }
 
-fn get_existing_build(name: &str) -> build::Build {
-    let build = build::Build::from_name(&name).unwrap();
-    if build.exists() {
-        build
+fn file_manager() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
     } else {
-        eprintln!("Build '{}' does not exists", name);
-        exit(1)
+        "xdg-open"
+    }
+}
+
+fn unset_settings(dir: &std::path::Path) -> error::Result<()> {
+    let removed = build::unset(dir)?;
+    if removed.is_empty() {
+        eprintln!("No settings in {}", dir.display());
     }
+    for setting in removed {
+        eprintln!("Removed {}", setting.display());
+    }
+    Ok(())
 }
@@ -1,24 +1,69 @@
 //! Manage LLVM/Clang builds
 
-use failure::{err_msg, format_err};
 use glob::glob;
 use log::*;
-use regex::Regex;
-use std::io::{Read, Write};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{env, fs};
+use std::{env, fmt, fs};
 
 use crate::config::*;
 use crate::error::*;
+use crate::lockdown;
+use crate::progress::{observer, run_phase, Phase};
+use crate::resource::{decoder, unpack};
+use crate::shared;
+use crate::snapshot;
+use crate::tmpfs;
+use crate::tree;
+use crate::version::{cmp_names, Version};
 
-const LLVMENV_FN: &'static str = ".llvmenv";
+const LLVMENV_FN: &str = ".llvmenv";
+const METADATA_FN: &str = ".llvmenv-metadata.toml";
+/// Version file of [asdf](https://asdf-vm.com/), whose `llvm` line is also used as local setting
+pub const TOOL_VERSIONS_FN: &str = ".tool-versions";
+const TOOL_VERSIONS_KEY: &str = "llvm";
+
+/// Metadata of build, stored in the prefix
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    /// Hash of the entry and its source revisions when built, see [Entry::build_hash](../entry/enum.Entry.html#method.build_hash)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_hash: Option<String>,
+    /// URL of the archive if installed by `llvmenv install`, see [prebuilt](../prebuilt/index.html)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prebuilt: Option<String>,
+    /// SHA-256 of the prebuilt archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Labels set by `llvmenv tag`, after the values since TOML cannot emit values after tables
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Parse `key=value` form of tag
+///
+/// ```
+/// # use llvmenv::build::parse_tag;
+/// assert_eq!(parse_tag("project=fw").unwrap(), ("project".into(), "fw".into()));
+/// assert_eq!(parse_tag("note=a=b").unwrap(), ("note".into(), "a=b".into()));
+/// assert!(parse_tag("project").is_err());
+/// assert!(parse_tag("=fw").is_err());
+/// ```
+pub fn parse_tag(tag: &str) -> Result<(String, String)> {
+    match tag.find('=') {
+        Some(i) if i > 0 => Ok((tag[..i].into(), tag[i + 1..].into())),
+        _ => Err(format_err!("Tag must be in key=value form: {}", tag)),
+    }
+}
 
 #[derive(Debug)]
 pub struct Build {
     name: String,             // name and id of build
     prefix: PathBuf,          // the path where the LLVM build realy exists
-    llvmenv: Option<PathBuf>, // path of .llvmenv
+    llvmenv: Option<PathBuf>, // path of .llvmenv or .tool-versions
 }
 
 impl Build {
@@ -39,17 +84,29 @@ impl Build {
         }
     }
 
+    /// Build of `name`, which may be the hash or the entry name of a [hashed prefix](../entry/index.html#hashed-prefix)
     pub fn from_name(name: &str) -> Result<Self> {
         if name == "system" {
             return Ok(Self::system());
         }
+        let data = data_dir_path()?;
+        let name = resolve_hashed(&data, name)?.unwrap_or_else(|| name.into());
         Ok(Build {
-            name: name.into(),
-            prefix: data_dir()?.join(name),
+            prefix: data.join(&name),
+            name,
             llvmenv: None,
         })
     }
 
+    /// Installed build of `name`, error if it does not exist
+    pub fn existing(name: &str) -> Result<Self> {
+        let build = Self::from_name(name)?;
+        if !build.exists() {
+            return Err(LlvmEnvError::UnknownBuild { name: name.into() });
+        }
+        Ok(build)
+    }
+
     pub fn exists(&self) -> bool {
         self.prefix.is_dir()
     }
@@ -69,6 +126,37 @@ impl Build {
         }
     }
 
+    pub fn metadata(&self) -> Result<Metadata> {
+        let path = self.prefix.join(METADATA_FN);
+        if !path.exists() {
+            return Ok(Metadata::default());
+        }
+        toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| format_err!("Broken metadata {}: {}", path.display(), e))
+    }
+
+    pub fn save_metadata(&self, metadata: &Metadata) -> Result<()> {
+        if self.name == "system" {
+            bail!("Cannot save metadata for system build");
+        }
+        if let Some(dir) = self.prefix.parent() {
+            shared::require_writable(dir)?;
+        }
+        lockdown::write(
+            &self.prefix,
+            METADATA_FN,
+            toml::to_string(metadata)?.as_bytes(),
+        )
+    }
+
+    /// Whether all of `tags` are set to the build
+    pub fn has_tags(&self, tags: &[(String, String)]) -> Result<bool> {
+        let metadata = self.metadata()?;
+        Ok(tags
+            .iter()
+            .all(|(k, v)| metadata.tags.get(k).map(|t| t == v).unwrap_or(false)))
+    }
+
     pub fn set_global(&self) -> Result<()> {
         self.set_local(&config_dir()?)
     }
@@ -81,16 +169,76 @@ impl Build {
         Ok(())
     }
 
-    pub fn archive(&self, verbose: bool) -> Result<()> {
+    /// Write the name of the build into the setting file `setting`, `.llvmenv` or `.tool-versions`
+    pub fn set_setting(&self, setting: &Path) -> Result<()> {
+        let dir = setting
+            .parent()
+            .ok_or_else(|| format_err!("Invalid setting: {}", setting.display()))?;
+        if setting.file_name() == Some(TOOL_VERSIONS_FN.as_ref()) {
+            self.set_tool_versions(dir)
+        } else {
+            self.set_local(dir)
+        }
+    }
+
+    /// Write `llvm ${name}` line into `.tool-versions` in `path`, keeping lines of other tools
+    pub fn set_tool_versions(&self, path: &Path) -> Result<()> {
+        let file = path.join(TOOL_VERSIONS_FN);
+        let current = if file.exists() {
+            fs::read_to_string(&file)?
+        } else {
+            String::new()
+        };
+        fs::write(&file, update_tool_versions(&current, &self.name))?;
+        info!("Write setting to {}", file.display());
+        Ok(())
+    }
+
+    /// Archive the build into `${data_dir}/${name}.tar.xz`, and returns its path.
+    /// It is compressed in parallel by pixz if found, or in a single thread otherwise.
+    pub fn archive(&self, verbose: bool) -> Result<PathBuf> {
         let filename = format!("{}.tar.xz", self.name);
-        Command::new("tar")
-            .arg(if verbose { "cvf" } else { "cf" })
-            .arg(&filename)
-            .arg("--use-compress-prog=pixz")
-            .arg(&self.name)
-            .current_dir(data_dir()?)
-            .check_run()?;
-        println!("{}", data_dir()?.join(filename).display());
+        let data = data_dir()?;
+        if find_tool("tar").is_some() && find_tool("pixz").is_some() {
+            Command::new("tar")
+                .arg(if verbose { "cvf" } else { "cf" })
+                .arg(&filename)
+                .arg("--use-compress-prog=pixz")
+                .arg(&self.name)
+                .current_dir(&data)
+                .check_run()?;
+        } else {
+            warn!("pixz is not found. Compress in a single thread.");
+            let tmp = tree::hidden_sibling(&data.join(&filename), "archiving");
+            let res = tree::interruptible(|| {
+                run_phase(Phase::Archive, &self.name, || self.archive_into(&tmp))
+            });
+            if let Err(e) = res {
+                let _ = fs::remove_file(&tmp);
+                return Err(e);
+            }
+            fs::rename(&tmp, data.join(&filename))?;
+        }
+        Ok(data.join(filename))
+    }
+
+    /// Write the prefix into `path` as `${name}/...` compressed by xz, with reporting the files archived
+    fn archive_into(&self, path: &Path) -> Result<()> {
+        let files = tree::walk(&self.prefix)?;
+        let mut tar = tar::Builder::new(xz2::write::XzEncoder::new(fs::File::create(path)?, 6));
+        tar.follow_symlinks(false);
+        let name =
+            |path: &Path| Path::new(&self.name).join(path.strip_prefix(&self.prefix).unwrap());
+        for dir in &files.dirs {
+            tar.append_dir(name(dir), dir)?;
+        }
+        let observer = observer();
+        for (i, file) in files.files.iter().enumerate() {
+            tree::check_interrupted()?;
+            tar.append_path_with_name(file, name(file))?;
+            observer.on_files(i as u64 + 1, Some(files.files.len() as u64));
+        }
+        tar.into_inner()?.finish()?;
         Ok(())
     }
 
@@ -103,7 +251,18 @@ impl Build {
     // Thread model: posix
     // InstalledDir: /usr/bin
     // ```
+    /// Version of LLVM. This reads `llvm-config.h` of the build if installed, and runs `clang --version` otherwise.
     pub fn version(&self) -> Result<(u32, u32, u32)> {
+        if self.name != "system" {
+            let header = self.prefix.join("include/llvm/Config/llvm-config.h");
+            if let Some(version) = fs::read_to_string(header)
+                .ok()
+                .as_deref()
+                .and_then(parse_header_version)
+            {
+                return Ok(version);
+            }
+        }
         let output = Command::new(self.prefix().join("bin").join("clang"))
             .arg("--version")
             .output()
@@ -116,85 +275,451 @@ impl Build {
         let output = ::std::str::from_utf8(&output.stdout)?;
         parse_version(output)
     }
+
+    /// Name of the entry of this build, stripping the hash of a [hashed prefix](../entry/index.html#hashed-prefix)
+    pub fn entry_name(&self) -> &str {
+        match self.name.find('-') {
+            Some(i) if is_hash(&self.name[..i]) => &self.name[i + 1..],
+            _ => &self.name,
+        }
+    }
+
+    /// Setting files referring to this build, i.e. the global setting,
+    /// and `.llvmenv` or `.tool-versions` in the current directory and its ancestors
+    pub fn references(&self) -> Result<Vec<PathBuf>> {
+        let mut refs = Vec::new();
+        for dir in setting_dirs(&env::current_dir()?)? {
+            for file in &[LLVMENV_FN, TOOL_VERSIONS_FN] {
+                let path = dir.join(file);
+                if refs.contains(&path) {
+                    continue;
+                }
+                if let Some(name) = read_setting(&path)? {
+                    if Build::from_name(&name).is_ok_and(|b| b.prefix == self.prefix) {
+                        refs.push(path);
+                    }
+                }
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Remove the build and the [references](#method.references) to it,
+    /// and also the source and build directories of its entry in the cache if `purge_cache`.
+    /// Returns the removed paths.
+    pub fn uninstall(&self, purge_cache: bool) -> Result<Vec<PathBuf>> {
+        if self.name == "system" {
+            bail!("Cannot uninstall system build");
+        }
+        if !self.exists() {
+            return Err(LlvmEnvError::UnknownBuild {
+                name: self.name.clone(),
+            });
+        }
+        if let Some(dir) = self.prefix.parent() {
+            shared::require_writable(dir)?;
+        }
+        snapshot::before("uninstall")?;
+        let mut removed = Vec::new();
+        for path in self.references()? {
+            unset_setting(&path)?;
+            removed.push(path);
+        }
+        info!("Remove build: {}", self.prefix.display());
+        tree::remove(&self.prefix)?;
+        removed.push(self.prefix.clone());
+        if purge_cache {
+            let mut dirs = vec![src_cache_dir()?, build_cache_dir()?];
+            dirs.extend(tmpfs::root());
+            for dir in &dirs {
+                let dir = dir.join(self.entry_name());
+                if dir.exists() {
+                    info!("Remove cache: {}", dir.display());
+                    tree::remove(&dir)?;
+                    removed.push(dir);
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// First `major.minor.patch` in the output of `--version`
+pub(crate) fn parse_version(version: &str) -> Result<(u32, u32, u32)> {
+    let version =
+        Version::find(version).ok_or(err_msg("Failed to parse $(clang --version) output"))?;
+    Ok((version.major, version.minor, version.patch))
+}
+
+/// Version in `#define LLVM_VERSION_MAJOR 17` and so on of `llvm-config.h`
+fn parse_header_version(header: &str) -> Option<(u32, u32, u32)> {
+    let define = |name: &str| {
+        header.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("#define"), Some(key), Some(value)) if key == name => value.parse().ok(),
+                _ => None,
+            }
+        })
+    };
+    Some((
+        define("LLVM_VERSION_MAJOR")?,
+        define("LLVM_VERSION_MINOR")?,
+        define("LLVM_VERSION_PATCH")?,
+    ))
 }
 
-fn parse_version(version: &str) -> Result<(u32, u32, u32)> {
-    let cap = Regex::new(r"(\d).(\d).(\d)")
-        .unwrap()
-        .captures(version)
-        .ok_or(err_msg("Failed to parse $(clang --version) output"))?;
-    let major = cap[1]
-        .parse()
-        .map_err(|e| format_err!("Fail to parse major version: {:?}", e))?;
-    let minor = cap[2]
-        .parse()
-        .map_err(|e| format_err!("Fail to parse minor version: {:?}", e))?;
-    let patch = cap[3]
-        .parse()
-        .map_err(|e| format_err!("Fail to parse patch version: {:?}", e))?;
-    Ok((major, minor, patch))
+/// Error if the build at `prefix` would overwrite an existing build whose name differs only in case,
+/// on a case-insensitive filesystem
+pub fn check_case_collision(prefix: &Path) -> Result<()> {
+    let (dir, name) = match (prefix.parent(), prefix.file_name()) {
+        (Some(dir), Some(name)) if dir.is_dir() => (dir, name.to_string_lossy().to_string()),
+        _ => return Ok(()),
+    };
+    for path in fs::read_dir(dir)? {
+        let other = path?.file_name().to_string_lossy().to_string();
+        if case_collisions(&[&name, &other]).is_empty() {
+            continue;
+        }
+        if is_case_insensitive(dir)? {
+            bail!(
+                "Build '{}' collides with the existing build '{}' on the case-insensitive filesystem of {}. \
+                 Use another name, or remove it by `llvmenv uninstall {}`.",
+                name,
+                other,
+                dir.display(),
+                other
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Shortest hash prefix accepted as the name of build
+const MIN_HASH_LEN: usize = 8;
+
+/// `hash` can be a hash of [hashed prefix](../entry/index.html#hashed-prefix) or its prefix
+fn is_hash(hash: &str) -> bool {
+    hash.len() >= MIN_HASH_LEN && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Seek `${hash}-${name}` directory in `data` matching `name` as its hash or entry name
+fn resolve_hashed(data: &Path, name: &str) -> Result<Option<String>> {
+    if data.join(name).is_dir() {
+        return Ok(None);
+    }
+    if !data.is_dir() {
+        return Ok(None);
+    }
+    let name_is_hash = is_hash(name);
+    let mut found = Vec::new();
+    for path in fs::read_dir(data)? {
+        let dir = path?.file_name().to_string_lossy().to_string();
+        let (hash, entry) = match dir.find('-') {
+            Some(i) => (&dir[..i], &dir[i + 1..]),
+            None => continue,
+        };
+        if !is_hash(hash) {
+            continue;
+        }
+        if entry == name || (name_is_hash && hash.starts_with(name)) {
+            found.push(dir.clone());
+        }
+    }
+    match found.len() {
+        0 => Ok(None),
+        1 => Ok(found.pop()),
+        _ => Err(format_err!(
+            "Build name '{}' is ambiguous: {}",
+            name,
+            found.join(", ")
+        )),
+    }
 }
 
 fn local_builds() -> Result<Vec<Build>> {
     Ok(glob(&format!("{}/*/bin", data_dir()?.display()))?
         .filter_map(|path| {
             if let Ok(path) = path {
-                path.parent().map(|path| Build::from_path(path))
+                path.parent().map(Build::from_path)
             } else {
                 None
             }
         })
+        // Hidden trees being removed or copied, see tree module
+        .filter(|build| !build.name.starts_with('.'))
         .collect())
 }
 
 pub fn builds() -> Result<Vec<Build>> {
     let mut bs = local_builds()?;
-    bs.sort_by(|a, b| a.name.cmp(&b.name));
+    bs.sort_by(|a, b| cmp_names(&a.name, &b.name));
     bs.insert(0, Build::system());
     Ok(bs)
 }
 
-fn load_local_env(path: &Path) -> Result<Option<Build>> {
-    let cand = path.join(LLVMENV_FN);
-    if !cand.exists() {
-        return Ok(None);
+/// Version of `llvm` in the content of `.tool-versions`
+fn parse_tool_versions(s: &str) -> Option<&str> {
+    s.lines()
+        .map(|line| line.split('#').next().unwrap().split_whitespace())
+        .filter_map(|mut words| match words.next() {
+            Some(TOOL_VERSIONS_KEY) => words.next(),
+            _ => None,
+        })
+        .next()
+}
+
+/// Replace (or append) `llvm` line in the content of `.tool-versions`
+fn update_tool_versions(s: &str, name: &str) -> String {
+    let line = format!("{} {}", TOOL_VERSIONS_KEY, name);
+    let mut found = false;
+    let mut lines: Vec<String> = s
+        .lines()
+        .map(|l| {
+            if l.split_whitespace().next() == Some(TOOL_VERSIONS_KEY) {
+                found = true;
+                line.clone()
+            } else {
+                l.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Remove `llvm` line from the content of `.tool-versions`
+fn remove_tool_versions(s: &str) -> String {
+    s.lines()
+        .filter(|l| l.split_whitespace().next() != Some(TOOL_VERSIONS_KEY))
+        .map(|l| format!("{}\n", l))
+        .collect()
+}
+
+/// Name of build in `.llvmenv` or `.tool-versions` at `path`, None if it does not exist
+fn read_setting(path: &Path) -> Result<Option<String>> {
+    // Read without checking existence since most of the directories have no settings
+    let s = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if path.file_name() == Some(TOOL_VERSIONS_FN.as_ref()) {
+        Ok(parse_tool_versions(&s).map(|name| name.to_string()))
+    } else {
+        Ok(Some(s.trim().to_string()))
     }
-    let mut f = fs::File::open(cand)?;
-    let mut s = String::new();
-    f.read_to_string(&mut s)?;
-    let name = s.trim();
-    let mut build = Build::from_name(name)?;
-    if build.exists() {
-        build.llvmenv = Some(path.into());
-        Ok(Some(build))
+}
+
+/// Remove the setting file `setting`, or only the `llvm` line of `.tool-versions`
+pub fn unset_setting(setting: &Path) -> Result<()> {
+    info!("Remove setting: {}", setting.display());
+    if setting.file_name() == Some(TOOL_VERSIONS_FN.as_ref()) {
+        let rest = remove_tool_versions(&fs::read_to_string(setting)?);
+        if rest.trim().is_empty() {
+            fs::remove_file(setting)?;
+        } else {
+            fs::write(setting, rest)?;
+        }
     } else {
-        Ok(None)
+        fs::remove_file(setting)?;
     }
+    Ok(())
+}
+
+/// Remove the settings in `dir`, i.e. `.llvmenv` and the `llvm` line of `.tool-versions`.
+/// Returns the removed settings.
+pub fn unset(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for file in &[LLVMENV_FN, TOOL_VERSIONS_FN] {
+        let setting = dir.join(file);
+        if read_setting(&setting)?.is_some() {
+            unset_setting(&setting)?;
+            removed.push(setting);
+        }
+    }
+    Ok(removed)
 }
 
-fn load_global_env() -> Result<Option<Build>> {
-    load_local_env(&config_dir()?)
+/// Directories where settings are searched in order, i.e. `dir`, its ancestors,
+/// and the directory of the global setting
+fn setting_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = dir.ancestors().map(Path::to_path_buf).collect();
+    dirs.push(config_dir_path()?);
+    Ok(dirs)
 }
 
+/// Setting in `dir` and the build name in it. `.llvmenv` has priority over `.tool-versions`.
+fn dir_setting(dir: &Path) -> Result<Option<(PathBuf, String)>> {
+    for file in &[LLVMENV_FN, TOOL_VERSIONS_FN] {
+        let path = dir.join(file);
+        if let Some(name) = read_setting(&path)? {
+            return Ok(Some((path, name)));
+        }
+    }
+    Ok(None)
+}
+
+/// Build selected by `.llvmenv` or `.tool-versions` from $PWD, the global setting, or the system build.
+/// Settings of builds which do not exist are skipped, see [dangling_settings](fn.dangling_settings.html).
 pub fn seek_build() -> Result<Build> {
-    // Seek .llvmenv from $PWD
-    let mut path = env::current_dir()?;
-    loop {
-        if let Some(mut build) = load_local_env(&path)? {
-            build.llvmenv = Some(path.join(LLVMENV_FN));
-            return Ok(build);
-        }
-        path = match path.parent() {
-            Some(path) => path.into(),
-            None => break,
+    Ok(seek_build_with_dangling()?.0)
+}
+
+/// Settings and the names in them skipped by [seek_build](fn.seek_build.html) since the builds do not exist,
+/// e.g. removed by hand or on an unmounted filesystem
+pub fn dangling_settings() -> Result<Vec<(PathBuf, String)>> {
+    Ok(seek_build_with_dangling()?.1)
+}
+
+/// [seek_build](fn.seek_build.html) and [dangling_settings](fn.dangling_settings.html) in one lookup.
+/// This only reads the setting files, and does not create the directories of llvmenv.
+pub fn seek_build_with_dangling() -> Result<(Build, Vec<(PathBuf, String)>)> {
+    let resolution = resolve(&env::current_dir()?)?;
+    Ok((resolution.build, resolution.dangling))
+}
+
+/// Current build for the commands. Settings of builds which do not exist are explained,
+/// and fixed interactively if llvmenv runs on a terminal (not in `$(llvmenv prefix)` of shell hooks).
+pub fn current_build() -> Result<Build> {
+    let (build, dangling) = seek_build_with_dangling()?;
+    if dangling.is_empty() {
+        return Ok(build);
+    }
+    let interactive = io::stdin().is_terminal() && io::stdout().is_terminal();
+    let names: Vec<String> = builds()?.iter().map(|b| b.name().to_string()).collect();
+    let mut fixed = false;
+    for (setting, name) in &dangling {
+        eprintln!(
+            "Build '{}' set by {} does not exist (removed, or on an unmounted filesystem). Using '{}' instead.",
+            name,
+            setting.display(),
+            build.name()
+        );
+        if !interactive {
+            eprintln!("Select an installed build by `llvmenv local/global <name>`, or remove the setting by `--unset`.");
+            continue;
+        }
+        eprintln!("Installed builds: {}", names.join(", "));
+        eprint!("Switch to a build (name), unset the setting (-), or keep it (empty): ");
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        match answer.trim() {
+            "" => {}
+            "-" => {
+                unset_setting(setting)?;
+                fixed = true;
+            }
+            answer => {
+                Build::existing(answer)?.set_setting(setting)?;
+                fixed = true;
+            }
+        }
+    }
+    if fixed {
+        seek_build()
+    } else {
+        Ok(build)
+    }
+}
+
+/// Where the build of [Resolution](struct.Resolution.html) is selected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// `.llvmenv` or `.tool-versions` in the directory or its ancestors, set by `llvmenv local`
+    Local(PathBuf),
+    /// Setting in the config directory, set by `llvmenv global`
+    Global(PathBuf),
+    /// No settings of existing builds
+    System,
+}
+
+/// Result of [resolve](fn.resolve.html) with how the build is selected, for `llvmenv version --verbose`
+#[derive(Debug)]
+pub struct Resolution {
+    pub build: Build,
+    pub source: Source,
+    /// Settings skipped since the builds do not exist, and the names in them
+    pub dangling: Vec<(PathBuf, String)>,
+    /// Directories searched in order until the setting is found
+    pub searched: Vec<PathBuf>,
+}
+
+/// Build selected for `dir` by `.llvmenv` or `.tool-versions` in `dir` and its ancestors,
+/// the global setting, or the system build, as [seek_build](fn.seek_build.html) for $PWD
+pub fn resolve(dir: &Path) -> Result<Resolution> {
+    let global = config_dir_path()?;
+    let mut dangling = Vec::new();
+    let mut searched = Vec::new();
+    for dir in setting_dirs(dir)? {
+        searched.push(dir.clone());
+        let (setting, name) = match dir_setting(&dir)? {
+            Some(setting) => setting,
+            None => continue,
         };
+        let mut build = Build::from_name(&name)?;
+        if build.exists() {
+            build.llvmenv = Some(setting.clone());
+            let source = if dir == global {
+                Source::Global(setting)
+            } else {
+                Source::Local(setting)
+            };
+            return Ok(Resolution {
+                build,
+                source,
+                dangling,
+                searched,
+            });
+        }
+        dangling.push((setting, name));
     }
-    // check global setting
-    if let Some(mut build) = load_global_env()? {
-        build.llvmenv = Some(config_dir()?.join(LLVMENV_FN));
-        return Ok(build);
+    Ok(Resolution {
+        build: Build::system(),
+        source: Source::System,
+        dangling,
+        searched,
+    })
+}
+
+impl fmt::Display for Resolution {
+    /// Lines describing the lookup, e.g. `'llvm-17' set by /work/.llvmenv (local)`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (setting, name) in &self.dangling {
+            writeln!(
+                f,
+                "skipped {}: build '{}' does not exist",
+                setting.display(),
+                name
+            )?;
+        }
+        match &self.source {
+            Source::Local(setting) => write!(
+                f,
+                "'{}' set by {} (local)",
+                self.build.name(),
+                setting.display()
+            ),
+            Source::Global(setting) => write!(
+                f,
+                "'{}' set by {} (global)",
+                self.build.name(),
+                setting.display()
+            ),
+            Source::System => write!(
+                f,
+                "'{}' since no settings are found in {} directories from {}",
+                self.build.name(),
+                self.searched.len(),
+                self.searched
+                    .first()
+                    .map(|d| d.display().to_string())
+                    .unwrap_or_default()
+            ),
+        }
     }
-    Ok(Build::system())
 }
 
 pub fn expand(archive: &Path, verbose: bool) -> Result<()> {
@@ -204,11 +729,16 @@ pub fn expand(archive: &Path, verbose: bool) -> Result<()> {
             archive.display()
         )));
     }
-    Command::new("tar")
-        .arg(if verbose { "xvf" } else { "xf" })
-        .arg(archive)
-        .current_dir(data_dir()?)
-        .check_run()?;
+    let data = data_dir()?;
+    if !verbose {
+        return unpack(archive, &data);
+    }
+    let mut tar = tar::Archive::new(decoder(archive)?);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        println!("{}", entry.path()?.display());
+        entry.unpack_in(&data)?;
+    }
     Ok(())
 }
 
@@ -216,6 +746,123 @@ pub fn expand(archive: &Path, verbose: bool) -> Result<()> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_metadata() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let build = Build::from_path(tmp_dir.path());
+        assert_eq!(build.metadata()?, Metadata::default());
+        let mut metadata = Metadata::default();
+        metadata.tags.insert("project".into(), "fw".into());
+        metadata.entry_hash = Some("0123abcd".into());
+        build.save_metadata(&metadata)?;
+        assert_eq!(build.metadata()?, metadata);
+        assert!(build.has_tags(&[("project".into(), "fw".into())])?);
+        assert!(!build.has_tags(&[("project".into(), "other".into())])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_tags_last() -> Result<()> {
+        let mut metadata = Metadata {
+            entry_hash: Some("0123abcd".into()),
+            prebuilt: Some("https://example.com/llvm.tar.xz".into()),
+            sha256: Some("ef01".into()),
+            ..Default::default()
+        };
+        metadata.tags.insert("project".into(), "fw".into());
+        let toml = toml::to_string(&metadata)?;
+        assert!(toml.trim_end().ends_with("[tags]\nproject = \"fw\""));
+        assert_eq!(toml::from_str::<Metadata>(&toml)?, metadata);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_hashed() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let data = tmp_dir.path();
+        fs::create_dir(data.join("7.0.0"))?;
+        fs::create_dir(data.join("0123456789abcdef-my-llvm"))?;
+        fs::create_dir(data.join("llvm-dev"))?;
+        assert_eq!(resolve_hashed(data, "7.0.0")?, None);
+        // Not a hashed prefix
+        assert_eq!(resolve_hashed(data, "dev")?, None);
+        let found = Some("0123456789abcdef-my-llvm".to_string());
+        assert_eq!(resolve_hashed(data, "my-llvm")?, found);
+        assert_eq!(resolve_hashed(data, "01234567")?, found);
+        assert_eq!(resolve_hashed(data, "0123")?, None);
+        fs::create_dir(data.join("fedcba9876543210-my-llvm"))?;
+        assert!(resolve_hashed(data, "my-llvm").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tool_versions() {
+        let s = "nodejs 10.15.0\nllvm 7.0.0 # pinned\n";
+        assert_eq!(parse_tool_versions(s), Some("7.0.0"));
+        assert_eq!(parse_tool_versions("nodejs 10.15.0\n"), None);
+        assert_eq!(
+            update_tool_versions(s, "8.0.0"),
+            "nodejs 10.15.0\nllvm 8.0.0\n"
+        );
+        assert_eq!(
+            update_tool_versions("nodejs 10.15.0", "8.0.0"),
+            "nodejs 10.15.0\nllvm 8.0.0\n"
+        );
+        assert_eq!(remove_tool_versions(s), "nodejs 10.15.0\n");
+        assert_eq!(remove_tool_versions("llvm 7.0.0\n"), "");
+    }
+
+    #[test]
+    fn test_unset() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let dir = tmp_dir.path();
+        fs::write(dir.join(LLVMENV_FN), "7.0.0")?;
+        fs::write(dir.join(TOOL_VERSIONS_FN), "nodejs 10.15.0\nllvm 7.0.0\n")?;
+        assert_eq!(
+            unset(dir)?,
+            vec![dir.join(LLVMENV_FN), dir.join(TOOL_VERSIONS_FN)]
+        );
+        assert!(!dir.join(LLVMENV_FN).exists());
+        assert_eq!(
+            fs::read_to_string(dir.join(TOOL_VERSIONS_FN))?,
+            "nodejs 10.15.0\n"
+        );
+        assert!(unset(dir)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let dir = tmp_dir.path().join("project");
+        let sub = dir.join("sub");
+        fs::create_dir_all(&sub)?;
+        fs::write(dir.join(LLVMENV_FN), "no-such-build")?;
+        fs::write(sub.join(TOOL_VERSIONS_FN), "llvm removed-build\n")?;
+        let resolution = resolve(&sub)?;
+        assert_eq!(resolution.searched[0], sub);
+        assert_eq!(
+            resolution.dangling[..2],
+            [
+                (sub.join(TOOL_VERSIONS_FN), "removed-build".to_string()),
+                (dir.join(LLVMENV_FN), "no-such-build".to_string())
+            ]
+        );
+        assert!(resolution.to_string().starts_with(&format!(
+            "skipped {}: build 'removed-build' does not exist\n",
+            sub.join(TOOL_VERSIONS_FN).display()
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_name() {
+        let build = |name: &str| Build::from_path(&Path::new("/data").join(name));
+        assert_eq!(build("0123456789abcdef-my-llvm").entry_name(), "my-llvm");
+        assert_eq!(build("llvm-mirror").entry_name(), "llvm-mirror");
+        assert_eq!(build("7.0.0").entry_name(), "7.0.0");
+    }
+
     #[test]
     fn test_parse_version() -> Result<()> {
         // https://github.com/termoshtt/llvmenv/issues/36
@@ -225,6 +872,13 @@ mod tests {
         assert_eq!(major, 6);
         assert_eq!(minor, 0);
         assert_eq!(patch, 1);
+        assert_eq!(parse_version("clang version 17.0.6")?, (17, 0, 6));
+        let header = "#define LLVM_VERSION_MAJOR 18\n#define LLVM_VERSION_MINOR 1\n#define LLVM_VERSION_PATCH 8\n";
+        assert_eq!(parse_header_version(header), Some((18, 1, 8)));
+        assert_eq!(
+            parse_header_version("#define LLVM_VERSION_MAJOR 18\n"),
+            None
+        );
         Ok(())
     }
 }
@@ -0,0 +1,202 @@
+//! Build backends driving the build system of LLVM
+//!
+//! [Entry](../entry/enum.Entry.html) does not run CMake directly, but through a [Builder]
+//! selected by `builder` of the entry:
+//!
+//! | `builder`      | Backend                    |
+//! |:---------------|:---------------------------|
+//! | (not set)      | detected, see below        |
+//! | `Makefile`     | [CMakeMakeBuilder]         |
+//! | `Ninja`        | [CMakeNinjaBuilder]        |
+//! | `VisualStudio` | [CMakeVisualStudioBuilder] |
+//!
+//! `builder` is also read from `generator`, and `Makefiles` is an alias of `Makefile`.
+//! Without it, the generator of the configured build directory is kept, or Ninja is used if found,
+//! then Unix Makefiles, and [CMakeDefaultBuilder] otherwise (e.g. Visual Studio on Windows without Ninja).
+//!
+//! Other build systems (e.g. GN or Bazel, which LLVM also maintains) can be added
+//! as another implementation without changing the build procedure of entries.
+//!
+//! [Builder]: ./trait.Builder.html
+//! [CMakeDefaultBuilder]: ./struct.CMakeDefaultBuilder.html
+//! [CMakeMakeBuilder]: ./struct.CMakeMakeBuilder.html
+//! [CMakeNinjaBuilder]: ./struct.CMakeNinjaBuilder.html
+//! [CMakeVisualStudioBuilder]: ./struct.CMakeVisualStudioBuilder.html
+
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+/// Build backend
+pub trait Builder: fmt::Debug {
+    /// Name shown in logs, e.g. `cmake-ninja`
+    fn name(&self) -> &'static str;
+
+    /// External tools which must be in `$PATH`
+    fn required_tools(&self) -> Vec<&'static str>;
+
+    /// Command generating the build directory `build` from the source `src`.
+    /// `options` are already formatted as `-DNAME=VALUE` (or `-UNAME`).
+    fn configure(&self, src: &Path, build: &Path, options: &[String]) -> Command;
+
+    /// Command building `target`, or the default target if None
+    fn build(&self, build: &Path, target: Option<&str>, nproc: usize) -> Command;
+
+    /// Whether `compile_commands.json` can be generated
+    fn exports_compile_commands(&self) -> bool {
+        true
+    }
+}
+
+/// `cmake -G <generator>`, or without `-G` if None
+fn cmake_configure(
+    generator: Option<&str>,
+    src: &Path,
+    build: &Path,
+    options: &[String],
+) -> Command {
+    let mut cmd = Command::new("cmake");
+    if let Some(generator) = generator {
+        cmd.args(["-G", generator]);
+    }
+    cmd.arg(src).args(options).current_dir(build);
+    cmd
+}
+
+/// `cmake --build`, passing `-j` to the native tool if `parallel`
+fn cmake_build(build: &Path, target: Option<&str>, nproc: usize, parallel: bool) -> Command {
+    let mut cmd = Command::new("cmake");
+    cmd.arg("--build").arg(build);
+    if let Some(target) = target {
+        cmd.args(["--target", target]);
+    }
+    if parallel {
+        cmd.args(["--", "-j", &nproc.to_string()]);
+    }
+    cmd
+}
+
+/// CMake with the default generator of the platform
+#[derive(Debug, Clone, Default)]
+pub struct CMakeDefaultBuilder;
+
+impl Builder for CMakeDefaultBuilder {
+    fn name(&self) -> &'static str {
+        "cmake"
+    }
+
+    fn required_tools(&self) -> Vec<&'static str> {
+        vec!["cmake"]
+    }
+
+    fn configure(&self, src: &Path, build: &Path, options: &[String]) -> Command {
+        cmake_configure(None, src, build, options)
+    }
+
+    fn build(&self, build: &Path, target: Option<&str>, nproc: usize) -> Command {
+        cmake_build(build, target, nproc, false)
+    }
+}
+
+/// CMake with Unix Makefiles
+#[derive(Debug, Clone, Default)]
+pub struct CMakeMakeBuilder;
+
+impl Builder for CMakeMakeBuilder {
+    fn name(&self) -> &'static str {
+        "cmake-make"
+    }
+
+    fn required_tools(&self) -> Vec<&'static str> {
+        vec!["cmake", "make"]
+    }
+
+    fn configure(&self, src: &Path, build: &Path, options: &[String]) -> Command {
+        cmake_configure(Some("Unix Makefiles"), src, build, options)
+    }
+
+    fn build(&self, build: &Path, target: Option<&str>, nproc: usize) -> Command {
+        cmake_build(build, target, nproc, true)
+    }
+}
+
+/// CMake with Ninja
+#[derive(Debug, Clone, Default)]
+pub struct CMakeNinjaBuilder;
+
+impl Builder for CMakeNinjaBuilder {
+    fn name(&self) -> &'static str {
+        "cmake-ninja"
+    }
+
+    fn required_tools(&self) -> Vec<&'static str> {
+        vec!["cmake", "ninja"]
+    }
+
+    fn configure(&self, src: &Path, build: &Path, options: &[String]) -> Command {
+        cmake_configure(Some("Ninja"), src, build, options)
+    }
+
+    fn build(&self, build: &Path, target: Option<&str>, nproc: usize) -> Command {
+        cmake_build(build, target, nproc, true)
+    }
+}
+
+/// CMake with Visual Studio 15 2017
+#[derive(Debug, Clone, Default)]
+pub struct CMakeVisualStudioBuilder;
+
+impl Builder for CMakeVisualStudioBuilder {
+    fn name(&self) -> &'static str {
+        "cmake-vs"
+    }
+
+    fn required_tools(&self) -> Vec<&'static str> {
+        vec!["cmake"]
+    }
+
+    fn configure(&self, src: &Path, build: &Path, options: &[String]) -> Command {
+        cmake_configure(Some("Visual Studio 15 2017"), src, build, options)
+    }
+
+    fn build(&self, build: &Path, target: Option<&str>, nproc: usize) -> Command {
+        cmake_build(build, target, nproc, false)
+    }
+
+    fn exports_compile_commands(&self) -> bool {
+        false // CMAKE_EXPORT_COMPILE_COMMANDS is only for Makefile and Ninja generators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_cmake_ninja() {
+        let builder = CMakeNinjaBuilder;
+        let cmd = builder.configure(Path::new("/src"), Path::new("/build"), &["-DA=1".into()]);
+        assert_eq!(args(&cmd), vec!["-G", "Ninja", "/src", "-DA=1"]);
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/build")));
+        let cmd = builder.build(Path::new("/build"), Some("install"), 4);
+        assert_eq!(
+            args(&cmd),
+            vec!["--build", "/build", "--target", "install", "--", "-j", "4"]
+        );
+    }
+
+    #[test]
+    fn test_cmake_default() {
+        let builder = CMakeDefaultBuilder;
+        let cmd = builder.configure(Path::new("/src"), Path::new("/build"), &[]);
+        assert_eq!(args(&cmd), vec!["/src"]);
+        let cmd = builder.build(Path::new("/build"), None, 4);
+        assert_eq!(args(&cmd), vec!["--build", "/build"]);
+    }
+}
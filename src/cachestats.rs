@@ -0,0 +1,228 @@
+//! Compile jobs served by the compiler cache
+//!
+//! Entries of a group built by `llvmenv build-entry --group` share many identical compile jobs,
+//! e.g. the tablegen outputs, the support libraries, and the runtimes of close versions.
+//! With sccache or ccache (see [entry](../entry/index.html#compiler-cache)), the statistics of the cache
+//! are read before and after building each entry, and the cache hits are reported for the entry,
+//! and for the whole group after the last build:
+//!
+//! ```text
+//! Compiler cache (ccache): 8 of 8 compile jobs hit the cache while building 'llvm-17'
+//! Compiler cache (ccache): 9 of 16 compile jobs hit the cache during the build of 2 entries
+//! ```
+//!
+//! The hits are counted during the builds, not only of the jobs shared with the previous entries in the group:
+//! results cached by earlier builds are hit as well. The statistics are global to the cache,
+//! so that compilations by other processes running at the same time are counted too.
+
+use regex::Regex;
+use std::fmt;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::entry::CompilerCache;
+use crate::error::*;
+
+/// Counters of the compiler cache
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Compile jobs counted
+    pub fn jobs(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Counters increased since `before`
+    ///
+    /// ```
+    /// # use llvmenv::cachestats::CacheStats;
+    /// let before = CacheStats { hits: 10, misses: 3 };
+    /// let after = CacheStats { hits: 15, misses: 6 };
+    /// assert_eq!(after.since(&before), CacheStats { hits: 5, misses: 3 });
+    /// // The statistics are zeroed by `ccache -z` in the meantime
+    /// assert_eq!(before.since(&after), CacheStats::default());
+    /// ```
+    pub fn since(&self, before: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits.saturating_sub(before.hits),
+            misses: self.misses.saturating_sub(before.misses),
+        }
+    }
+
+    /// Parse `ccache --print-stats`, tab-separated pairs of the key and the value
+    fn from_ccache(out: &str) -> Option<CacheStats> {
+        let mut stats = CacheStats::default();
+        let mut found = false;
+        for line in out.lines() {
+            let mut kv = line.split('\t');
+            let (key, value) = match (
+                kv.next(),
+                kv.next().and_then(|v| v.trim().parse::<u64>().ok()),
+            ) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            match key {
+                // ccache 4 and 3.7 respectively
+                "direct_cache_hit"
+                | "preprocessed_cache_hit"
+                | "cache_hit_direct"
+                | "cache_hit_preprocessed" => stats.hits += value,
+                "cache_miss" => stats.misses += value,
+                _ => continue,
+            }
+            found = true;
+        }
+        if found {
+            Some(stats)
+        } else {
+            None
+        }
+    }
+
+    /// Parse `sccache --show-stats`
+    fn from_sccache(out: &str) -> Option<CacheStats> {
+        static HITS: OnceLock<Regex> = OnceLock::new();
+        static MISSES: OnceLock<Regex> = OnceLock::new();
+        let hits = HITS.get_or_init(|| Regex::new(r"(?m)^Cache hits\s+(\d+)\s*$").unwrap());
+        let misses = MISSES.get_or_init(|| Regex::new(r"(?m)^Cache misses\s+(\d+)\s*$").unwrap());
+        let count = |re: &Regex| -> Option<u64> { re.captures(out)?[1].parse().ok() };
+        Some(CacheStats {
+            hits: count(hits)?,
+            misses: count(misses)?,
+        })
+    }
+
+    /// Current statistics of `cache`, None if the tool does not show them
+    pub fn read(cache: CompilerCache) -> Option<CacheStats> {
+        if is_dry_run() {
+            return None;
+        }
+        let arg = match cache {
+            CompilerCache::Ccache => "--print-stats",
+            CompilerCache::Sccache => "--show-stats",
+        };
+        let output = Command::new(cache.tool()).arg(arg).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let out = String::from_utf8_lossy(&output.stdout);
+        match cache {
+            CompilerCache::Ccache => CacheStats::from_ccache(&out),
+            CompilerCache::Sccache => CacheStats::from_sccache(&out),
+        }
+    }
+}
+
+/// Hits of the cache for each entry built, see [module level doc](index.html)
+#[derive(Debug, Clone)]
+pub struct Savings {
+    cache: CompilerCache,
+    entries: Vec<(String, CacheStats)>,
+}
+
+impl Savings {
+    pub fn new(cache: CompilerCache) -> Self {
+        Savings {
+            cache,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, stats: CacheStats) {
+        self.entries.push((name.to_string(), stats));
+    }
+
+    pub fn total(&self) -> CacheStats {
+        self.entries
+            .iter()
+            .fold(CacheStats::default(), |acc, (_, stats)| CacheStats {
+                hits: acc.hits + stats.hits,
+                misses: acc.misses + stats.misses,
+            })
+    }
+
+    /// Message of the hits for the entry `name`
+    pub fn entry_message(&self, name: &str) -> Option<String> {
+        let (_, stats) = self.entries.iter().rev().find(|(n, _)| n == name)?;
+        Some(format!(
+            "Compiler cache ({}): {} of {} compile jobs hit the cache while building '{}'",
+            self.cache.tool(),
+            stats.hits,
+            stats.jobs(),
+            name
+        ))
+    }
+}
+
+impl fmt::Display for Savings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        write!(
+            f,
+            "Compiler cache ({}): {} of {} compile jobs hit the cache during the build of {} entries",
+            self.cache.tool(),
+            total.hits,
+            total.jobs(),
+            self.entries.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stats() {
+        let ccache = "stats_updated_timestamp\t1700000000\ndirect_cache_hit\t120\npreprocessed_cache_hit\t8\ncache_miss\t40\n";
+        assert_eq!(
+            CacheStats::from_ccache(ccache),
+            Some(CacheStats {
+                hits: 128,
+                misses: 40
+            })
+        );
+        assert_eq!(
+            CacheStats::from_ccache("cache directory /home/user/.ccache\n"),
+            None
+        );
+
+        let sccache = "\
+Compile requests                     200
+Compile requests executed            180
+Cache hits                           150
+Cache hits (C/C++)                   150
+Cache misses                          30
+Cache misses (C/C++)                  30
+Cache hits rate                    83.33 %
+";
+        assert_eq!(
+            CacheStats::from_sccache(sccache),
+            Some(CacheStats {
+                hits: 150,
+                misses: 30
+            })
+        );
+        assert_eq!(CacheStats::from_sccache("sccache 0.7.4\n"), None);
+    }
+
+    #[test]
+    fn test_savings() {
+        let mut savings = Savings::new(CompilerCache::Ccache);
+        savings.add("llvm-16", CacheStats { hits: 1, misses: 7 });
+        savings.add("llvm-17", CacheStats { hits: 8, misses: 0 });
+        assert_eq!(
+            savings.entry_message("llvm-17").unwrap(),
+            "Compiler cache (ccache): 8 of 8 compile jobs hit the cache while building 'llvm-17'"
+        );
+        assert_eq!(
+            savings.to_string(),
+            "Compiler cache (ccache): 9 of 16 compile jobs hit the cache during the build of 2 entries"
+        );
+    }
+}
@@ -1,19 +1,33 @@
 use dirs;
-use failure::{bail, err_msg};
+use fs2::FileExt;
 use log::info;
+use serde_derive::Deserialize;
 use std::fs;
-use std::env;
-use std::ffi::OsString;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(target_os = "macos")]
+use std::{env, ffi::OsString};
 
-use crate::error::Result;
+use crate::error::{bail, err_msg, format_err, Result};
+use crate::shared;
 
-pub const APP_NAME: &'static str = "llvmenv";
-pub const ENTRY_TOML: &'static str = "entry.toml";
+pub const APP_NAME: &str = "llvmenv";
+pub const ENTRY_TOML: &str = "entry.toml";
+pub const CONFIG_TOML: &str = "config.toml";
 
-const LLVM_MIRROR: &str = include_str!("llvm-mirror.toml");
+/// entry.toml generated by `llvmenv init`. Built-in entries are not copied into it.
+const ENTRY_TEMPLATE: &str = r#"# Entries to build LLVM/Clang, see https://docs.rs/llvmenv/*/llvmenv/entry/index.html
+#
+# Built-in entries (llvm-project, llvm-mirror, and the official releases, see `llvmenv entries`) are available
+# without being defined here, and an entry of the same name here overrides it.
+#
+# [my-llvm]
+# path   = "~/src/llvm"
+# target = ["X86"]
+"#;
 
+#[cfg(target_os = "macos")]
 fn is_absolute_path(path: OsString) -> Option<PathBuf> {
     let path = PathBuf::from(path);
     if path.is_absolute() {
@@ -23,91 +37,793 @@ fn is_absolute_path(path: OsString) -> Option<PathBuf> {
     }
 }
 
-// We do this here and on the other functions below, because 
-// for some reason the 'dirs' library does not follow the XDG 
+// We do this here and on the other functions below, because
+// for some reason the 'dirs' library does not follow the XDG
 // specification on macOS
 #[cfg(target_os = "macos")]
-pub fn config_dir() -> Result<PathBuf> {
-    let path = env::var_os("XDG_CONFIG_HOME")
+fn xdg_dir(var: &str, default: &str, _: fn() -> Option<PathBuf>) -> Result<PathBuf> {
+    Ok(env::var_os(var)
         .and_then(is_absolute_path)
-        .or_else(|| dirs::home_dir().map(|h| h.join(".config")))
+        .or_else(|| dirs::home_dir().map(|h| h.join(default)))
         .ok_or(err_msg("Unsupported OS"))?
-        .join(APP_NAME);
-    if !path.exists() {
-        fs::create_dir_all(&path)?;
-    }
-    Ok(path)
+        .join(APP_NAME))
 }
 
 #[cfg(not(target_os = "macos"))]
-pub fn config_dir() -> Result<PathBuf> {
-    let path = dirs::config_dir()
-        .ok_or(err_msg("Unsupported OS"))?
-        .join(APP_NAME);
+fn xdg_dir(_: &str, _: &str, dir: fn() -> Option<PathBuf>) -> Result<PathBuf> {
+    Ok(dir().ok_or(err_msg("Unsupported OS"))?.join(APP_NAME))
+}
+
+fn create_dir(path: PathBuf) -> Result<PathBuf> {
     if !path.exists() {
         fs::create_dir_all(&path)?;
     }
     Ok(path)
 }
 
-#[cfg(target_os = "macos")]
-pub fn cache_dir() -> Result<PathBuf> {
-    let path = env::var_os("XDG_CACHE_HOME")
-        .and_then(is_absolute_path)
-        .or_else(|| dirs::home_dir().map(|h| h.join(".cache")))
-        .ok_or(err_msg("Unsupported OS"))?
-        .join(APP_NAME);
+/// Create the directory which may be [shared](../shared/index.html) by users
+fn create_shared_dir(path: PathBuf) -> Result<PathBuf> {
     if !path.exists() {
         fs::create_dir_all(&path)?;
+        shared::share_dir(&path)?;
     }
     Ok(path)
 }
 
-#[cfg(not(target_os = "macos"))]
+/// Path of [config_dir](fn.config_dir.html) without creating it, for reading settings on the startup
+pub fn config_dir_path() -> Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config", dirs::config_dir)
+}
+
+/// Path of [data_dir](fn.data_dir.html) without creating it, for looking up builds on the startup
+pub fn data_dir_path() -> Result<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share", dirs::data_dir)
+}
+
+pub fn config_dir() -> Result<PathBuf> {
+    create_dir(config_dir_path()?)
+}
+
+/// Path of [cache_dir](fn.cache_dir.html) without creating it
+pub fn cache_dir_path() -> Result<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache", dirs::cache_dir)
+}
+
 pub fn cache_dir() -> Result<PathBuf> {
-    let path = dirs::cache_dir()
-        .ok_or(err_msg("Unsupported OS"))?
-        .join(APP_NAME);
+    create_shared_dir(cache_dir_path()?)
+}
+
+pub fn data_dir() -> Result<PathBuf> {
+    create_shared_dir(data_dir_path()?)
+}
+
+/// Global setting of llvmenv, decoded from `$XDG_CONFIG_HOME/llvmenv/config.toml`
+///
+/// ```toml
+/// [paths]
+/// tmp = "~/.cache/llvmenv-tmp"
+/// build = "C:/b"
+///
+/// [audit]
+/// enabled = true
+///
+/// [build]
+/// jobs = 8
+/// builder = "Ninja"
+///
+/// [download]
+/// retries = 5
+/// backoff = 2
+///
+/// [network]
+/// https_proxy = "http://proxy.example.com:8080"
+/// no_proxy = "localhost,.example.com"
+/// ca_bundle = "~/corp-root.pem"
+///
+/// [power]
+/// battery_jobs = 2
+/// pause_below = 20
+///
+/// [snapshot]
+/// enabled = true
+///
+/// [lockdown]
+/// enabled = true
+///
+/// [shared]
+/// group = "llvm"
+/// umask = "002"
+/// ```
+///
+/// `llvmenv config get/set/unset <key> [value]` manages the [keys](./constant.CONFIG_KEYS.html)
+/// without editing the file by hand.
+#[derive(Deserialize, Debug, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub build: BuildConfig,
+    #[serde(default)]
+    pub download: DownloadConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub power: PowerConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    #[serde(default)]
+    pub lockdown: LockdownConfig,
+    #[serde(default)]
+    pub shared: SharedConfig,
+}
+
+/// `[shared]` section of config.toml, directories shared by users, see [shared](../shared/index.html)
+#[derive(Deserialize, Debug, Default)]
+pub struct SharedConfig {
+    /// Group owning the data and cache directories, a name or an ID
+    pub group: Option<String>,
+    /// Umask of llvmenv and the build commands in octal, e.g. "002"
+    pub umask: Option<String>,
+}
+
+/// `[lockdown]` section of config.toml, see [lockdown](../lockdown/index.html)
+#[derive(Deserialize, Debug, Default)]
+pub struct LockdownConfig {
+    /// Make the builds read-only after installing them
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// `[snapshot]` section of config.toml, see [snapshot](../snapshot/index.html)
+#[derive(Deserialize, Debug, Default)]
+pub struct SnapshotConfig {
+    /// Snapshot the builds before removing or replacing them
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// `[power]` section of config.toml, builds on battery and under thermal throttling, see [power](../power/index.html)
+#[derive(Deserialize, Debug, Default)]
+pub struct PowerConfig {
+    /// Adjust the builds for the power and thermal state, true if unset
+    pub enabled: Option<bool>,
+    /// Number of jobs on battery power, half of the default if unset
+    pub battery_jobs: Option<usize>,
+    /// Number of jobs under thermal throttling, half of the default if unset
+    pub throttled_jobs: Option<usize>,
+    /// Pause the build while on battery below this percentage
+    pub pause_below: Option<u8>,
+}
+
+impl PowerConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+/// `[network]` section of config.toml, used if the environment variables are unset, see [network](../network/index.html)
+#[derive(Deserialize, Debug, Default)]
+pub struct NetworkConfig {
+    /// Proxy of HTTP, as `http_proxy`
+    pub http_proxy: Option<String>,
+    /// Proxy of HTTPS, as `https_proxy`
+    pub https_proxy: Option<String>,
+    /// Comma-separated hosts accessed without the proxies, as `no_proxy`
+    pub no_proxy: Option<String>,
+    /// PEM file of CA certificates trusted in addition to the system ones, as `LLVMENV_CA_BUNDLE`
+    pub ca_bundle: Option<String>,
+}
+
+/// `[download]` section of config.toml, retries of interrupted downloads of Tar archives
+#[derive(Deserialize, Debug, Default)]
+pub struct DownloadConfig {
+    /// Number of retries resuming the download, 3 if unset
+    pub retries: Option<u32>,
+    /// Seconds to wait before the first retry, doubled for each retry. 1 if unset
+    pub backoff: Option<u64>,
+}
+
+impl DownloadConfig {
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+
+    pub fn backoff(&self) -> u64 {
+        self.backoff.unwrap_or(1)
+    }
+}
+
+/// `[build]` section of config.toml, defaults of `llvmenv build-entry`
+#[derive(Deserialize, Debug, Default)]
+pub struct BuildConfig {
+    /// Number of parallel jobs used if `-j` is not given, the number of CPUs if unset
+    pub jobs: Option<usize>,
+    /// CMake generator used if neither `-G` nor `builder` of the entry is given
+    pub builder: Option<String>,
+    /// Use ccache as `--ccache`
+    #[serde(default)]
+    pub ccache: bool,
+    /// Build with low priority as `--nice`, see [priority](../priority/index.html)
+    #[serde(default)]
+    pub nice: bool,
+}
+
+/// `[audit]` section of config.toml, see [audit](../audit/index.html) module
+#[derive(Deserialize, Debug, Default)]
+pub struct AuditConfig {
+    /// Append every invocation into the audit log
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// `[paths]` section of config.toml
+#[derive(Deserialize, Debug, Default)]
+pub struct PathsConfig {
+    /// Scratch directory used for git probing, downloads, and extraction
+    pub tmp: Option<String>,
+    /// Root of build trees instead of `${cache}/build`, e.g. a short one to keep paths within `MAX_PATH` on Windows.
+    /// The trees are placed in its `llvmenv` subdirectory, where [gc](../gc/index.html) removes unknown directories.
+    pub build: Option<String>,
+}
+
+impl Config {
+    /// Scratch directory set by `[paths].tmp`
+    pub fn tmp_dir(&self) -> Result<Option<PathBuf>> {
+        Ok(match &self.paths.tmp {
+            Some(tmp) => Some(PathBuf::from(shellexpand::full(tmp)?.to_string())),
+            None => None,
+        })
+    }
+
+    /// Root of build trees set by `[paths].build`
+    pub fn build_root(&self) -> Result<Option<PathBuf>> {
+        Ok(match &self.paths.build {
+            Some(build) => Some(PathBuf::from(shellexpand::full(build)?.to_string())),
+            None => None,
+        })
+    }
+}
+
+/// Load config.toml. Default setting is used if it does not exist.
+pub fn load_config() -> Result<Config> {
+    let path = config_dir_path()?.join(CONFIG_TOML);
     if !path.exists() {
-        fs::create_dir_all(&path)?;
+        return Ok(Config::default());
     }
-    Ok(path)
+    toml::from_str(&fs::read_to_string(&path)?)
+        .map_err(|e| format_err!("Invalid config {}: {}", path.display(), e))
 }
 
-#[cfg(target_os = "macos")]
-pub fn data_dir() -> Result<PathBuf> {
-    let path = env::var_os("XDG_DATA_HOME")
-        .and_then(is_absolute_path)
-        .or_else(|| dirs::home_dir().map(|h| h.join(".local/share")))
-        .ok_or(err_msg("Unsupported OS"))?
-        .join(APP_NAME);
+/// Type of the value of a config key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+    String,
+    Bool,
+    Integer,
+}
+
+/// Keys of config.toml managed by `llvmenv config`
+pub const CONFIG_KEYS: [(&str, ValueKind); 21] = [
+    ("paths.tmp", ValueKind::String),
+    ("paths.build", ValueKind::String),
+    ("audit.enabled", ValueKind::Bool),
+    ("build.jobs", ValueKind::Integer),
+    ("build.builder", ValueKind::String),
+    ("build.ccache", ValueKind::Bool),
+    ("build.nice", ValueKind::Bool),
+    ("download.retries", ValueKind::Integer),
+    ("download.backoff", ValueKind::Integer),
+    ("network.http_proxy", ValueKind::String),
+    ("network.https_proxy", ValueKind::String),
+    ("network.no_proxy", ValueKind::String),
+    ("network.ca_bundle", ValueKind::String),
+    ("power.enabled", ValueKind::Bool),
+    ("power.battery_jobs", ValueKind::Integer),
+    ("power.throttled_jobs", ValueKind::Integer),
+    ("power.pause_below", ValueKind::Integer),
+    ("snapshot.enabled", ValueKind::Bool),
+    ("lockdown.enabled", ValueKind::Bool),
+    ("shared.group", ValueKind::String),
+    ("shared.umask", ValueKind::String),
+];
+
+fn key_kind(key: &str) -> Result<ValueKind> {
+    CONFIG_KEYS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| {
+            format_err!(
+                "Unknown config key '{}' (known: {})",
+                key,
+                CONFIG_KEYS
+                    .iter()
+                    .map(|(k, _)| *k)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+fn parse_value(key: &str, value: &str) -> Result<toml::Value> {
+    Ok(match key_kind(key)? {
+        ValueKind::String => toml::Value::String(value.into()),
+        ValueKind::Bool => toml::Value::Boolean(
+            value
+                .parse()
+                .map_err(|_| format_err!("{} must be true or false: {}", key, value))?,
+        ),
+        ValueKind::Integer => toml::Value::Integer(
+            value
+                .parse()
+                .map_err(|_| format_err!("{} must be an integer: {}", key, value))?,
+        ),
+    })
+}
+
+fn split_key(key: &str) -> Result<(&str, &str)> {
+    key_kind(key)?;
+    let mut parts = key.splitn(2, '.');
+    Ok((parts.next().unwrap(), parts.next().unwrap()))
+}
+
+/// Value of the dotted `key` in the TOML table, e.g. `build.jobs`
+fn get_value<'a>(config: &'a toml::Value, key: &str) -> Result<Option<&'a toml::Value>> {
+    let (section, name) = split_key(key)?;
+    Ok(config.get(section).and_then(|table| table.get(name)))
+}
+
+fn set_value(config: &mut toml::Value, key: &str, value: &str) -> Result<()> {
+    let (section, name) = split_key(key)?;
+    let value = parse_value(key, value)?;
+    let root = config
+        .as_table_mut()
+        .ok_or_else(|| err_msg("config.toml is not a table"))?;
+    let table = root
+        .entry(section.to_string())
+        .or_insert_with(|| toml::Value::Table(Default::default()))
+        .as_table_mut()
+        .ok_or_else(|| format_err!("[{}] is not a table", section))?;
+    table.insert(name.to_string(), value);
+    Ok(())
+}
+
+/// Remove `key`, returns false if it is not set
+fn unset_value(config: &mut toml::Value, key: &str) -> Result<bool> {
+    let (section, name) = split_key(key)?;
+    let root = match config.as_table_mut() {
+        Some(root) => root,
+        None => return Ok(false),
+    };
+    let removed = match root.get_mut(section).and_then(|t| t.as_table_mut()) {
+        Some(table) => table.remove(name).is_some(),
+        None => false,
+    };
+    if root
+        .get(section)
+        .and_then(|t| t.as_table())
+        .is_some_and(|t| t.is_empty())
+    {
+        root.remove(section);
+    }
+    Ok(removed)
+}
+
+fn load_config_value(path: &Path) -> Result<toml::Value> {
     if !path.exists() {
-        fs::create_dir_all(&path)?;
+        return Ok(toml::Value::Table(Default::default()));
     }
-    Ok(path)
+    toml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| format_err!("Invalid config {}: {}", path.display(), e))
 }
 
-#[cfg(not(target_os = "macos"))]
-pub fn data_dir() -> Result<PathBuf> {
-    let path = dirs::data_dir()
-        .ok_or(err_msg("Unsupported OS"))?
-        .join(APP_NAME);
+fn save_config_value(path: &Path, config: toml::Value) -> Result<()> {
+    // Check before writing not to break config.toml
+    let _: Config = config
+        .clone()
+        .try_into()
+        .map_err(|e| format_err!("Invalid config: {}", e))?;
+    fs::write(path, toml::to_string(&config)?)?;
+    Ok(())
+}
+
+/// Value of `key` in config.toml as a string, None if unset
+pub fn config_get(key: &str) -> Result<Option<String>> {
+    let config = load_config_value(&config_dir()?.join(CONFIG_TOML))?;
+    Ok(get_value(&config, key)?.map(|value| match value {
+        toml::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }))
+}
+
+/// Set `key` to `value` in config.toml. Note that comments in config.toml are not kept.
+pub fn config_set(key: &str, value: &str) -> Result<()> {
+    let path = config_dir()?.join(CONFIG_TOML);
+    let mut config = load_config_value(&path)?;
+    set_value(&mut config, key, value)?;
+    save_config_value(&path, config)
+}
+
+/// Remove `key` from config.toml
+pub fn config_unset(key: &str) -> Result<()> {
+    let path = config_dir()?.join(CONFIG_TOML);
+    let mut config = load_config_value(&path)?;
+    if !unset_value(&mut config, key)? {
+        info!("{} is not set", key);
+        return Ok(());
+    }
+    save_config_value(&path, config)
+}
+
+/// Whether the filesystem of `dir` is case-insensitive, as by default on macOS and Windows
+pub fn is_case_insensitive(dir: &Path) -> Result<bool> {
+    let probe = tempfile::Builder::new()
+        .prefix("llvmenv-case-")
+        .tempfile_in(dir)?;
+    let name = probe
+        .path()
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_uppercase();
+    Ok(dir.join(name).exists())
+}
+
+/// Pairs of names which become the same on case-insensitive filesystems
+///
+/// ```
+/// # use llvmenv::config::case_collisions;
+/// assert_eq!(case_collisions(&["llvm-dev", "17.0.6", "LLVM-dev"]), vec![("llvm-dev", "LLVM-dev")]);
+/// assert!(case_collisions(&["llvm-dev", "llvm-dev2"]).is_empty());
+/// ```
+pub fn case_collisions<'a>(names: &[&'a str]) -> Vec<(&'a str, &'a str)> {
+    let mut found = Vec::new();
+    for (i, a) in names.iter().enumerate() {
+        for b in &names[i + 1..] {
+            if a != b && a.to_lowercase() == b.to_lowercase() {
+                found.push((*a, *b));
+            }
+        }
+    }
+    found
+}
+
+/// Version of the directory layout under `cache_dir()`
+///
+/// - 0: `${cache}/${entry}` for sources, `${cache}/${entry}/build` for builds,
+///   and `${cache}/.tar_download` shared by all downloads (until v0.2.2)
+/// - 1: `${cache}/src/${entry}`, `${cache}/build/${entry}`, `${cache}/download/`, and `${cache}/api/`
+///
+/// The upgrade from 0 moves the sources, and discards the build trees since their CMakeCache.txt points at
+/// the old source directories. The next build configures them again.
+pub const CACHE_LAYOUT_VERSION: u32 = 1;
+const CACHE_LAYOUT_FN: &str = "layout-version";
+/// Locked while upgrading the layout, so that concurrent llvmenv do not move the same directories
+const CACHE_LAYOUT_LOCK_FN: &str = "layout.lock";
+
+fn cache_layout_version(cache: &Path) -> Result<u32> {
+    let marker = cache.join(CACHE_LAYOUT_FN);
+    let version = if marker.exists() {
+        fs::read_to_string(&marker)?
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("Broken cache layout marker: {}", marker.display()))?
+    } else {
+        0
+    };
+    if version > CACHE_LAYOUT_VERSION {
+        bail!(
+            "Cache directory {} is managed by newer llvmenv (layout version {})",
+            cache.display(),
+            version
+        );
+    }
+    Ok(version)
+}
+
+fn cache_layout_dir(name: &str) -> Result<PathBuf> {
+    let cache = cache_dir()?;
+    if cache_layout_version(&cache)? < CACHE_LAYOUT_VERSION {
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(cache.join(CACHE_LAYOUT_LOCK_FN))?;
+        lock.lock_exclusive()?;
+        // Upgraded by another process while waiting the lock
+        if cache_layout_version(&cache)? < CACHE_LAYOUT_VERSION {
+            upgrade_cache_layout(&cache)?;
+            fs::write(
+                cache.join(CACHE_LAYOUT_FN),
+                format!("{}\n", CACHE_LAYOUT_VERSION),
+            )?;
+        }
+        lock.unlock()?;
+    }
+    let path = cache.join(name);
     if !path.exists() {
         fs::create_dir_all(&path)?;
     }
     Ok(path)
 }
 
-/// Initialize configure file
-pub fn init_config() -> Result<()> {
+fn upgrade_cache_layout(cache: &Path) -> Result<()> {
+    info!("Upgrade cache layout to version {}", CACHE_LAYOUT_VERSION);
+    let old_download = cache.join(".tar_download");
+    if old_download.exists() {
+        fs::remove_dir_all(&old_download)?;
+    }
+    let src = cache.join("src");
+    fs::create_dir_all(&src)?;
+    for path in fs::read_dir(cache)? {
+        let path = path?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        if !path.is_dir() || ["src", "build", "download", "api"].contains(&name.as_str()) {
+            continue;
+        }
+        // Configured for the old path of the source
+        let old_build = path.join("build");
+        if old_build.is_dir() {
+            info!("Remove old build tree: {}", old_build.display());
+            fs::remove_dir_all(&old_build)?;
+        }
+        info!("Move {} into {}", path.display(), src.display());
+        fs::rename(&path, src.join(&name))?;
+    }
+    Ok(())
+}
+
+/// Directory where the sources of remote entries are checked out
+pub fn src_cache_dir() -> Result<PathBuf> {
+    cache_layout_dir("src")
+}
+
+/// Directory where the build trees of entries are placed, `llvmenv` in `[paths].build` if set
+pub fn build_cache_dir() -> Result<PathBuf> {
+    match load_config()?.build_root()? {
+        // Not the root itself, which may have other files removed by gc
+        Some(path) => create_shared_dir(path.join(APP_NAME)),
+        None => cache_layout_dir("build"),
+    }
+}
+
+/// `MAX_PATH` of Windows. Filesystem operations of llvmenv exceed it by the `\\?\` prefix added by std,
+/// but compilers and linkers of MSVC fail.
+const MAX_PATH: usize = 260;
+
+/// Longest relative paths in build trees of LLVM are about this length, e.g. object files of clang-tools-extra
+const BUILD_TREE_DEPTH: usize = 150;
+
+/// Warning if paths in the build tree at `dir` may exceed `MAX_PATH` of Windows
+///
+/// ```
+/// # use llvmenv::config::path_length_warning;
+/// # use std::path::Path;
+/// assert!(path_length_warning(Path::new("C:/b/llvm-project")).is_none());
+/// let deep = format!("C:/Users/{}/AppData/Local/llvmenv/build/llvm-project", "x".repeat(80));
+/// assert!(path_length_warning(Path::new(&deep)).is_some());
+/// ```
+pub fn path_length_warning(dir: &Path) -> Option<String> {
+    let len = dir.as_os_str().len();
+    if len + BUILD_TREE_DEPTH <= MAX_PATH {
+        return None;
+    }
+    Some(format!(
+        "Warning: Paths in the build tree {} ({} characters) may exceed MAX_PATH ({}) and break MSVC builds. \
+         Use a short build root, e.g. `llvmenv config set paths.build C:/b`",
+        dir.display(),
+        len,
+        MAX_PATH
+    ))
+}
+
+/// Directory for downloading archives. Each download uses its own temporal directory in it,
+/// and interrupted ones leave `*.part` files resumed by the next download.
+pub fn download_cache_dir() -> Result<PathBuf> {
+    cache_layout_dir("download")
+}
+
+/// Directory where responses of GitHub API are cached with their ETags
+pub fn api_cache_dir() -> Result<PathBuf> {
+    cache_layout_dir("api")
+}
+
+/// Initialize configure file. If `force`, the existing one is [backed up](fn.backup_config.html) and replaced.
+pub fn init_config(force: bool) -> Result<()> {
     let dir = config_dir()?;
     let entry = dir.join(ENTRY_TOML);
-    if !entry.exists() {
-        info!("Create default entry setting: {}", entry.display());
-        let mut f = fs::File::create(entry)?;
-        f.write(LLVM_MIRROR.as_bytes())?;
-    } else {
-        bail!("Setting already exists.");
+    if entry.exists() {
+        if !force {
+            bail!("Setting already exists. Use `llvmenv init --force` to replace it.");
+        }
+        let backup = backup_config()?;
+        info!("Backup existing setting into {}", backup.display());
     }
+    info!("Create entry setting: {}", entry.display());
+    let mut f = fs::File::create(entry)?;
+    f.write_all(ENTRY_TEMPLATE.as_bytes())?;
     Ok(())
 }
+
+/// Directory of the backups of entry.toml and config.toml
+pub fn backup_dir() -> Result<PathBuf> {
+    Ok(config_dir()?.join("backups"))
+}
+
+/// Copy entry.toml and config.toml into `${backup_dir}/${timestamp}`, e.g. `backups/20190115T093000Z`
+pub fn backup_config() -> Result<PathBuf> {
+    let dir = config_dir()?;
+    let files: Vec<&str> = [ENTRY_TOML, CONFIG_TOML]
+        .iter()
+        .cloned()
+        .filter(|name| dir.join(name).exists())
+        .collect();
+    if files.is_empty() {
+        bail!("Nothing to backup in {}", dir.display());
+    }
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let stamp = crate::audit::format_utc(secs).replace(['-', ':'], "");
+    let mut backup = backup_dir()?.join(&stamp);
+    let mut i = 1;
+    while backup.exists() {
+        backup = backup_dir()?.join(format!("{}-{}", stamp, i));
+        i += 1;
+    }
+    fs::create_dir_all(&backup)?;
+    for name in files {
+        fs::copy(dir.join(name), backup.join(name))?;
+    }
+    Ok(backup)
+}
+
+/// Names of backups, oldest first
+pub fn backups() -> Result<Vec<String>> {
+    let dir = backup_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Restore the backup of `name`, or the latest one if None. The current setting is backed up before.
+/// Returns the name of the restored backup.
+pub fn restore_config(name: Option<&str>) -> Result<String> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => backups()?
+            .pop()
+            .ok_or_else(|| err_msg("No backups. Create one by `llvmenv config backup`."))?,
+    };
+    let backup = backup_dir()?.join(&name);
+    if !backup.is_dir() {
+        bail!(
+            "Backup '{}' is not found in {}",
+            name,
+            backup_dir()?.display()
+        );
+    }
+    let dir = config_dir()?;
+    if dir.join(ENTRY_TOML).exists() || dir.join(CONFIG_TOML).exists() {
+        let current = backup_config()?;
+        info!("Backup current setting into {}", current.display());
+    }
+    for file in &[ENTRY_TOML, CONFIG_TOML] {
+        let src = backup.join(file);
+        let dest = dir.join(file);
+        if src.exists() {
+            fs::copy(&src, &dest)?;
+        } else if dest.exists() {
+            fs::remove_file(&dest)?;
+        }
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_value() -> Result<()> {
+        let mut config: toml::Value = toml::from_str("[paths]\ntmp = \"/tmp\"\n")?;
+        set_value(&mut config, "build.jobs", "8")?;
+        set_value(&mut config, "audit.enabled", "true")?;
+        set_value(&mut config, "download.retries", "5")?;
+        set_value(&mut config, "paths.build", "C:/b")?;
+        assert!(set_value(&mut config, "build.jobs", "many").is_err());
+        assert!(set_value(&mut config, "build.unknown", "1").is_err());
+        assert_eq!(
+            get_value(&config, "build.jobs")?,
+            Some(&toml::Value::Integer(8))
+        );
+        let parsed: Config = config.clone().try_into()?;
+        assert_eq!(parsed.build.jobs, Some(8));
+        assert!(parsed.audit.enabled);
+        assert_eq!(parsed.download.retries(), 5);
+        assert_eq!(parsed.download.backoff(), 1);
+        assert_eq!(parsed.build_root()?, Some(PathBuf::from("C:/b")));
+
+        assert!(unset_value(&mut config, "paths.tmp")?);
+        assert!(!unset_value(&mut config, "paths.tmp")?);
+        assert!(unset_value(&mut config, "paths.build")?);
+        assert!(config.get("paths").is_none());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_case_sensitive() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        assert!(!is_case_insensitive(tmp_dir.path())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_cache_layout() -> Result<()> {
+        let cache = tempfile::TempDir::new()?;
+        let cache = cache.path();
+        fs::create_dir_all(cache.join("llvm-mirror/build"))?;
+        fs::write(cache.join("llvm-mirror/build/CMakeCache.txt"), "")?;
+        fs::write(cache.join("llvm-mirror/CMakeLists.txt"), "")?;
+        fs::create_dir_all(cache.join(".tar_download"))?;
+        upgrade_cache_layout(cache)?;
+        assert!(cache.join("src/llvm-mirror/CMakeLists.txt").is_file());
+        assert!(!cache.join("build/llvm-mirror").exists());
+        assert!(!cache.join("src/llvm-mirror/build").exists());
+        assert!(!cache.join(".tar_download").exists());
+        Ok(())
+    }
+
+    /// Key and value of its kind as given to `llvmenv config set`
+    fn key_value() -> impl proptest::strategy::Strategy<Value = (&'static str, String)> {
+        use proptest::prelude::*;
+        proptest::sample::select(CONFIG_KEYS.to_vec()).prop_flat_map(|(key, kind)| {
+            let value = match kind {
+                ValueKind::String => "[a-zA-Z0-9/:._ -]{1,16}".boxed(),
+                ValueKind::Bool => proptest::bool::ANY.prop_map(|b| b.to_string()).boxed(),
+                ValueKind::Integer => (0u32..1000).prop_map(|n| n.to_string()).boxed(),
+            };
+            (Just(key), value)
+        })
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(128))]
+
+        /// Values set by `llvmenv config set` are read back by `config get`
+        #[test]
+        fn test_config_roundtrip((key, value) in key_value()) {
+            let mut config = toml::Value::Table(Default::default());
+            set_value(&mut config, key, &value).unwrap();
+            let got = match get_value(&config, key).unwrap().unwrap() {
+                toml::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            };
+            proptest::prop_assert_eq!(got, value);
+            let config: toml::Value = toml::to_string(&config).unwrap().parse().unwrap();
+            proptest::prop_assert!(get_value(&config, key).unwrap().is_some());
+        }
+
+        /// Invalid keys and values are rejected without panics
+        #[test]
+        fn test_config_any(key in "[a-z.]{0,16}", value in "\\PC{0,16}") {
+            let mut config = toml::Value::Table(Default::default());
+            let _ = set_value(&mut config, &key, &value);
+            let _ = unset_value(&mut config, &key);
+        }
+    }
+}
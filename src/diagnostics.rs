@@ -0,0 +1,211 @@
+//! Warnings and errors of compilers in build logs
+//!
+//! The output (stdout and stderr) of configure, build, and install is saved into `llvmenv-build.log`
+//! in the build directory, and the log of the previous build into `llvmenv-build.log.1`.
+//! `build-entry` prints a summary of the diagnostics in it after the build.
+//! `llvmenv build-log <entry>` (or `llvmenv log`) prints the log, `--tail <n>` only the last lines,
+//! `--follow` keeps printing the lines of the running build, and `--errors-only` shows only the lines about errors.
+//!
+//! ```
+//! use llvmenv::diagnostics::*;
+//!
+//! let log = "\
+//! [1/3] Building CXX object a.cpp.o
+//! /src/a.cpp:1:2: warning: unused variable 'x' [-Wunused-variable]
+//! /src/a.cpp:1:2: warning: unused variable 'x' [-Wunused-variable]
+//! /src/b.cpp:3:4: error: use of undeclared identifier 'y'
+//! ";
+//! let summary = Summary::from_log(log);
+//! assert_eq!(summary.warnings, 1);
+//! assert_eq!(summary.error_files, vec!["/src/b.cpp".to_string()]);
+//! ```
+
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Name of the log file in the build directory
+pub const BUILD_LOG_FN: &str = "llvmenv-build.log";
+
+/// Name of the log file of the previous build
+pub const PREVIOUS_BUILD_LOG_FN: &str = "llvmenv-build.log.1";
+
+/// Number of warning kinds shown in the summary
+const TOP_WARNINGS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Diagnostic of GCC/Clang, e.g. `path:line:col: warning: message [-Wflag]`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub severity: Severity,
+    pub message: String,
+    /// Warning flag, e.g. `-Wunused-variable`
+    pub flag: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn parse(line: &str) -> Option<Self> {
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = RE.get_or_init(|| {
+            Regex::new(
+                r"^(?P<file>[^:\s][^:]*):(?P<line>\d+):(?:(?P<col>\d+):)? (?:fatal )?(?P<sev>warning|error): (?P<msg>.*?)(?: \[(?P<flag>-W[^\]]+)\])?$",
+            )
+            .unwrap()
+        });
+        let cap = re.captures(line.trim_end())?;
+        Some(Diagnostic {
+            file: cap["file"].into(),
+            line: cap["line"].parse().ok()?,
+            column: cap.name("col").and_then(|c| c.as_str().parse().ok()),
+            severity: if &cap["sev"] == "error" {
+                Severity::Error
+            } else {
+                Severity::Warning
+            },
+            message: cap["msg"].into(),
+            flag: cap.name("flag").map(|f| f.as_str().to_string()),
+        })
+    }
+}
+
+/// Whether the line is about an error, i.e. an error diagnostic, a failed step of ninja,
+/// or an error of CMake
+pub fn is_error_line(line: &str) -> bool {
+    if let Some(diag) = Diagnostic::parse(line) {
+        return diag.severity == Severity::Error;
+    }
+    line.starts_with("FAILED: ") || line.starts_with("CMake Error") || line.contains("*** [")
+}
+
+/// Last `n` lines of `log`
+///
+/// ```
+/// # use llvmenv::diagnostics::tail;
+/// assert_eq!(tail("a\nb\nc\n", 2), "b\nc\n");
+/// assert_eq!(tail("a\nb", 5), "a\nb");
+/// assert_eq!(tail("a\nb\n", 0), "");
+/// ```
+pub fn tail(log: &str, n: usize) -> &str {
+    let body = log.strip_suffix('\n').unwrap_or(log);
+    if n == 0 {
+        return "";
+    }
+    match body.rmatch_indices('\n').nth(n - 1) {
+        Some((i, _)) => &log[i + 1..],
+        None => log,
+    }
+}
+
+/// Deduplicated summary of diagnostics
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Number of unique warnings
+    pub warnings: usize,
+    /// Number of unique warnings for each flag, `(no flag)` if absent
+    pub warning_kinds: BTreeMap<String, usize>,
+    /// Number of unique errors
+    pub errors: usize,
+    /// Files where errors occur
+    pub error_files: Vec<String>,
+}
+
+impl Summary {
+    pub fn from_log(log: &str) -> Self {
+        // The same header is compiled many times, or the line is repeated by builders
+        let diags: BTreeSet<Diagnostic> = log.lines().filter_map(Diagnostic::parse).collect();
+        let mut summary = Summary::default();
+        let mut error_files = BTreeSet::new();
+        for diag in diags {
+            match diag.severity {
+                Severity::Warning => {
+                    summary.warnings += 1;
+                    let kind = diag.flag.unwrap_or_else(|| "(no flag)".into());
+                    *summary.warning_kinds.entry(kind).or_insert(0) += 1;
+                }
+                Severity::Error => {
+                    summary.errors += 1;
+                    error_files.insert(diag.file);
+                }
+            }
+        }
+        summary.error_files = error_files.into_iter().collect();
+        summary
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings == 0 && self.errors == 0
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Warnings: {}", self.warnings)?;
+        let mut kinds: Vec<_> = self.warning_kinds.iter().collect();
+        kinds.sort_by(|a, b| b.1.cmp(a.1));
+        for (kind, n) in kinds.iter().take(TOP_WARNINGS) {
+            writeln!(f, "  {:>6} {}", n, kind)?;
+        }
+        write!(f, "Errors: {}", self.errors)?;
+        for file in &self.error_files {
+            write!(f, "\n  {}", file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostic() {
+        let d = Diagnostic::parse(
+            "/src/lib/Support/a.cpp:12:5: warning: unused parameter 'x' [-Wunused-parameter]",
+        )
+        .unwrap();
+        assert_eq!(d.file, "/src/lib/Support/a.cpp");
+        assert_eq!((d.line, d.column), (12, Some(5)));
+        assert_eq!(d.severity, Severity::Warning);
+        assert_eq!(d.message, "unused parameter 'x'");
+        assert_eq!(d.flag.as_deref(), Some("-Wunused-parameter"));
+
+        let d = Diagnostic::parse("b.h:3: fatal error: 'c.h' file not found").unwrap();
+        assert_eq!(d.severity, Severity::Error);
+        assert_eq!(d.column, None);
+        assert_eq!(d.flag, None);
+
+        assert!(Diagnostic::parse("[12/345] Building CXX object a.cpp.o").is_none());
+        assert!(Diagnostic::parse("-- Configuring done").is_none());
+    }
+
+    #[test]
+    fn test_is_error_line() {
+        assert!(is_error_line("a.cpp:1:2: error: expected ';'"));
+        assert!(is_error_line("FAILED: lib/Support/CMakeFiles/a.cpp.o"));
+        assert!(is_error_line("CMake Error at CMakeLists.txt:10 (message):"));
+        assert!(!is_error_line("a.cpp:1:2: warning: unused [-Wunused]"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let log = "a.cpp:1:2: warning: x [-Wunused]\nb.cpp:1:2: warning: y [-Wunused]\nb.cpp:2:2: warning: z\nc.cpp:1:1: error: e\nc.cpp:2:1: error: f\n";
+        let summary = Summary::from_log(log);
+        assert_eq!(summary.warnings, 3);
+        assert_eq!(summary.warning_kinds["-Wunused"], 2);
+        assert_eq!(summary.errors, 2);
+        assert_eq!(summary.error_files, vec!["c.cpp".to_string()]);
+        assert_eq!(
+            summary.to_string(),
+            "Warnings: 3\n       2 -Wunused\n       1 (no flag)\nErrors: 2\n  c.cpp"
+        );
+    }
+}
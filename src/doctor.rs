@@ -0,0 +1,206 @@
+//! Diagnose the environment using a build
+//!
+//! `llvmenv doctor [name]` checks that
+//!
+//! - `llvm-symbolizer` found in `$PATH` and those set by `ASAN_SYMBOLIZER_PATH` and so on
+//!   (see [environment](../environment/index.html)) have the same version as the build.
+//!   Otherwise sanitizer reports and backtraces of Rust are garbled after switching builds.
+//! - the data and cache directories, and their entries, match `[shared]` of config.toml and are writable
+//!   if the directories are (see [shared](../shared/index.html)).
+//!
+//! and exits with 1 if any error is found.
+
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build::{parse_version, Build};
+use crate::config::{cache_dir_path, data_dir_path};
+use crate::environment::{self, SYMBOLIZER_VARS};
+use crate::error::*;
+use crate::shared;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    fn new(status: Status, message: String) -> Self {
+        Check { status, message }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warn",
+            Status::Error => "error",
+        };
+        write!(f, "[{}] {}", status, self.message)
+    }
+}
+
+fn format_version((major, minor, patch): (u32, u32, u32)) -> String {
+    format!("{}.{}.{}", major, minor, patch)
+}
+
+/// Version of `llvm-symbolizer`, e.g. `LLVM version 7.0.0` in its `--version`
+pub fn symbolizer_version(path: &Path) -> Result<(u32, u32, u32)> {
+    let output = Command::new(path).arg("--version").output()?;
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Compare the version of `path` (found by `source`) with `expected`
+fn check_version(
+    source: &str,
+    path: &Path,
+    expected: (u32, u32, u32),
+    version: Result<(u32, u32, u32)>,
+) -> Check {
+    match version {
+        Ok(version) if version == expected => Check::new(
+            Status::Ok,
+            format!("{} is {} ({})", source, format_version(version), path.display()),
+        ),
+        Ok(version) => Check::new(
+            Status::Error,
+            format!(
+                "{} is {} ({}), but the build is {}. Run `eval \"$(llvmenv env)\"` to use llvm-symbolizer of the build.",
+                source,
+                format_version(version),
+                path.display(),
+                format_version(expected)
+            ),
+        ),
+        Err(e) => Check::new(
+            Status::Warning,
+            format!("Cannot detect the version of {} ({}): {}", source, path.display(), e),
+        ),
+    }
+}
+
+/// Check `llvm-symbolizer` in `$PATH` and `*_SYMBOLIZER_PATH` variables
+pub fn check_symbolizer(build: &Build) -> Vec<Check> {
+    let expected = match build.version() {
+        Ok(version) => version,
+        Err(e) => {
+            return vec![Check::new(
+                Status::Warning,
+                format!(
+                    "Cannot detect the version of build '{}': {}",
+                    build.name(),
+                    e
+                ),
+            )]
+        }
+    };
+    let mut checks = Vec::new();
+    if environment::symbolizer(build).is_none() {
+        checks.push(Check::new(
+            Status::Warning,
+            format!(
+                "llvm-symbolizer is not installed in build '{}'",
+                build.name()
+            ),
+        ));
+    }
+    match find_tool("llvm-symbolizer") {
+        Some(path) => {
+            let version = symbolizer_version(&path);
+            checks.push(check_version(
+                "llvm-symbolizer in $PATH",
+                &path,
+                expected,
+                version,
+            ));
+        }
+        None => checks.push(Check::new(
+            Status::Warning,
+            "llvm-symbolizer is not found in $PATH".into(),
+        )),
+    }
+    for var in SYMBOLIZER_VARS.iter() {
+        if let Some(path) = env::var_os(var) {
+            let path = PathBuf::from(path);
+            let version = symbolizer_version(&path);
+            checks.push(check_version(
+                &format!("${}", var),
+                &path,
+                expected,
+                version,
+            ));
+        }
+    }
+    checks
+}
+
+/// Check the permissions of the data and cache directories shared by users
+pub fn check_shared() -> Vec<Check> {
+    let mut checks = Vec::new();
+    for dir in [data_dir_path(), cache_dir_path()]
+        .iter()
+        .filter_map(|dir| dir.as_ref().ok())
+    {
+        if !dir.is_dir() {
+            continue;
+        }
+        match shared::check_dir(dir) {
+            Ok(found) if found.is_empty() => {
+                if !shared::is_writable(dir) {
+                    checks.push(Check::new(
+                        Status::Ok,
+                        format!(
+                            "{} is read-only, builds in it can be used but not modified",
+                            dir.display()
+                        ),
+                    ));
+                }
+            }
+            Ok(found) => {
+                for (path, reason) in found {
+                    checks.push(Check::new(Status::Error, format!("{} is {}", path, reason)));
+                }
+            }
+            Err(e) => checks.push(Check::new(
+                Status::Warning,
+                format!("Cannot check permissions of {}: {}", dir.display(), e),
+            )),
+        }
+    }
+    checks
+}
+
+/// All checks for the build
+pub fn run(build: &Build) -> Vec<Check> {
+    let mut checks = check_symbolizer(build);
+    checks.extend(check_shared());
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_version() {
+        let path = Path::new("/usr/bin/llvm-symbolizer");
+        let check = check_version("llvm-symbolizer in $PATH", path, (7, 0, 0), Ok((7, 0, 0)));
+        assert_eq!(check.status, Status::Ok);
+        let check = check_version("llvm-symbolizer in $PATH", path, (7, 0, 0), Ok((6, 0, 1)));
+        assert_eq!(check.status, Status::Error);
+        assert!(check
+            .to_string()
+            .starts_with("[error] llvm-symbolizer in $PATH is 6.0.1"));
+    }
+}
@@ -3,12 +3,15 @@
 //! entry.toml
 //! -----------
 //! **entry** in llvmenv describes how to compile LLVM/Clang, and set by `$XDG_CONFIG_HOME/llvmenv/entry.toml`.
-//! `llvmenv init` generates default setting:
+//! Built-in entries are layered under it, i.e. available without copying them into entry.toml
+//! and overridden by the user's entry of the same name. The built-in `llvm-project` entry builds
+//! the monorepo (see [Monorepo](#monorepo)), and the deprecated `llvm-mirror` entry is:
 //!
 //! ```toml
 //! [llvm-mirror]
 //! url    = "https://github.com/llvm-mirror/llvm"
 //! target = ["X86"]
+//! deprecated = { note = "llvm-mirror is archived and no longer updated", alternative = "llvm-project" }
 //!
 //! [[llvm-mirror.tools]]
 //! name = "clang"
@@ -43,49 +46,338 @@
 //! Entry is regarded as *local* if there is `path` property, and *remote* if there is `url` property.
 //! Other options are common to *remote* entries.
 //!
+//! CMake options
+//! --------------
+//! `option` (or `cmake_options`) of the entry is passed to CMake as `-DKEY=VALUE` after the options set by llvmenv,
+//! so that it overrides them, e.g. `LLVM_TARGETS_TO_BUILD` of `target`. Booleans are passed as `ON` or `OFF`:
+//!
+//! ```toml
+//! [llvm-mirror.cmake_options]
+//! LLVM_ENABLE_ASSERTIONS = true
+//! LLVM_USE_LINKER        = "lld"
+//! LLVM_TARGETS_TO_BUILD  = "X86;AArch64"
+//! ```
+//!
+//! Parallel jobs
+//! --------------
+//! `LLVM_PARALLEL_LINK_JOBS` and `LLVM_PARALLEL_COMPILE_JOBS` are set automatically based on
+//! the physical memory and `build_type`, and can be overridden by `option` of the entry:
+//!
+//! ```toml
+//! [llvm-mirror.option]
+//! LLVM_PARALLEL_LINK_JOBS = "2"
+//! ```
+//!
+//! Linker
+//! -------
+//! mold or lld is used as `LLVM_USE_LINKER` if found in `$PATH` (or lld in builds of llvmenv).
+//! lld of a build is linked into `.llvmenv-linker/` of the build tree, which is put on `$PATH` of CMake and the build
+//! instead of giving its path, since GCC before 12 accepts only the names of linkers by `-fuse-ld`.
+//! `linker = "gold"` in the entry selects a linker explicitly, and `linker = "default"` disables it.
+//! If CMake rejects the linker or it crashes during the build, lld, gold, bfd,
+//! and the default linker are tried in this order.
+//!
+//! Compiler launcher
+//! ------------------
+//! `launcher` sets a command wrapping each compiler invocation, e.g. distributed or tracing tools:
+//!
+//! ```toml
+//! [llvm-mirror]
+//! url      = "https://github.com/llvm-mirror/llvm"
+//! launcher = ["icecc"]
+//! ```
+//!
+//! Compiler cache
+//! ---------------
+//! sccache or ccache is used to cache compilations if found in `$PATH`, in this order, so that rebuilding
+//! after changing an option only recompiles the affected sources. `sccache = true` or `ccache = true`
+//! in the entry (or `build-entry --ccache`) selects one explicitly, and `false` disables its detection.
+//! ccache is enabled by `LLVM_CCACHE_BUILD`, and sccache is set as the compiler launcher:
+//!
+//! ```toml
+//! [llvm-mirror]
+//! url     = "https://github.com/llvm-mirror/llvm"
+//! sccache = true
+//! ```
+//!
+//! Build profiles
+//! ---------------
+//! `build_type` of the entry (or `build-entry --build-type`) is passed as `CMAKE_BUILD_TYPE`:
+//! `Release` (default), `Debug`, `RelWithDebInfo`, or `MinSizeRel`:
+//!
+//! ```toml
+//! [17.0.6-checked]
+//! url        = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-17.0.6.tar.gz"
+//! build_type = "RelWithDebInfo"
+//! ```
+//!
+//! `--build-type` other than `build_type` of the entry is built in its own build directory and installed into
+//! its own prefix, e.g. `build-entry 17.0.6 --build-type Debug` into `17.0.6-debug`, so that Debug and Release
+//! builds of the same version live side by side. Entries keep their name as the prefix with their own `build_type`.
+//!
+//! Hashed prefix
+//! --------------
+//! `hashed_prefix = true` in the entry (or `build-entry --hashed-prefix`) installs it into
+//! `${data_dir}/${hash}-${name}` like the Nix store, where `${hash}` is computed from the settings of the sources
+//! (URLs, branches, and tags, or the local path), the options affecting the build, and the host.
+//! Such a build can be referred by either the whole directory name, its hash (or a unique prefix of it),
+//! or the entry name if it is not ambiguous.
+//!
+//! The hash is known before downloading the sources, and does not include the revisions they are resolved to.
+//! An entry following a branch keeps its prefix when the branch moves, and the build is replaced in place
+//! (see [Rebuild detection](#rebuild-detection)). Pin a `tag` to install each release into its own prefix.
+//!
+//! Rebuild detection
+//! ------------------
+//! After a successful build, the hash of the entry and the git revisions of its sources is stored
+//! into the metadata of the build. `build-entry` does nothing if the build is up to date,
+//! and `build-entry --force` rebuilds it anyway.
+//!
+//! `llvmenv rebuild <name>` updates the existing sources and builds incrementally in the existing build directory,
+//! so that a one-line change only recompiles the affected files. CMake is re-run only if the options
+//! affecting the build (the same as the hash above) changed since the last configure.
+//!
+//! Component sets
+//! ---------------
+//! `set` defines named sets of components (targets of `LLVM_DISTRIBUTION_COMPONENTS`),
+//! and `components` (or `build-entry --components compilers,analysis`) selects sets to be installed:
+//!
+//! ```toml
+//! [llvm-mirror]
+//! url = "https://github.com/llvm-mirror/llvm"
+//! set.compilers = ["clang", "lld", "clang-resource-headers"]
+//! set.analysis = ["clang-tidy", "scan-build"]
+//! components = ["compilers"]
+//! ```
+//!
+//! Then only the `distribution` target is built, and `install-distribution` produces a minimal prefix.
+//! The existing prefix is cleared before the install not to leave the components of a previous install.
+//! `LLVM_DISTRIBUTION_COMPONENTS` is removed from the CMake cache when no sets are selected.
+//!
+//! Groups
+//! -------
+//! `group` puts entries together, e.g. patch releases of a major version:
+//!
+//! ```toml
+//! [15.0.6]
+//! url   = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-15.0.6.tar.gz"
+//! group = "release-line-15"
+//!
+//! [15.0.7]
+//! url   = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-15.0.7.tar.gz"
+//! group = "release-line-15"
+//! ```
+//!
+//! `llvmenv entries --group release-line-15` lists them, and `llvmenv build-entry --group release-line-15`
+//! builds all of them.
+//!
+//! Monorepo
+//! ---------
+//! `projects` builds the [llvm-project](https://github.com/llvm/llvm-project) monorepo, i.e. CMake runs on its `llvm/`
+//! directory with `LLVM_ENABLE_PROJECTS` (and `LLVM_ENABLE_RUNTIMES` for compiler-rt, libc, libcxx, libcxxabi, and libunwind):
+//!
+//! ```toml
+//! [llvm-project]
+//! url      = "https://github.com/llvm/llvm-project"
+//! branch   = "release/17.x"
+//! projects = ["clang", "lld", "libcxx", "libcxxabi"]
+//! ```
+//!
+//! `tag = "llvmorg-17.0.6"` instead of `branch` clones only the tagged commit of a Git repository,
+//! for reproducible checkouts of releases without the history.
+//! `tools` cannot be used with `projects` since the monorepo already contains them.
+//! Sources with the monorepo layout, e.g. archives of llvm-project, are built from `llvm/` even without `projects`.
+//!
+//! Per-OS settings
+//! ----------------
+//! `linux`, `macos`, and `windows` tables override `option`, `env`, and `builder` on the host OS,
+//! so that one entry.toml can be shared in a team using several OSes:
+//!
+//! ```toml
+//! [llvm-15]
+//! url     = "https://github.com/llvm/llvm-project/archive/refs/tags/llvmorg-15.0.7.tar.gz"
+//! builder = "Ninja"
+//!
+//! [llvm-15.macos]
+//! option = { DEFAULT_SYSROOT = "/Library/Developer/CommandLineTools/SDKs/MacOSX.sdk" }
+//!
+//! [llvm-15.windows]
+//! builder = "VisualStudio"
+//! env     = { CC = "cl", CXX = "cl" }
+//! ```
+//!
+//! `env` sets environment variables of CMake configure and build.
+//!
+//! Mirrors
+//! --------
+//! `url` can be a list of mirrors in the order of priority. They are tried in order until the download succeeds,
+//! and the mirror succeeded is recorded in `${cache_dir}/mirrors.toml` to be tried first next time:
+//!
+//! ```toml
+//! [7.0.0]
+//! url = [
+//!   "http://releases.llvm.org/7.0.0/llvm-7.0.0.src.tar.xz",
+//!   "https://github.com/llvm/llvm-project/releases/download/llvmorg-7.0.0/llvm-7.0.0.src.tar.xz",
+//! ]
+//! ```
+//!
+//! The first URL is regarded as the URL of the entry, e.g. for the [hashed prefix](#hashed-prefix).
+//!
+//! Build on tmpfs
+//! ---------------
+//! `build_in = "tmpfs"` places the build tree on a RAM-backed filesystem if it fits in the free memory,
+//! and falls back to the disk otherwise, see [tmpfs](../tmpfs/index.html).
+//! The tree is removed after the install unless `keep_tmpfs = true`.
+//!
+//! Deprecated entries
+//! -------------------
+//! `deprecated` marks an entry to be warned on building it, with the reason, the alternative,
+//! and LLVM build options working around known build breaks:
+//!
+//! ```toml
+//! [old-llvm]
+//! path = "/path/to/llvm-5"
+//! deprecated = { note = "broken on GCC 11", alternative = "llvm-15", workaround = { CMAKE_CXX_FLAGS = "-include limits" } }
+//! ```
+//!
+//! The built-in releases of LLVM 8 or older are deprecated, and so are entries using the SVN repository of LLVM.
+//!
 //! Pre-defined entries
 //! ------------------
 //!
-//! There is also pre-defined entries corresponding to the LLVM/Clang releases:
+//! There is also pre-defined (built-in) entries corresponding to the LLVM/Clang releases:
 //!
 //! ```shell
 //! $ llvmenv entries
-//! llvm-mirror
-//! 7.0.0
-//! 6.0.1
-//! 6.0.0
-//! 5.0.2
-//! 5.0.1
-//! 4.0.1
-//! 4.0.0
-//! 3.9.1
-//! 3.9.0
+//! llvm-mirror (deprecated)
+//! llvm-project
+//! 7.0.0 (deprecated)
+//! 6.0.1 (deprecated)
+//! 6.0.0 (deprecated)
+//! 5.0.2 (deprecated)
+//! 5.0.1 (deprecated)
+//! 4.0.1 (deprecated)
+//! 4.0.0 (deprecated)
+//! 3.9.1 (deprecated)
+//! 3.9.0 (deprecated)
 //! ```
 //!
-//! These are compiled with the default setting as shown above. You have to create entry manually
-//! if you want to use custom settings.
+//! These are compiled with the default setting as shown above. Define an entry of the same name
+//! in entry.toml if you want to use custom settings.
 
-use failure::bail;
 use itertools::*;
 use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize as _, Deserializer};
 use serde_derive::Deserialize;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::OsString;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 use std::{fs, process};
 use toml;
 
+use crate::build::{builds, check_case_collision, Build};
+use crate::builder::*;
 use crate::config::*;
+use crate::diagnostics::{self, Summary, BUILD_LOG_FN, PREVIOUS_BUILD_LOG_FN};
 use crate::error::*;
-use crate::resource::Resource;
+use crate::fetcher;
+use crate::hostinfo;
+use crate::jobs::{Outcome, Scheduler};
+use crate::lockdown;
+use crate::priority::PriorityExt;
+use crate::progress::{self, observer, parse_build_step, run_phase, Phase, ALL_PHASES};
+use crate::shared;
+use crate::snapshot;
+use crate::timing;
+use crate::tmpfs;
+use crate::tree;
+use crate::verify;
+use crate::version::cmp_names;
+
+/// Built-in entries layered under entry.toml
+const BUILTIN_ENTRIES: &str = include_str!("llvm-mirror.toml");
+
+const CMAKE_CACHE_TXT: &str = "CMakeCache.txt";
+/// Directory in the build tree put on $PATH with lld of a build, see [Linker](index.html#linker)
+const LINKER_DIR: &str = ".llvmenv-linker";
+const COMPILE_COMMANDS_JSON: &str = "compile_commands.json";
+
+/// Marker in build directory created when a build is interrupted
+const INTERRUPTED_FN: &str = ".llvmenv-interrupted";
+/// Hash of the entry when the build directory is configured, see [rebuild](enum.Entry.html#method.rebuild)
+const CONFIGURED_FN: &str = ".llvmenv-configured";
+
+/// Known failures found in the output of configure and build
+#[derive(Debug, Default)]
+struct BuildFailure {
+    /// Linker seems to be killed, typically by OOM killer
+    link_oom: bool,
+    /// Linker crashed by itself
+    linker_crash: bool,
+    /// CMake linker check failed for `LLVM_USE_LINKER`
+    linker_rejected: bool,
+}
+
+impl BuildFailure {
+    fn scan(&mut self, line: &str) {
+        static LINK_OOM: OnceLock<Regex> = OnceLock::new();
+        static LINKER_CRASH: OnceLock<Regex> = OnceLock::new();
+        static LINKER_REJECTED: OnceLock<Regex> = OnceLock::new();
+        let link_oom = LINK_OOM.get_or_init(|| {
+            Regex::new(concat!(
+                r"collect2: fatal error: ld terminated with signal 9|",
+                r"ld(\.\w+)? terminated with signal 9|",
+                r"linker command failed due to signal|",
+                r"ld(\.\w+)?: .*(out of memory|cannot allocate memory)|",
+                r"error: unable to execute command: Killed|",
+                // Exit status of a command killed by SIGKILL reported by make,
+                // since `cmake --build` itself exits with 1 for any failures
+                r"make(\[\d+\])?: \*\*\* .*Error 137"
+            ))
+            .unwrap()
+        });
+        let linker_crash = LINKER_CRASH.get_or_init(|| {
+            Regex::new(concat!(
+                r"ld(\.\w+)? terminated with signal (4|6|7|11)|",
+                r"linker command failed due to signal|",
+                r"(ld\.lld|mold|ld\.gold): .*(internal error|Segmentation fault|Stack dump)"
+            ))
+            .unwrap()
+        });
+        let linker_rejected = LINKER_REJECTED.get_or_init(|| {
+            // FATAL_ERROR of HandleLLVMOptions.cmake
+            Regex::new(r"^\s*Host compiler does not support '-fuse-ld=").unwrap()
+        });
+        if link_oom.is_match(line) {
+            self.link_oom = true;
+        }
+        if linker_crash.is_match(line) {
+            self.linker_crash = true;
+        }
+        if linker_rejected.is_match(line) {
+            self.linker_rejected = true;
+        }
+    }
+}
 
 /// Option for CMake Generators
 ///
 /// - Official document: [CMake Generators](https://cmake.org/cmake/help/latest/manual/cmake-generators.7.html)
-#[derive(Deserialize, PartialEq, Debug)]
+#[derive(Deserialize, PartialEq, Debug, Clone, Default)]
 pub enum CMakeGenerator {
     /// Use platform default generator (without -G option)
+    #[default]
     Platform,
     /// Unix Makefile
+    #[serde(alias = "Makefiles")]
     Makefile,
     /// Ninja builder
     Ninja,
@@ -93,65 +385,154 @@ pub enum CMakeGenerator {
     VisualStudio,
 }
 
-impl CMakeGenerator {
+impl FromStr for CMakeGenerator {
+    type Err = LlvmEnvError;
+
     /// ```
     /// # use llvmenv::entry::CMakeGenerator;
+    /// # use std::str::FromStr;
     /// assert_eq!(CMakeGenerator::from_str("Makefile").unwrap(), CMakeGenerator::Makefile);
+    /// assert_eq!(CMakeGenerator::from_str("Unix Makefiles").unwrap(), CMakeGenerator::Makefile);
     /// assert_eq!(CMakeGenerator::from_str("Ninja").unwrap(), CMakeGenerator::Ninja);
     /// assert_eq!(CMakeGenerator::from_str("vs").unwrap(), CMakeGenerator::VisualStudio);
     /// assert_eq!(CMakeGenerator::from_str("VisualStudio").unwrap(), CMakeGenerator::VisualStudio);
     /// assert!(CMakeGenerator::from_str("Unknown").is_err());
     /// ```
-    pub fn from_str(builder: &str) -> Result<Self> {
+    fn from_str(builder: &str) -> Result<Self> {
         Ok(match builder.to_ascii_lowercase().as_str() {
-            "makefile" => CMakeGenerator::Makefile,
+            "makefile" | "makefiles" | "unix makefiles" => CMakeGenerator::Makefile,
             "ninja" => CMakeGenerator::Ninja,
             "visualstudio" | "vs" => CMakeGenerator::VisualStudio,
-            _ => bail!("Unsupported Generator: {}", builder),
+            _ => return Err(format_err!("Unsupported Generator: {}", builder)),
         })
     }
+}
 
-    fn option(&self) -> Vec<String> {
+impl CMakeGenerator {
+    /// [Builder](../builder/trait.Builder.html) backend of this generator
+    pub fn backend(&self) -> Box<dyn Builder> {
         match self {
-            CMakeGenerator::Platform => Vec::new(),
-            CMakeGenerator::Makefile => vec!["-G", "Unix Makefiles"],
-            CMakeGenerator::Ninja => vec!["-G", "Ninja"],
-            CMakeGenerator::VisualStudio => vec!["-G", "Visual Studio 15 2017"],
+            CMakeGenerator::Platform => Box::new(CMakeDefaultBuilder),
+            CMakeGenerator::Makefile => Box::new(CMakeMakeBuilder),
+            CMakeGenerator::Ninja => Box::new(CMakeNinjaBuilder),
+            CMakeGenerator::VisualStudio => Box::new(CMakeVisualStudioBuilder),
         }
-        .into_iter()
-        .map(|s| s.into())
-        .collect()
     }
 
-    fn build_option(&self, nproc: usize) -> Vec<String> {
-        match self {
-            CMakeGenerator::VisualStudio | CMakeGenerator::Platform => Vec::new(),
-            CMakeGenerator::Makefile | CMakeGenerator::Ninja => {
-                vec!["--".into(), "-j".into(), format!("{}", nproc)]
-            }
+    /// Whether the tools required by this generator are found in `$PATH`
+    pub fn is_available(&self) -> bool {
+        self.backend()
+            .required_tools()
+            .iter()
+            .all(|tool| find_tool(tool).is_some())
+    }
+
+    /// Generator used if an entry does not specify it: Ninja if found, then Unix Makefiles (except on Windows),
+    /// or the platform default
+    pub fn detect() -> Self {
+        let candidates: &[CMakeGenerator] = if cfg!(windows) {
+            &[CMakeGenerator::Ninja]
+        } else {
+            &[CMakeGenerator::Ninja, CMakeGenerator::Makefile]
+        };
+        candidates
+            .iter()
+            .find(|g| g.is_available())
+            .cloned()
+            .unwrap_or(CMakeGenerator::Platform)
+    }
+
+    /// Generator of `CMAKE_GENERATOR` in CMakeCache.txt, e.g. `Unix Makefiles`
+    fn from_cmake_name(name: &str) -> Option<Self> {
+        match name {
+            "Ninja" => Some(CMakeGenerator::Ninja),
+            "Unix Makefiles" => Some(CMakeGenerator::Makefile),
+            name if name.starts_with("Visual Studio") => Some(CMakeGenerator::VisualStudio),
+            _ => None,
         }
     }
 }
 
-impl Default for CMakeGenerator {
-    fn default() -> Self {
-        CMakeGenerator::Platform
-    }
+/// Filesystem of the build tree, see [tmpfs](../tmpfs/index.html)
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildIn {
+    /// Build directory in the cache, or `paths.build` of config.toml
+    #[default]
+    Disk,
+    /// RAM-backed filesystem if the tree fits, and the disk otherwise
+    Tmpfs,
 }
 
-/// CMake build type
-#[derive(Deserialize, Debug)]
+/// CMake build type, see [Build profiles](index.html#build-profiles)
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
 pub enum BuildType {
     Debug,
+    #[default]
     Release,
+    RelWithDebInfo,
+    MinSizeRel,
+}
+
+impl FromStr for BuildType {
+    type Err = LlvmEnvError;
+
+    /// ```
+    /// # use llvmenv::entry::BuildType;
+    /// assert_eq!("debug".parse::<BuildType>().unwrap(), BuildType::Debug);
+    /// assert_eq!("RelWithDebInfo".parse::<BuildType>().unwrap(), BuildType::RelWithDebInfo);
+    /// assert!("Fast".parse::<BuildType>().is_err());
+    /// ```
+    fn from_str(build_type: &str) -> Result<Self> {
+        BuildType::ALL
+            .iter()
+            .find(|t| format!("{:?}", t).eq_ignore_ascii_case(build_type))
+            .copied()
+            .ok_or_else(|| format_err!("Unsupported build type: {}", build_type))
+    }
 }
 
-impl Default for BuildType {
-    fn default() -> Self {
-        BuildType::Release
+impl BuildType {
+    pub const ALL: [BuildType; 4] = [
+        BuildType::Debug,
+        BuildType::Release,
+        BuildType::RelWithDebInfo,
+        BuildType::MinSizeRel,
+    ];
+
+    fn has_debug_info(&self) -> bool {
+        match self {
+            BuildType::Debug | BuildType::RelWithDebInfo => true,
+            BuildType::Release | BuildType::MinSizeRel => false,
+        }
+    }
+
+    /// Suffix of the prefix and build directory, e.g. `-debug`, empty for Release
+    pub fn suffix(&self) -> String {
+        match self {
+            BuildType::Release => String::new(),
+            t => format!("-{:?}", t).to_ascii_lowercase(),
+        }
     }
 }
 
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// Default `LLVM_PARALLEL_LINK_JOBS` and `LLVM_PARALLEL_COMPILE_JOBS` not to exhaust memory
+///
+/// Linking LLVM tools takes a few GB each, and much more with debug info.
+/// Compile jobs are limited only if memory is insufficient for `nproc` jobs.
+fn parallel_jobs(memory: u64, nproc: usize, debug_info: bool) -> (usize, Option<usize>) {
+    let (per_link, per_compile) = if debug_info {
+        (8 * GB, 2 * GB)
+    } else {
+        (2 * GB, GB)
+    };
+    let link = ((memory / per_link) as usize).clamp(1, nproc.max(1));
+    let compile = ((memory / per_compile) as usize).max(1);
+    (link, if compile < nproc { Some(compile) } else { None })
+}
+
 /// LLVM Tools e.g. clang, compiler-rt, and so on.
 #[derive(Deserialize, Debug, Clone)]
 pub struct Tool {
@@ -161,12 +542,14 @@ pub struct Tool {
     pub url: String,
     /// Git branch (not for SVN)
     pub branch: Option<String>,
+    /// Git tag cloned shallowly, e.g. `llvmorg-17.0.6` (not for SVN)
+    pub tag: Option<String>,
     /// Relative install Path (see the example of clang-extra in [module level doc](index.html))
     pub relative_path: Option<String>,
 }
 
 impl Tool {
-    fn rel_path(&self) -> String {
+    pub(crate) fn rel_path(&self) -> String {
         match self.relative_path {
             Some(ref rel_path) => rel_path.to_string(),
             None => format!("tools/{}", self.name),
@@ -175,12 +558,15 @@ impl Tool {
 }
 
 /// Setting for both Remote and Local entries. TOML setting file will be decoded into this struct.
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct EntrySetting {
-    /// URL of remote LLVM resource, see also [resouce](../resource/index.html) module
-    pub url: Option<String>,
+    /// URL of remote LLVM resource, or the list of its [mirrors](index.html#mirrors),
+    /// see also [resouce](../resource/index.html) module
+    pub url: Option<Urls>,
     /// Branch of remote LLVM resource, if a source repository
     pub branch: Option<String>,
+    /// Tag of remote LLVM Git repository, cloned shallowly without the history
+    pub tag: Option<String>,
     /// Path of local LLVM source dir
     pub path: Option<String>,
     /// Additional LLVM Tools, e.g. clang, openmp, lld, and so on.
@@ -189,15 +575,371 @@ pub struct EntrySetting {
     /// Target to be build. Empty means all backend
     #[serde(default)]
     pub target: Vec<String>,
-    /// Additional LLVM build options
-    #[serde(default)]
+    /// Additional LLVM build options passed as `-DKEY=VALUE`, also read from `cmake_options`
+    #[serde(default, alias = "cmake_options", deserialize_with = "cmake_values")]
     pub option: HashMap<String, String>,
-    /// CMake Generator option (-G option in cmake)
-    #[serde(default)]
+    /// CMake Generator option (-G option in cmake), selecting the [backend](../builder/index.html).
+    /// Also read from `generator`, and detected if not set, see [generator](./enum.Entry.html#method.generator).
+    #[serde(default, alias = "generator")]
     pub builder: CMakeGenerator,
     ///  Option for `CMAKE_BUILD_TYPE`
     #[serde(default)]
     pub build_type: BuildType,
+    /// `build_type` is overridden by `--build-type`, see [Build profiles](index.html#build-profiles)
+    #[serde(skip)]
+    pub profile: bool,
+    /// Place the build tree on tmpfs by `"tmpfs"`, see [tmpfs](../tmpfs/index.html)
+    #[serde(default)]
+    pub build_in: BuildIn,
+    /// Keep the build tree on tmpfs after the install for `llvmenv rebuild`, see [tmpfs](../tmpfs/index.html)
+    #[serde(default)]
+    pub keep_tmpfs: bool,
+    /// Linker passed to `LLVM_USE_LINKER`, e.g. "lld", "mold", or "gold".
+    /// Detected automatically if absent, and "default" uses the default linker of the compiler.
+    pub linker: Option<String>,
+    /// Compiler launcher command and its arguments, e.g. `["icecc"]` or `["bear", "--append", "--"]`,
+    /// set to `CMAKE_C_COMPILER_LAUNCHER` and `CMAKE_CXX_COMPILER_LAUNCHER`
+    #[serde(default)]
+    pub launcher: Vec<String>,
+    /// Use ccache by `LLVM_CCACHE_BUILD`, detected if absent, see [module level doc](index.html#compiler-cache)
+    pub ccache: Option<bool>,
+    /// Use sccache as the compiler launcher, detected if absent
+    pub sccache: Option<bool>,
+    /// Enable `CMAKE_EXPORT_COMPILE_COMMANDS`, and copy compile_commands.json into the source directory
+    #[serde(default)]
+    pub compile_commands: bool,
+    /// Install into `${hash}-${name}`, see [module level doc](index.html#hashed-prefix)
+    #[serde(default)]
+    pub hashed_prefix: bool,
+    /// Named sets of components, see [module level doc](index.html#component-sets)
+    #[serde(default)]
+    pub set: BTreeMap<String, Vec<String>>,
+    /// Names of sets to be installed. All components are installed if empty.
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Group of entries operated together, see [module level doc](index.html#groups)
+    pub group: Option<String>,
+    /// Warned on building the entry, see [module level doc](index.html#deprecated-entries)
+    pub deprecated: Option<Deprecation>,
+    /// Projects of the llvm-project monorepo, e.g. `["clang", "lld", "libcxx"]`,
+    /// see [module level doc](index.html#monorepo)
+    #[serde(default)]
+    pub projects: Vec<String>,
+    /// Environment variables of CMake configure and build
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Overrides on Linux, see [module level doc](index.html#per-os-settings)
+    pub linux: Option<OsSetting>,
+    /// Overrides on macOS
+    pub macos: Option<OsSetting>,
+    /// Overrides on Windows
+    pub windows: Option<OsSetting>,
+}
+
+/// Values of CMake options, with booleans as `ON`/`OFF` and numbers as written
+fn cmake_values<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<HashMap<String, String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Bool(bool),
+        Int(i64),
+        Str(String),
+    }
+    Ok(HashMap::<String, Value>::deserialize(d)?
+        .into_iter()
+        .map(|(k, v)| {
+            let v = match v {
+                Value::Bool(true) => "ON".into(),
+                Value::Bool(false) => "OFF".into(),
+                Value::Int(n) => n.to_string(),
+                Value::Str(s) => s,
+            };
+            (k, v)
+        })
+        .collect())
+}
+
+/// URL of a source, or its mirrors in the order of priority, see [module level doc](index.html#mirrors)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Urls(Vec<String>);
+
+impl Urls {
+    /// The first URL, regarded as the URL of the entry
+    pub fn primary(&self) -> &str {
+        &self.0[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|url| url.as_str())
+    }
+}
+
+impl From<&str> for Urls {
+    fn from(url: &str) -> Self {
+        Urls(vec![url.to_string()])
+    }
+}
+
+impl From<String> for Urls {
+    fn from(url: String) -> Self {
+        Urls(vec![url])
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Urls {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            One(String),
+            Many(Vec<String>),
+        }
+        match Value::deserialize(d)? {
+            Value::One(url) => Ok(Urls(vec![url])),
+            Value::Many(urls) if urls.is_empty() => {
+                Err(serde::de::Error::custom("url must not be empty"))
+            }
+            Value::Many(urls) => Ok(Urls(urls)),
+        }
+    }
+}
+
+/// Setting overriding [EntrySetting](./struct.EntrySetting.html) on a specific OS
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct OsSetting {
+    /// Merged into `option` of the entry
+    #[serde(default, alias = "cmake_options", deserialize_with = "cmake_values")]
+    pub option: HashMap<String, String>,
+    /// Merged into `env` of the entry
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Replaces `builder` of the entry
+    #[serde(alias = "generator")]
+    pub builder: Option<CMakeGenerator>,
+}
+
+impl EntrySetting {
+    /// Merge the overrides for `os` (`linux`, `macos`, or `windows` as `std::env::consts::OS`)
+    fn merge_os(&mut self, os: &str) {
+        let (linux, macos, windows) = (self.linux.take(), self.macos.take(), self.windows.take());
+        let overrides = match os {
+            "linux" => linux,
+            "macos" => macos,
+            "windows" => windows,
+            _ => None,
+        };
+        if let Some(overrides) = overrides {
+            self.option.extend(overrides.option);
+            self.env.extend(overrides.env);
+            if let Some(builder) = overrides.builder {
+                self.builder = builder;
+            }
+        }
+    }
+}
+
+/// Projects built by `LLVM_ENABLE_RUNTIMES` instead of `LLVM_ENABLE_PROJECTS`
+const RUNTIMES: [&str; 5] = ["compiler-rt", "libc", "libcxx", "libcxxabi", "libunwind"];
+
+/// `-DLLVM_ENABLE_PROJECTS` and `-DLLVM_ENABLE_RUNTIMES` options for the monorepo `projects`
+fn enable_projects(projects: &[String], option: &HashMap<String, String>) -> Vec<String> {
+    let (runtimes, projects): (Vec<String>, Vec<String>) = projects
+        .iter()
+        .cloned()
+        .partition(|p| RUNTIMES.contains(&p.as_str()));
+    let mut opts = Vec::new();
+    if !projects.is_empty() && !option.contains_key("LLVM_ENABLE_PROJECTS") {
+        opts.push(format!("-DLLVM_ENABLE_PROJECTS={}", cmake_list(&projects)));
+    }
+    if !runtimes.is_empty() && !option.contains_key("LLVM_ENABLE_RUNTIMES") {
+        opts.push(format!("-DLLVM_ENABLE_RUNTIMES={}", cmake_list(&runtimes)));
+    }
+    opts
+}
+
+/// Why an entry is deprecated and what to use instead
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Deprecation {
+    /// Reason, e.g. the source is gone or known build breaks on modern compilers
+    pub note: Option<String>,
+    /// Entry or command to be used instead
+    pub alternative: Option<String>,
+    /// LLVM build options working around known build breaks
+    #[serde(default)]
+    pub workaround: BTreeMap<String, String>,
+}
+
+impl Deprecation {
+    /// Warning shown on building the entry `name`
+    pub fn warning(&self, name: &str) -> String {
+        let mut s = format!("warning: entry '{}' is deprecated", name);
+        if let Some(note) = &self.note {
+            write!(s, ": {}", note).unwrap();
+        }
+        if let Some(alternative) = &self.alternative {
+            write!(s, "\n  Use {} instead.", alternative).unwrap();
+        }
+        if !self.workaround.is_empty() {
+            let options = self
+                .workaround
+                .iter()
+                .map(|(k, v)| format!("{} = {:?}", k, v))
+                .join(", ");
+            write!(
+                s,
+                "\n  Set `option = {{ {} }}` of the entry in entry.toml to work around known build breaks.",
+                options
+            )
+            .unwrap();
+        }
+        s
+    }
+}
+
+/// Parse `NAME:TYPE=VALUE` lines of CMakeCache.txt
+fn parse_cmake_cache(cache: &str) -> BTreeMap<String, String> {
+    cache
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with("//"))
+        .filter_map(|line| {
+            let (key, value) = line.split_at(line.find('=')?);
+            let name = key.split(':').next()?;
+            Some((name.to_string(), value[1..].to_string()))
+        })
+        .collect()
+}
+
+/// Join into CMake list, with escaping `;` in each element
+fn cmake_list(items: &[String]) -> String {
+    items.iter().map(|item| item.replace(';', "\\;")).join(";")
+}
+
+/// Result of [Entry::build](./enum.Entry.html#method.build)
+#[derive(Debug, Default)]
+pub struct BuildSummary {
+    /// Linker used via `LLVM_USE_LINKER`, None for the default linker
+    pub linker: Option<String>,
+}
+
+impl fmt::Display for BuildSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Linker: {}", self.linker.as_deref().unwrap_or("default"))
+    }
+}
+
+/// Seek fast linker: mold and lld in $PATH, or lld in builds of llvmenv, see [lld_of_builds](fn.lld_of_builds.html)
+fn detect_linker() -> Option<String> {
+    if find_tool("mold").is_some() {
+        return Some("mold".into());
+    }
+    if find_tool("ld.lld").is_some() || lld_of_builds().is_some() {
+        return Some("lld".into());
+    }
+    None
+}
+
+/// `ld.lld` in builds of llvmenv if it is not in $PATH
+fn lld_of_builds() -> Option<PathBuf> {
+    if find_tool("ld.lld").is_some() {
+        return None;
+    }
+    builds()
+        .ok()?
+        .iter()
+        .map(|b| b.prefix().join("bin").join("ld.lld"))
+        .find(|path| path.is_file())
+}
+
+/// Tool caching compilations, see [module level doc](index.html#compiler-cache)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompilerCache {
+    Ccache,
+    Sccache,
+}
+
+impl CompilerCache {
+    pub fn tool(&self) -> &'static str {
+        match self {
+            CompilerCache::Ccache => "ccache",
+            CompilerCache::Sccache => "sccache",
+        }
+    }
+}
+
+/// Remove the directory, or print `rm -rf` if [dry run](../error/fn.set_dry_run.html)
+fn remove_dir(dir: &Path) -> Result<()> {
+    if is_dry_run() {
+        println!("rm -rf {}", quote_if_needed(&dir.display().to_string()));
+        return Ok(());
+    }
+    tree::remove(dir)
+}
+
+/// Mirrors succeeded last for each entry, in the cache directory
+const MIRRORS_FN: &str = "mirrors.toml";
+
+fn load_mirrors() -> BTreeMap<String, String> {
+    cache_dir()
+        .ok()
+        .and_then(|dir| fs::read_to_string(dir.join(MIRRORS_FN)).ok())
+        .and_then(|toml_str| toml::from_str(&toml_str).ok())
+        .unwrap_or_default()
+}
+
+fn save_mirrors(mirrors: &BTreeMap<String, String>) -> Result<()> {
+    fs::write(cache_dir()?.join(MIRRORS_FN), toml::to_string(mirrors)?)?;
+    Ok(())
+}
+
+/// Number of tools downloaded concurrently
+const DOWNLOAD_JOBS: usize = 4;
+
+/// Tools which have to be downloaded before each of `tools`, since a tool placed inside another one,
+/// e.g. clang-extra in `tools/clang/tools/extra`, is downloaded into it
+fn download_deps(tools: &[&Tool]) -> Vec<Vec<usize>> {
+    let paths: Vec<PathBuf> = tools
+        .iter()
+        .map(|tool| PathBuf::from(tool.rel_path()))
+        .collect();
+    paths
+        .iter()
+        .map(|path| {
+            (0..paths.len())
+                .filter(|&i| paths[i] != *path && path.starts_with(&paths[i]))
+                .collect()
+        })
+        .collect()
+}
+
+/// Download `tools` into `src_dir`, `DOWNLOAD_JOBS` at once by the [scheduler](../jobs/index.html).
+/// The error of the first tool failed is returned after all downloads finish.
+fn download_tools(src_dir: &Path, tools: &[&Tool]) -> Result<()> {
+    let mut scheduler = Scheduler::new(DOWNLOAD_JOBS);
+    for tool in tools {
+        scheduler.add(&tool.name, move || {
+            let res = fetcher::from_url(&tool.url, tool.branch.clone(), tool.tag.clone())
+                .and_then(|src| src.download(&src_dir.join(tool.rel_path())));
+            if let Err(e) = &res {
+                warn!("Failed to download {}: {}", tool.name, e);
+            }
+            res
+        });
+    }
+    for (job, deps) in download_deps(tools).into_iter().enumerate() {
+        for dep in deps {
+            scheduler.requires(job, dep);
+        }
+    }
+    for outcome in scheduler.run() {
+        match outcome {
+            Outcome::Done => {}
+            Outcome::Failed(e) => return Err(e),
+            Outcome::Skipped(reason) => bail!("Download skipped since {}", reason),
+        }
+    }
+    Ok(())
 }
 
 /// Describes how to compile LLVM/Clang
@@ -219,12 +961,19 @@ pub enum Entry {
 }
 
 impl Entry {
-    fn parse_setting(name: &str, setting: EntrySetting) -> Result<Self> {
+    pub(crate) fn parse_setting(name: &str, mut setting: EntrySetting) -> Result<Self> {
+        setting.merge_os(env::consts::OS);
         if setting.path.is_some() && setting.url.is_some() {
             bail!("One of Path or URL are allowed");
         }
+        if !setting.projects.is_empty() && !setting.tools.is_empty() {
+            bail!(
+                "'projects' of the monorepo and 'tools' cannot be used together: {}",
+                name
+            );
+        }
         if let Some(path) = &setting.path {
-            if setting.tools.len() > 0 {
+            if !setting.tools.is_empty() {
                 warn!("'tools' must be used with URL, ignored");
             }
             return Ok(Entry::Local {
@@ -236,7 +985,7 @@ impl Entry {
         if let Some(url) = &setting.url {
             return Ok(Entry::Remote {
                 name: name.into(),
-                url: url.clone(),
+                url: url.primary().to_string(),
                 tools: setting.tools.clone(),
                 setting,
             });
@@ -245,8 +994,9 @@ impl Entry {
     }
 }
 
-fn load_entry_toml(toml_str: &str) -> Result<Vec<Entry>> {
-    let entries: HashMap<String, EntrySetting> = toml::from_str(toml_str)?;
+/// Entries in `toml_str` in the format of entry.toml, see [module level doc](index.html)
+pub fn load_entry_toml(toml_str: &str) -> Result<Vec<Entry>> {
+    let entries: BTreeMap<String, EntrySetting> = toml::from_str(toml_str)?;
     entries
         .into_iter()
         .map(|(name, setting)| Entry::parse_setting(&name, setting))
@@ -265,14 +1015,9 @@ fn official_releases() -> Result<Vec<Entry>> {
         (3, 9, 1),
         (3, 9, 0),
     ]
-    .into_iter()
+    .iter()
     .map(|(major, minor, patch)| {
         let version = format!("{}.{}.{}", major, minor, patch);
-        let mut setting = EntrySetting::default();
-        setting.url = Some(format!(
-            "http://releases.llvm.org/{version}/llvm-{version}.src.tar.xz",
-            version = version
-        ));
         let clang = Tool {
             name: "clang".into(),
             url: format!(
@@ -280,6 +1025,7 @@ fn official_releases() -> Result<Vec<Entry>> {
                 version = version
             ),
             branch: None,
+            tag: None,
             relative_path: None,
         };
         let lld = Tool {
@@ -289,19 +1035,87 @@ fn official_releases() -> Result<Vec<Entry>> {
                 version = version
             ),
             branch: None,
+            tag: None,
             relative_path: None,
         };
-        setting.tools = vec![clang, lld];
+        // Old releases do not build with recent compilers, e.g. missing <limits> with GCC 11
+        let deprecated = if *major <= 8 {
+            Some(Deprecation {
+                note: Some(format!(
+                    "LLVM {} does not build with GCC 11 or later",
+                    version
+                )),
+                alternative: Some(format!(
+                    "the prebuilt binary by `llvmenv install {}`",
+                    version
+                )),
+                workaround: vec![(
+                    "CMAKE_CXX_FLAGS".to_string(),
+                    "-include limits -include cstdint".to_string(),
+                )]
+                .into_iter()
+                .collect(),
+            })
+        } else {
+            None
+        };
+        let setting = EntrySetting {
+            url: Some(
+                format!(
+                    "http://releases.llvm.org/{version}/llvm-{version}.src.tar.xz",
+                    version = version
+                )
+                .into(),
+            ),
+            tools: vec![clang, lld],
+            deprecated,
+            ..Default::default()
+        };
         Entry::parse_setting(&version, setting)
     })
     .collect()
 }
 
+/// Entries compiled into llvmenv, i.e. `llvm-project`, `llvm-mirror`, and the official releases
+pub fn builtin_entries() -> Result<Vec<Entry>> {
+    let mut entries = load_entry_toml(BUILTIN_ENTRIES)?;
+    entries.append(&mut official_releases()?);
+    Ok(entries)
+}
+
+/// Layer `builtin` entries under `user` ones. User entries win on name conflicts.
+fn merge_entries(mut user: Vec<Entry>, builtin: Vec<Entry>) -> Vec<Entry> {
+    let names: Vec<String> = user.iter().map(|e| e.name().to_string()).collect();
+    user.extend(
+        builtin
+            .into_iter()
+            .filter(|e| !names.iter().any(|n| n == e.name())),
+    );
+    user
+}
+
+/// User entries in entry.toml (if exists) followed by the built-in entries not overridden by them
 pub fn load_entries() -> Result<Vec<Entry>> {
     let global_toml = config_dir()?.join(ENTRY_TOML);
-    let mut entries = load_entry_toml(&fs::read_to_string(global_toml)?)?;
-    let mut official = official_releases()?;
-    entries.append(&mut official);
+    let user = if global_toml.exists() {
+        load_entry_toml(&fs::read_to_string(&global_toml)?)
+            .map_err(|e| format_err!("Invalid {}: {}", global_toml.display(), e))?
+    } else {
+        Vec::new()
+    };
+    let entries = merge_entries(user, builtin_entries()?);
+    let names: Vec<&str> = entries.iter().map(|e| e.name()).collect();
+    if let Some((a, b)) = case_collisions(&names).first() {
+        // Their source, build, and prefix directories are the same
+        if is_case_insensitive(&data_dir()?)? {
+            bail!(
+                "Entries '{}' and '{}' collide on the case-insensitive filesystem. Rename one of them in {}",
+                a,
+                b,
+                global_toml.display()
+            );
+        }
+    }
     Ok(entries)
 }
 
@@ -312,7 +1126,34 @@ pub fn load_entry(name: &str) -> Result<Entry> {
             return Ok(entry);
         }
     }
-    bail!("No entries are found: {}", name);
+    Err(LlvmEnvError::UnknownEntry { name: name.into() })
+}
+
+/// Entries of the [group](index.html#groups) sorted by name, the latest first for versions
+pub fn load_group(group: &str) -> Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = load_entries()?
+        .into_iter()
+        .filter(|entry| entry.group() == Some(group))
+        .collect();
+    entries.sort_by(|a, b| cmp_names(a.name(), b.name()));
+    if entries.is_empty() {
+        bail!("No entries in group '{}'", group);
+    }
+    Ok(entries)
+}
+
+/// Names, groups, and deprecation of `entries` for `llvmenv entries --json`
+pub fn entries_json(entries: &[Entry]) -> serde_json::Value {
+    entries
+        .iter()
+        .map(|entry| {
+            serde_json::json!({
+                "name": entry.name(),
+                "group": entry.group(),
+                "deprecated": entry.deprecation().is_some(),
+            })
+        })
+        .collect()
 }
 
 impl Entry {
@@ -331,49 +1172,268 @@ impl Entry {
     }
 
     pub fn set_builder(&mut self, builder: &str) -> Result<()> {
-        let builder = CMakeGenerator::from_str(builder)?;
+        let builder = builder.parse()?;
         self.setting_mut().builder = builder;
         Ok(())
     }
 
-    pub fn checkout(&self) -> Result<()> {
-        match self {
-            Entry::Remote { url, tools, setting, .. } => {
-                if !self.src_dir()?.is_dir() {
-                    let src = Resource::from_url(url, setting.branch.clone())?;
-                    src.download(&self.src_dir()?)?;
-                }
-                for tool in tools {
-                    let path = self.src_dir()?.join(tool.rel_path());
-                    if !path.is_dir() {
-                        let src = Resource::from_url(&tool.url, tool.branch.clone())?;
-                        src.download(&path)?;
-                    }
-                }
-            }
-            Entry::Local { path, .. } => {
-                if !path.is_dir() {
-                    bail!("Path '{}' is not a directory", path.display())
-                }
-            }
+    /// Set the builder only if the entry does not specify it, and its tools are found
+    pub fn set_default_builder(&mut self, builder: &str) -> Result<()> {
+        if self.setting().builder != CMakeGenerator::Platform {
+            return Ok(());
+        }
+        let generator: CMakeGenerator = builder.parse()?;
+        if generator.is_available() {
+            self.setting_mut().builder = generator;
+        } else {
+            warn!("Default builder {} is not available on this host", builder);
         }
         Ok(())
     }
 
-    pub fn clean_cache_dir(&self) -> Result<()> {
-        info!("Remove cache dir: {}", self.src_dir()?.display());
-        fs::remove_dir_all(self.src_dir()?)?;
-        Ok(())
+    /// Generator to build with: `builder` (or `generator`) of the entry if set,
+    /// the generator of the configured build directory, or [detected](./enum.CMakeGenerator.html#method.detect)
+    pub fn generator(&self) -> Result<CMakeGenerator> {
+        let builder = &self.setting().builder;
+        if *builder != CMakeGenerator::Platform {
+            return Ok(builder.clone());
+        }
+        // CMake cannot change the generator of a configured build directory
+        let configured = self
+            .cmake_cache()
+            .ok()
+            .and_then(|vars| CMakeGenerator::from_cmake_name(vars.get("CMAKE_GENERATOR")?));
+        Ok(configured.unwrap_or_else(CMakeGenerator::detect))
     }
 
-    pub fn update(&self) -> Result<()> {
-        match self {
-            Entry::Remote { url, tools, setting, .. } => {
-                let src = Resource::from_url(url, setting.branch.clone())?;
-                src.update(&self.src_dir()?)?;
-                for tool in tools {
-                    let src = Resource::from_url(&tool.url, tool.branch.clone())?;
-                    src.update(&self.src_dir()?.join(tool.rel_path()))?;
+    pub fn group(&self) -> Option<&str> {
+        self.setting().group.as_deref()
+    }
+
+    /// Deprecation set in the entry, or detected for the sources known to be gone
+    pub fn deprecation(&self) -> Option<Deprecation> {
+        if let Some(deprecated) = &self.setting().deprecated {
+            return Some(deprecated.clone());
+        }
+        let svn = match self {
+            Entry::Remote { url, tools, .. } => std::iter::once(url)
+                .chain(tools.iter().map(|t| &t.url))
+                .any(|url| url.contains("llvm.org/svn")),
+            Entry::Local { .. } => false,
+        };
+        if svn {
+            return Some(Deprecation {
+                note: Some(
+                    "the SVN repository of LLVM is gone since the migration to GitHub".into(),
+                ),
+                alternative: Some("the Git repository https://github.com/llvm/llvm-project".into()),
+                ..Default::default()
+            });
+        }
+        None
+    }
+
+    pub fn set_compile_commands(&mut self, compile_commands: bool) {
+        self.setting_mut().compile_commands = compile_commands;
+    }
+
+    pub fn set_build_type(&mut self, build_type: BuildType) {
+        let setting = self.setting_mut();
+        if setting.build_type != build_type {
+            setting.build_type = build_type;
+            setting.profile = true;
+        }
+    }
+
+    pub fn build_type(&self) -> BuildType {
+        self.setting().build_type
+    }
+
+    /// Suffix of the build directory and the prefix for `--build-type`, see [Build profiles](index.html#build-profiles)
+    fn profile_suffix(&self) -> String {
+        if self.setting().profile {
+            self.build_type().suffix()
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn set_hashed_prefix(&mut self, hashed_prefix: bool) {
+        self.setting_mut().hashed_prefix = hashed_prefix;
+    }
+
+    pub fn hashed_prefix(&self) -> bool {
+        self.setting().hashed_prefix
+    }
+
+    /// Select [component sets](index.html#component-sets) to be installed
+    pub fn set_components(&mut self, sets: &[String]) -> Result<()> {
+        self.setting_mut().components = sets.to_vec();
+        self.distribution_components()?;
+        Ok(())
+    }
+
+    /// Components in the selected sets without duplicates, empty if all components are installed
+    pub fn distribution_components(&self) -> Result<Vec<String>> {
+        let setting = self.setting();
+        let mut components: Vec<String> = Vec::new();
+        for name in &setting.components {
+            let set = setting.set.get(name).ok_or_else(|| {
+                format_err!(
+                    "Component set '{}' is not defined in '{}' (defined: {})",
+                    name,
+                    self.name(),
+                    setting.set.keys().join(", ")
+                )
+            })?;
+            for component in set {
+                if !components.contains(component) {
+                    components.push(component.clone());
+                }
+            }
+        }
+        Ok(components)
+    }
+
+    /// Hash of the settings of the sources, the options, and the host, used for the [hashed prefix](index.html#hashed-prefix).
+    /// It does not depend on the checked out revisions, see [build_hash](#method.build_hash) for them.
+    pub fn hash(&self) -> String {
+        let setting = self.setting();
+        let mut s = String::new();
+        match self {
+            Entry::Remote { url, tools, .. } => {
+                writeln!(s, "url={}", url).unwrap();
+                writeln!(s, "branch={}", setting.branch.as_deref().unwrap_or("")).unwrap();
+                if let Some(tag) = &setting.tag {
+                    writeln!(s, "tag={}", tag).unwrap();
+                }
+                for tool in tools {
+                    writeln!(
+                        s,
+                        "tool={} {} {} {}",
+                        tool.name,
+                        tool.url,
+                        tool.branch.as_deref().unwrap_or(""),
+                        tool.rel_path()
+                    )
+                    .unwrap();
+                    if let Some(tag) = &tool.tag {
+                        writeln!(s, "tool_tag={} {}", tool.name, tag).unwrap();
+                    }
+                }
+            }
+            Entry::Local { path, .. } => writeln!(s, "path={}", path.display()).unwrap(),
+        }
+        writeln!(s, "target={}", setting.target.join(",")).unwrap();
+        if !setting.projects.is_empty() {
+            writeln!(s, "projects={}", setting.projects.join(",")).unwrap();
+        }
+        for (k, v) in setting.option.iter().sorted() {
+            writeln!(s, "option={}={}", k, v).unwrap();
+        }
+        for (k, v) in setting.env.iter().sorted() {
+            writeln!(s, "env={}={}", k, v).unwrap();
+        }
+        writeln!(s, "build_type={:?}", setting.build_type).unwrap();
+        writeln!(s, "linker={}", setting.linker.as_deref().unwrap_or("")).unwrap();
+        if let Ok(components) = self.distribution_components() {
+            if !components.is_empty() {
+                writeln!(s, "components={}", components.join(",")).unwrap();
+            }
+        }
+        writeln!(s, "host={}-{}", env::consts::ARCH, env::consts::OS).unwrap();
+        Sha256::digest(s.as_bytes())
+            .iter()
+            .take(16)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn checkout(&self) -> Result<()> {
+        match self {
+            Entry::Remote { tools, .. } => {
+                if !self.src_dir()?.is_dir() {
+                    self.download_from_mirrors()?;
+                }
+                let src_dir = self.src_dir()?;
+                let missing: Vec<&Tool> = tools
+                    .iter()
+                    .filter(|tool| !src_dir.join(tool.rel_path()).is_dir())
+                    .collect();
+                download_tools(&src_dir, &missing)?;
+            }
+            Entry::Local { path, .. } => {
+                if !path.is_dir() {
+                    bail!("Path '{}' is not a directory", path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Download the source from the mirrors in order, starting with the one succeeded last time
+    fn download_from_mirrors(&self) -> Result<()> {
+        let setting = self.setting();
+        let urls = match &setting.url {
+            Some(urls) => urls,
+            None => return Ok(()),
+        };
+        let mut mirrors = load_mirrors();
+        let mut urls: Vec<&str> = urls.iter().collect();
+        if let Some(last) = mirrors.get(self.name()) {
+            if let Some(i) = urls.iter().position(|url| url == last) {
+                let last = urls.remove(i);
+                urls.insert(0, last);
+            }
+        }
+        let dest = self.src_dir()?;
+        for (i, url) in urls.iter().enumerate() {
+            let res = fetcher::from_url(url, setting.branch.clone(), setting.tag.clone())
+                .and_then(|src| src.download(&dest));
+            match res {
+                Ok(()) => {
+                    if urls.len() > 1 && !is_dry_run() {
+                        info!("Downloaded '{}' from {}", self.name(), url);
+                        mirrors.insert(self.name().to_string(), url.to_string());
+                        save_mirrors(&mirrors)?;
+                    }
+                    return Ok(());
+                }
+                Err(e) if i + 1 < urls.len() => {
+                    warn!(
+                        "Download from {} failed: {}. Try the next mirror {}",
+                        url,
+                        e,
+                        urls[i + 1]
+                    );
+                    if dest.exists() && !is_dry_run() {
+                        tree::remove(&dest)?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("URLs of the entry are not empty")
+    }
+
+    pub fn clean_cache_dir(&self) -> Result<()> {
+        info!("Remove cache dir: {}", self.src_dir()?.display());
+        remove_dir(&self.src_dir()?)
+    }
+
+    pub fn update(&self) -> Result<()> {
+        match self {
+            Entry::Remote {
+                url,
+                tools,
+                setting,
+                ..
+            } => {
+                let src = fetcher::from_url(url, setting.branch.clone(), setting.tag.clone())?;
+                src.update(&self.src_dir()?)?;
+                for tool in tools {
+                    let src = fetcher::from_url(&tool.url, tool.branch.clone(), tool.tag.clone())?;
+                    src.update(&self.src_dir()?.join(tool.rel_path()))?;
                 }
             }
             Entry::Local { .. } => {}
@@ -390,13 +1450,35 @@ impl Entry {
 
     pub fn src_dir(&self) -> Result<PathBuf> {
         Ok(match self {
-            Entry::Remote { name, .. } => cache_dir()?.join(name),
+            Entry::Remote { name, .. } => src_cache_dir()?.join(name),
             Entry::Local { path, .. } => path.into(),
         })
     }
 
+    /// Directory of the top-level CMakeLists.txt of LLVM, i.e. `llvm/` of the llvm-project monorepo
+    pub fn cmake_source_dir(&self) -> Result<PathBuf> {
+        let src = self.src_dir()?;
+        let llvm = src.join("llvm");
+        if !self.setting().projects.is_empty() || llvm.join("CMakeLists.txt").exists() {
+            Ok(llvm)
+        } else {
+            Ok(src)
+        }
+    }
+
+    /// Name of the build directory, e.g. `17.0.6-debug`
+    fn build_dir_name(&self) -> String {
+        format!("{}{}", self.name(), self.profile_suffix())
+    }
+
     pub fn build_dir(&self) -> Result<PathBuf> {
-        let dir = self.src_dir()?.join("build");
+        let name = self.build_dir_name();
+        let dir = build_cache_dir()?.join(&name);
+        if self.setting().build_in == BuildIn::Tmpfs {
+            if let Some(tmpfs) = tmpfs::build_dir(&name, self.build_type(), &dir)? {
+                return Ok(tmpfs);
+            }
+        }
         if !dir.exists() {
             info!("Create build dir: {}", dir.display());
             fs::create_dir_all(&dir)?;
@@ -404,60 +1486,677 @@ impl Entry {
         Ok(dir)
     }
 
+    /// Variables in CMakeCache.txt of the build directory
+    pub fn cmake_cache(&self) -> Result<BTreeMap<String, String>> {
+        let path = self.build_dir()?.join(CMAKE_CACHE_TXT);
+        if !path.exists() {
+            bail!(
+                "'{}' is not configured yet: {}",
+                self.name(),
+                path.display()
+            );
+        }
+        Ok(parse_cmake_cache(&fs::read_to_string(&path)?))
+    }
+
     pub fn clean_build_dir(&self) -> Result<()> {
         info!("Remove build dir: {}", self.build_dir()?.display());
-        fs::remove_dir_all(self.build_dir()?)?;
-        Ok(())
+        remove_dir(&self.build_dir()?)
     }
 
     pub fn prefix(&self) -> Result<PathBuf> {
-        Ok(data_dir()?.join(self.name()))
+        // The hash already differs for each build type
+        if self.hashed_prefix() {
+            Ok(data_dir()?.join(format!("{}-{}", self.hash(), self.name())))
+        } else {
+            Ok(data_dir()?.join(format!("{}{}", self.name(), self.profile_suffix())))
+        }
     }
 
-    pub fn build(&self, nproc: usize, use_ccache: bool) -> Result<()> {
-        self.configure(use_ccache)?;
-        process::Command::new("cmake")
-            .args(&[
-                "--build",
-                &format!("{}", self.build_dir()?.display()),
-                "--target",
-                "install",
-            ])
-            .args(&self.setting().builder.build_option(nproc))
-            .check_run()?;
+    /// Linker used for building this entry, see [EntrySetting::linker](./struct.EntrySetting.html#structfield.linker)
+    pub fn linker(&self) -> Option<String> {
+        let setting = self.setting();
+        if let Some(linker) = setting.option.get("LLVM_USE_LINKER") {
+            return Some(linker.clone());
+        }
+        match setting.linker.as_deref() {
+            Some("default") => None,
+            Some(linker) => Some(linker.into()),
+            None => detect_linker(),
+        }
+    }
+
+    /// Compiler cache used in the build. `use_ccache` is given by `build-entry --ccache` or `build.ccache` of config.
+    pub fn compiler_cache(&self, use_ccache: bool) -> Option<CompilerCache> {
+        let setting = self.setting();
+        if setting.sccache == Some(true) {
+            return Some(CompilerCache::Sccache);
+        }
+        if use_ccache || setting.ccache == Some(true) {
+            return Some(CompilerCache::Ccache);
+        }
+        // Caches set by the user options are not detected
+        if !setting.launcher.is_empty() || setting.option.contains_key("LLVM_CCACHE_BUILD") {
+            return None;
+        }
+        if setting.sccache.is_none() && find_tool("sccache").is_some() {
+            return Some(CompilerCache::Sccache);
+        }
+        if setting.ccache.is_none() && find_tool("ccache").is_some() {
+            return Some(CompilerCache::Ccache);
+        }
+        None
+    }
+
+    pub fn build(&self, nproc: usize, use_ccache: bool) -> Result<BuildSummary> {
+        self.build_phases(nproc, use_ccache, &ALL_PHASES)
+    }
+
+    /// Run only configure, build, and install in `phases`. Download should be done by
+    /// [checkout](#method.checkout) before.
+    pub fn build_phases(
+        &self,
+        nproc: usize,
+        use_ccache: bool,
+        phases: &[Phase],
+    ) -> Result<BuildSummary> {
+        if phases.contains(&Phase::Install) {
+            check_case_collision(&self.prefix()?)?;
+            shared::require_writable(&data_dir_path()?)?;
+            if self.prefix()?.exists() {
+                snapshot::before("build-entry")?;
+            }
+        }
+        let marker = self.build_dir()?.join(INTERRUPTED_FN);
+        if marker.exists() {
+            warn!(
+                "Previous build of '{}' has been interrupted: {}",
+                self.name(),
+                fs::read_to_string(&marker)?.trim()
+            );
+        }
+        if is_dry_run() {
+            return self.configure_and_build(nproc, use_ccache, phases);
+        }
+        // Reinstall into a locked prefix
+        let relock = phases.contains(&Phase::Install) && lockdown::is_locked(&self.prefix()?);
+        if relock {
+            lockdown::unlock(&self.prefix()?)?;
+        }
+        if phases
+            .iter()
+            .any(|p| [Phase::Configure, Phase::Build, Phase::Install].contains(p))
+        {
+            let log = self.build_log()?;
+            // Keep the log of a failed build after retrying
+            if log.exists() {
+                fs::rename(&log, log.with_file_name(PREVIOUS_BUILD_LOG_FN))?;
+            }
+            fs::File::create(log)?;
+        }
+        let res = self.configure_and_build(nproc, use_ccache, phases);
+        match &res {
+            Ok(_) => {
+                if marker.exists() {
+                    fs::remove_file(&marker)?;
+                }
+                if !phases.contains(&Phase::Install) {
+                    return res;
+                }
+                let build = Build::from_path(&self.prefix()?);
+                let mut metadata = build.metadata()?;
+                metadata.entry_hash = Some(self.build_hash()?);
+                build.save_metadata(&metadata)?;
+                verify::record(&build)?;
+                if relock {
+                    lockdown::lock(build.prefix())?;
+                } else {
+                    lockdown::after_install(&build)?;
+                }
+                self.release_tmpfs()?;
+            }
+            Err(e) => {
+                if relock {
+                    lockdown::lock(&self.prefix()?)?;
+                }
+                if e.is_timeout() {
+                    fs::write(&marker, format!("{}\n", e))?;
+                }
+            }
+        }
+        res
+    }
+
+    /// Remove the build tree on tmpfs except the logs after the install, unless `keep_tmpfs`
+    fn release_tmpfs(&self) -> Result<()> {
+        if self.setting().build_in != BuildIn::Tmpfs || self.setting().keep_tmpfs {
+            return Ok(());
+        }
+        if let Some(dir) = tmpfs::root().map(|root| root.join(self.build_dir_name())) {
+            if dir.is_dir() {
+                info!("Release build tree on tmpfs: {}", dir.display());
+                tmpfs::release(&dir, &[BUILD_LOG_FN, PREVIOUS_BUILD_LOG_FN])?;
+            }
+        }
         Ok(())
     }
 
-    fn configure(&self, use_ccache: bool) -> Result<()> {
+    /// Output of configure and build saved in the build directory
+    pub fn build_log(&self) -> Result<PathBuf> {
+        Ok(self.build_dir()?.join(BUILD_LOG_FN))
+    }
+
+    fn append_log(&self) -> Result<fs::File> {
+        Ok(fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.build_log()?)?)
+    }
+
+    fn read_log(&self) -> Result<String> {
+        let path = self.build_log()?;
+        if !path.exists() {
+            bail!("No build log of '{}': {}", self.name(), path.display());
+        }
+        Ok(String::from_utf8_lossy(&fs::read(&path)?).to_string())
+    }
+
+    /// Summary of warnings and errors in the last build log
+    pub fn diagnostics(&self) -> Result<Summary> {
+        Ok(Summary::from_log(&self.read_log()?))
+    }
+
+    /// Lines about errors in the last build log
+    pub fn error_lines(&self) -> Result<Vec<String>> {
+        Ok(self
+            .read_log()?
+            .lines()
+            .filter(|line| diagnostics::is_error_line(line))
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    /// `HEAD` of git checkouts in the source directory, i.e. LLVM and its tools
+    fn source_revisions(&self) -> Result<Vec<String>> {
+        let src = self.src_dir()?;
+        let mut dirs = vec![src.clone()];
+        if let Entry::Remote { tools, .. } = self {
+            dirs.extend(tools.iter().map(|tool| src.join(tool.rel_path())));
+        }
+        Ok(dirs
+            .iter()
+            .filter(|dir| dir.join(".git").exists())
+            .filter_map(|dir| {
+                let output = process::Command::new("git")
+                    .args(["rev-parse", "HEAD"])
+                    .current_dir(dir)
+                    .output()
+                    .ok()?;
+                if output.status.success() {
+                    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// [hash](#method.hash) combined with the revisions of the checked out sources
+    pub fn build_hash(&self) -> Result<String> {
+        let mut s = self.hash();
+        for rev in self.source_revisions()? {
+            s.push('\n');
+            s.push_str(&rev);
+        }
+        Ok(Sha256::digest(s.as_bytes())
+            .iter()
+            .take(16)
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+
+    /// Download and build the entry unless it is installed and [up to date](#method.is_up_to_date),
+    /// and returns the build
+    pub fn ensure_built(&self, nproc: usize) -> Result<Build> {
+        self.checkout()?;
+        if !self.is_up_to_date()? {
+            self.build(nproc, false)?;
+        }
+        Ok(Build::from_path(&self.prefix()?))
+    }
+
+    /// Whether the build exists and is built from the current entry and sources
+    pub fn is_up_to_date(&self) -> Result<bool> {
+        let build = Build::from_path(&self.prefix()?);
+        if !build.exists() {
+            return Ok(false);
+        }
+        Ok(build.metadata()?.entry_hash == Some(self.build_hash()?))
+    }
+
+    /// Whether the build directory has to be configured, i.e. it is not configured yet,
+    /// or the CMake options, the generator, or the environment changed since the last configure
+    pub fn needs_configure(&self, nproc: usize, use_ccache: bool) -> Result<bool> {
+        let dir = self.build_dir()?;
+        if !dir.join(CMAKE_CACHE_TXT).exists() {
+            return Ok(true);
+        }
+        let configured = fs::read_to_string(dir.join(CONFIGURED_FN)).unwrap_or_default();
+        Ok(
+            configured.trim()
+                != self.configure_hash(&self.configure_options(nproc, use_ccache)?)?,
+        )
+    }
+
+    /// Hash of the generator, `opts` of CMake, and the environment, recorded by each configure
+    fn configure_hash(&self, opts: &[String]) -> Result<String> {
+        let mut s = format!("generator={}\n", self.generator()?.backend().name());
+        for opt in opts {
+            writeln!(s, "option={}", opt).unwrap();
+        }
+        for (k, v) in self.setting().env.iter().sorted() {
+            writeln!(s, "env={}={}", k, v).unwrap();
+        }
+        Ok(Sha256::digest(s.as_bytes())
+            .iter()
+            .take(16)
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+
+    /// Update the sources, and build incrementally in the existing build directory.
+    /// CMake is re-run only if [needs_configure](#method.needs_configure).
+    pub fn rebuild(&self, nproc: usize, use_ccache: bool) -> Result<BuildSummary> {
+        if !self.src_dir()?.is_dir() {
+            bail!(
+                "Source of '{}' is not checked out yet. Run `llvmenv build-entry {}` first.",
+                self.name(),
+                self.name()
+            );
+        }
+        run_phase(Phase::Update, self.name(), || self.update())?;
+        let phases = if self.needs_configure(nproc, use_ccache)? {
+            info!(
+                "Options of '{}' changed since the last configure",
+                self.name()
+            );
+            vec![Phase::Configure, Phase::Build, Phase::Install]
+        } else {
+            vec![Phase::Build, Phase::Install]
+        };
+        self.build_phases(nproc, use_ccache, &phases)
+    }
+
+    /// Whether the last build in the build directory has been interrupted, e.g. by timeout
+    pub fn is_interrupted(&self) -> Result<bool> {
+        Ok(self.build_dir()?.join(INTERRUPTED_FN).exists())
+    }
+
+    /// Linkers to be tried in order: [linker](#method.linker), lld, gold, bfd, and the default
+    fn linker_candidates(&self) -> Vec<Option<String>> {
+        let mut candidates = vec![self.linker()];
+        for (linker, exe) in &[("lld", "ld.lld"), ("gold", "ld.gold"), ("bfd", "ld.bfd")] {
+            let linker = Some(linker.to_string());
+            if find_tool(exe).is_some() && !candidates.contains(&linker) {
+                candidates.push(linker);
+            }
+        }
+        if !candidates.contains(&None) {
+            candidates.push(None);
+        }
+        candidates
+    }
+
+    fn configure_and_build(
+        &self,
+        nproc: usize,
+        use_ccache: bool,
+        phases: &[Phase],
+    ) -> Result<BuildSummary> {
+        let configure = phases.contains(&Phase::Configure);
+        let install = phases.contains(&Phase::Install);
+        let build_phase = if phases.contains(&Phase::Build) {
+            Some(Phase::Build)
+        } else if install {
+            Some(Phase::Install)
+        } else {
+            None
+        };
+        let candidates = self.linker_candidates();
+        let mut nproc = nproc;
+        let mut oom_retried = false;
+        let mut i = 0;
+        loop {
+            let linker = candidates[i].clone();
+            let has_next = i + 1 < candidates.len();
+            info!("Use linker: {}", linker.as_deref().unwrap_or("default"));
+            let mut extra = vec![format!(
+                "-DLLVM_USE_LINKER={}",
+                linker.as_deref().unwrap_or("")
+            )];
+            if i > 0 {
+                // Cached result of the linker check in LLVM's CMake
+                extra.push("-UCXX_SUPPORTS_CUSTOM_LINKER".into());
+            }
+            if oom_retried {
+                extra.push("-DLLVM_PARALLEL_LINK_JOBS=1".into());
+            }
+            let mut failure = BuildFailure::default();
+            if configure {
+                let res = run_phase(Phase::Configure, self.name(), || {
+                    self.configure(nproc, use_ccache, &extra, &mut failure)
+                });
+                match res {
+                    Ok(_) => self.copy_compile_commands()?,
+                    Err(e) => {
+                        if failure.linker_rejected && has_next {
+                            warn!(
+                                "CMake rejects linker {:?}, fallback to the next one",
+                                linker
+                            );
+                            i += 1;
+                            continue;
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            let phase = match build_phase {
+                Some(phase) => phase,
+                None => return Ok(BuildSummary { linker }),
+            };
+            let res = run_phase(phase, self.name(), || {
+                self.run_build(nproc, install, &mut failure)
+            });
+            match res {
+                Ok(_) => return Ok(BuildSummary { linker }),
+                // Retries below require re-configure
+                Err(e) if !configure => return Err(e),
+                Err(e) => {
+                    if failure.link_oom && !oom_retried {
+                        oom_retried = true;
+                        nproc = (nproc / 2).max(1);
+                        warn!(
+                            "Linker seems to be killed by out of memory. Retry with LLVM_PARALLEL_LINK_JOBS=1 and {} jobs",
+                            nproc
+                        );
+                        continue;
+                    }
+                    if failure.linker_crash && has_next {
+                        warn!("Linker {:?} crashed, fallback to the next one", linker);
+                        i += 1;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Copy compile_commands.json generated by CMake into the source directory,
+    /// where it is kept even if the build directory is cleaned
+    fn copy_compile_commands(&self) -> Result<()> {
+        if !self.setting().compile_commands || is_dry_run() {
+            return Ok(());
+        }
+        let generated = self.build_dir()?.join(COMPILE_COMMANDS_JSON);
+        if generated.exists() {
+            let dest = self.src_dir()?.join(COMPILE_COMMANDS_JSON);
+            info!("Copy {} to {}", generated.display(), dest.display());
+            fs::copy(&generated, &dest)?;
+        } else {
+            warn!(
+                "{} is not generated. Generator may not support it.",
+                COMPILE_COMMANDS_JSON
+            );
+        }
+        Ok(())
+    }
+
+    /// Put lld of a build on $PATH of `command` if it is not there, see [Linker](index.html#linker)
+    fn set_linker_path(&self, command: &mut process::Command) -> Result<()> {
+        let lld = match lld_of_builds() {
+            Some(lld) => lld,
+            None => return Ok(()),
+        };
+        let dir = self.build_dir()?.join(LINKER_DIR);
+        if !is_dry_run() {
+            let link = dir.join("ld.lld");
+            if fs::symlink_metadata(&link).is_ok() {
+                fs::remove_file(&link)?;
+            }
+            fs::create_dir_all(&dir)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&lld, &link)?;
+            #[cfg(not(unix))]
+            fs::copy(&lld, &link)?;
+        }
+        let path = match self.setting().env.get("PATH") {
+            Some(path) => OsString::from(path),
+            None => env::var_os("PATH").unwrap_or_default(),
+        };
+        let mut dirs = vec![dir];
+        dirs.extend(env::split_paths(&path));
+        let path = env::join_paths(dirs).map_err(|e| format_err!("Invalid PATH: {}", e))?;
+        command.env("PATH", path);
+        Ok(())
+    }
+
+    /// Run `cmake --build`, with `--target install` if `install`.
+    /// `distribution` and `install-distribution` are used instead if components are selected,
+    /// and the latter installs into the cleared prefix.
+    fn run_build(&self, nproc: usize, install: bool, failure: &mut BuildFailure) -> Result<()> {
+        let observer = observer();
+        let mut log = self.append_log()?;
+        let distribution = !self.distribution_components()?.is_empty();
+        let target = match (install, distribution) {
+            (true, false) => Some("install"),
+            (true, true) => Some("install-distribution"),
+            (false, true) => Some("distribution"),
+            (false, false) => None,
+        };
+        let prefix = self.prefix()?;
+        if install && distribution && prefix.exists() && !is_dry_run() {
+            info!(
+                "Clear {} to install the selected components",
+                prefix.display()
+            );
+            tree::remove(&prefix)?;
+        }
+        let prior = timing::History::load()?.seconds_per_step(self.name(), nproc);
+        let start = Instant::now();
+        let mut steps = 0;
+        let mut command = self
+            .generator()?
+            .backend()
+            .build(&self.build_dir()?, target, nproc);
+        command.envs(&self.setting().env);
+        self.set_linker_path(&mut command)?;
+        let res = command.lower_priority().check_run_lines(|line| {
+            if let Some((step, total)) = parse_build_step(line) {
+                if steps == 0 && prior.is_some() {
+                    if let Some(estimate) = timing::remaining(prior, 0, total, Duration::ZERO) {
+                        progress::message(&format!(
+                            "Estimated build time of '{}': about {} for {} steps",
+                            self.name(),
+                            timing::format_duration(estimate),
+                            total
+                        ));
+                    }
+                }
+                steps = total;
+                observer.on_build_step(step, total);
+                if let Some(remaining) = timing::remaining(prior, step, total, start.elapsed()) {
+                    observer.on_build_remaining(remaining);
+                }
+            }
+            failure.scan(line);
+            let _ = writeln!(log, "{}", line);
+        });
+        if res.is_ok() && steps > 0 && !is_dry_run() {
+            let record = timing::Record {
+                seconds: start.elapsed().as_secs(),
+                steps,
+                jobs: nproc,
+            };
+            let mut history = timing::History::load()?;
+            history.add(self.name(), record);
+            if let Err(e) = history.save() {
+                warn!("Cannot record the build time: {}", e);
+            }
+        }
+        let stage = if install {
+            Phase::Install
+        } else {
+            Phase::Build
+        };
+        res.map_err(|e| self.build_failed(stage, e))
+    }
+
+    /// Failure of CMake as [BuildFailed](../error/enum.LlvmEnvError.html#variant.BuildFailed) with the build log,
+    /// except for missing tools and timeouts
+    fn build_failed(&self, stage: Phase, e: CommandError) -> LlvmEnvError {
+        match e {
+            CommandError::ErrorCode { .. } | CommandError::TerminatedBySignal { .. } => {
+                match self.build_log() {
+                    Ok(log_path) => LlvmEnvError::BuildFailed {
+                        stage,
+                        log_path,
+                        source: e,
+                    },
+                    Err(_) => e.into(),
+                }
+            }
+            e => e.into(),
+        }
+    }
+
+    /// `extra` options are passed after the entry setting, i.e. override it
+    fn configure(
+        &self,
+        nproc: usize,
+        use_ccache: bool,
+        extra: &[String],
+        failure: &mut BuildFailure,
+    ) -> Result<()> {
+        let setting = self.setting();
+        let backend = self.generator()?.backend();
+        for tool in backend.required_tools() {
+            check_tool(tool)?;
+        }
+        if let Some(cache) = self.compiler_cache(use_ccache) {
+            check_tool(cache.tool())?;
+            info!("Use compiler cache: {}", cache.tool());
+        }
+        info!("Configure '{}' with {}", self.name(), backend.name());
+        let mut opts = self.configure_options(nproc, use_ccache)?;
+        let hash = self.configure_hash(&opts)?;
+        opts.extend_from_slice(extra);
+        let mut log = self.append_log()?;
+        if is_dry_run() {
+            println!(
+                "mkdir -p {}",
+                quote_if_needed(&self.build_dir()?.display().to_string())
+            );
+        }
+        let mut command = backend.configure(&self.cmake_source_dir()?, &self.build_dir()?, &opts);
+        command.envs(&setting.env);
+        self.set_linker_path(&mut command)?;
+        command
+            .lower_priority()
+            .check_run_lines(|line| {
+                failure.scan(line);
+                let _ = writeln!(log, "{}", line);
+            })
+            .map_err(|e| self.build_failed(Phase::Configure, e))?;
+        if !is_dry_run() {
+            fs::write(self.build_dir()?.join(CONFIGURED_FN), hash)?;
+        }
+        Ok(())
+    }
+
+    /// Options of CMake configure from the entry and the host, without the linker
+    fn configure_options(&self, nproc: usize, use_ccache: bool) -> Result<Vec<String>> {
         let setting = self.setting();
-        let mut opts = setting.builder.option();
-        opts.push(format!("{}", self.src_dir()?.display()));
+        let backend = self.generator()?.backend();
+        let mut opts = Vec::new();
         opts.push(format!(
             "-DCMAKE_INSTALL_PREFIX={}",
             data_dir()?.join(self.prefix()?).display()
         ));
         opts.push(format!("-DCMAKE_BUILD_TYPE={:?}", setting.build_type));
-        if setting.target.len() > 0 {
+        if !setting.target.is_empty() {
             opts.push(format!(
                 "-DLLVM_TARGETS_TO_BUILD={}",
                 setting.target.iter().join(";")
             ));
         }
-        if use_ccache {
+        if let Some(memory) = hostinfo::total_memory() {
+            let (link, compile) = parallel_jobs(memory, nproc, setting.build_type.has_debug_info());
+            if !setting.option.contains_key("LLVM_PARALLEL_LINK_JOBS") {
+                info!(
+                    "LLVM_PARALLEL_LINK_JOBS={} for {} GB memory",
+                    link,
+                    memory / GB
+                );
+                opts.push(format!("-DLLVM_PARALLEL_LINK_JOBS={}", link));
+            }
+            if let Some(compile) = compile {
+                if !setting.option.contains_key("LLVM_PARALLEL_COMPILE_JOBS") {
+                    info!(
+                        "LLVM_PARALLEL_COMPILE_JOBS={} for {} GB memory",
+                        compile,
+                        memory / GB
+                    );
+                    opts.push(format!("-DLLVM_PARALLEL_COMPILE_JOBS={}", compile));
+                }
+            }
+        }
+        opts.extend(enable_projects(&setting.projects, &setting.option));
+        if setting.compile_commands {
+            if !backend.exports_compile_commands() {
+                warn!("{} does not support compile_commands.json", backend.name());
+            }
+            opts.push("-DCMAKE_EXPORT_COMPILE_COMMANDS=ON".into());
+        }
+        let cache = self.compiler_cache(use_ccache);
+        let mut launcher = setting.launcher.clone();
+        if cache == Some(CompilerCache::Sccache) {
+            if launcher.is_empty() {
+                launcher.push("sccache".into());
+            } else {
+                warn!("sccache is not used since launcher {:?} is set", launcher);
+            }
+        }
+        if !launcher.is_empty() {
+            let launcher = cmake_list(&launcher);
+            opts.push(format!("-DCMAKE_C_COMPILER_LAUNCHER={}", launcher));
+            opts.push(format!("-DCMAKE_CXX_COMPILER_LAUNCHER={}", launcher));
+        }
+        let components = self.distribution_components()?;
+        if !setting.option.contains_key("LLVM_DISTRIBUTION_COMPONENTS") {
+            if components.is_empty() {
+                // Selected in the previous configure
+                opts.push("-ULLVM_DISTRIBUTION_COMPONENTS".into());
+            } else {
+                opts.push(format!(
+                    "-DLLVM_DISTRIBUTION_COMPONENTS={}",
+                    cmake_list(&components)
+                ));
+            }
+        }
+        let ccache = cache == Some(CompilerCache::Ccache);
+        if ccache {
             // Override user settings if given on command line
             opts.push("-DLLVM_CCACHE_BUILD=ON".to_owned());
         }
         for (k, v) in &setting.option {
             match k.as_ref() {
-                "LLVM_CCACHE_BUILD" if use_ccache => continue,
-                _ => opts.push(format!("-D{}={}", k, v))
+                "LLVM_CCACHE_BUILD" if ccache => continue,
+                _ => opts.push(format!("-D{}={}", k, v)),
             }
         }
-        process::Command::new("cmake")
-            .args(&opts)
-            .current_dir(self.build_dir()?)
-            .check_run()?;
-        Ok(())
+        Ok(opts)
     }
 }
 
@@ -470,27 +2169,589 @@ mod tests {
         let setting = EntrySetting {
             url: None,
             branch: None,
+            tag: None,
             path: None,
             tools: Default::default(),
             option: Default::default(),
             builder: Default::default(),
             build_type: Default::default(),
+            profile: false,
+            build_in: Default::default(),
+            keep_tmpfs: false,
             target: Default::default(),
+            linker: None,
+            launcher: Default::default(),
+            ccache: None,
+            sccache: None,
+            compile_commands: false,
+            hashed_prefix: false,
+            set: Default::default(),
+            components: Default::default(),
+            group: None,
+            deprecated: None,
+            projects: Default::default(),
+            env: Default::default(),
+            linux: None,
+            macos: None,
+            windows: None,
         };
         assert!(Entry::parse_setting("no_entry", setting).is_err());
 
         let setting = EntrySetting {
             url: Some("http://llvm.org/svn/llvm-project/llvm/trunk".into()),
             branch: None,
+            tag: None,
             path: Some("~/.config/llvmenv".into()),
             tools: Default::default(),
             option: Default::default(),
             builder: Default::default(),
             build_type: Default::default(),
+            profile: false,
+            build_in: Default::default(),
+            keep_tmpfs: false,
             target: Default::default(),
+            linker: None,
+            launcher: Default::default(),
+            ccache: None,
+            sccache: None,
+            compile_commands: false,
+            hashed_prefix: false,
+            set: Default::default(),
+            components: Default::default(),
+            group: None,
+            deprecated: None,
+            projects: Default::default(),
+            env: Default::default(),
+            linux: None,
+            macos: None,
+            windows: None,
         };
         assert!(Entry::parse_setting("duplicated", setting).is_err());
 
         Ok(())
     }
+
+    #[test]
+    fn test_mirrors() -> Result<()> {
+        let entries = load_entry_toml(
+            r#"
+            [mirrored]
+            url = ["https://a.example.com/llvm.tar.xz", "https://b.example.com/llvm.tar.xz"]
+            [single]
+            url = "https://a.example.com/llvm.tar.xz"
+            "#,
+        )?;
+        let urls = entries[0].setting().url.as_ref().unwrap();
+        assert_eq!(urls.iter().collect::<Vec<_>>().len(), 2);
+        assert_eq!(urls.primary(), "https://a.example.com/llvm.tar.xz");
+        assert_eq!(
+            entries[1].setting().url,
+            Some("https://a.example.com/llvm.tar.xz".into())
+        );
+        assert!(load_entry_toml("[empty]\nurl = []\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash() -> Result<()> {
+        let setting = EntrySetting {
+            url: Some("https://github.com/llvm-mirror/llvm".into()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("a", setting.clone())?;
+        assert_eq!(entry.hash().len(), 32);
+        // name and options not affecting the product are not hashed
+        let mut other = Entry::parse_setting("b", setting.clone())?;
+        other.set_compile_commands(true);
+        assert_eq!(entry.hash(), other.hash());
+        let mut setting = setting;
+        setting.target = vec!["X86".into()];
+        assert_ne!(entry.hash(), Entry::parse_setting("a", setting)?.hash());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compiler_cache() -> Result<()> {
+        let entry = |setting: EntrySetting| {
+            let setting = EntrySetting {
+                url: Some("https://github.com/llvm-mirror/llvm".into()),
+                ..setting
+            };
+            Entry::parse_setting("a", setting)
+        };
+        let disabled = EntrySetting {
+            ccache: Some(false),
+            sccache: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(entry(disabled.clone())?.compiler_cache(false), None);
+        assert_eq!(
+            entry(disabled)?.compiler_cache(true),
+            Some(CompilerCache::Ccache)
+        );
+        let sccache = EntrySetting {
+            sccache: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            entry(sccache)?.compiler_cache(true),
+            Some(CompilerCache::Sccache)
+        );
+        let launcher = EntrySetting {
+            launcher: vec!["icecc".into()],
+            ..Default::default()
+        };
+        assert_eq!(entry(launcher)?.compiler_cache(false), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_configure_hash() -> Result<()> {
+        let hash = |setting: EntrySetting, use_ccache: bool| -> Result<String> {
+            let setting = EntrySetting {
+                path: Some("/path/to/llvm".into()),
+                ccache: Some(false),
+                sccache: Some(false),
+                ..setting
+            };
+            let entry = Entry::parse_setting("a", setting)?;
+            entry.configure_hash(&entry.configure_options(4, use_ccache)?)
+        };
+        let base = hash(Default::default(), false)?;
+        assert_eq!(hash(Default::default(), false)?, base);
+        assert_ne!(hash(Default::default(), true)?, base);
+        let compile_commands = EntrySetting {
+            compile_commands: true,
+            ..Default::default()
+        };
+        assert_ne!(hash(compile_commands, false)?, base);
+        let launcher = EntrySetting {
+            launcher: vec!["icecc".into()],
+            ..Default::default()
+        };
+        assert_ne!(hash(launcher, false)?, base);
+        let generator = |builder| EntrySetting {
+            builder,
+            ..Default::default()
+        };
+        assert_ne!(
+            hash(generator(CMakeGenerator::Makefile), false)?,
+            hash(generator(CMakeGenerator::Ninja), false)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_hash() -> Result<()> {
+        let src = tempfile::TempDir::new()?;
+        let setting = EntrySetting {
+            path: Some(src.path().display().to_string()),
+            ..Default::default()
+        };
+        let entry = Entry::parse_setting("local", setting)?;
+        assert_eq!(entry.build_hash()?, entry.build_hash()?);
+        assert_ne!(entry.build_hash()?, entry.hash());
+
+        process::Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(src.path())
+            .check_run()?;
+        process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=a",
+                "-c",
+                "user.email=a@b",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "init",
+            ])
+            .current_dir(src.path())
+            .check_run()?;
+        let before = entry.build_hash()?;
+        process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=a",
+                "-c",
+                "user.email=a@b",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "next",
+            ])
+            .current_dir(src.path())
+            .check_run()?;
+        assert_ne!(entry.build_hash()?, before);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_entries() -> Result<()> {
+        let user = load_entry_toml(
+            r#"
+            [llvm-mirror]
+            path = "/path/to/llvm"
+            [mine]
+            path = "/path/to/mine"
+            "#,
+        )?;
+        let entries = merge_entries(user, builtin_entries()?);
+        let mirrors: Vec<&Entry> = entries
+            .iter()
+            .filter(|e| e.name() == "llvm-mirror")
+            .collect();
+        assert_eq!(mirrors.len(), 1);
+        assert!(matches!(mirrors[0], Entry::Local { .. }));
+        assert!(entries.iter().any(|e| e.name() == "mine"));
+        assert!(entries.iter().any(|e| e.name() == "7.0.0"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_projects() -> Result<()> {
+        let projects: Vec<String> = vec!["clang".into(), "libcxx".into(), "lld".into()];
+        assert_eq!(
+            enable_projects(&projects, &HashMap::new()),
+            vec![
+                "-DLLVM_ENABLE_PROJECTS=clang;lld",
+                "-DLLVM_ENABLE_RUNTIMES=libcxx"
+            ]
+        );
+        let mut option = HashMap::new();
+        option.insert(
+            "LLVM_ENABLE_RUNTIMES".to_string(),
+            "libcxx;libcxxabi".to_string(),
+        );
+        assert_eq!(
+            enable_projects(&projects, &option),
+            vec!["-DLLVM_ENABLE_PROJECTS=clang;lld"]
+        );
+
+        let entries = load_entry_toml(
+            r#"
+            [mono]
+            url = "https://github.com/llvm/llvm-project"
+            projects = ["clang"]
+            "#,
+        )?;
+        assert!(entries[0].cmake_source_dir()?.ends_with("mono/llvm"));
+        assert!(load_entry_toml(
+            r#"
+            [mono]
+            url = "https://github.com/llvm/llvm-project"
+            projects = ["clang"]
+            [[mono.tools]]
+            name = "clang"
+            url = "https://github.com/llvm-mirror/clang"
+            "#,
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_deps() -> Result<()> {
+        let entries = load_entry_toml(BUILTIN_ENTRIES)?;
+        let mirror = entries.iter().find(|e| e.name() == "llvm-mirror").unwrap();
+        let tools: Vec<&Tool> = match mirror {
+            Entry::Remote { tools, .. } => tools.iter().collect(),
+            Entry::Local { .. } => unreachable!(),
+        };
+        let names: Vec<&str> = tools.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["clang", "clang-extra"]);
+        assert_eq!(download_deps(&tools), vec![vec![], vec![0]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_type() -> Result<()> {
+        assert_eq!(BuildType::Release.suffix(), "");
+        assert_eq!(BuildType::Debug.suffix(), "-debug");
+        assert_eq!(BuildType::RelWithDebInfo.suffix(), "-relwithdebinfo");
+        let setting = EntrySetting {
+            url: Some("https://github.com/llvm-mirror/llvm".into()),
+            ..Default::default()
+        };
+        let release = Entry::parse_setting("a", setting.clone())?;
+        let mut debug = Entry::parse_setting("a", setting.clone())?;
+        debug.set_build_type(BuildType::Debug);
+        assert_ne!(release.hash(), debug.hash());
+        assert_eq!(debug.profile_suffix(), "-debug");
+        // The entry's own build type keeps the name
+        let debug_setting = EntrySetting {
+            build_type: BuildType::Debug,
+            ..setting
+        };
+        let own = Entry::parse_setting("a", debug_setting.clone())?;
+        assert_eq!(own.profile_suffix(), "");
+        let mut same = Entry::parse_setting("a", debug_setting)?;
+        same.set_build_type(BuildType::Debug);
+        assert_eq!(same.profile_suffix(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmake_options() -> Result<()> {
+        let entries: BTreeMap<String, EntrySetting> = toml::from_str(
+            r#"
+            [a]
+            path = "/path/to/llvm"
+            [a.cmake_options]
+            LLVM_ENABLE_ASSERTIONS = true
+            LLVM_ENABLE_LIBXML2 = false
+            LLVM_PARALLEL_LINK_JOBS = 2
+            LLVM_TARGETS_TO_BUILD = "X86;AArch64"
+            "#,
+        )?;
+        let option = &entries["a"].option;
+        assert_eq!(option["LLVM_ENABLE_ASSERTIONS"], "ON");
+        assert_eq!(option["LLVM_ENABLE_LIBXML2"], "OFF");
+        assert_eq!(option["LLVM_PARALLEL_LINK_JOBS"], "2");
+        assert_eq!(option["LLVM_TARGETS_TO_BUILD"], "X86;AArch64");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_os() -> Result<()> {
+        let entries: BTreeMap<String, EntrySetting> = toml::from_str(
+            r#"
+            [a]
+            path = "/path/to/llvm"
+            option = { LLVM_ENABLE_ASSERTIONS = "ON", LLVM_ENABLE_LIBXML2 = "OFF" }
+            [a.macos]
+            option = { LLVM_ENABLE_LIBXML2 = "ON" }
+            env = { SDKROOT = "/path/to/sdk" }
+            [a.windows]
+            builder = "VisualStudio"
+            "#,
+        )?;
+        let setting = &entries["a"];
+
+        let mut macos = setting.clone();
+        macos.merge_os("macos");
+        assert_eq!(macos.option["LLVM_ENABLE_LIBXML2"], "ON");
+        assert_eq!(macos.option["LLVM_ENABLE_ASSERTIONS"], "ON");
+        assert_eq!(macos.env["SDKROOT"], "/path/to/sdk");
+        assert_eq!(macos.builder, CMakeGenerator::Platform);
+
+        let mut windows = setting.clone();
+        windows.merge_os("windows");
+        assert_eq!(windows.option["LLVM_ENABLE_LIBXML2"], "OFF");
+        assert_eq!(windows.builder, CMakeGenerator::VisualStudio);
+        assert!(windows.windows.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deprecation() -> Result<()> {
+        let entries = builtin_entries()?;
+        let old = entries.iter().find(|e| e.name() == "3.9.0").unwrap();
+        let deprecation = old.deprecation().unwrap();
+        assert!(deprecation.workaround.contains_key("CMAKE_CXX_FLAGS"));
+        assert!(deprecation
+            .warning("3.9.0")
+            .contains("llvmenv install 3.9.0"));
+
+        let svn = load_entry_toml(
+            r#"
+            [svn]
+            url = "http://llvm.org/svn/llvm-project/llvm/trunk"
+            [local]
+            path = "/path/to/llvm"
+            "#,
+        )?;
+        for entry in svn {
+            match entry.name() {
+                "svn" => assert!(entry.deprecation().unwrap().note.unwrap().contains("SVN")),
+                _ => assert!(entry.deprecation().is_none()),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_group() -> Result<()> {
+        let entries = load_entry_toml(
+            r#"
+            [a]
+            path = "/path/to/a"
+            group = "release-line-7"
+            [b]
+            path = "/path/to/b"
+            "#,
+        )?;
+        let a = entries.iter().find(|e| e.name() == "a").unwrap();
+        let b = entries.iter().find(|e| e.name() == "b").unwrap();
+        assert_eq!(a.group(), Some("release-line-7"));
+        assert_eq!(b.group(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_components() -> Result<()> {
+        let mut entries = load_entry_toml(
+            r#"
+            [llvm]
+            url = "https://github.com/llvm-mirror/llvm"
+            set.compilers = ["clang", "lld"]
+            set.analysis = ["clang-tidy", "scan-build", "clang"]
+            "#,
+        )?;
+        let entry = &mut entries[0];
+        assert!(entry.distribution_components()?.is_empty());
+        let hash = entry.hash();
+        entry.set_components(&["compilers".into(), "analysis".into()])?;
+        assert_eq!(
+            entry.distribution_components()?,
+            vec!["clang", "lld", "clang-tidy", "scan-build"]
+        );
+        assert_ne!(entry.hash(), hash);
+        assert!(entry.set_components(&["unknown".into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cmake_cache() {
+        let cache = "# This is the CMakeCache file.\n\n// Build type\nCMAKE_BUILD_TYPE:STRING=Release\nLLVM_TARGETS_TO_BUILD:STRING=X86;AArch64\nLLVM_ENABLE_ASSERTIONS:BOOL=\n";
+        let vars = parse_cmake_cache(cache);
+        assert_eq!(vars.len(), 3);
+        assert_eq!(vars["CMAKE_BUILD_TYPE"], "Release");
+        assert_eq!(vars["LLVM_TARGETS_TO_BUILD"], "X86;AArch64");
+        assert_eq!(vars["LLVM_ENABLE_ASSERTIONS"], "");
+    }
+
+    #[test]
+    fn test_generator() -> Result<()> {
+        let entries: BTreeMap<String, EntrySetting> = toml::from_str(
+            r#"
+            [a]
+            path = "/path/to/llvm"
+            generator = "Makefiles"
+            [b]
+            path = "/path/to/llvm"
+            [b.windows]
+            generator = "VisualStudio"
+            "#,
+        )?;
+        assert_eq!(entries["a"].builder, CMakeGenerator::Makefile);
+        assert_eq!(entries["b"].builder, CMakeGenerator::Platform);
+        assert_eq!(
+            entries["b"].windows.as_ref().unwrap().builder,
+            Some(CMakeGenerator::VisualStudio)
+        );
+
+        let entry = Entry::parse_setting("a", entries["a"].clone())?;
+        assert_eq!(entry.generator()?, CMakeGenerator::Makefile);
+        assert_eq!(
+            CMakeGenerator::from_cmake_name("Ninja"),
+            Some(CMakeGenerator::Ninja)
+        );
+        assert_eq!(
+            CMakeGenerator::from_cmake_name("Visual Studio 17 2022"),
+            Some(CMakeGenerator::VisualStudio)
+        );
+        assert_eq!(CMakeGenerator::from_cmake_name("Xcode"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_jobs() {
+        assert_eq!(parallel_jobs(16 * GB, 8, false), (8, None));
+        assert_eq!(parallel_jobs(16 * GB, 16, false), (8, None));
+        assert_eq!(parallel_jobs(16 * GB, 16, true), (2, Some(8)));
+        assert_eq!(parallel_jobs(GB, 4, true), (1, Some(1)));
+    }
+
+    #[test]
+    fn test_cmake_list() {
+        let launcher = ["bear".to_string(), "--append".into(), "--".into()];
+        assert_eq!(cmake_list(&launcher), "bear;--append;--");
+        assert_eq!(cmake_list(&["a;b".to_string()]), "a\\;b");
+    }
+
+    #[test]
+    fn test_build_failure() {
+        let scan = |line| {
+            let mut failure = BuildFailure::default();
+            failure.scan(line);
+            failure
+        };
+        let f = scan("collect2: fatal error: ld terminated with signal 9 [Killed]");
+        assert!(f.link_oom && !f.linker_crash);
+        let f = scan("collect2: fatal error: ld terminated with signal 11 [Segmentation fault]");
+        assert!(!f.link_oom && f.linker_crash);
+        let f =
+            scan("clang: error: linker command failed due to signal (use -v to see invocation)");
+        assert!(f.link_oom && f.linker_crash);
+        let f =
+            scan("clang: error: linker command failed with exit code 1 (use -v to see invocation)");
+        assert!(!f.link_oom && !f.linker_crash && !f.linker_rejected);
+        let f = scan("make[2]: *** [tools/lto/CMakeFiles/LTO.dir/build.make:170: lib/libLTO.so.17] Error 137");
+        assert!(f.link_oom);
+        let f = scan("  Host compiler does not support '-fuse-ld=mold'");
+        assert!(f.linker_rejected);
+        let f = scan("-- LLVM_USE_LINKER: mold");
+        assert!(!f.linker_rejected);
+    }
+
+    /// Value of `cmake_options` as a TOML literal, and as passed to CMake
+    fn option_value() -> impl proptest::strategy::Strategy<Value = (String, String)> {
+        use proptest::prelude::*;
+        prop_oneof![
+            "[a-zA-Z0-9;_ -]{0,12}".prop_map(|s| (format!("\"{}\"", s), s)),
+            any::<bool>().prop_map(|b| (b.to_string(), if b { "ON" } else { "OFF" }.to_string())),
+            any::<i32>().prop_map(|n| (n.to_string(), n.to_string())),
+        ]
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(128))]
+
+        #[test]
+        fn test_entry_toml(
+            entries in proptest::collection::btree_map(
+                "[a-z][a-z0-9_-]{0,8}",
+                (
+                    proptest::bool::ANY,
+                    proptest::collection::btree_map("[A-Z][A-Z0-9_]{0,12}", option_value(), 0..4),
+                ),
+                1..4,
+            )
+        ) {
+            let mut toml_str = String::new();
+            for (name, (local, options)) in &entries {
+                toml_str += &format!("[{}]\n", name);
+                if *local {
+                    toml_str += &format!("path = \"/path/to/{}\"\n", name);
+                } else {
+                    toml_str += "url = \"https://github.com/llvm/llvm-project\"\n";
+                }
+                toml_str += &format!("[{}.cmake_options]\n", name);
+                for (key, (literal, _)) in options {
+                    toml_str += &format!("{} = {}\n", key, literal);
+                }
+            }
+            let parsed = load_entry_toml(&toml_str).unwrap();
+            proptest::prop_assert_eq!(parsed.len(), entries.len());
+            for (entry, (name, (local, options))) in parsed.iter().zip(&entries) {
+                proptest::prop_assert_eq!(entry.name(), name.as_str());
+                proptest::prop_assert_eq!(matches!(entry, Entry::Local { .. }), *local);
+                for (key, (_, value)) in options {
+                    proptest::prop_assert_eq!(&entry.setting().option[key], value);
+                }
+            }
+        }
+
+        /// Broken entry.toml is rejected without panics
+        #[test]
+        fn test_entry_toml_any(
+            toml_str in "(\\[[a-z.]{1,6}\\]\n|[a-z_]{1,8} = (\"[^\"\\\\\n]{0,6}\"|-?[0-9]{1,3}|true|\\[\\]|\\{\\}|\\[\"[a-z]{0,3}\"\\])\n){0,10}"
+        ) {
+            let _ = load_entry_toml(&toml_str);
+        }
+    }
 }
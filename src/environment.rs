@@ -0,0 +1,403 @@
+//! Environment variables to use a build
+//!
+//! `llvmenv env [name]` prints them as POSIX shell exports:
+//!
+//! ```shell
+//! eval "$(llvmenv env)"
+//! ```
+//!
+//! In addition to `PATH`, binaries built with the sanitizers of the build need
+//! their runtime libraries and `llvm-symbolizer` of the same version, so
+//!
+//! - `ASAN_SYMBOLIZER_PATH`, `MSAN_SYMBOLIZER_PATH`, `LLVM_SYMBOLIZER_PATH`, and `LLVM_SYMBOLIZER`
+//!   are set to `llvm-symbolizer` of the build, which also comes first in `PATH`
+//! - the compiler-rt library directory, e.g. `${prefix}/lib/clang/7.0.0/lib/linux`,
+//!   is prepended to `LD_LIBRARY_PATH` (`DYLD_LIBRARY_PATH` on macOS)
+//!
+//! `${prefix}/share/man` is also prepended to `MANPATH` so that `man clang` shows the page
+//! of the version in use, and `llvmenv man <tool>` opens it even without the exports.
+//!
+//! `llvmenv exec <name> -- <cmd>...` runs a command with them, and also `LLVM_SYS_<version>_PREFIX`
+//! for the llvm-sys crate and `${prefix}/lib` in `LD_LIBRARY_PATH` for the programs linked with the shared
+//! libraries of LLVM, without switching the build, e.g. to test against several versions in a CI job:
+//!
+//! ```shell
+//! for v in 16.0.6 17.0.6; do llvmenv exec $v -- cargo test; done
+//! ```
+//!
+//! `llvmenv llvm-sys-env [name]` prints only the variable of the llvm-sys crate (used by inkwell) for the build,
+//! with the version given by `llvm-config --version`, e.g. `eval "$(llvmenv llvm-sys-env --export)"`.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build::{parse_version, Build};
+use crate::error::*;
+
+/// Variables pointing at `llvm-symbolizer`, read by sanitizers, LLVM tools, and Rust backtraces
+pub const SYMBOLIZER_VARS: [&str; 4] = [
+    "ASAN_SYMBOLIZER_PATH",
+    "MSAN_SYMBOLIZER_PATH",
+    "LLVM_SYMBOLIZER_PATH",
+    "LLVM_SYMBOLIZER",
+];
+
+/// Variable of the search path of the dynamic loader
+pub const LIBRARY_PATH_VAR: &str = if cfg!(target_os = "macos") {
+    "DYLD_LIBRARY_PATH"
+} else if cfg!(windows) {
+    "PATH"
+} else {
+    "LD_LIBRARY_PATH"
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Set(PathBuf),
+    /// Prepend directories to the current value
+    Prepend(Vec<PathBuf>),
+    /// Prepend directories to the current value, keeping the default of the system
+    /// if unset by an empty entry, e.g. `MANPATH=/path/to/man:`
+    PrependWithDefault(Vec<PathBuf>),
+}
+
+/// Directories of sanitizer runtimes (`libclang_rt.*`) in the build
+pub fn compiler_rt_lib_dirs(build: &Build) -> Vec<PathBuf> {
+    let pattern = build.prefix().join("lib/clang/*/lib/*");
+    let mut dirs: Vec<PathBuf> = glob::glob(&pattern.to_string_lossy())
+        .map(|paths| paths.filter_map(|path| path.ok()).collect())
+        .unwrap_or_default();
+    dirs.retain(|dir| has_runtime(dir));
+    dirs.sort();
+    dirs
+}
+
+fn has_runtime(dir: &Path) -> bool {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .any(|e| e.file_name().to_string_lossy().starts_with("libclang_rt.")),
+        Err(_) => false,
+    }
+}
+
+/// `llvm-symbolizer` of the build
+pub fn symbolizer(build: &Build) -> Option<PathBuf> {
+    let path = build.prefix().join("bin").join(if cfg!(windows) {
+        "llvm-symbolizer.exe"
+    } else {
+        "llvm-symbolizer"
+    });
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Directory of man pages in the build
+pub fn man_dir(build: &Build) -> Option<PathBuf> {
+    let dir = build.prefix().join("share/man");
+    if dir.is_dir() {
+        Some(dir)
+    } else {
+        None
+    }
+}
+
+/// Man page of `tool` in the build, e.g. `${prefix}/share/man/man1/clang.1`
+pub fn man_page(build: &Build, tool: &str) -> Option<PathBuf> {
+    let pattern = man_dir(build)?.join("man*").join(format!("{}.*", tool));
+    glob::glob(&pattern.to_string_lossy())
+        .ok()?
+        .filter_map(|path| path.ok())
+        .min()
+}
+
+/// Variables to be set for the build, in order
+pub fn vars(build: &Build) -> Vec<(String, Value)> {
+    let mut vars = Vec::new();
+    let bin = build.prefix().join("bin");
+    // Do not move /usr/bin to the top of $PATH
+    if build.name() != "system" && bin.is_dir() {
+        vars.push(("PATH".to_string(), Value::Prepend(vec![bin.clone()])));
+    }
+    if let Some(symbolizer) = symbolizer(build) {
+        for var in SYMBOLIZER_VARS.iter() {
+            vars.push((var.to_string(), Value::Set(symbolizer.clone())));
+        }
+    }
+    let rt = compiler_rt_lib_dirs(build);
+    if !rt.is_empty() {
+        vars.push((LIBRARY_PATH_VAR.to_string(), Value::Prepend(rt)));
+    }
+    if build.name() != "system" {
+        if let Some(man) = man_dir(build) {
+            vars.push(("MANPATH".to_string(), Value::PrependWithDefault(vec![man])));
+        }
+    }
+    vars
+}
+
+/// POSIX shell script exporting `vars`
+pub fn export_script(vars: &[(String, Value)]) -> String {
+    let mut script = String::new();
+    for (name, value) in vars {
+        let line = match value {
+            Value::Set(path) => {
+                format!("export {}={}\n", name, shell_quote(&path.to_string_lossy()))
+            }
+            Value::Prepend(dirs) => {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|d| d.to_string_lossy().to_string())
+                    .collect();
+                format!(
+                    "export {0}={1}${{{0}:+:${0}}}\n",
+                    name,
+                    shell_quote(&dirs.join(":"))
+                )
+            }
+            Value::PrependWithDefault(dirs) => {
+                let dirs: Vec<String> = dirs
+                    .iter()
+                    .map(|d| d.to_string_lossy().to_string())
+                    .collect();
+                format!("export {0}={1}:${0}\n", name, shell_quote(&dirs.join(":")))
+            }
+        };
+        script.push_str(&line);
+    }
+    script
+}
+
+/// Value of the variable with the current environment
+pub fn resolve(name: &str, value: &Value) -> Result<OsString> {
+    Ok(match value {
+        Value::Set(path) => path.clone().into_os_string(),
+        Value::Prepend(dirs) => {
+            let current = env::var_os(name).unwrap_or_default();
+            env::join_paths(dirs.iter().cloned().chain(env::split_paths(&current)))?
+        }
+        Value::PrependWithDefault(dirs) => {
+            let current = env::var_os(name).unwrap_or_default();
+            let mut paths = dirs.clone();
+            if current.is_empty() {
+                paths.push(PathBuf::new());
+            } else {
+                paths.extend(env::split_paths(&current));
+            }
+            env::join_paths(paths)?
+        }
+    })
+}
+
+/// Name of the variable of the prefix read by the llvm-sys crate
+///
+/// ```
+/// # use llvmenv::environment::llvm_sys_var;
+/// assert_eq!(llvm_sys_var(17, 0), "LLVM_SYS_170_PREFIX");
+/// ```
+pub fn llvm_sys_var(major: u32, minor: u32) -> String {
+    format!("LLVM_SYS_{}{}_PREFIX", major, minor)
+}
+
+/// `llvm-config` of the build, or the one in `PATH` for the system build
+fn llvm_config(build: &Build) -> Result<PathBuf> {
+    let path = build.prefix().join("bin").join(if cfg!(windows) {
+        "llvm-config.exe"
+    } else {
+        "llvm-config"
+    });
+    if path.is_file() {
+        return Ok(path);
+    }
+    if build.name() == "system" {
+        if let Some(path) = find_tool("llvm-config") {
+            return Ok(path);
+        }
+    }
+    bail!("llvm-config is not installed in build '{}'", build.name())
+}
+
+fn llvm_config_output(llvm_config: &Path, arg: &str) -> Result<String> {
+    let output = Command::new(llvm_config)
+        .arg(arg)
+        .output()
+        .map_err(|e| format_err!("Cannot execute {}: {}", llvm_config.display(), e))?;
+    if !output.status.success() {
+        bail!("{} {} failed", llvm_config.display(), arg);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// `LLVM_SYS_<version>_PREFIX` and the prefix of the build for the llvm-sys crate, by its `llvm-config`
+pub fn llvm_sys_env(build: &Build) -> Result<(String, PathBuf)> {
+    let llvm_config = llvm_config(build)?;
+    let (major, minor, _) = parse_version(&llvm_config_output(&llvm_config, "--version")?)?;
+    let prefix = if build.name() == "system" {
+        PathBuf::from(llvm_config_output(&llvm_config, "--prefix")?)
+    } else {
+        build.prefix().to_path_buf()
+    };
+    Ok((llvm_sys_var(major, minor), prefix))
+}
+
+/// Set `vars` to the command
+pub fn apply(cmd: &mut Command, vars: &[(String, Value)]) -> Result<()> {
+    for (name, value) in vars {
+        cmd.env(name, resolve(name, value)?);
+    }
+    Ok(())
+}
+
+/// Run `cmd` with the variables of the build and `LLVM_SYS_<version>_PREFIX`, and returns its exit code
+/// [vars](fn.vars.html) with `${prefix}/lib` and `LLVM_SYS_<version>_PREFIX` for `llvmenv exec`
+fn exec_vars(build: &Build) -> Result<Vec<(String, Value)>> {
+    let mut vars = vars(build);
+    let lib = build.prefix().join("lib");
+    // Shared libraries of LLVM, e.g. libLLVM.so, for the programs linked with them. DLLs are in bin on Windows.
+    if build.name() != "system" && !cfg!(windows) && lib.is_dir() {
+        match vars.iter_mut().find(|(var, _)| var == LIBRARY_PATH_VAR) {
+            Some((_, Value::Prepend(dirs))) => dirs.insert(0, lib),
+            _ => vars.push((LIBRARY_PATH_VAR.to_string(), Value::Prepend(vec![lib]))),
+        }
+    }
+    match llvm_sys_env(build) {
+        Ok((var, prefix)) => vars.push((var, Value::Set(prefix))),
+        // The system may not have LLVM but other tools
+        Err(_) if build.name() == "system" => {}
+        Err(e) => return Err(e),
+    }
+    Ok(vars)
+}
+
+pub fn exec(build: &Build, cmd: &[String]) -> Result<i32> {
+    let (program, args) = cmd
+        .split_first()
+        .ok_or_else(|| err_msg("No command is given, e.g. `llvmenv exec 17.0.6 -- cargo test`"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    apply(&mut command, &exec_vars(build)?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt as _;
+        // Returns only on failure
+        let e = command.exec();
+        bail!("Cannot execute {}: {}", program, e);
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .map_err(|e| format_err!("Cannot execute {}: {}", program, e))?;
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_llvm_sys_env() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("llvm-18");
+        let build = Build::from_path(&prefix);
+        assert!(llvm_sys_env(&build).is_err());
+
+        fs::create_dir_all(prefix.join("bin"))?;
+        let llvm_config = prefix.join("bin/llvm-config");
+        fs::write(&llvm_config, "#!/bin/sh\necho 18.1.8\n")?;
+        fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755))?;
+        assert_eq!(
+            llvm_sys_env(&build)?,
+            ("LLVM_SYS_181_PREFIX".to_string(), prefix)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_vars() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("7.0.0");
+        let rt = prefix.join("lib/clang/7.0.0/lib/linux");
+        fs::create_dir_all(&rt)?;
+        fs::create_dir_all(prefix.join("lib/clang/7.0.0/include"))?;
+        fs::write(rt.join("libclang_rt.asan-x86_64.so"), "")?;
+        fs::create_dir_all(prefix.join("bin"))?;
+        let symbolizer = prefix.join("bin/llvm-symbolizer");
+        fs::write(&symbolizer, "")?;
+
+        let man = prefix.join("share/man/man1");
+        fs::create_dir_all(&man)?;
+        fs::write(man.join("clang.1"), "")?;
+
+        let build = Build::from_path(&prefix);
+        assert_eq!(compiler_rt_lib_dirs(&build), vec![rt.clone()]);
+        assert_eq!(man_page(&build, "clang"), Some(man.join("clang.1")));
+        assert_eq!(man_page(&build, "lld"), None);
+        let vars = vars(&build);
+        assert!(vars.contains(&(
+            "ASAN_SYMBOLIZER_PATH".into(),
+            Value::Set(symbolizer.clone())
+        )));
+        assert!(vars.contains(&(LIBRARY_PATH_VAR.into(), Value::Prepend(vec![rt]))));
+        assert!(vars.contains(&(
+            "MANPATH".into(),
+            Value::PrependWithDefault(vec![prefix.join("share/man")])
+        )));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_vars() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("llvm-18");
+        let rt = prefix.join("lib/clang/18/lib/linux");
+        fs::create_dir_all(&rt)?;
+        fs::write(rt.join("libclang_rt.asan-x86_64.so"), "")?;
+        fs::create_dir_all(prefix.join("bin"))?;
+        let llvm_config = prefix.join("bin/llvm-config");
+        fs::write(&llvm_config, "#!/bin/sh\necho 18.1.8\n")?;
+        fs::set_permissions(&llvm_config, fs::Permissions::from_mode(0o755))?;
+
+        let build = Build::from_path(&prefix);
+        let vars = exec_vars(&build)?;
+        assert!(vars.contains(&(
+            LIBRARY_PATH_VAR.into(),
+            Value::Prepend(vec![prefix.join("lib"), rt])
+        )));
+        assert!(vars.contains(&("LLVM_SYS_181_PREFIX".into(), Value::Set(prefix.clone()))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_script() {
+        let vars = vec![
+            (
+                "ASAN_SYMBOLIZER_PATH".into(),
+                Value::Set("/p/bin/llvm-symbolizer".into()),
+            ),
+            (
+                "LD_LIBRARY_PATH".into(),
+                Value::Prepend(vec!["/p/lib/a".into(), "/p/lib/b".into()]),
+            ),
+            (
+                "MANPATH".into(),
+                Value::PrependWithDefault(vec!["/p/share/man".into()]),
+            ),
+        ];
+        assert_eq!(
+            export_script(&vars),
+            "export ASAN_SYMBOLIZER_PATH='/p/bin/llvm-symbolizer'\nexport LD_LIBRARY_PATH='/p/lib/a:/p/lib/b'${LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}\nexport MANPATH='/p/share/man':$MANPATH\n"
+        );
+    }
+}
@@ -1,22 +1,483 @@
-use failure::{Error, Fail};
+//! Errors of llvmenv
+//!
+//! Functions of llvmenv return [LlvmEnvError](./enum.LlvmEnvError.html), whose variants tell
+//! the classes of failures apart, e.g. a download which may succeed by retrying, or a build failure
+//! whose details are in the build log. `llvmenv` command exits with the code of the error:
+//!
+//! | Code | Error                                                              |
+//! |:-----|:-------------------------------------------------------------------|
+//! | 1    | other errors                                                       |
+//! | 2    | unknown entry or build                                             |
+//! | 3    | download, or HTTP request to GitHub or remotes                     |
+//! | 4    | checksum mismatch of a downloaded file                             |
+//! | 5    | configure, build, or install of an entry (see the build log)       |
+//! | 124  | killed by `--timeout`                                              |
+//! | 130  | interrupted by Ctrl-C while removing or copying a tree             |
+//! | 127  | required tool is not found                                         |
+
+use log::info;
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
 use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::interrupt;
+use crate::progress::Phase;
 
-pub type Result<T> = ::std::result::Result<T, Error>;
+/// Exit code when `build-entry --timeout` exceeded, same as timeout(1)
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+pub type Result<T> = ::std::result::Result<T, LlvmEnvError>;
 pub type CommandResult = ::std::result::Result<(), CommandError>;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
+pub enum LlvmEnvError {
+    #[error("Download of {url} failed: {source}")]
+    DownloadError {
+        url: String,
+        #[source]
+        source: Box<LlvmEnvError>,
+    },
+    #[error("Downloaded {} does not match: expected {expected}, but {actual}", path.display())]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("Failed to {stage} (see {}): {source}", log_path.display())]
+    BuildFailed {
+        stage: Phase,
+        log_path: PathBuf,
+        #[source]
+        source: CommandError,
+    },
+    #[error("No entries are found: {name}")]
+    UnknownEntry { name: String },
+    #[error("Build '{name}' does not exist")]
+    UnknownBuild { name: String },
+    /// Ctrl-C while an operation of [tree](../tree/index.html) runs
+    #[error("Interrupted")]
+    Interrupted,
+    #[error(transparent)]
+    Command(#[from] CommandError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl LlvmEnvError {
+    /// Exit code of `llvmenv` command, see [module level doc](index.html)
+    ///
+    /// ```
+    /// # use llvmenv::error::*;
+    /// assert_eq!(LlvmEnvError::UnknownEntry { name: "8.0.0".into() }.exit_code(), 2);
+    /// assert_eq!(err_msg("other").exit_code(), 1);
+    /// ```
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LlvmEnvError::UnknownEntry { .. } | LlvmEnvError::UnknownBuild { .. } => 2,
+            LlvmEnvError::DownloadError { .. } | LlvmEnvError::Http(_) => 3,
+            LlvmEnvError::ChecksumMismatch { .. } => 4,
+            LlvmEnvError::BuildFailed { .. } => 5,
+            LlvmEnvError::Command(CommandError::Timeout { .. }) => TIMEOUT_EXIT_CODE,
+            LlvmEnvError::Command(CommandError::ToolNotFound { .. }) => 127,
+            LlvmEnvError::Interrupted => 130,
+            _ => 1,
+        }
+    }
+
+    /// Whether external commands are killed by the deadline of [set_timeout](fn.set_timeout.html)
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, LlvmEnvError::Command(CommandError::Timeout { .. }))
+    }
+}
+
+/// Errors of other libraries which callers do not tell apart, kept as their messages
+macro_rules! from_other {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for LlvmEnvError {
+                fn from(e: $ty) -> Self {
+                    LlvmEnvError::Other(e.to_string())
+                }
+            }
+        )*
+    };
+}
+
+from_other!(
+    fmt::Error,
+    std::str::Utf8Error,
+    env::JoinPathsError,
+    fs_extra::error::Error,
+    glob::PatternError,
+    shellexpand::LookupError<env::VarError>,
+    url::ParseError,
+    zip::result::ZipError
+);
+
+/// Error of a message, for failures which callers do not tell apart
+pub fn err_msg<D: fmt::Display>(msg: D) -> LlvmEnvError {
+    LlvmEnvError::Other(msg.to_string())
+}
+
+/// [LlvmEnvError::Other](enum.LlvmEnvError.html#variant.Other) of a formatted message
+macro_rules! format_err {
+    ($($arg:tt)*) => {
+        $crate::error::LlvmEnvError::Other(format!($($arg)*))
+    };
+}
+
+/// Return [format_err](macro.format_err.html) as an error
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::error::format_err!($($arg)*))
+    };
+}
+
+pub(crate) use {bail, format_err};
+
+#[derive(Debug, Error)]
 pub enum CommandError {
-    #[fail(display = "Exit with error-code({}): {}", errno, cmd)]
+    #[error("Exit with error-code({errno}): {cmd}")]
     ErrorCode { errno: i32, cmd: String },
-    #[fail(display = "External command not found: {}", cmd)]
+    #[error("External command not found: {cmd}")]
     CommandNotFound { cmd: String },
-    #[fail(display = "Terminated by signal: {}", cmd)]
+    #[error("Terminated by signal: {cmd}")]
     TerminatedBySignal { cmd: String },
+    #[error("Required tool '{tool}' is not found. {hint}")]
+    ToolNotFound { tool: String, hint: String },
+    #[error("Killed by timeout ({timeout:?}): {cmd}")]
+    Timeout { timeout: Duration, cmd: String },
+}
+
+static DEADLINE: Mutex<Option<(Instant, Duration)>> = Mutex::new(None);
+
+/// Kill external commands (and their child processes) running after `timeout` from now,
+/// not counting the time they are [paused](fn.set_paused.html)
+///
+/// ```
+/// # use llvmenv::error::*;
+/// # use std::{process::Command, time::Duration};
+/// set_timeout(Duration::from_secs(1));
+/// # #[cfg(unix)]
+/// match Command::new("sleep").arg("10").check_run() {
+///     Err(CommandError::Timeout { .. }) => {}
+///     res => panic!("Unexpected result: {:?}", res),
+/// }
+/// ```
+pub fn set_timeout(timeout: Duration) {
+    *DEADLINE.lock().unwrap() = Some((Instant::now() + timeout, timeout));
+}
+
+fn deadline() -> Option<(Instant, Duration)> {
+    *DEADLINE.lock().unwrap()
+}
+
+/// Postpone the deadline by the time the commands were paused
+fn extend_deadline(paused: Duration) {
+    if let Some((deadline, _)) = DEADLINE.lock().unwrap().as_mut() {
+        *deadline += paused;
+    }
+}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Print external commands run by [CommandExt](trait.CommandExt.html) (and the downloads of archives)
+/// as lines of a shell script instead of running them, for `llvmenv --dry-run`
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::SeqCst);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+static SUSPENDABLE: AtomicBool = AtomicBool::new(false);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Run external commands in their own process groups to [pause](fn.set_paused.html) them with their children
+pub fn set_suspendable(suspendable: bool) {
+    SUSPENDABLE.store(suspendable, Ordering::SeqCst);
+}
+
+/// Stop the external commands running by `check_run_lines` or `check_run_progress` (and their children)
+/// until `set_paused(false)`. It works on Unix, with [suspendable](fn.set_suspendable.html) commands.
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+/// Quote `s` for POSIX shells only if it contains special characters
+pub(crate) fn quote_if_needed(s: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !s.is_empty() && s.chars().all(plain) {
+        s.into()
+    } else {
+        shell_quote(s)
+    }
+}
+
+/// Shell line running `command` with its directory and environment variables
+///
+/// ```
+/// # use llvmenv::error::command_line;
+/// # use std::process::Command;
+/// let mut cmake = Command::new("cmake");
+/// cmake.args(["-G", "Unix Makefiles", "../llvm"]).current_dir("/tmp/build").env("CC", "clang");
+/// assert_eq!(command_line(&cmake), "(cd /tmp/build && CC=clang cmake -G 'Unix Makefiles' ../llvm)");
+/// assert_eq!(command_line(Command::new("git").arg("pull")), "git pull");
+/// ```
+pub fn command_line(command: &process::Command) -> String {
+    let mut words: Vec<String> = command
+        .get_envs()
+        .filter_map(|(key, value)| {
+            let value = value?;
+            Some(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                quote_if_needed(&value.to_string_lossy())
+            ))
+        })
+        .collect();
+    words.push(quote_if_needed(&command.get_program().to_string_lossy()));
+    words.extend(
+        command
+            .get_args()
+            .map(|arg| quote_if_needed(&arg.to_string_lossy())),
+    );
+    let line = words.join(" ");
+    match command.get_current_dir() {
+        Some(dir) => format!(
+            "(cd {} && {})",
+            quote_if_needed(&dir.to_string_lossy()),
+            line
+        ),
+        None => line,
+    }
+}
+
+/// Print the command instead of running it if [dry run](fn.set_dry_run.html)
+fn dry_run(command: &process::Command) -> bool {
+    if is_dry_run() {
+        println!("{}", command_line(command));
+    }
+    is_dry_run()
+}
+
+/// Parse duration like `90`, `90s`, `30m`, `2h`, or `1h30m`
+///
+/// ```
+/// # use llvmenv::error::parse_duration;
+/// # use std::time::Duration;
+/// assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+/// assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+/// assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(90 * 60));
+/// assert!(parse_duration("1x").is_err());
+/// assert!(parse_duration("").is_err());
+/// ```
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    if let Ok(secs) = s.parse() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let mut secs = 0;
+    let mut num = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format_err!("Invalid unit '{}' in duration: {}", c, s)),
+        };
+        let n: u64 = num
+            .parse()
+            .map_err(|_| format_err!("Invalid duration: {}", s))?;
+        secs += n * unit;
+        num.clear();
+    }
+    if !num.is_empty() || s.is_empty() {
+        bail!("Invalid duration: {}", s);
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// How to install external tools used in llvmenv
+fn install_hint(tool: &str) -> String {
+    match tool {
+        "git" | "svn" | "tar" | "dpkg-deb" | "rpmbuild" | "ssh" | "rsync" | "ccache" => format!("Please install {} using your package manager.", tool),
+        "cmake" => "Please install CMake (https://cmake.org/download/).".into(),
+        "ninja" => "Please install Ninja (https://ninja-build.org/), or use another builder by `-G` option.".into(),
+        "make" => "Please install GNU Make, or use another builder by `-G` option.".into(),
+        "wix" => "Please install WiX Toolset v4 (https://wixtoolset.org/) by `dotnet tool install --global wix`.".into(),
+        "secret-tool" => "Please install secret-tool of libsecret (e.g. libsecret-tools package).".into(),
+        "sccache" => "Please install sccache (https://github.com/mozilla/sccache), e.g. by `cargo install sccache`.".into(),
+        "pixz" => "Please install pixz (https://github.com/vasi/pixz).".into(),
+        _ => "Please install it and make it available in $PATH.".into(),
+    }
+}
+
+/// Quote for POSIX shells
+///
+/// ```
+/// # use llvmenv::error::shell_quote;
+/// assert_eq!(shell_quote("it's"), r"'it'\''s'");
+/// ```
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Whether stdin is a terminal
+pub fn is_tty() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Search an executable in $PATH
+pub fn find_tool(tool: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(tool))
+        .find(|path| path.is_file())
+}
+
+/// Check an external tool exists in $PATH, and returns the first line of `{tool} --version`
+pub fn check_tool(tool: &str) -> ::std::result::Result<String, CommandError> {
+    let output = process::Command::new(tool)
+        .arg("--version")
+        .output()
+        .map_err(|_| CommandError::ToolNotFound {
+            tool: tool.into(),
+            hint: install_hint(tool),
+        })?;
+    let version = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_string();
+    info!("Found {}: {}", tool, version);
+    Ok(version)
 }
 
 pub trait CommandExt {
     fn silent(&mut self) -> &mut Self;
     fn check_run(&mut self) -> CommandResult;
+    /// Run with passing each line of stdout and stderr to `f`. Lines are also echoed to the terminal.
+    fn check_run_lines<F: FnMut(&str)>(&mut self, f: F) -> CommandResult;
+    /// Run with passing each line of stdout and stderr, also split by `\r` for the progress of tools like git, to `f`.
+    /// Only lines for which `f` returns false are echoed to the terminal.
+    fn check_run_progress<F: FnMut(&str) -> bool>(&mut self, f: F) -> CommandResult;
+}
+
+fn check_status(st: process::ExitStatus, cmd: String) -> CommandResult {
+    match st.code() {
+        Some(errno) => {
+            if errno != 0 {
+                Err(CommandError::ErrorCode { errno, cmd })
+            } else {
+                Ok(())
+            }
+        }
+        None => Err(CommandError::TerminatedBySignal { cmd }),
+    }
+}
+
+fn spawn_error(command: &process::Command, e: io::Error, cmd: &str) -> CommandError {
+    if e.kind() == io::ErrorKind::NotFound {
+        let tool = command.get_program().to_string_lossy().to_string();
+        let hint = install_hint(&tool);
+        CommandError::ToolNotFound { tool, hint }
+    } else {
+        CommandError::CommandNotFound { cmd: cmd.into() }
+    }
+}
+
+fn spawn(
+    command: &mut process::Command,
+    cmd: &str,
+) -> ::std::result::Result<(process::Child, Option<interrupt::Forward>), CommandError> {
+    let own_group = deadline().is_some() || SUSPENDABLE.load(Ordering::SeqCst);
+    if own_group {
+        // Use a new process group to kill or stop the command with its children
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt as UnixCommandExt;
+            command.process_group(0);
+        }
+        // Git prompting in the background group would be stopped by SIGTTIN
+        if env::var_os("GIT_TERMINAL_PROMPT").is_none() {
+            command.env("GIT_TERMINAL_PROMPT", "0");
+        }
+    }
+    let child = command.spawn().map_err(|e| spawn_error(command, e, cmd))?;
+    // Ctrl-C reaches only the foreground group of llvmenv
+    let forward = if own_group {
+        Some(interrupt::forward(child.id()))
+    } else {
+        None
+    };
+    Ok((child, forward))
+}
+
+fn kill(child: &mut process::Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+/// Stop or continue the process group of the child process
+fn suspend(child: &process::Child, stop: bool) {
+    #[cfg(unix)]
+    unsafe {
+        let signal = if stop { libc::SIGSTOP } else { libc::SIGCONT };
+        libc::kill(-(child.id() as i32), signal);
+    }
+    #[cfg(not(unix))]
+    let _ = (child, stop);
+}
+
+/// Wait the child process at most until the deadline
+fn wait(child: &mut process::Child, cmd: String) -> CommandResult {
+    let not_found = |_| CommandError::CommandNotFound { cmd: cmd.clone() };
+    let (deadline, timeout) = match deadline() {
+        Some(deadline) => deadline,
+        None => return check_status(child.wait().map_err(not_found)?, cmd),
+    };
+    loop {
+        if let Some(st) = child.try_wait().map_err(not_found)? {
+            return check_status(st, cmd);
+        }
+        if Instant::now() >= deadline {
+            kill(child);
+            let _ = child.wait();
+            return Err(CommandError::Timeout { timeout, cmd });
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
 }
 
 impl CommandExt for process::Command {
@@ -25,19 +486,158 @@ impl CommandExt for process::Command {
             .stderr(process::Stdio::null())
     }
     fn check_run(&mut self) -> CommandResult {
+        if dry_run(self) {
+            return Ok(());
+        }
         let cmd = format!("{:?}", self);
-        let st = self
-            .status()
-            .map_err(|_| CommandError::CommandNotFound { cmd: cmd.clone() })?;
-        match st.code() {
-            Some(errno) => {
-                if errno != 0 {
-                    Err(CommandError::ErrorCode { errno, cmd })
-                } else {
-                    Ok(())
+        let (mut child, _guard) = spawn(self, &cmd)?;
+        wait(&mut child, cmd)
+    }
+    fn check_run_lines<F: FnMut(&str)>(&mut self, mut f: F) -> CommandResult {
+        run_lines(self, false, |line, is_stderr| {
+            echo(line, is_stderr);
+            f(line);
+        })
+    }
+    fn check_run_progress<F: FnMut(&str) -> bool>(&mut self, mut f: F) -> CommandResult {
+        run_lines(self, true, |line, is_stderr| {
+            if !f(line) {
+                echo(line, is_stderr);
+            }
+        })
+    }
+}
+
+fn echo(line: &str, is_stderr: bool) {
+    if is_stderr {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Run `command` with passing each line of stdout and stderr to `f` with the flag whether it is stderr
+fn run_lines<F: FnMut(&str, bool)>(
+    command: &mut process::Command,
+    split_cr: bool,
+    mut f: F,
+) -> CommandResult {
+    if dry_run(command) {
+        return Ok(());
+    }
+    let cmd = format!("{:?}", command);
+    let (mut child, _guard) = spawn(
+        command
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped()),
+        &cmd,
+    )?;
+    let (tx, rx) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, tx.clone(), false, split_cr);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, tx, true, split_cr);
+    }
+    let suspendable = SUSPENDABLE.load(Ordering::SeqCst);
+    // Since when the command is stopped
+    let mut stopped: Option<Instant> = None;
+    loop {
+        if suspendable && is_paused() != stopped.is_some() {
+            match stopped.take() {
+                Some(since) => extend_deadline(since.elapsed()),
+                None => stopped = Some(Instant::now()),
+            }
+            suspend(&child, stopped.is_some());
+        }
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok((line, is_stderr)) => f(&line, is_stderr),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                if let Some((deadline, _)) = deadline() {
+                    if stopped.is_none() && Instant::now() >= deadline {
+                        break;
+                    }
                 }
             }
-            None => Err(CommandError::TerminatedBySignal { cmd }),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    if let Some(since) = stopped {
+        extend_deadline(since.elapsed());
+        suspend(&child, false);
+    }
+    wait(&mut child, cmd)
+}
+
+/// Send each line read from `r` with the flag whether it is stderr.
+/// Lines are also split by `\r` if `split_cr`, and then empty lines are skipped.
+fn forward_lines<R: io::Read + Send + 'static>(
+    r: R,
+    tx: mpsc::Sender<(String, bool)>,
+    is_stderr: bool,
+    split_cr: bool,
+) {
+    thread::spawn(move || {
+        let mut line = Vec::new();
+        let send = |line: &mut Vec<u8>| {
+            let s = String::from_utf8_lossy(line)
+                .trim_end_matches('\r')
+                .to_string();
+            line.clear();
+            if split_cr && s.is_empty() {
+                return true;
+            }
+            tx.send((s, is_stderr)).is_ok()
+        };
+        for byte in io::BufReader::new(r).bytes() {
+            let byte = match byte {
+                Ok(byte) => byte,
+                Err(_) => break,
+            };
+            if byte == b'\n' || (split_cr && byte == b'\r') {
+                if !send(&mut line) {
+                    return;
+                }
+            } else {
+                line.push(byte);
+            }
+        }
+        if !line.is_empty() {
+            send(&mut line);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_not_found() {
+        let tool = "llvmenv-no-such-tool";
+        match check_tool(tool) {
+            Err(CommandError::ToolNotFound { tool: t, .. }) => assert_eq!(t, tool),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+        match process::Command::new(tool).check_run() {
+            Err(CommandError::ToolNotFound { tool: t, .. }) => assert_eq!(t, tool),
+            e => panic!("Unexpected result: {:?}", e),
+        }
+    }
+
+    #[cfg(unix)]
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(64))]
+
+        /// Quoted paths, e.g. of home directories with spaces or non-ASCII characters, reach the shell as is
+        #[test]
+        fn test_shell_quote(s in "[^\\x00]*") {
+            let output = process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", shell_quote(&s)))
+                .output()
+                .unwrap();
+            proptest::prop_assert_eq!(String::from_utf8(output.stdout).unwrap(), s);
         }
     }
 }
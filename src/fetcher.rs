@@ -0,0 +1,329 @@
+//! Fetchers of LLVM/Clang sources, including external plugins
+//!
+//! Built-in [Resource](../resource/enum.Resource.html)s (Git, SVN, and Tar archives) are used
+//! for `http`, `https`, `git`, `ssh`, `svn`, and `file` URLs, and an external plugin for the other schemes.
+//! A plugin is an executable named `llvmenv-fetch-<scheme>` in `$PATH`, e.g.
+//!
+//! ```toml
+//! [internal-llvm]
+//! url = "artifactory://artifacts.example.com/llvm/llvm-7.0.0.src.tar.xz"
+//! ```
+//!
+//! is fetched by `llvmenv-fetch-artifactory`. `<scheme>+https://...` URLs also use the plugin
+//! of `<scheme>`, for hosts which are reachable by HTTPS but require another protocol.
+//!
+//! Protocol
+//! ---------
+//! llvmenv runs the plugin with a JSON request written into its stdin:
+//!
+//! ```json
+//! {"version": 1, "action": "download", "url": "artifactory://...", "branch": null, "tag": null, "dest": "/path/to/src"}
+//! ```
+//!
+//! where `action` is `download` (into an empty `dest`, moved to the source directory on success)
+//! or `update` (of `dest` downloaded before).
+//! The plugin reports by JSON lines in its stdout, and stderr is shown to the user as is:
+//!
+//! ```json
+//! {"type": "progress", "received": 1024, "total": 4096}
+//! {"type": "error", "message": "401 Unauthorized"}
+//! {"type": "done"}
+//! ```
+//!
+//! The fetch is regarded as succeeded only if the plugin exits with 0 after `done`.
+//! Plugins are not run by `build-entry --offline`, which fails as for the other downloads.
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use url::Url;
+
+use crate::error::*;
+use crate::network;
+use crate::progress::{observer, run_phase, Phase};
+use crate::resource::Resource;
+use crate::tree;
+
+/// Prefix of the executable names of plugins
+pub const PLUGIN_PREFIX: &str = "llvmenv-fetch-";
+/// Version of the protocol sent in requests
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Downloads and updates a source
+pub trait Fetcher: fmt::Debug {
+    /// URL of the source
+    fn url(&self) -> &str;
+    /// Download the source into the directory `dest`
+    fn download(&self, dest: &Path) -> Result<()>;
+    /// Update the source downloaded into `dest` before
+    fn update(&self, dest: &Path) -> Result<()>;
+}
+
+impl Fetcher for Resource {
+    fn url(&self) -> &str {
+        Resource::url(self)
+    }
+
+    fn download(&self, dest: &Path) -> Result<()> {
+        Resource::download(self, dest)
+    }
+
+    fn update(&self, dest: &Path) -> Result<()> {
+        Resource::update(self, dest)
+    }
+}
+
+/// Fetcher for `url`, a built-in [Resource](../resource/enum.Resource.html) for the built-in schemes,
+/// and a plugin for the others
+pub fn from_url(
+    url: &str,
+    branch: Option<String>,
+    tag: Option<String>,
+) -> Result<Box<dyn Fetcher>> {
+    if let Some(scheme) = plugin_scheme(url).filter(|scheme| !is_builtin_scheme(scheme)) {
+        if let Some(plugin) = Plugin::find(&scheme, url, branch.clone(), tag.clone()) {
+            info!("Use fetcher plugin: {}", plugin.exe.display());
+            return Ok(Box::new(plugin));
+        }
+        bail!(
+            "No fetcher for '{}' scheme: {}{} is not found in $PATH",
+            scheme,
+            PLUGIN_PREFIX,
+            scheme
+        );
+    }
+    Ok(Box::new(Resource::from_url(url, branch, tag)?))
+}
+
+fn is_builtin_scheme(scheme: &str) -> bool {
+    ["http", "https", "git", "ssh", "svn", "file"].contains(&scheme)
+}
+
+/// Scheme whose plugin is looked up, i.e. `<scheme>` of `<scheme>+https://...`
+///
+/// ```
+/// # use llvmenv::fetcher::plugin_scheme;
+/// assert_eq!(plugin_scheme("artifactory://example.com/llvm.tar.xz").unwrap(), "artifactory");
+/// assert_eq!(plugin_scheme("corp+https://git.example.com/llvm").unwrap(), "corp");
+/// assert_eq!(plugin_scheme("https://github.com/llvm-mirror/llvm").unwrap(), "https");
+/// assert!(plugin_scheme("/path/to/llvm").is_none());
+/// ```
+pub fn plugin_scheme(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let scheme = url.scheme();
+    Some(scheme.split('+').next().unwrap_or(scheme).to_string())
+}
+
+/// External fetcher `llvmenv-fetch-<scheme>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plugin {
+    pub exe: PathBuf,
+    pub url: String,
+    pub branch: Option<String>,
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Request<'a> {
+    version: u32,
+    action: &'a str,
+    url: &'a str,
+    branch: Option<&'a str>,
+    tag: Option<&'a str>,
+    dest: &'a Path,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message {
+    Progress { received: u64, total: Option<u64> },
+    Error { message: String },
+    Done,
+}
+
+impl Plugin {
+    /// Plugin for `scheme` in `$PATH`
+    pub fn find(
+        scheme: &str,
+        url: &str,
+        branch: Option<String>,
+        tag: Option<String>,
+    ) -> Option<Self> {
+        let name = format!("{}{}", PLUGIN_PREFIX, scheme);
+        let exe = find_tool(&name).or_else(|| find_tool(&format!("{}.exe", name)))?;
+        Some(Plugin {
+            exe,
+            url: url.into(),
+            branch,
+            tag,
+        })
+    }
+
+    /// Run the plugin, which is regarded as using the network and not run by `--offline`
+    fn run(&self, action: &str, dest: &Path) -> Result<()> {
+        network::check_online()?;
+        let request = serde_json::to_string(&Request {
+            version: PROTOCOL_VERSION,
+            action,
+            url: &self.url,
+            branch: self.branch.as_deref(),
+            tag: self.tag.as_deref(),
+            dest,
+        })?;
+        if is_dry_run() {
+            println!(
+                "echo {} | {}",
+                shell_quote(&request),
+                quote_if_needed(&self.exe.display().to_string())
+            );
+            return Ok(());
+        }
+        let mut child = Command::new(&self.exe)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        {
+            let mut stdin = child.stdin.take().expect("stdin is piped");
+            writeln!(stdin, "{}", request)?;
+        }
+        let observer = observer();
+        let mut done = false;
+        let mut error = None;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(Message::Progress { received, total }) => observer.on_bytes(received, total),
+                Ok(Message::Error { message }) => error = Some(message),
+                Ok(Message::Done) => done = true,
+                Err(e) => warn!(
+                    "Invalid message from {}: {} ({})",
+                    self.exe.display(),
+                    line,
+                    e
+                ),
+            }
+        }
+        let status = child.wait()?;
+        if let Some(message) = error {
+            bail!("{} failed: {}", self.exe.display(), message);
+        }
+        if !status.success() || !done {
+            return Err(format_err!(
+                "{} exited with {} without reporting completion",
+                self.exe.display(),
+                status
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Fetcher for Plugin {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Download into a hidden sibling of `dest` renamed on success, not to leave a partial source at `dest`
+    fn download(&self, dest: &Path) -> Result<()> {
+        run_phase(Phase::Download, &self.url, || {
+            if is_dry_run() {
+                println!("mkdir -p {}", quote_if_needed(&dest.display().to_string()));
+                return self.run("download", dest);
+            }
+            let part = tree::hidden_sibling(dest, "fetching");
+            fs::create_dir_all(&part)?;
+            match self.run("download", &part) {
+                Ok(()) => Ok(fs::rename(&part, dest)?),
+                Err(e) => {
+                    if let Err(e) = tree::remove(&part) {
+                        warn!("Cannot remove {}: {}", part.display(), e);
+                    }
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    fn update(&self, dest: &Path) -> Result<()> {
+        run_phase(Phase::Update, &self.url, || self.run("update", dest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() -> Result<()> {
+        let msg: Message =
+            serde_json::from_str(r#"{"type": "progress", "received": 10, "total": null}"#)?;
+        assert_eq!(
+            msg,
+            Message::Progress {
+                received: 10,
+                total: None
+            }
+        );
+        let msg: Message = serde_json::from_str(r#"{"type": "done"}"#)?;
+        assert_eq!(msg, Message::Done);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plugin() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let exe = tmp_dir.path().join("llvmenv-fetch-test");
+        fs::write(
+            &exe,
+            "#!/bin/sh\nread req\necho \"$req\" > \"$0.req\"\necho '{\"type\": \"progress\", \"received\": 1}'\necho '{\"type\": \"done\"}'\n",
+        )?;
+        fs::set_permissions(&exe, fs::Permissions::from_mode(0o755))?;
+        let plugin = Plugin {
+            exe: exe.clone(),
+            url: "test://example.com/llvm".into(),
+            branch: None,
+            tag: None,
+        };
+        let dest = tmp_dir.path().join("src");
+        plugin.download(&dest)?;
+        let req = fs::read_to_string(tmp_dir.path().join("llvmenv-fetch-test.req"))?;
+        assert!(req.contains(r#""action":"download""#));
+        assert!(req.contains(r#""url":"test://example.com/llvm""#));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plugin_failure() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let exe = tmp_dir.path().join("llvmenv-fetch-test");
+        fs::write(
+            &exe,
+            "#!/bin/sh\nread req\necho '{\"type\": \"error\", \"message\": \"401 Unauthorized\"}'\nexit 1\n",
+        )?;
+        fs::set_permissions(&exe, fs::Permissions::from_mode(0o755))?;
+        let plugin = Plugin {
+            exe,
+            url: "test://example.com/llvm".into(),
+            branch: None,
+            tag: None,
+        };
+        let dest = tmp_dir.path().join("src");
+        assert!(plugin.download(&dest).is_err());
+        assert!(!dest.exists());
+        assert_eq!(fs::read_dir(tmp_dir.path())?.count(), 1);
+        Ok(())
+    }
+}
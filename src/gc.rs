@@ -0,0 +1,216 @@
+//! Garbage collection of the cache and data directories
+//!
+//! `llvmenv gc` lists the files which are no longer used with their sizes, and removes them with `--yes`:
+//!
+//! - sources in `${cache}/src` and build trees in `${cache}/build` (and on [tmpfs](../tmpfs/index.html)) of entries which are neither
+//!   in `entry.toml` nor built-in, nor installed (e.g. by `llvmenv sync`)
+//! - temporal directories and partial files (`*.part`) of interrupted downloads in `${cache}/download`, untouched for an hour
+//! - directories in the data directory which are not builds (without `bin/`), e.g. of failed installation,
+//!   untouched for an hour not to remove the one being installed, and the rest of builds whose removal is interrupted (see [tree](../tree/index.html))
+//! - archives created by `llvmenv archive` whose build no longer exists, and partial archives of interrupted ones
+
+use log::info;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::build;
+use crate::config::*;
+use crate::entry::{self, BuildType};
+use crate::error::Result;
+use crate::shared;
+use crate::snapshot;
+use crate::tmpfs;
+use crate::tree;
+
+/// Downloads and installations modified in this duration may be in progress
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Directories in the data directory other than builds
+const DATA_DIRS: [&str; 2] = ["reports", "shims"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Source,
+    BuildTree,
+    Download,
+    Prefix,
+    Archive,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Kind::Source => "source",
+            Kind::BuildTree => "build tree",
+            Kind::Download => "download",
+            Kind::Prefix => "broken build",
+            Kind::Archive => "archive",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Garbage {
+    pub kind: Kind,
+    pub path: PathBuf,
+    /// Total size of files in bytes
+    pub size: u64,
+}
+
+/// Size in bytes with a binary unit
+///
+/// ```
+/// # use llvmenv::gc::human_size;
+/// assert_eq!(human_size(512), "512 B");
+/// assert_eq!(human_size(3 * 1024 * 1024 / 2), "1.5 MB");
+/// assert_eq!(human_size(40 * 1024 * 1024 * 1024), "40.0 GB");
+/// ```
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Total size and the last modification time of files under `path`, without following symlinks
+fn usage(path: &Path) -> Result<(u64, SystemTime)> {
+    let meta = fs::symlink_metadata(path)?;
+    let mut size = meta.len();
+    let mut modified = meta.modified()?;
+    if meta.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let (s, m) = usage(&entry?.path())?;
+            size += s;
+            modified = modified.max(m);
+        }
+    }
+    Ok((size, modified))
+}
+
+/// Children of `dir` whose names are not in `used`
+fn orphans(dir: &Path, used: &BTreeSet<String>) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !used.contains(&name) {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Scan the cache and data directories, see [module level doc](index.html)
+pub fn scan() -> Result<Vec<Garbage>> {
+    let builds = build::builds()?;
+    let mut used: BTreeSet<String> = entry::load_entries()?
+        .iter()
+        .map(|e| e.name().to_string())
+        .collect();
+    used.extend(builds.iter().map(|b| b.entry_name().to_string()));
+
+    let mut found = Vec::new();
+    for path in orphans(&src_cache_dir()?, &used)? {
+        found.push((Kind::Source, path));
+    }
+    // Build trees of the profiles other than Release, e.g. `17.0.6-debug`
+    let trees: BTreeSet<String> = used
+        .iter()
+        .flat_map(|name| {
+            BuildType::ALL
+                .iter()
+                .map(move |t| format!("{}{}", name, t.suffix()))
+        })
+        .collect();
+    let mut tree_dirs = vec![build_cache_dir()?];
+    tree_dirs.extend(tmpfs::root());
+    for dir in &tree_dirs {
+        for path in orphans(dir, &trees)? {
+            found.push((Kind::BuildTree, path));
+        }
+    }
+    let now = SystemTime::now();
+    for path in orphans(&download_cache_dir()?, &BTreeSet::new())? {
+        let (_, modified) = usage(&path)?;
+        if now.duration_since(modified).unwrap_or_default() >= MIN_AGE {
+            found.push((Kind::Download, path));
+        }
+    }
+    let names: BTreeSet<String> = builds.iter().map(|b| b.name().to_string()).collect();
+    let data = data_dir()?;
+    let mut used: BTreeSet<String> = DATA_DIRS.iter().map(|d| d.to_string()).collect();
+    used.extend(names.iter().cloned());
+    for path in orphans(&data, &used)? {
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if path.is_dir() {
+            let (_, modified) = usage(&path)?;
+            if now.duration_since(modified).unwrap_or_default() >= MIN_AGE {
+                found.push((Kind::Prefix, path));
+            }
+        } else if name.starts_with('.') && name.contains(".tar.xz.archiving-") {
+            found.push((Kind::Archive, path));
+        } else if let Some(build) = name.strip_suffix(".tar.xz") {
+            if !names.contains(build) {
+                found.push((Kind::Archive, path));
+            }
+        }
+    }
+
+    found
+        .into_iter()
+        .map(|(kind, path)| {
+            let (size, _) = usage(&path)?;
+            Ok(Garbage { kind, path, size })
+        })
+        .collect()
+}
+
+/// Remove the garbage found by [scan](fn.scan.html)
+pub fn remove(garbage: &[Garbage]) -> Result<()> {
+    for dir in garbage.iter().filter_map(|g| g.path.parent()) {
+        shared::require_writable(dir)?;
+    }
+    if !garbage.is_empty() {
+        snapshot::before("gc")?;
+    }
+    for g in garbage {
+        info!("Remove {}: {}", g.kind, g.path.display());
+        tree::remove(&g.path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orphans() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let dir = tmp_dir.path();
+        fs::create_dir_all(dir.join("llvm-mirror/llvm"))?;
+        fs::create_dir(dir.join("removed"))?;
+        fs::write(dir.join("removed/a"), "12345")?;
+        let used = ["llvm-mirror".to_string()].iter().cloned().collect();
+        assert_eq!(orphans(dir, &used)?, vec![dir.join("removed")]);
+        assert_eq!(orphans(&dir.join("none"), &used)?, Vec::<PathBuf>::new());
+        let (size, _) = usage(&dir.join("removed"))?;
+        assert!(size >= 5);
+        Ok(())
+    }
+}
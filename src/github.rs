@@ -0,0 +1,272 @@
+//! Client of GitHub API aware of its rate limit
+//!
+//! Unauthenticated requests to GitHub API are limited to 60 requests per hour for each IP address,
+//! which is easily exhausted on CI machines sharing an address. This client
+//!
+//! - authenticates with `$GITHUB_TOKEN`, or the token stored by `llvmenv auth login api.github.com`
+//! - caches responses in `${cache}/api/` and revalidates them by conditional requests (ETag),
+//!   which are not counted in the rate limit if not modified
+//! - waits and retries if the limit will be reset soon, and otherwise uses the stale cache if exists
+//!   (also when GitHub is unreachable)
+//!
+//! `llvmenv releases` lists the releases of LLVM using it.
+
+use log::{info, warn};
+use reqwest::header::{AUTHORIZATION, ETAG, IF_NONE_MATCH, RETRY_AFTER, USER_AGENT};
+use serde_derive::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::auth;
+use crate::config::api_cache_dir;
+use crate::error::{bail, format_err, Result};
+use crate::network;
+
+pub const API_URL: &str = "https://api.github.com";
+/// Repository of LLVM
+pub const LLVM_REPO: &str = "llvm/llvm-project";
+
+/// Longest wait for the reset of the rate limit
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// Number of retries after waiting for the rate limit
+const MAX_RETRY: u32 = 3;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub name: String,
+    pub size: u64,
+    pub browser_download_url: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug)]
+pub struct Client {
+    client: reqwest::Client,
+    token: Option<String>,
+    cache_dir: PathBuf,
+}
+
+impl Client {
+    pub fn new() -> Result<Self> {
+        let token = env::var("GITHUB_TOKEN")
+            .ok()
+            .filter(|t| !t.is_empty())
+            .or_else(|| auth::token_for_url(API_URL));
+        Ok(Client {
+            client: network::client()?,
+            token,
+            cache_dir: api_cache_dir()?,
+        })
+    }
+
+    /// Body of `GET ${API_URL}${path}`, e.g. `/repos/llvm/llvm-project/releases`
+    pub fn get(&self, path: &str) -> Result<String> {
+        let cache = self.cache_dir.join(cache_name(path));
+        let etag_path = cache.with_extension("etag");
+        let etag = if cache.exists() {
+            fs::read_to_string(&etag_path).ok()
+        } else {
+            None
+        };
+        let mut attempt = 0;
+        loop {
+            let mut req = self
+                .client
+                .get(&format!("{}{}", API_URL, path))
+                .header(USER_AGENT, concat!("llvmenv/", env!("CARGO_PKG_VERSION")));
+            if let Some(token) = &self.token {
+                req = req.header(AUTHORIZATION, format!("token {}", token));
+            }
+            if let Some(etag) = &etag {
+                req = req.header(IF_NONE_MATCH, etag.as_str());
+            }
+            let mut res = match req.send() {
+                Ok(res) => res,
+                Err(e) if cache.exists() => {
+                    warn!(
+                        "Cannot connect to GitHub API ({}), use cached response of {}",
+                        e, path
+                    );
+                    return Ok(fs::read_to_string(&cache)?);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let status = res.status().as_u16();
+            if status == 304 {
+                info!("Not modified: {}", path);
+                return Ok(fs::read_to_string(&cache)?);
+            }
+            if res.status().is_success() {
+                let body = res.text()?;
+                fs::write(&cache, &body)?;
+                match res.headers().get(ETAG).and_then(|v| v.to_str().ok()) {
+                    Some(etag) => fs::write(&etag_path, etag)?,
+                    None => {
+                        let _ = fs::remove_file(&etag_path);
+                    }
+                }
+                return Ok(body);
+            }
+            let header = |name: &str| {
+                res.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok())
+            };
+            let wait = rate_limit_wait(
+                status,
+                header("x-ratelimit-remaining"),
+                header("x-ratelimit-reset"),
+                header(RETRY_AFTER.as_str()),
+                now(),
+            );
+            match wait {
+                Some(wait) if wait <= MAX_WAIT && attempt < MAX_RETRY => {
+                    warn!(
+                        "Rate limit of GitHub API exceeded, retry after {}s",
+                        wait.as_secs()
+                    );
+                    thread::sleep(wait);
+                    attempt += 1;
+                }
+                Some(wait) => {
+                    if cache.exists() {
+                        warn!(
+                            "Rate limit of GitHub API exceeded, use cached response of {}",
+                            path
+                        );
+                        return Ok(fs::read_to_string(&cache)?);
+                    }
+                    bail!(
+                        "Rate limit of GitHub API exceeded, reset after {}s. Set $GITHUB_TOKEN or run `llvmenv auth login api.github.com` to raise the limit.",
+                        wait.as_secs()
+                    );
+                }
+                None => {
+                    return Err(format_err!(
+                        "GitHub API returns {} for {}",
+                        res.status(),
+                        path
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Release of the tag, e.g. `llvmorg-17.0.6`
+    pub fn release(&self, repo: &str, tag: &str) -> Result<Release> {
+        let body = self.get(&format!("/repos/{}/releases/tags/{}", repo, tag))?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Releases of the repository, newest first
+    pub fn releases(&self, repo: &str) -> Result<Vec<Release>> {
+        let body = self.get(&format!("/repos/{}/releases?per_page=100", repo))?;
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// File name of the cache for the API path
+fn cache_name(path: &str) -> String {
+    let name: String = path
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.json", name)
+}
+
+/// Time to wait if the response is a rate limit error
+///
+/// GitHub returns 403 (or 429) with `x-ratelimit-remaining: 0` and `x-ratelimit-reset` in epoch seconds
+/// for the primary rate limit, and `retry-after` for the secondary rate limit.
+fn rate_limit_wait(
+    status: u16,
+    remaining: Option<u64>,
+    reset: Option<u64>,
+    retry_after: Option<u64>,
+    now: u64,
+) -> Option<Duration> {
+    if status != 403 && status != 429 {
+        return None;
+    }
+    if let Some(secs) = retry_after {
+        return Some(Duration::from_secs(secs));
+    }
+    if remaining == Some(0) {
+        // +1 for the difference of clocks
+        return Some(Duration::from_secs(
+            reset.unwrap_or(now).saturating_sub(now) + 1,
+        ));
+    }
+    if status == 429 {
+        return Some(Duration::from_secs(60));
+    }
+    None // Forbidden for other reasons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_wait() {
+        assert_eq!(rate_limit_wait(404, None, None, None, 0), None);
+        assert_eq!(rate_limit_wait(403, Some(10), None, None, 0), None);
+        assert_eq!(
+            rate_limit_wait(403, Some(0), Some(130), None, 100),
+            Some(Duration::from_secs(31))
+        );
+        assert_eq!(
+            rate_limit_wait(403, Some(0), Some(50), None, 100),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            rate_limit_wait(403, None, None, Some(5), 100),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_cache_name() {
+        assert_eq!(
+            cache_name("/repos/llvm/llvm-project/releases?per_page=100"),
+            "repos_llvm_llvm-project_releases_per_page_100.json"
+        );
+    }
+
+    #[test]
+    fn test_parse_releases() -> Result<()> {
+        let body = r#"[{"tag_name": "llvmorg-17.0.6", "prerelease": false, "assets": [
+            {"name": "clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz", "size": 10,
+             "browser_download_url": "https://github.com/llvm/llvm-project/releases/download/llvmorg-17.0.6/a.tar.xz"}]}]"#;
+        let releases: Vec<Release> = serde_json::from_str(body)?;
+        assert_eq!(releases[0].tag_name, "llvmorg-17.0.6");
+        assert_eq!(releases[0].assets[0].size, 10);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Capabilities of the host
+//!
+//! `llvmenv hostinfo [--json]` shows the CPUs, memory, free disk space of the cache directory,
+//! glibc version, and the compilers, linkers, and build tools found in `$PATH` with their versions.
+//! They are also used for the defaults of `llvmenv build-entry`:
+//!
+//! - the number of parallel jobs is the number of CPUs, limited by the memory (1 GB for each job)
+//! - Ninja is used if found (then Unix Makefiles) and no builder is given by the entry, `-G`,
+//!   or `build.builder` of config.toml, see [CMakeGenerator::detect](../entry/enum.CMakeGenerator.html#method.detect)
+
+use serde_derive::Serialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::cache_dir;
+use crate::error::*;
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// Tools detected in `$PATH`
+pub const TOOLS: [&str; 15] = [
+    "cc", "c++", "gcc", "g++", "clang", "clang++", "ld", "ld.lld", "ld.gold", "mold", "cmake",
+    "ninja", "make", "ccache", "sccache",
+];
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ToolInfo {
+    pub name: String,
+    pub path: PathBuf,
+    /// First line of `--version`
+    pub version: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HostInfo {
+    pub os: String,
+    pub arch: String,
+    pub cpus: usize,
+    /// Total physical memory in bytes
+    pub memory: Option<u64>,
+    /// Free space in bytes of the cache directory, where sources are downloaded and built
+    pub disk_free: Option<u64>,
+    pub glibc: Option<String>,
+    pub tools: Vec<ToolInfo>,
+}
+
+impl HostInfo {
+    /// Detect the capabilities of the host. This runs `--version` of the tools.
+    pub fn detect() -> Self {
+        HostInfo {
+            os: env::consts::OS.into(),
+            arch: env::consts::ARCH.into(),
+            cpus: num_cpus::get(),
+            memory: total_memory(),
+            disk_free: cache_dir()
+                .ok()
+                .and_then(|dir| fs2::available_space(dir).ok()),
+            glibc: glibc_version(),
+            tools: TOOLS
+                .iter()
+                .filter_map(|name| {
+                    let path = find_tool(name)?;
+                    let version = check_tool(name).ok()?;
+                    Some(ToolInfo {
+                        name: name.to_string(),
+                        path,
+                        version,
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+fn gb(bytes: Option<u64>) -> String {
+    match bytes {
+        Some(bytes) => format!("{:.1} GB", bytes as f64 / GB as f64),
+        None => "unknown".into(),
+    }
+}
+
+impl fmt::Display for HostInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Host:      {}-{}", self.arch, self.os)?;
+        writeln!(f, "CPUs:      {}", self.cpus)?;
+        writeln!(f, "Memory:    {}", gb(self.memory))?;
+        writeln!(f, "Disk free: {}", gb(self.disk_free))?;
+        if let Some(glibc) = &self.glibc {
+            writeln!(f, "glibc:     {}", glibc)?;
+        }
+        writeln!(f, "Tools:")?;
+        for tool in &self.tools {
+            writeln!(
+                f,
+                "  {:<9} {} ({})",
+                tool.name,
+                tool.version,
+                tool.path.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Total physical memory in bytes
+pub fn total_memory() -> Option<u64> {
+    if cfg!(target_os = "linux") {
+        let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+        let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+        let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kb * 1024)
+    } else if cfg!(target_os = "macos") {
+        let output = Command::new("sysctl")
+            .args(["-n", "hw.memsize"])
+            .output()
+            .ok()?;
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Memory available for new processes in bytes, without swapping, on Linux
+pub fn available_memory() -> Option<u64> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Version of glibc, e.g. `2.35`, by `getconf GNU_LIBC_VERSION`
+fn glibc_version() -> Option<String> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let output = Command::new("getconf")
+        .arg("GNU_LIBC_VERSION")
+        .output()
+        .ok()?;
+    let out = String::from_utf8_lossy(&output.stdout);
+    out.trim().strip_prefix("glibc ").map(|v| v.to_string())
+}
+
+/// Number of parallel jobs for `cpus` and `memory`, 1 GB for each job
+///
+/// ```
+/// # use llvmenv::hostinfo::jobs_for;
+/// let gb = 1024 * 1024 * 1024;
+/// assert_eq!(jobs_for(16, Some(64 * gb)), 16);
+/// assert_eq!(jobs_for(16, Some(8 * gb)), 8);
+/// assert_eq!(jobs_for(16, None), 16);
+/// assert_eq!(jobs_for(4, Some(gb / 2)), 1);
+/// ```
+pub fn jobs_for(cpus: usize, memory: Option<u64>) -> usize {
+    match memory {
+        Some(memory) => cpus.min((memory / GB) as usize).max(1),
+        None => cpus.max(1),
+    }
+}
+
+/// Default number of parallel jobs on this host
+pub fn default_jobs() -> usize {
+    jobs_for(num_cpus::get(), total_memory())
+}
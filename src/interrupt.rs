@@ -0,0 +1,166 @@
+//! Ctrl-C (SIGINT) handling
+//!
+//! The terminal sends Ctrl-C only to its foreground process group, i.e. llvmenv and the commands sharing it.
+//! Commands run in their own process groups (with `--timeout`, or paused on low battery, see [power](../power/index.html))
+//! are registered while alive, and one process-wide handler, installed on the first use, forwards Ctrl-C to all of them,
+//! also when several commands run concurrently.
+//!
+//! While an operation on a directory tree is running (see [tree](../tree/index.html)), the handler sets a flag checked
+//! by it instead of killing llvmenv. Otherwise llvmenv is killed as by default, unless Ctrl-C has been forwarded to
+//! a command whose failure is reported as usual. Ctrl-C ignored by the parent (e.g. `nohup`) stays ignored.
+//!
+//! Commands in their own groups are in the background of the terminal, and stopped by SIGTTIN if they read it,
+//! e.g. the credential prompts of git. They run with `GIT_TERMINAL_PROMPT=0` (unless set) so that git fails instead.
+//! Use a credential helper or an SSH agent for private repositories with `--timeout`.
+
+#[cfg(unix)]
+use log::warn;
+#[cfg(unix)]
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+
+/// Commands forwarded Ctrl-C at the same time, more than the jobs of any scheduler
+#[cfg(unix)]
+const MAX_GROUPS: usize = 64;
+
+/// Process groups of the live commands, 0 for free slots
+#[cfg(unix)]
+static GROUPS: [AtomicI32; MAX_GROUPS] = [const { AtomicI32::new(0) }; MAX_GROUPS];
+
+/// Depth of the running [interruptible](fn.interruptible.html) operations
+static INTERRUPTIBLE: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+static INSTALL: Once = Once::new();
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    let mut forwarded = false;
+    for group in GROUPS.iter() {
+        let pgid = group.load(Ordering::SeqCst);
+        if pgid > 0 {
+            unsafe {
+                libc::kill(-pgid, libc::SIGINT);
+            }
+            forwarded = true;
+        }
+    }
+    if INTERRUPTIBLE.load(Ordering::SeqCst) > 0 {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    } else if !forwarded {
+        // Only async-signal-safe functions here
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::raise(libc::SIGINT);
+        }
+    }
+}
+
+/// Install the handler once, unless Ctrl-C is ignored
+fn install() {
+    #[cfg(unix)]
+    INSTALL.call_once(|| unsafe {
+        let previous = libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+        if previous == libc::SIG_IGN {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+        }
+    });
+}
+
+/// Registration of a process group forwarded Ctrl-C, removed on drop
+pub(crate) struct Forward {
+    #[cfg(unix)]
+    slot: Option<usize>,
+}
+
+/// Forward Ctrl-C to the process group `pgid` while the returned value is alive
+pub(crate) fn forward(pgid: u32) -> Forward {
+    install();
+    #[cfg(unix)]
+    {
+        let slot = GROUPS.iter().position(|group| {
+            group
+                .compare_exchange(0, pgid as i32, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        if slot.is_none() {
+            warn!(
+                "Ctrl-C is not forwarded to process group {}: more than {} commands",
+                pgid, MAX_GROUPS
+            );
+        }
+        Forward { slot }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pgid;
+        Forward {}
+    }
+}
+
+impl Drop for Forward {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            if let Some(slot) = self.slot {
+                GROUPS[slot].store(0, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+struct InterruptibleGuard;
+
+impl Drop for InterruptibleGuard {
+    fn drop(&mut self) {
+        INTERRUPTIBLE.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Run `f` with Ctrl-C setting the flag of [interrupted](fn.interrupted.html) instead of killing the process.
+/// The flag is cleared when the outermost one starts.
+pub(crate) fn interruptible<T, F: FnOnce() -> T>(f: F) -> T {
+    install();
+    if INTERRUPTIBLE.fetch_add(1, Ordering::SeqCst) == 0 {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+    let _guard = InterruptibleGuard;
+    f()
+}
+
+/// Ctrl-C is pressed in [interruptible](fn.interruptible.html)
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_forward() {
+        let groups = |pgid: i32| {
+            GROUPS
+                .iter()
+                .filter(|g| g.load(Ordering::SeqCst) == pgid)
+                .count()
+        };
+        // Process groups which do not exist
+        let a = forward(0x7fff_fff0);
+        let b = forward(0x7fff_fff1);
+        assert_eq!(groups(0x7fff_fff0), 1);
+        assert_eq!(groups(0x7fff_fff1), 1);
+        drop(a);
+        assert_eq!(groups(0x7fff_fff0), 0);
+        assert_eq!(groups(0x7fff_fff1), 1);
+        drop(b);
+        assert_eq!(groups(0x7fff_fff1), 0);
+    }
+}
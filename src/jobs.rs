@@ -0,0 +1,321 @@
+//! Scheduler of concurrent jobs
+//!
+//! [Scheduler](./struct.Scheduler.html) runs jobs by a bounded number of worker threads in the order of
+//! their dependencies, and is used for the downloads of tools and for building several entries
+//! (`build-entry --group`), where the sources of the next entries are downloaded while building one.
+//!
+//! - [requires](./struct.Scheduler.html#method.requires) runs a job after another one succeeds.
+//!   If it fails, the job is skipped, and so are the jobs requiring it.
+//! - [after](./struct.Scheduler.html#method.after) only orders jobs, e.g. builds one by one.
+//! - A job which panics fails as if it returned an error.
+//! - A timeout or Ctrl-C (and any failure if [fail_fast](./struct.Scheduler.html#method.fail_fast))
+//!   cancels the jobs not started yet. Running jobs are not stopped.
+//!
+//! The progress of the outermost scheduler, e.g. of the entries rather than of their tools,
+//! is reported by [on_jobs](../progress/trait.ProgressObserver.html#method.on_jobs),
+//! and the bars of the jobs are drawn together by [TerminalObserver](../progress/struct.TerminalObserver.html).
+//!
+//! ```
+//! use llvmenv::jobs::{Outcome, Scheduler};
+//!
+//! let mut s = Scheduler::new(2);
+//! let download = s.add("download", || Ok(()));
+//! let build = s.add("build", || Ok(()));
+//! s.requires(build, download);
+//! let outcomes = s.run();
+//! assert!(outcomes.iter().all(|o| o.is_done()));
+//! ```
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::error::*;
+use crate::progress::observer;
+
+/// Index of a job in the [Scheduler](./struct.Scheduler.html)
+pub type JobId = usize;
+
+/// Number of schedulers running, to report only the outermost one
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+type Task<'a> = Box<dyn FnOnce() -> Result<()> + Send + 'a>;
+
+/// Result of a job
+#[derive(Debug)]
+pub enum Outcome {
+    Done,
+    Failed(LlvmEnvError),
+    /// Not run since a required job failed, or the scheduler is cancelled
+    Skipped(String),
+}
+
+impl Outcome {
+    pub fn is_done(&self) -> bool {
+        matches!(self, Outcome::Done)
+    }
+}
+
+/// Failure of a job which panicked, with the message of the panic
+fn panicked(name: &str, payload: Box<dyn Any + Send>) -> LlvmEnvError {
+    let msg = payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".into());
+    format_err!("'{}' panicked: {}", name, msg)
+}
+
+struct Job {
+    name: String,
+    requires: Vec<JobId>,
+    after: Vec<JobId>,
+}
+
+/// Jobs with dependencies, see [module level doc](index.html)
+pub struct Scheduler<'a> {
+    workers: usize,
+    fail_fast: bool,
+    jobs: Vec<Job>,
+    tasks: Vec<Task<'a>>,
+}
+
+/// State shared by the workers
+struct State {
+    outcomes: Vec<Option<Outcome>>,
+    started: Vec<bool>,
+    cancelled: bool,
+}
+
+impl State {
+    /// Next job to run, after skipping the jobs which cannot be run. None if nothing is ready.
+    fn next(&mut self, jobs: &[Job]) -> Option<JobId> {
+        loop {
+            let mut skipped = false;
+            for (id, job) in jobs.iter().enumerate() {
+                if self.started[id] {
+                    continue;
+                }
+                let reason = if self.cancelled {
+                    Some("cancelled".to_string())
+                } else {
+                    job.requires
+                        .iter()
+                        .find(|&&dep| {
+                            matches!(
+                                self.outcomes[dep],
+                                Some(Outcome::Failed(_)) | Some(Outcome::Skipped(_))
+                            )
+                        })
+                        .map(|&dep| format!("'{}' failed", jobs[dep].name))
+                };
+                if let Some(reason) = reason {
+                    self.started[id] = true;
+                    self.outcomes[id] = Some(Outcome::Skipped(reason));
+                    skipped = true;
+                    continue;
+                }
+                let finished =
+                    |deps: &[JobId]| deps.iter().all(|&dep| self.outcomes[dep].is_some());
+                if finished(&job.requires) && finished(&job.after) {
+                    self.started[id] = true;
+                    return Some(id);
+                }
+            }
+            // Skipping may make others ready or skipped
+            if !skipped {
+                return None;
+            }
+        }
+    }
+
+    fn done(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.is_some()).count()
+    }
+}
+
+impl<'a> Scheduler<'a> {
+    /// Scheduler running at most `workers` jobs at once
+    pub fn new(workers: usize) -> Self {
+        Scheduler {
+            workers: workers.max(1),
+            fail_fast: false,
+            jobs: Vec::new(),
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Cancel the jobs not started yet if any job fails
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Add a job named `name` (used in the reason of skipped jobs)
+    pub fn add<F>(&mut self, name: &str, task: F) -> JobId
+    where
+        F: FnOnce() -> Result<()> + Send + 'a,
+    {
+        self.jobs.push(Job {
+            name: name.into(),
+            requires: Vec::new(),
+            after: Vec::new(),
+        });
+        self.tasks.push(Box::new(task));
+        self.jobs.len() - 1
+    }
+
+    /// Run `job` only after `dep` succeeds
+    pub fn requires(&mut self, job: JobId, dep: JobId) {
+        self.jobs[job].requires.push(dep);
+    }
+
+    /// Run `job` after `dep` finishes, whether it succeeds or not
+    pub fn after(&mut self, job: JobId, dep: JobId) {
+        self.jobs[job].after.push(dep);
+    }
+
+    /// Run all jobs, and return their outcomes in the order added
+    pub fn run(self) -> Vec<Outcome> {
+        let total = self.jobs.len();
+        let tasks: Vec<Mutex<Option<Task>>> = self
+            .tasks
+            .into_iter()
+            .map(|task| Mutex::new(Some(task)))
+            .collect();
+        let (jobs, workers, fail_fast) = (&self.jobs, self.workers, self.fail_fast);
+        let state = Mutex::new(State {
+            outcomes: (0..total).map(|_| None).collect(),
+            started: vec![false; total],
+            cancelled: false,
+        });
+        let cond = Condvar::new();
+        let outermost = RUNNING.fetch_add(1, Ordering::SeqCst) == 0;
+        let observer = observer();
+        let report = |done: usize| {
+            if outermost {
+                observer.on_jobs(done, total);
+            }
+        };
+        report(0);
+        thread::scope(|s| {
+            for _ in 0..workers.min(total) {
+                s.spawn(|| loop {
+                    let id = {
+                        let mut state = state.lock().unwrap();
+                        loop {
+                            if let Some(id) = state.next(jobs) {
+                                break id;
+                            }
+                            if state.started.iter().all(|&started| started) {
+                                cond.notify_all();
+                                return;
+                            }
+                            state = cond.wait(state).unwrap();
+                        }
+                    };
+                    let task = tasks[id].lock().unwrap().take().expect("Job runs once");
+                    // A panic would leave the job unfinished, and the other workers waiting for it
+                    let outcome = match panic::catch_unwind(AssertUnwindSafe(task)) {
+                        Ok(Ok(())) => Outcome::Done,
+                        Ok(Err(e)) => Outcome::Failed(e),
+                        Err(payload) => Outcome::Failed(panicked(&jobs[id].name, payload)),
+                    };
+                    let mut state = state.lock().unwrap();
+                    if let Outcome::Failed(e) = &outcome {
+                        if fail_fast || e.is_timeout() || matches!(e, LlvmEnvError::Interrupted) {
+                            state.cancelled = true;
+                        }
+                    }
+                    state.outcomes[id] = Some(outcome);
+                    report(state.done());
+                    cond.notify_all();
+                });
+            }
+        });
+        let outcomes: Vec<Outcome> = state
+            .into_inner()
+            .unwrap()
+            .outcomes
+            .into_iter()
+            .map(|o| o.unwrap_or_else(|| Outcome::Skipped("cancelled".into())))
+            .collect();
+        report(total);
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dependencies() {
+        let order = Mutex::new(Vec::new());
+        let mut s = Scheduler::new(4);
+        let a = s.add("a", || {
+            order.lock().unwrap().push("a");
+            Err(err_msg("a is broken"))
+        });
+        let b = s.add("b", || {
+            order.lock().unwrap().push("b");
+            Ok(())
+        });
+        let c = s.add("c", || Ok(()));
+        let d = s.add("d", || Ok(()));
+        s.after(b, a);
+        s.requires(c, a);
+        s.requires(d, c);
+        let outcomes = s.run();
+        assert_eq!(*order.lock().unwrap(), vec!["a", "b"]);
+        assert!(matches!(outcomes[a], Outcome::Failed(_)));
+        assert!(outcomes[b].is_done());
+        assert!(matches!(&outcomes[c], Outcome::Skipped(reason) if reason == "'a' failed"));
+        assert!(matches!(&outcomes[d], Outcome::Skipped(reason) if reason == "'c' failed"));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let running = AtomicUsize::new(0);
+        let max = AtomicUsize::new(0);
+        let mut s = Scheduler::new(2);
+        for i in 0..8 {
+            s.add(&i.to_string(), || {
+                let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(n, Ordering::SeqCst);
+                thread::sleep(std::time::Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        assert!(s.run().iter().all(|o| o.is_done()));
+        assert!(max.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn test_panic() {
+        let mut s = Scheduler::new(2);
+        let a = s.add("a", || panic!("a is broken"));
+        let b = s.add("b", || Ok(()));
+        let c = s.add("c", || Ok(()));
+        s.requires(b, a);
+        let outcomes = s.run();
+        assert!(
+            matches!(&outcomes[a], Outcome::Failed(e) if e.to_string() == "'a' panicked: a is broken")
+        );
+        assert!(matches!(&outcomes[b], Outcome::Skipped(reason) if reason == "'a' failed"));
+        assert!(outcomes[c].is_done());
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let mut s = Scheduler::new(1).fail_fast(true);
+        s.add("a", || Err(err_msg("a is broken")));
+        s.add("b", || Ok(()));
+        let outcomes = s.run();
+        assert!(matches!(&outcomes[1], Outcome::Skipped(reason) if reason == "cancelled"));
+    }
+}
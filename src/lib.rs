@@ -1,5 +1,84 @@
+//! Manage multiple LLVM/Clang builds
+//!
+//! This crate is the library behind the `llvmenv` command, for tools driving it programmatically,
+//! e.g. build scripts linking libLLVM:
+//!
+//! - [Entry](entry/enum.Entry.html) describes how to build LLVM/Clang, loaded from `entry.toml`
+//!   and the built-in entries by [load_entry](entry/fn.load_entry.html)
+//! - [Build](build/struct.Build.html) is an installed LLVM/Clang, listed by [builds](build/fn.builds.html),
+//!   and the current one is selected by [seek_build](build/fn.seek_build.html) as `llvmenv prefix`,
+//!   or by [resolve](build/fn.resolve.html) for any directory with the setting deciding it
+//! - [Resource](resource/enum.Resource.html) downloads sources from Git/SVN repositories or Tar archives
+//! - [config](config/index.html) locates the directories of llvmenv and loads `config.toml`
+//! - [LlvmEnvError](error/enum.LlvmEnvError.html) tells failures apart, e.g. an unknown entry, a download, or a build
+//!
+//! ```no_run
+//! # fn main() -> llvmenv::error::Result<()> {
+//! // Build the entry unless installed and up to date, and use it
+//! let entry = llvmenv::load_entry("llvm-project")?;
+//! let build = entry.ensure_built(llvmenv::hostinfo::default_jobs())?;
+//! println!("cargo:rustc-env=LLVM_SYS_PREFIX={}", build.prefix().display());
+//!
+//! // The build selected by `llvmenv global` or `llvmenv local`
+//! let current = llvmenv::seek_build()?;
+//! println!("{} {:?}", current.name(), current.version()?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Long-running operations report their progress to the [observer](progress/index.html) and by [log](https://docs.rs/log)
+//! instead of printing it, while the output of subprocesses (e.g. CMake) is passed through to stdout/stderr.
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Either `native-tls` or `rustls` feature is required for HTTPS");
+
+pub mod analyze;
+pub mod asdf;
+pub mod audit;
+pub mod auth;
 pub mod build;
+pub mod builder;
+pub mod cachestats;
 pub mod config;
+pub mod diagnostics;
+pub mod doctor;
 pub mod entry;
+pub mod environment;
 pub mod error;
+pub mod fetcher;
+pub mod gc;
+pub mod github;
+pub mod hostinfo;
+pub mod interrupt;
+pub mod jobs;
+pub mod lock;
+pub mod lockdown;
+pub mod network;
+pub mod package;
+pub mod plugin;
+pub mod power;
+pub mod prebuilt;
+pub mod priority;
+pub mod progress;
+pub mod remote;
+pub mod report;
 pub mod resource;
+pub mod serve;
+pub mod setup;
+pub mod shared;
+pub mod shell;
+pub mod shim;
+pub mod snapshot;
+pub mod state;
+pub mod timing;
+pub mod tmpfs;
+pub mod toolchain;
+pub mod tree;
+pub mod verify;
+pub mod version;
+
+pub use crate::build::{builds, seek_build, Build};
+pub use crate::config::{cache_dir, config_dir, data_dir, load_config, Config};
+pub use crate::entry::{load_entries, load_entry, Entry};
+pub use crate::error::LlvmEnvError;
+pub use crate::resource::Resource;
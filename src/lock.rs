@@ -0,0 +1,329 @@
+//! Lockfile pinning the LLVM/Clang build of a project
+//!
+//! llvmenv.lock
+//! -------------
+//! `llvmenv sync` seeks `llvmenv.lock` (or `.llvmenv.toml`) from the current directory to its parents,
+//! builds the pinned entry if it has not been installed yet, and sets it as the local build
+//! of the directory where the lockfile exists.
+//!
+//! ```toml
+//! name = "7.0.0"
+//! ```
+//!
+//! An entry which is not listed in `entry.toml` can be defined inline in the same format:
+//!
+//! ```toml
+//! name = "my-llvm"
+//!
+//! [entry]
+//! url    = "https://github.com/llvm-mirror/llvm"
+//! target = ["X86"]
+//! ```
+//!
+//! Pins
+//! -----
+//! `llvmenv sync --pin` records the sources of the entry as checked out into the lockfile,
+//! the commit of each Git checkout and the SHA-256 of each Tar archive with the URL it came from:
+//!
+//! ```toml
+//! [[pin]]
+//! path     = "."
+//! url      = "https://github.com/llvm/llvm-project"
+//! revision = "6009708b4367171ccdbf4b5905cb6a803753fe18"
+//!
+//! [[pin]]
+//! path   = "tools/clang"
+//! url    = "https://example.com/mirror/cfe-7.0.0.src.tar.xz"
+//! sha256 = "550212711c752697d2f82c648714a7221b1207fd9441543ff4aa9e3be45bba55"
+//! ```
+//!
+//! Then `llvmenv sync` checks out the pinned commits (fetching them if needed), and fails before the build
+//! if an archive does not match its checksum, so that every checkout of the project builds the same sources
+//! even if the entry follows a branch or a mirror is replaced. `path` is relative to the source directory of the entry.
+//! An existing build is used as is, and `llvmenv sync --pin` updates the pins from the current sources.
+
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build::Build;
+use crate::entry::{self, Entry, EntrySetting};
+use crate::error::*;
+use crate::network;
+use crate::resource;
+
+/// Names of lockfile, searched in this order
+pub const LOCK_FILES: [&str; 2] = ["llvmenv.lock", ".llvmenv.toml"];
+
+/// Pinned build, decoded from lockfile
+#[derive(Deserialize, Debug)]
+pub struct Lock {
+    /// Name of entry, also used as the name of build
+    pub name: String,
+    /// Inline entry setting. The entry is searched from entry.toml and official releases if absent.
+    pub entry: Option<EntrySetting>,
+    /// Sources pinned by `llvmenv sync --pin`, see [module level doc](index.html#pins)
+    #[serde(default)]
+    pub pin: Vec<Pin>,
+    /// Path of the lockfile
+    #[serde(skip)]
+    path: PathBuf,
+    /// Directory where the lockfile exists
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// Source of the entry pinned by the lockfile
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pin {
+    /// Directory relative to the source directory of the entry, `.` for the top one
+    pub path: String,
+    /// URL where the source is downloaded from
+    pub url: String,
+    /// Commit of a Git checkout
+    pub revision: Option<String>,
+    /// SHA-256 of a Tar archive
+    pub sha256: Option<String>,
+}
+
+/// Output of git in `dir`
+fn git_output(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format_err!("Cannot execute git: {}", e))?;
+    if !output.status.success() {
+        bail!("git {} failed in {}", args.join(" "), dir.display());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Pins of the sources of `entry` as checked out. SVN checkouts and local entries are not pinned.
+pub fn resolve(entry: &Entry) -> Result<Vec<Pin>> {
+    let src = entry.src_dir()?;
+    let mut paths = vec![".".to_string()];
+    if let Entry::Remote { tools, .. } = entry {
+        paths.extend(tools.iter().map(|tool| tool.rel_path()));
+    }
+    let mut pins = Vec::new();
+    for path in paths {
+        let dir = src.join(&path);
+        if dir.join(".git").exists() {
+            pins.push(Pin {
+                url: git_output(&dir, &["remote", "get-url", "origin"])?,
+                revision: Some(git_output(&dir, &["rev-parse", "HEAD"])?),
+                sha256: None,
+                path,
+            });
+        } else if let Some((sha256, url)) = resource::archive_record(&dir) {
+            pins.push(Pin {
+                path,
+                url,
+                revision: None,
+                sha256: Some(sha256),
+            });
+        } else if dir.is_dir() && !matches!(entry, Entry::Local { .. }) {
+            warn!("Source {} cannot be pinned", dir.display());
+        }
+    }
+    Ok(pins)
+}
+
+impl Pin {
+    /// Check out the pinned commit in `src`, or fail if the archive extracted there does not match
+    pub fn apply(&self, src: &Path) -> Result<()> {
+        let dir = src.join(&self.path);
+        if !dir.is_dir() {
+            bail!(
+                "Pinned source {} ({}) is not checked out",
+                dir.display(),
+                self.url
+            );
+        }
+        if let Some(revision) = &self.revision {
+            if git_output(&dir, &["rev-parse", "HEAD"])? != *revision {
+                let commit = format!("{}^{{commit}}", revision);
+                if git_output(&dir, &["cat-file", "-e", &commit]).is_err() {
+                    network::check_online()?;
+                    info!("Fetch pinned commit {} into {}", revision, dir.display());
+                    network::git()?
+                        .args(["fetch", "--depth", "1", "origin", revision])
+                        .current_dir(&dir)
+                        .check_run()?;
+                }
+                info!("Checkout pinned commit {} in {}", revision, dir.display());
+                network::git()?
+                    .args(["checkout", "--detach", revision])
+                    .current_dir(&dir)
+                    .check_run()?;
+            }
+        }
+        if let Some(sha256) = &self.sha256 {
+            match resource::archive_record(&dir) {
+                Some((actual, _)) if actual == *sha256 => {}
+                Some((actual, url)) => bail!(
+                    "Archive of {} from {} has SHA-256 {}, but {} is pinned",
+                    dir.display(),
+                    url,
+                    actual,
+                    sha256
+                ),
+                None => bail!(
+                    "Archive of {} is not recorded to check the pinned SHA-256. Remove it to download again.",
+                    dir.display()
+                ),
+            }
+        }
+        if let Some((_, url)) = resource::archive_record(&dir) {
+            if url != self.url {
+                warn!(
+                    "{} is downloaded from {}, not {}",
+                    dir.display(),
+                    url,
+                    self.url
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Lock {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let mut lock: Lock = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| format_err!("Invalid lockfile {}: {}", path.display(), e))?;
+        lock.dir = path
+            .parent()
+            .ok_or_else(|| format_err!("Invalid lockfile path: {}", path.display()))?
+            .to_owned();
+        lock.path = path.to_owned();
+        Ok(lock)
+    }
+
+    /// Seek lockfile from `path` to its parents
+    pub fn seek(path: &Path) -> Result<Option<Self>> {
+        for dir in path.ancestors() {
+            for name in &LOCK_FILES {
+                let cand = dir.join(name);
+                if cand.is_file() {
+                    info!("Lockfile found: {}", cand.display());
+                    return Ok(Some(Self::from_path(&cand)?));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entry(&self) -> Result<Entry> {
+        match &self.entry {
+            Some(setting) => Entry::parse_setting(&self.name, setting.clone()),
+            None => entry::load_entry(&self.name),
+        }
+    }
+
+    /// Build the pinned entry if not installed, and set it as local build
+    pub fn sync(&self, nproc: usize) -> Result<Build> {
+        // Hashed prefix must match the pinned entry exactly
+        let entry = match &self.entry {
+            Some(setting) if setting.hashed_prefix => Some(self.entry()?),
+            _ => None,
+        };
+        let build = match &entry {
+            Some(entry) => Build::from_path(&entry.prefix()?),
+            None => Build::from_name(&self.name)?,
+        };
+        if build.exists() {
+            info!("Build '{}' already exists", build.name());
+        } else {
+            let entry = match entry {
+                Some(entry) => entry,
+                None => self.entry()?,
+            };
+            entry.checkout()?;
+            let src = entry.src_dir()?;
+            for pin in &self.pin {
+                pin.apply(&src)?;
+            }
+            entry.build(nproc, false)?;
+        }
+        build.set_local(&self.dir)?;
+        Ok(build)
+    }
+
+    /// Record the sources of the entry into the lockfile, see [module level doc](index.html#pins)
+    pub fn write_pins(&mut self) -> Result<&[Pin]> {
+        let entry = self.entry()?;
+        entry.checkout()?;
+        self.pin = resolve(&entry)?;
+        let mut lock: toml::Value = toml::from_str(&fs::read_to_string(&self.path)?)
+            .map_err(|e| format_err!("Invalid lockfile {}: {}", self.path.display(), e))?;
+        if let toml::Value::Table(table) = &mut lock {
+            table.insert("pin".into(), toml::Value::try_from(&self.pin)?);
+        }
+        fs::write(&self.path, toml::to_string(&lock)?)?;
+        Ok(&self.pin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seek_lock() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let sub = tmp_dir.path().join("sub");
+        fs::create_dir_all(&sub)?;
+        assert!(Lock::seek(&sub)?.is_none());
+
+        fs::write(
+            tmp_dir.path().join(".llvmenv.toml"),
+            "name = \"my-llvm\"\n[entry]\nurl = \"https://github.com/llvm-mirror/llvm\"\n",
+        )?;
+        let lock = Lock::seek(&sub)?.unwrap();
+        assert_eq!(lock.name, "my-llvm");
+        assert_eq!(lock.dir(), tmp_dir.path());
+        assert_eq!(lock.entry()?.name(), "my-llvm");
+        assert!(lock.pin.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let src = tmp_dir.path();
+        fs::create_dir_all(src.join("tools/clang"))?;
+        fs::write(
+            src.join("tools/clang").join(resource::ARCHIVE_RECORD_FN),
+            "abcd https://example.com/clang.tar.xz\n",
+        )?;
+        let pin = |sha256: &str| Pin {
+            path: "tools/clang".into(),
+            url: "https://example.com/clang.tar.xz".into(),
+            revision: None,
+            sha256: Some(sha256.into()),
+        };
+        pin("abcd").apply(src)?;
+        let e = pin("ef01").apply(src).unwrap_err();
+        assert!(e.to_string().contains("ef01 is pinned"));
+        let missing = Pin {
+            path: "tools/lld".into(),
+            ..pin("abcd")
+        };
+        assert!(missing.apply(src).is_err());
+
+        let lock: Lock = toml::from_str(
+            "name = \"17.0.6\"\n[[pin]]\npath = \".\"\nurl = \"https://github.com/llvm/llvm-project\"\nrevision = \"6009708b\"\n",
+        )
+        .unwrap();
+        assert_eq!(lock.pin[0].revision.as_deref(), Some("6009708b"));
+        Ok(())
+    }
+}
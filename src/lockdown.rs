@@ -0,0 +1,218 @@
+//! Read-only builds
+//!
+//! With `lockdown.enabled = true` in config.toml, the files and directories in the prefix of a build are made
+//! read-only after `llvmenv build-entry` or `llvmenv install` installs it, so that the toolchain is not modified
+//! in place by accident, e.g. by `make install` of another project with a wrong prefix:
+//!
+//! ```toml
+//! [lockdown]
+//! enabled = true
+//! ```
+//!
+//! llvmenv unlocks the prefix while changing it, and locks it again: reinstalling by `build-entry`,
+//! the metadata written by `llvmenv tag`, the manifest by `verify --record`, and the relocation by `llvmenv pull`.
+//! Locked builds are unlocked before they are removed.
+//!
+//! `llvmenv unlock <prefix>` makes a build writable for editing it by hand, and `llvmenv lock <prefix>` locks it
+//! again. Both accept the name of a build as well.
+//!
+//! A prefix is locked if its directory is read-only. Lock removes the write permissions for all on Unix,
+//! and unlock restores those allowed by the umask, e.g. also the one of the group with `shared.umask = "002"`
+//! (see [shared](../shared/index.html)), and always the one of the owner. Symbolic links are kept as is.
+
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::build::Build;
+use crate::config::load_config;
+use crate::error::*;
+#[cfg(unix)]
+use crate::shared;
+use crate::tree;
+
+/// The prefix has been [locked](fn.lock.html)
+pub fn is_locked(prefix: &Path) -> bool {
+    fs::symlink_metadata(prefix)
+        .map(|meta| meta.is_dir() && meta.permissions().readonly())
+        .unwrap_or(false)
+}
+
+/// Write permissions restored by unlock under `umask`, always including the one of the owner
+fn write_mode(umask: u32) -> u32 {
+    0o200 | (0o222 & !umask)
+}
+
+/// Write permissions restored by unlock under the umask of the process
+fn unlocked_mode() -> u32 {
+    #[cfg(unix)]
+    return write_mode(shared::current_umask());
+    #[cfg(not(unix))]
+    return write_mode(0);
+}
+
+/// Replace the write permissions of `path` by `write`, i.e. read-only by 0
+fn set_write_mode(path: &Path, write: u32) -> Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        return Ok(());
+    }
+    let mut perms = meta.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        perms.set_mode(perms.mode() & !0o222 | write);
+    }
+    #[cfg(not(unix))]
+    perms.set_readonly(write == 0);
+    fs::set_permissions(path, perms)?;
+    Ok(())
+}
+
+fn set_tree_write_mode(prefix: &Path, write: u32) -> Result<()> {
+    let tree = tree::walk(prefix)?;
+    tree::for_each(&tree.files, |file| set_write_mode(file, write))?;
+    // The prefix is the last, so that an interrupted one is still locked
+    for dir in tree.dirs.iter().rev() {
+        set_write_mode(dir, write)?;
+    }
+    Ok(())
+}
+
+/// Make the files and directories in `prefix` read-only
+pub fn lock(prefix: &Path) -> Result<()> {
+    info!("Lock {}", prefix.display());
+    set_tree_write_mode(prefix, 0)
+}
+
+/// Make the files and directories in `prefix` writable by the owner, and the others allowed by the umask
+pub fn unlock(prefix: &Path) -> Result<()> {
+    info!("Unlock {}", prefix.display());
+    set_tree_write_mode(prefix, unlocked_mode())
+}
+
+/// Lock a build just installed if `lockdown.enabled`
+pub fn after_install(build: &Build) -> Result<()> {
+    if load_config()?.lockdown.enabled {
+        lock(build.prefix())?;
+    }
+    Ok(())
+}
+
+/// Run `f` changing the files in `prefix`, with unlocking it and locking it again if locked
+pub fn unlocked<T, F: FnOnce() -> Result<T>>(prefix: &Path, f: F) -> Result<T> {
+    if !is_locked(prefix) {
+        return f();
+    }
+    unlock(prefix)?;
+    let res = f();
+    lock(prefix)?;
+    res
+}
+
+/// Write `contents` into the file `name` in `prefix`, e.g. the metadata.
+/// Only the prefix directory and the file are unlocked if locked, instead of the whole tree.
+pub fn write(prefix: &Path, name: &str, contents: &[u8]) -> Result<()> {
+    let path = prefix.join(name);
+    if !is_locked(prefix) {
+        fs::write(&path, contents)?;
+        return Ok(());
+    }
+    let write = unlocked_mode();
+    set_write_mode(prefix, write)?;
+    if path.exists() {
+        set_write_mode(&path, write)?;
+    }
+    let res = fs::write(&path, contents);
+    if path.exists() {
+        set_write_mode(&path, 0)?;
+    }
+    set_write_mode(prefix, 0)?;
+    Ok(res?)
+}
+
+/// Prefix given to `llvmenv lock` and `unlock`, as a path or the name of a build
+pub fn prefix_of(prefix: &str) -> Result<PathBuf> {
+    let path = Path::new(prefix);
+    if path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    let build = Build::from_name(prefix)?;
+    if build.name() == "system" {
+        bail!("Cannot lock or unlock system build");
+    }
+    if !build.exists() {
+        return Err(LlvmEnvError::UnknownBuild {
+            name: prefix.to_string(),
+        });
+    }
+    Ok(build.prefix().to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readonly(path: &Path) -> bool {
+        fs::metadata(path).unwrap().permissions().readonly()
+    }
+
+    #[test]
+    fn test_lock() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("17.0.6");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::write(prefix.join("bin/clang"), "")?;
+        assert!(!is_locked(&prefix));
+
+        lock(&prefix)?;
+        assert!(is_locked(&prefix));
+        assert!(readonly(&prefix.join("bin")) && readonly(&prefix.join("bin/clang")));
+
+        write(&prefix, ".llvmenv-metadata.toml", b"[tags]\n")?;
+        assert!(is_locked(&prefix));
+        assert!(readonly(&prefix.join(".llvmenv-metadata.toml")));
+
+        unlocked(&prefix, || {
+            Ok(fs::write(prefix.join("bin/clang"), "#!/bin/sh\n")?)
+        })?;
+        assert!(is_locked(&prefix) && readonly(&prefix.join("bin/clang")));
+
+        unlock(&prefix)?;
+        assert!(!is_locked(&prefix));
+        assert!(!readonly(&prefix.join("bin")) && !readonly(&prefix.join("bin/clang")));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unlock_umask() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let tmp_dir = tempfile::TempDir::new()?;
+        // Group-writable prefix shared with `shared.umask = "002"`
+        let prefix = tmp_dir.path().join("17.0.6");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::write(prefix.join("bin/clang"), "")?;
+        fs::set_permissions(prefix.join("bin/clang"), fs::Permissions::from_mode(0o775))?;
+        fs::set_permissions(prefix.join("bin"), fs::Permissions::from_mode(0o2775))?;
+        fs::set_permissions(&prefix, fs::Permissions::from_mode(0o775))?;
+
+        lock(&prefix)?;
+        assert_eq!(mode(&prefix.join("bin/clang")), 0o555);
+        set_tree_write_mode(&prefix, write_mode(0o002))?;
+        assert_eq!(mode(&prefix), 0o775);
+        assert_eq!(mode(&prefix.join("bin/clang")), 0o775);
+        // The setgid bit is kept
+        assert_eq!(
+            fs::metadata(prefix.join("bin"))?.permissions().mode() & 0o7777,
+            0o2775
+        );
+
+        lock(&prefix)?;
+        set_tree_write_mode(&prefix, write_mode(0o022))?;
+        assert_eq!(mode(&prefix.join("bin/clang")), 0o755);
+        assert_eq!(write_mode(0o222), 0o200);
+        Ok(())
+    }
+}
@@ -0,0 +1,560 @@
+//! OS packages of a build
+//!
+//! `llvmenv package [name] --format deb|rpm` wraps the build into a Debian or RPM package
+//! installed into `/opt/llvmenv/${name}` (changed by `--install-prefix`).
+//! The package registers `clang` (with `clang++`, `ld.lld`, and `llvm-config` as slaves)
+//! into the alternatives system, so that `update-alternatives --config clang` switches
+//! among the packages of several builds. The RPM package is relocatable by `rpm --prefix`.
+//!
+//! `dpkg-deb` or `rpmbuild` is required.
+//!
+//! Windows
+//! --------
+//! `--format zip` creates a portable zip of the build with `add-to-path.ps1`,
+//! which adds (or removes by `-Remove`) its `bin` directory into `PATH` of the current user.
+//! `--format msi` creates an installer by [WiX Toolset](https://wixtoolset.org/) v4 on Windows.
+//! It installs into `%ProgramFiles%\llvmenv\${name}` and appends its `bin` into `PATH`
+//! unless `msiexec /i ${package}.msi ADDTOPATH=0`. The installer is not signed;
+//! sign it by `signtool` of your organization before distribution.
+
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use tempfile::TempDir;
+
+use crate::build::Build;
+use crate::error::*;
+use crate::tree;
+
+/// Default root of `--install-prefix`
+pub const INSTALL_ROOT: &str = "/opt/llvmenv";
+
+/// Executables registered into alternatives, the first one is the master
+const ALTERNATIVES: [&str; 4] = ["clang", "clang++", "ld.lld", "llvm-config"];
+
+/// Format of package
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Debian package (.deb)
+    Deb,
+    /// RPM package (.rpm)
+    Rpm,
+    /// Portable zip archive (.zip)
+    Zip,
+    /// Windows installer (.msi)
+    Msi,
+}
+
+impl FromStr for Format {
+    type Err = LlvmEnvError;
+
+    /// ```
+    /// # use llvmenv::package::Format;
+    /// # use std::str::FromStr;
+    /// assert_eq!(Format::from_str("deb").unwrap(), Format::Deb);
+    /// assert_eq!(Format::from_str("RPM").unwrap(), Format::Rpm);
+    /// assert_eq!(Format::from_str("msi").unwrap(), Format::Msi);
+    /// assert!(Format::from_str("apk").is_err());
+    /// ```
+    fn from_str(format: &str) -> Result<Self> {
+        Ok(match format.to_ascii_lowercase().as_str() {
+            "deb" => Format::Deb,
+            "rpm" => Format::Rpm,
+            "zip" => Format::Zip,
+            "msi" => Format::Msi,
+            _ => return Err(format_err!("Unsupported package format: {}", format)),
+        })
+    }
+}
+
+/// Properties of package derived from build
+#[derive(Debug, Clone)]
+pub struct PackageInfo {
+    /// Package name, e.g. `llvmenv-7.0.0`
+    pub name: String,
+    /// Version of clang, `0.0.0` if unknown
+    pub version: String,
+    /// Where the build is installed by the package
+    pub install_prefix: PathBuf,
+    /// Executables registered into alternatives
+    pub alternatives: Vec<String>,
+    /// Priority in alternatives, larger for newer version
+    pub priority: u32,
+}
+
+impl PackageInfo {
+    pub fn new(build: &Build, install_prefix: Option<PathBuf>) -> Self {
+        let name: String = build
+            .name()
+            .to_lowercase()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let (version, priority) = match build.version() {
+            Ok((major, minor, patch)) => (
+                format!("{}.{}.{}", major, minor, patch),
+                major * 10000 + minor * 100 + patch,
+            ),
+            Err(_) => ("0.0.0".into(), 0),
+        };
+        let bin = build.prefix().join("bin");
+        PackageInfo {
+            name: format!("llvmenv-{}", name.trim_matches('-')),
+            version,
+            install_prefix: install_prefix
+                .unwrap_or_else(|| Path::new(INSTALL_ROOT).join(build.name())),
+            alternatives: ALTERNATIVES
+                .iter()
+                .filter(|exe| bin.join(exe).is_file())
+                .map(|exe| exe.to_string())
+                .collect(),
+            priority,
+        }
+    }
+
+    /// Shell command registering executables into alternatives
+    fn install_alternatives(&self) -> Option<String> {
+        let (master, slaves) = self.alternatives.split_first()?;
+        let bin = self.install_prefix.join("bin");
+        let mut cmd = format!(
+            "update-alternatives --install /usr/bin/{0} {0} {1}/{0} {2}",
+            master,
+            bin.display(),
+            self.priority
+        );
+        for slave in slaves {
+            cmd += &format!(
+                " \\\n  --slave /usr/bin/{0} {0} {1}/{0}",
+                slave,
+                bin.display()
+            );
+        }
+        Some(cmd)
+    }
+
+    fn remove_alternatives(&self) -> Option<String> {
+        let master = self.alternatives.first()?;
+        Some(format!(
+            "update-alternatives --remove {0} {1}/{0}",
+            master,
+            self.install_prefix.join("bin").display()
+        ))
+    }
+}
+
+fn deb_arch() -> &'static str {
+    match env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "i386",
+        "powerpc64" => "ppc64el",
+        arch => arch,
+    }
+}
+
+/// Content of `DEBIAN/control`
+pub fn deb_control(info: &PackageInfo) -> String {
+    format!(
+        "Package: {name}
+Version: {version}
+Architecture: {arch}
+Maintainer: llvmenv
+Depends: libc6, libstdc++6, zlib1g
+Section: devel
+Priority: optional
+Description: LLVM/Clang built by llvmenv
+ Installed into {prefix}
+",
+        name = info.name,
+        version = info.version,
+        arch = deb_arch(),
+        prefix = info.install_prefix.display(),
+    )
+}
+
+/// Content of `DEBIAN/postinst` or `DEBIAN/prerm`
+fn deb_script(cmd: Option<String>) -> String {
+    format!("#!/bin/sh\nset -e\n{}\n", cmd.unwrap_or_default())
+}
+
+/// Content of the spec file of rpmbuild, copying the build from `prefix`
+pub fn rpm_spec(info: &PackageInfo, prefix: &Path) -> String {
+    let install_prefix = info.install_prefix.display();
+    let relocate = info
+        .install_prefix
+        .parent()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "/".into());
+    format!(
+        "Name: {name}
+Version: {version}
+Release: 1
+Summary: LLVM/Clang built by llvmenv
+License: Apache-2.0 WITH LLVM-exception
+Prefix: {relocate}
+Requires: glibc, libstdc++, zlib
+AutoReqProv: no
+
+%description
+LLVM/Clang built by llvmenv, installed into {install_prefix}
+
+%install
+mkdir -p %{{buildroot}}{install_prefix}
+cp -a {prefix} %{{buildroot}}{install_prefix}
+
+%post
+{post}
+
+%preun
+if [ $1 -eq 0 ]; then
+  {preun}
+fi
+
+%files
+{install_prefix}
+",
+        name = info.name,
+        version = info.version,
+        relocate = relocate,
+        install_prefix = install_prefix,
+        // `%` starts a macro of rpmbuild
+        prefix = shell_quote(&format!("{}/.", prefix.display())).replace('%', "%%"),
+        post = info.install_alternatives().unwrap_or_else(|| ":".into()),
+        preun = info.remove_alternatives().unwrap_or_else(|| ":".into()),
+    )
+}
+
+/// Script in zip archive registering its `bin` into `PATH` of the current user
+const ADD_TO_PATH_PS1: &str = r#"# Add (or remove by -Remove) bin directory of this build into PATH of the current user
+param([switch]$Remove)
+$bin = Join-Path $PSScriptRoot "bin"
+$path = [Environment]::GetEnvironmentVariable("Path", "User")
+$paths = @($path -split ";" | Where-Object { $_ -and $_ -ne $bin })
+if (-not $Remove) { $paths += $bin }
+[Environment]::SetEnvironmentVariable("Path", ($paths -join ";"), "User")
+"#;
+
+/// GUID derived from `seed`, to keep `UpgradeCode` of MSI among the versions of a package
+fn guid(seed: &str) -> String {
+    let hash: String = Sha256::digest(seed.as_bytes())
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hash[0..8],
+        &hash[8..12],
+        &hash[12..16],
+        &hash[16..20],
+        &hash[20..32]
+    )
+}
+
+/// Version of MSI, whose components must be numbers
+fn msi_version(version: &str) -> &str {
+    if version.split('.').all(|n| n.parse::<u32>().is_ok()) {
+        version
+    } else {
+        "0.0.0"
+    }
+}
+
+/// Escape for attribute values of XML
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Content of WiX v4 source installing `prefix`
+pub fn wxs(info: &PackageInfo, prefix: &Path) -> String {
+    let dir_name = info
+        .install_prefix
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| info.name.clone());
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!-- Generated by llvmenv -->
+<Wix xmlns="http://wixtoolset.org/schemas/v4/wxs">
+  <Package Name="{name}" Manufacturer="llvmenv" Version="{version}" UpgradeCode="{upgrade}">
+    <MajorUpgrade DowngradeErrorMessage="A newer version of {name} is already installed." />
+    <MediaTemplate EmbedCab="yes" />
+    <Property Id="ADDTOPATH" Value="1" />
+    <StandardDirectory Id="ProgramFiles64Folder">
+      <Directory Id="LLVMENVFOLDER" Name="llvmenv">
+        <Directory Id="INSTALLFOLDER" Name="{dir_name}" />
+      </Directory>
+    </StandardDirectory>
+    <Feature Id="Main">
+      <Files Directory="INSTALLFOLDER" Include="{prefix}\**" />
+      <Component Id="AddToPath" Directory="INSTALLFOLDER" Condition="ADDTOPATH = 1">
+        <RegistryValue Root="HKLM" Key="Software\llvmenv\{name}" Name="AddToPath" Type="integer" Value="1" KeyPath="yes" />
+        <Environment Id="PATH" Name="PATH" Value="[INSTALLFOLDER]bin" Permanent="no" Part="last" Action="set" System="yes" />
+      </Component>
+    </Feature>
+  </Package>
+</Wix>
+"#,
+        name = info.name,
+        version = msi_version(&info.version),
+        upgrade = guid(&info.name),
+        dir_name = xml_escape(&dir_name),
+        prefix = xml_escape(&prefix.display().to_string()),
+    )
+}
+
+/// Add files in `dir` into `zip` under `base`. Symbolic links (e.g. `clang++` to `clang`) are stored as links
+/// with the unix mode, extracted as links by `unzip`, and skipped with a warning on Windows.
+fn zip_dir<W: Write + io::Seek>(zip: &mut zip::ZipWriter<W>, dir: &Path, base: &str) -> Result<()> {
+    for path in fs::read_dir(dir)? {
+        let path = path?.path();
+        let name = format!("{}/{}", base, path.file_name().unwrap().to_string_lossy());
+        let meta = fs::symlink_metadata(&path)?;
+        #[allow(unused_mut)]
+        let mut opt = zip::write::FileOptions::default();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            opt = opt.unix_permissions(meta.permissions().mode());
+        }
+        if meta.file_type().is_symlink() {
+            if cfg!(unix) {
+                let target = fs::read_link(&path)?;
+                zip.add_symlink(name, target.to_string_lossy().replace('\\', "/"), opt)?;
+            } else {
+                warn!("Symbolic link is not packaged: {}", path.display());
+            }
+        } else if meta.is_dir() {
+            zip.add_directory(name.as_str(), opt)?;
+            zip_dir(zip, &path, &name)?;
+        } else {
+            zip.start_file(name.as_str(), opt)?;
+            io::copy(&mut fs::File::open(&path)?, zip)?;
+        }
+    }
+    Ok(())
+}
+
+fn package_zip(build: &Build, info: &PackageInfo, out: &Path) -> Result<PathBuf> {
+    let path = out.join(format!("{}-{}.zip", info.name, info.version));
+    let mut zip = zip::ZipWriter::new(fs::File::create(&path)?);
+    zip_dir(&mut zip, build.prefix(), build.name())?;
+    zip.start_file(
+        format!("{}/add-to-path.ps1", build.name()).as_str(),
+        zip::write::FileOptions::default(),
+    )?;
+    zip.write_all(ADD_TO_PATH_PS1.as_bytes())?;
+    zip.finish()?;
+    Ok(path)
+}
+
+fn package_msi(build: &Build, info: &PackageInfo, out: &Path) -> Result<PathBuf> {
+    if !cfg!(windows) {
+        bail!("MSI package can be created only on Windows");
+    }
+    check_tool("wix")?;
+    let staging = TempDir::new()?;
+    let src = staging.path().join(format!("{}.wxs", info.name));
+    fs::write(&src, wxs(info, build.prefix()))?;
+    let msi = out.join(format!("{}-{}.msi", info.name, info.version));
+    Command::new("wix")
+        .arg("build")
+        .arg(&src)
+        .arg("-arch")
+        .arg("x64")
+        .arg("-o")
+        .arg(&msi)
+        .check_run()?;
+    Ok(msi)
+}
+
+fn package_deb(build: &Build, info: &PackageInfo, out: &Path) -> Result<PathBuf> {
+    check_tool("dpkg-deb")?;
+    let staging = TempDir::new()?;
+    let root = staging.path().join("root");
+    let rel = info
+        .install_prefix
+        .strip_prefix("/")
+        .unwrap_or(&info.install_prefix);
+    tree::copy(build.prefix(), &root.join(rel))?;
+    let debian = root.join("DEBIAN");
+    fs::create_dir_all(&debian)?;
+    fs::write(debian.join("control"), deb_control(info))?;
+    for (script, cmd) in &[
+        ("postinst", info.install_alternatives()),
+        ("prerm", info.remove_alternatives()),
+    ] {
+        let path = debian.join(script);
+        fs::write(&path, deb_script(cmd.clone()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    let deb = out.join(format!("{}_{}_{}.deb", info.name, info.version, deb_arch()));
+    Command::new("dpkg-deb")
+        .arg("--build")
+        .arg("--root-owner-group")
+        .arg(&root)
+        .arg(&deb)
+        .check_run()?;
+    Ok(deb)
+}
+
+fn package_rpm(build: &Build, info: &PackageInfo, out: &Path) -> Result<PathBuf> {
+    check_tool("rpmbuild")?;
+    let staging = TempDir::new()?;
+    let spec = staging.path().join(format!("{}.spec", info.name));
+    fs::write(&spec, rpm_spec(info, build.prefix()))?;
+    Command::new("rpmbuild")
+        .arg("-bb")
+        .arg("--define")
+        .arg(format!("_topdir {}", staging.path().display()))
+        .arg("--define")
+        .arg(format!("_rpmdir {}", out.display()))
+        .arg("--define")
+        .arg("_build_id_links none")
+        .arg(&spec)
+        .check_run()?;
+    let rpm = out.join(env::consts::ARCH).join(format!(
+        "{}-{}-1.{}.rpm",
+        info.name,
+        info.version,
+        env::consts::ARCH
+    ));
+    if !rpm.exists() {
+        bail!("rpmbuild does not create {}", rpm.display());
+    }
+    Ok(rpm)
+}
+
+/// Create package of `build` in `out` directory, and returns its path
+pub fn package(
+    build: &Build,
+    format: Format,
+    install_prefix: Option<PathBuf>,
+    out: &Path,
+) -> Result<PathBuf> {
+    if build.name() == "system" {
+        bail!("Cannot package system build");
+    }
+    let info = PackageInfo::new(build, install_prefix);
+    fs::create_dir_all(out)?;
+    let out = out.canonicalize()?;
+    info!("Create {:?} package of '{}'", format, build.name());
+    match format {
+        Format::Deb => package_deb(build, &info, &out),
+        Format::Rpm => package_rpm(build, &info, &out),
+        Format::Zip => package_zip(build, &info, &out),
+        Format::Msi => package_msi(build, &info, &out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_info() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("My_LLVM");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::write(prefix.join("bin/clang"), "")?;
+        fs::write(prefix.join("bin/ld.lld"), "")?;
+        let info = PackageInfo::new(&Build::from_path(&prefix), None);
+        assert_eq!(info.name, "llvmenv-my-llvm");
+        assert_eq!(info.install_prefix, Path::new("/opt/llvmenv/My_LLVM"));
+        assert_eq!(info.alternatives, vec!["clang", "ld.lld"]);
+        assert_eq!(
+            info.install_alternatives().unwrap(),
+            "update-alternatives --install /usr/bin/clang clang /opt/llvmenv/My_LLVM/bin/clang 0 \\\n  --slave /usr/bin/ld.lld ld.lld /opt/llvmenv/My_LLVM/bin/ld.lld"
+        );
+        assert!(deb_control(&info).starts_with("Package: llvmenv-my-llvm\nVersion: 0.0.0\n"));
+        let spec = rpm_spec(&info, &prefix);
+        assert!(spec.contains("Prefix: /opt/llvmenv\n"));
+        assert!(spec.contains(&format!(
+            "cp -a '{}/.' %{{buildroot}}/opt/llvmenv/My_LLVM\n",
+            prefix.display()
+        )));
+        let wxs = wxs(&info, &prefix);
+        assert!(wxs.contains(&format!("UpgradeCode=\"{}\"", guid("llvmenv-my-llvm"))));
+        assert!(wxs.contains("<Directory Id=\"INSTALLFOLDER\" Name=\"My_LLVM\" />"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_priority() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let priority = |major: u32, minor: u32, patch: u32| -> Result<u32> {
+            let prefix = tmp_dir
+                .path()
+                .join(format!("{}.{}.{}", major, minor, patch));
+            fs::create_dir_all(prefix.join("include/llvm/Config"))?;
+            fs::write(
+                prefix.join("include/llvm/Config/llvm-config.h"),
+                format!(
+                    "#define LLVM_VERSION_MAJOR {}\n#define LLVM_VERSION_MINOR {}\n#define LLVM_VERSION_PATCH {}\n",
+                    major, minor, patch
+                ),
+            )?;
+            Ok(PackageInfo::new(&Build::from_path(&prefix), None).priority)
+        };
+        assert_eq!(priority(17, 0, 6)?, 170006);
+        // Newer minor versions win over patch releases with two digits
+        assert!(priority(3, 9, 1)? > priority(3, 8, 10)?);
+        assert!(priority(18, 1, 0)? > priority(17, 0, 16)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_zip() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("my-llvm");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::write(prefix.join("bin/clang"), "clang")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("clang", prefix.join("bin/clang++"))?;
+        let path = package(
+            &Build::from_path(&prefix),
+            Format::Zip,
+            None,
+            tmp_dir.path(),
+        )?;
+        assert_eq!(path.file_name().unwrap(), "llvmenv-my-llvm-0.0.0.zip");
+        let mut zip = zip::ZipArchive::new(fs::File::open(&path)?)?;
+        assert!(zip.by_name("my-llvm/bin/clang").is_ok());
+        assert!(zip.by_name("my-llvm/add-to-path.ps1").is_ok());
+        #[cfg(unix)]
+        {
+            let mut link = zip.by_name("my-llvm/bin/clang++")?;
+            assert_eq!(link.unix_mode().unwrap() & 0o170000, 0o120000);
+            let mut target = String::new();
+            io::Read::read_to_string(&mut link, &mut target)?;
+            assert_eq!(target, "clang");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_guid() {
+        let g = guid("llvmenv-7.0.0");
+        assert_eq!(g.len(), 36);
+        assert_eq!(g, guid("llvmenv-7.0.0"));
+        assert_ne!(g, guid("llvmenv-8.0.0"));
+    }
+}
@@ -0,0 +1,140 @@
+//! Subcommand plugins
+//!
+//! Like git, `llvmenv foo args..` runs an executable `llvmenv-foo` in `$PATH` with `args..`
+//! if `foo` is not a subcommand of llvmenv. `llvmenv plugins` lists the plugins found.
+//! The plugin is executed with the environment variables:
+//!
+//! | Variable             | Value                                             |
+//! |:---------------------|:--------------------------------------------------|
+//! | `LLVMENV`            | Path of the llvmenv executable                    |
+//! | `LLVMENV_VERSION`    | Version of llvmenv                                |
+//! | `LLVMENV_CONFIG_DIR` | Directory of entry.toml and config.toml           |
+//! | `LLVMENV_DATA_DIR`   | Directory where builds are installed              |
+//! | `LLVMENV_CACHE_DIR`  | Directory of sources and build directories        |
+//! | `LLVMENV_BUILD`      | Name of the current build (not set if none)       |
+//! | `LLVMENV_PREFIX`     | Prefix of the current build (not set if none)     |
+//!
+//! `llvmenv-fetch-*` executables are [fetcher plugins](../fetcher/index.html), not subcommands.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build;
+use crate::config::{cache_dir, config_dir, data_dir};
+use crate::error::*;
+use crate::fetcher;
+
+pub const PLUGIN_PREFIX: &str = "llvmenv-";
+
+/// Name of the subcommand of a plugin executable, e.g. `foo` of `llvmenv-foo`
+///
+/// ```
+/// # use llvmenv::plugin::subcommand_name;
+/// assert_eq!(subcommand_name("llvmenv-foo"), Some("foo".to_string()));
+/// assert_eq!(subcommand_name("llvmenv-foo.exe"), Some("foo".to_string()));
+/// assert_eq!(subcommand_name("llvmenv-fetch-artifactory"), None);
+/// assert_eq!(subcommand_name("llvmenv"), None);
+/// ```
+pub fn subcommand_name(file_name: &str) -> Option<String> {
+    let name = file_name.strip_prefix(PLUGIN_PREFIX)?;
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+    if name.is_empty() || file_name.starts_with(fetcher::PLUGIN_PREFIX) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// Plugin executable of `subcommand` in `$PATH`
+pub fn find(subcommand: &str) -> Option<PathBuf> {
+    if subcommand.is_empty() || subcommand.starts_with('-') || subcommand.contains(['/', '\\']) {
+        return None;
+    }
+    let name = format!("{}{}", PLUGIN_PREFIX, subcommand);
+    subcommand_name(&name)?;
+    find_tool(&name).or_else(|| find_tool(&format!("{}.exe", name)))
+}
+
+/// Plugins in `$PATH` by the subcommand names. The first one in `$PATH` is used for duplicates.
+pub fn plugins() -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    let paths = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&paths) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if !is_executable(&path) {
+                continue;
+            }
+            if let Some(name) = subcommand_name(&entry.file_name().to_string_lossy()) {
+                plugins.entry(name).or_insert(path);
+            }
+        }
+    }
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Environment variables describing llvmenv for plugins
+pub fn vars() -> Result<Vec<(String, OsString)>> {
+    let mut vars = vec![
+        (
+            "LLVMENV_VERSION".to_string(),
+            env!("CARGO_PKG_VERSION").into(),
+        ),
+        ("LLVMENV_CONFIG_DIR".to_string(), config_dir()?.into()),
+        ("LLVMENV_DATA_DIR".to_string(), data_dir()?.into()),
+        ("LLVMENV_CACHE_DIR".to_string(), cache_dir()?.into()),
+    ];
+    if let Ok(exe) = env::current_exe() {
+        vars.push(("LLVMENV".to_string(), exe.into()));
+    }
+    if let Ok(build) = build::seek_build() {
+        vars.push(("LLVMENV_BUILD".to_string(), build.name().into()));
+        vars.push(("LLVMENV_PREFIX".to_string(), build.prefix().into()));
+    }
+    Ok(vars)
+}
+
+/// Run the plugin `exe` with `args`, returning its exit code
+pub fn run(exe: &Path, args: &[OsString]) -> Result<i32> {
+    let status = Command::new(exe).args(args).envs(vars()?).status()?;
+    // None if killed by a signal
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_executable() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let exe = tmp_dir.path().join("llvmenv-foo");
+        fs::write(&exe, "#!/bin/sh\n")?;
+        assert!(!is_executable(&exe));
+        fs::set_permissions(&exe, fs::Permissions::from_mode(0o755))?;
+        assert!(is_executable(&exe));
+        assert!(!is_executable(tmp_dir.path()));
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! Power and thermal aware builds on laptops
+//!
+//! `llvmenv build-entry` and `rebuild` check the power source and the thermal state of the host before building:
+//!
+//! - on battery power, the default number of jobs is reduced to `power.battery_jobs` (half if unset)
+//! - under thermal throttling, it is reduced to `power.throttled_jobs` (half if unset)
+//! - with `power.pause_below = <percent>`, the build is paused while on battery below the percentage,
+//!   and resumed on AC power. The power source is checked every `POLL_INTERVAL` during the build.
+//!
+//! `-j` and `build.jobs` are used as is, and `power.enabled = false` disables all of them.
+//!
+//! ```toml
+//! [power]
+//! battery_jobs = 2
+//! pause_below  = 20
+//! ```
+//!
+//! The state is read from `/sys/class/power_supply` and `/sys/class/thermal` on Linux, and by `pmset -g batt` and
+//! `pmset -g therm` on macOS. It is not detected on the other OSes. Pausing sends `SIGSTOP` and `SIGCONT` to
+//! the commands of the build and their children, which are run in their own process group for it, on Unix.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::PowerConfig;
+use crate::error;
+use crate::progress;
+
+/// Interval of checking the power source while paused and building
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Power source and thermal state of the host
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    /// Charge of the battery in percent
+    pub battery_percent: Option<u8>,
+    /// CPUs are throttled by the temperature
+    pub throttled: bool,
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+impl PowerState {
+    /// Current state, None if it cannot be detected on this OS
+    pub fn detect() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            Some(Self::from_sysfs(Path::new("/sys/class")))
+        } else if cfg!(target_os = "macos") {
+            let pmset = |arg: &str| -> Option<String> {
+                let output = Command::new("pmset").args(["-g", arg]).output().ok()?;
+                Some(String::from_utf8_lossy(&output.stdout).to_string())
+            };
+            Some(Self::from_pmset(
+                &pmset("batt")?,
+                &pmset("therm").unwrap_or_default(),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// State from `power_supply` and `thermal` under `class`, i.e. `/sys/class` on Linux
+    fn from_sysfs(class: &Path) -> Self {
+        let mut state = PowerState::default();
+        let mut mains_online = false;
+        let mut discharging = false;
+        if let Ok(supplies) = fs::read_dir(class.join("power_supply")) {
+            for supply in supplies.filter_map(|s| s.ok()).map(|s| s.path()) {
+                match read_trimmed(&supply.join("type")).as_deref() {
+                    Some("Mains") => {
+                        mains_online |= read_trimmed(&supply.join("online")).as_deref() == Some("1")
+                    }
+                    Some("Battery") => {
+                        discharging |=
+                            read_trimmed(&supply.join("status")).as_deref() == Some("Discharging");
+                        if let Some(capacity) =
+                            read_trimmed(&supply.join("capacity")).and_then(|c| c.parse().ok())
+                        {
+                            state.battery_percent = Some(capacity);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        state.on_battery = discharging && !mains_online;
+        if let Ok(zones) = fs::read_dir(class.join("thermal")) {
+            for zone in zones.filter_map(|z| z.ok()).map(|z| z.path()) {
+                let temp: i64 = match read_trimmed(&zone.join("temp")).and_then(|t| t.parse().ok())
+                {
+                    Some(temp) => temp,
+                    None => continue,
+                };
+                // Passive trip points are where the CPUs are throttled
+                for i in 0.. {
+                    let kind = match read_trimmed(&zone.join(format!("trip_point_{}_type", i))) {
+                        Some(kind) => kind,
+                        None => break,
+                    };
+                    let trip: Option<i64> =
+                        read_trimmed(&zone.join(format!("trip_point_{}_temp", i)))
+                            .and_then(|t| t.parse().ok());
+                    if kind == "passive" && trip.is_some_and(|trip| trip > 0 && temp >= trip) {
+                        state.throttled = true;
+                    }
+                }
+            }
+        }
+        state
+    }
+
+    /// State from the outputs of `pmset -g batt` and `pmset -g therm` on macOS
+    ///
+    /// ```
+    /// # use llvmenv::power::PowerState;
+    /// let batt = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=1)\t45%; discharging; 3:12 remaining present: true\n";
+    /// let therm = "CPU_Speed_Limit \t= 70\n";
+    /// let state = PowerState::from_pmset(batt, therm);
+    /// assert_eq!(state, PowerState { on_battery: true, battery_percent: Some(45), throttled: true });
+    /// let ac = PowerState::from_pmset("Now drawing from 'AC Power'\n", "CPU_Speed_Limit \t= 100\n");
+    /// assert!(!ac.on_battery && !ac.throttled);
+    /// ```
+    pub fn from_pmset(batt: &str, therm: &str) -> Self {
+        let battery_percent = batt
+            .split_whitespace()
+            .find_map(|word| word.strip_suffix("%;").and_then(|p| p.parse().ok()));
+        let speed_limit: Option<u32> = therm
+            .lines()
+            .find(|line| line.trim_start().starts_with("CPU_Speed_Limit"))
+            .and_then(|line| line.split('=').nth(1))
+            .and_then(|limit| limit.trim().parse().ok());
+        PowerState {
+            on_battery: batt.contains("'Battery Power'"),
+            battery_percent,
+            throttled: speed_limit.is_some_and(|limit| limit < 100),
+        }
+    }
+
+    /// The build is to be paused by `pause_below`
+    fn should_pause(&self, pause_below: Option<u8>) -> bool {
+        match (pause_below, self.battery_percent) {
+            (Some(threshold), Some(percent)) => self.on_battery && percent < threshold,
+            _ => false,
+        }
+    }
+}
+
+/// Number of jobs for `state` instead of `jobs`, with the reason if reduced
+///
+/// ```
+/// # use llvmenv::config::PowerConfig;
+/// # use llvmenv::power::{jobs_for, PowerState};
+/// let config = PowerConfig::default();
+/// let battery = PowerState { on_battery: true, ..Default::default() };
+/// assert_eq!(jobs_for(&battery, &config, 8).0, 4);
+/// assert_eq!(jobs_for(&PowerState::default(), &config, 8), (8, None));
+/// let config = PowerConfig { battery_jobs: Some(2), ..Default::default() };
+/// assert_eq!(jobs_for(&battery, &config, 8).0, 2);
+/// ```
+pub fn jobs_for(
+    state: &PowerState,
+    config: &PowerConfig,
+    jobs: usize,
+) -> (usize, Option<&'static str>) {
+    let half = (jobs / 2).max(1);
+    let mut reduced = (jobs, None);
+    if state.throttled {
+        reduced = (
+            config.throttled_jobs.unwrap_or(half).min(reduced.0),
+            Some("CPUs are throttled by the temperature"),
+        );
+    }
+    if state.on_battery {
+        reduced = (
+            config.battery_jobs.unwrap_or(half).min(reduced.0),
+            Some("on battery power"),
+        );
+    }
+    (reduced.0.max(1), reduced.1)
+}
+
+/// Default number of `jobs` reduced for the current power and thermal state, see [module level doc](index.html)
+pub fn default_jobs(config: &PowerConfig, jobs: usize) -> usize {
+    if !config.enabled() {
+        return jobs;
+    }
+    let state = match PowerState::detect() {
+        Some(state) => state,
+        None => return jobs,
+    };
+    match jobs_for(&state, config, jobs) {
+        (reduced, Some(reason)) if reduced < jobs => {
+            progress::message(&format!(
+                "Use {} jobs instead of {} since {}",
+                reduced, jobs, reason
+            ));
+            reduced
+        }
+        _ => jobs,
+    }
+}
+
+/// Thread pausing the build while on battery below `power.pause_below`, stopped when dropped
+pub struct Watch {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        error::set_paused(false);
+        error::set_suspendable(false);
+    }
+}
+
+/// Start watching the power source during a build if `power.pause_below` is set
+pub fn watch(config: &PowerConfig) -> Option<Watch> {
+    let pause_below = config
+        .pause_below
+        .filter(|_| config.enabled() && cfg!(unix))?;
+    PowerState::detect()?;
+    error::set_suspendable(true);
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    thread::spawn(move || {
+        let mut paused = false;
+        while !stopped.load(Ordering::SeqCst) {
+            if let Some(state) = PowerState::detect() {
+                let pause = state.should_pause(Some(pause_below));
+                if pause && !paused {
+                    progress::message(&format!(
+                        "Pause the build on battery power at {}% (power.pause_below = {}) until AC power is connected",
+                        state.battery_percent.unwrap_or(0),
+                        pause_below
+                    ));
+                } else if !pause && paused {
+                    progress::message("Resume the build");
+                }
+                if pause != paused && !stopped.load(Ordering::SeqCst) {
+                    error::set_paused(pause);
+                    paused = pause;
+                }
+            }
+            // Sleep in short steps to stop soon after the build
+            for _ in 0..POLL_INTERVAL.as_secs() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+    Some(Watch { stop })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_from_sysfs() {
+        let class = tempfile::TempDir::new().unwrap();
+        let class = class.path();
+        write(&class.join("power_supply/AC/type"), "Mains\n");
+        write(&class.join("power_supply/AC/online"), "0\n");
+        write(&class.join("power_supply/BAT0/type"), "Battery\n");
+        write(&class.join("power_supply/BAT0/status"), "Discharging\n");
+        write(&class.join("power_supply/BAT0/capacity"), "15\n");
+        write(&class.join("thermal/thermal_zone0/temp"), "92000\n");
+        write(
+            &class.join("thermal/thermal_zone0/trip_point_0_type"),
+            "critical\n",
+        );
+        write(
+            &class.join("thermal/thermal_zone0/trip_point_0_temp"),
+            "105000\n",
+        );
+        write(
+            &class.join("thermal/thermal_zone0/trip_point_1_type"),
+            "passive\n",
+        );
+        write(
+            &class.join("thermal/thermal_zone0/trip_point_1_temp"),
+            "95000\n",
+        );
+        let state = PowerState::from_sysfs(class);
+        assert_eq!(
+            state,
+            PowerState {
+                on_battery: true,
+                battery_percent: Some(15),
+                throttled: false
+            }
+        );
+        assert!(state.should_pause(Some(20)));
+        assert!(!state.should_pause(None));
+
+        write(&class.join("power_supply/AC/online"), "1\n");
+        write(&class.join("thermal/thermal_zone0/temp"), "96000\n");
+        let state = PowerState::from_sysfs(class);
+        assert!(!state.on_battery && state.throttled);
+        assert!(!state.should_pause(Some(20)));
+    }
+}
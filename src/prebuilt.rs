@@ -0,0 +1,431 @@
+//! Install official prebuilt LLVM/Clang releases
+//!
+//! Building LLVM takes hours, while the official releases are also distributed as prebuilt archives.
+//! `llvmenv install 17.0.6` downloads the archive for the current platform from GitHub releases
+//! (or [releases.llvm.org](https://releases.llvm.org/) for old versions not on GitHub),
+//! verifies it, and extracts it into `${data_dir}/17.0.6` as a build:
+//!
+//! ```shell
+//! $ llvmenv install 17.0.6
+//! $ llvmenv global 17.0.6
+//! ```
+//!
+//! Verification
+//! -------------
+//! - The size of the archive is compared with the one reported by GitHub
+//! - The detached signature (`*.sig`) of the release is checked by `gpg --verify`.
+//!   Import [the release keys](https://releases.llvm.org/release-keys.asc) beforehand.
+//!   The install fails if the release has no signature or `gpg` is not installed,
+//!   and `--no-verify` skips it, i.e. only the size is verified.
+//!
+//! The URL and SHA-256 of the archive are stored in the metadata of the build.
+
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::build::{check_case_collision, Build};
+use crate::config::{data_dir, download_cache_dir};
+use crate::error::*;
+use crate::github::{self, Asset};
+use crate::lockdown;
+use crate::network;
+use crate::progress::{run_phase, Phase};
+use crate::resource::{download_file, download_resumable, scratch_dir, unpack, EXTRACT_RATIO};
+use crate::shared;
+use crate::snapshot;
+use crate::tree;
+use crate::verify;
+use crate::version::{Pre, Version, VersionReq};
+
+/// Prebuilt archive to be downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Archive {
+    pub name: String,
+    pub url: String,
+    /// Expected size in bytes, if known
+    pub size: Option<u64>,
+    /// URL of the detached signature
+    pub sig_url: Option<String>,
+}
+
+/// Substrings of the archive names for the platform, e.g. `x86_64-linux-gnu` and `Linux-X64`
+/// (the names are changed since LLVM 19)
+pub fn platform_patterns(os: &str, arch: &str) -> Vec<&'static str> {
+    match (os, arch) {
+        ("linux", "x86_64") => vec!["x86_64-linux-gnu", "Linux-X64"],
+        ("linux", "aarch64") => vec!["aarch64-linux-gnu", "Linux-ARM64"],
+        ("macos", "x86_64") => vec!["x86_64-apple-darwin", "macOS-X64"],
+        ("macos", "aarch64") => vec!["arm64-apple-darwin", "macOS-ARM64"],
+        ("windows", "x86_64") => vec!["x86_64-pc-windows-msvc", "Windows-X64"],
+        _ => Vec::new(),
+    }
+}
+
+fn is_archive(name: &str) -> bool {
+    name.ends_with(".tar.xz") || name.ends_with(".tar.gz")
+}
+
+/// Asset of the platform. If there are several, e.g. for each Ubuntu release, the one built on `distro`
+/// (`ubuntu-22.04` form) is preferred, and otherwise the last one in name order (usually the newest).
+pub fn select_asset<'a>(
+    assets: &'a [Asset],
+    os: &str,
+    arch: &str,
+    distro: Option<&str>,
+) -> Option<&'a Asset> {
+    let patterns = platform_patterns(os, arch);
+    let mut candidates: Vec<&Asset> = assets
+        .iter()
+        .filter(|a| is_archive(&a.name) && patterns.iter().any(|p| a.name.contains(p)))
+        .collect();
+    if let Some(distro) = distro {
+        if let Some(asset) = candidates.iter().find(|a| a.name.contains(distro)) {
+            return Some(asset);
+        }
+    }
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+    candidates.pop()
+}
+
+/// `${ID}-${VERSION_ID}` of /etc/os-release, e.g. `ubuntu-22.04`
+fn host_distro() -> Option<String> {
+    let release = fs::read_to_string("/etc/os-release").ok()?;
+    let value = |key: &str| {
+        release
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(|v| v.trim_matches('"').to_string())
+    };
+    Some(format!("{}-{}", value("ID=")?, value("VERSION_ID=")?))
+}
+
+/// Archives on releases.llvm.org, used for the versions not released on GitHub
+fn legacy_candidates(version: &str, os: &str, arch: &str) -> Vec<Archive> {
+    let triples: &[&str] = match (os, arch) {
+        ("linux", "x86_64") => &[
+            "x86_64-linux-gnu-ubuntu-18.04",
+            "x86_64-linux-gnu-ubuntu-16.04",
+            "x86_64-linux-gnu-ubuntu-14.04",
+        ],
+        ("linux", "aarch64") => &["aarch64-linux-gnu"],
+        ("macos", "x86_64") => &["x86_64-apple-darwin"],
+        _ => &[],
+    };
+    triples
+        .iter()
+        .map(|triple| {
+            let name = format!("clang+llvm-{}-{}.tar.xz", version, triple);
+            let url = format!("https://releases.llvm.org/{}/{}", version, name);
+            Archive {
+                sig_url: Some(format!("{}.sig", url)),
+                name,
+                url,
+                size: None,
+            }
+        })
+        .collect()
+}
+
+fn exists(client: &reqwest::Client, url: &str) -> bool {
+    client
+        .head(url)
+        .send()
+        .map(|res| res.status().is_success())
+        .unwrap_or(false)
+}
+
+/// Latest release on GitHub matching `version`, e.g. `17` or `>=17` (see [VersionReq](../version/struct.VersionReq.html)).
+/// `version` is returned as is if it is a `major.minor.patch` version.
+pub fn resolve_version(version: &str) -> Result<String> {
+    let req: VersionReq = match version.parse() {
+        Ok(req) => req,
+        Err(_) => return Ok(version.to_string()),
+    };
+    if req.is_exact() {
+        return Ok(version.to_string());
+    }
+    let releases = github::Client::new()?.releases(github::LLVM_REPO)?;
+    let latest = releases
+        .iter()
+        .filter(|release| !release.prerelease)
+        .filter_map(|release| Version::from_name(&release.tag_name))
+        .filter(|v| v.pre == Pre::Release && req.matches(v))
+        .max()
+        .ok_or_else(|| format_err!("No release of LLVM matches '{}'", version))?;
+    info!("Resolve '{}' into {}", version, latest);
+    Ok(latest.to_string())
+}
+
+/// Prebuilt archive of `version` for the current platform
+pub fn find_archive(version: &str) -> Result<Archive> {
+    let (os, arch) = (env::consts::OS, env::consts::ARCH);
+    if platform_patterns(os, arch).is_empty() {
+        bail!("No prebuilt releases for {}-{}", arch, os);
+    }
+    let tag = format!("llvmorg-{}", version);
+    match github::Client::new()?.release(github::LLVM_REPO, &tag) {
+        Ok(release) => {
+            let asset = select_asset(&release.assets, os, arch, host_distro().as_deref())
+                .ok_or_else(|| {
+                    format_err!(
+                        "No prebuilt archive for {}-{} in release {}. See `llvmenv releases --assets`.",
+                        arch,
+                        os,
+                        tag
+                    )
+                })?;
+            let sig = format!("{}.sig", asset.name);
+            Ok(Archive {
+                name: asset.name.clone(),
+                url: asset.browser_download_url.clone(),
+                size: Some(asset.size),
+                sig_url: release
+                    .assets
+                    .iter()
+                    .find(|a| a.name == sig)
+                    .map(|a| a.browser_download_url.clone()),
+            })
+        }
+        Err(e) => {
+            info!(
+                "Release {} is not found on GitHub ({}), try releases.llvm.org",
+                tag, e
+            );
+            let client = network::client()?;
+            legacy_candidates(version, os, arch)
+                .into_iter()
+                .find(|archive| exists(&client, &archive.url))
+                .ok_or_else(|| {
+                    format_err!(
+                        "No prebuilt release of LLVM {} for {}-{}",
+                        version,
+                        arch,
+                        os
+                    )
+                })
+        }
+    }
+}
+
+pub(crate) fn sha256_file(path: &Path) -> Result<String> {
+    let mut f = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn download(url: &str, path: &Path) -> Result<()> {
+    info!("Download {}", url);
+    download_file(url, path)
+}
+
+/// Check the size and the signature of the downloaded archive. Fails if the signature cannot be checked,
+/// unless `signature` is false.
+fn verify(archive: &Archive, path: &Path, signature: bool) -> Result<()> {
+    if let Some(size) = archive.size {
+        let actual = fs::metadata(path)?.len();
+        if actual != size {
+            return Err(LlvmEnvError::ChecksumMismatch {
+                path: path.into(),
+                expected: format!("{} bytes", size),
+                actual: format!("{} bytes", actual),
+            });
+        }
+    }
+    if !signature {
+        warn!(
+            "The signature of {} is not verified by --no-verify",
+            archive.name
+        );
+        return Ok(());
+    }
+    let sig_url = archive.sig_url.as_ref().ok_or_else(|| {
+        format_err!(
+            "No signature of {} is published. Use --no-verify to install it verified only by the size.",
+            archive.name
+        )
+    })?;
+    if find_tool("gpg").is_none() {
+        bail!(
+            "gpg is not found to verify the signature of {}. Install it, or use --no-verify.",
+            archive.name
+        );
+    }
+    let sig = path.with_extension("xz.sig");
+    download(sig_url, &sig)?;
+    Command::new("gpg")
+        .arg("--verify")
+        .arg(&sig)
+        .arg(path)
+        .check_run()
+        .map_err(|e| {
+            format_err!(
+                "Signature of {} is not verified: {}. Import https://releases.llvm.org/release-keys.asc by `gpg --import`, or use --no-verify.",
+                archive.name,
+                e
+            )
+        })?;
+    Ok(())
+}
+
+/// Install the prebuilt archive of `version` as the build `name`, returns the build
+pub fn install(version: &str, name: Option<&str>, force: bool, signature: bool) -> Result<Build> {
+    let version = &resolve_version(version)?;
+    let name = name.unwrap_or(version);
+    let prefix = data_dir()?.join(name);
+    check_case_collision(&prefix)?;
+    shared::require_writable(&data_dir()?)?;
+    if prefix.exists() && !force {
+        bail!(
+            "Build '{}' already exists. Use --force to replace it.",
+            name
+        );
+    }
+    let archive = find_archive(version)?;
+    info!("Install {} as '{}'", archive.name, name);
+    let tmp_dir = scratch_dir(
+        download_cache_dir()?,
+        archive.size.unwrap_or(0) * EXTRACT_RATIO,
+    )?;
+    let working = tmp_dir.path();
+    let path = working.join(&archive.name);
+    run_phase(Phase::Download, &archive.url, || {
+        // Resumed from the download cache if interrupted, and moved into the working directory when complete
+        info!("Download {}", archive.url);
+        let downloaded = download_resumable(&archive.url, &download_cache_dir()?)?;
+        if fs::rename(&downloaded, &path).is_err() {
+            // [paths].tmp on another filesystem
+            fs::copy(&downloaded, &path)?;
+            fs::remove_file(&downloaded)?;
+        }
+        verify(&archive, &path, signature)
+    })?;
+    let sha256 = sha256_file(&path)?;
+    // Extracted next to the prefix, so that the existing build is kept until the new one is ready
+    let staging = tree::hidden_sibling(&prefix, "installing");
+    fs::create_dir(&staging)?;
+    let res = run_phase(Phase::Install, name, || {
+        unpack(&path, &staging)?;
+        let top: Vec<PathBuf> = fs::read_dir(&staging)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect();
+        let root = match top.as_slice() {
+            [dir] if dir.is_dir() => dir.clone(),
+            _ => staging.clone(),
+        };
+        if prefix.exists() {
+            snapshot::before("install")?;
+            tree::remove(&prefix)?;
+        }
+        fs::rename(&root, &prefix)?;
+        Ok(())
+    });
+    if staging.exists() {
+        tree::remove(&staging)?;
+    }
+    res?;
+    let build = Build::from_path(&prefix);
+    let mut metadata = build.metadata()?;
+    metadata.prebuilt = Some(archive.url.clone());
+    metadata.sha256 = Some(sha256);
+    build.save_metadata(&metadata)?;
+    verify::record(&build)?;
+    lockdown::after_install(&build)?;
+    Ok(build)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.into(),
+            size: 0,
+            browser_download_url: format!("https://example.com/{}", name),
+        }
+    }
+
+    #[test]
+    fn test_select_asset() {
+        let assets = vec![
+            asset("clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz"),
+            asset("clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz.sig"),
+            asset("clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-20.04.tar.xz"),
+            asset("clang+llvm-17.0.6-arm64-apple-darwin22.0.tar.xz"),
+            asset("LLVM-17.0.6-win64.exe"),
+        ];
+        let name = |a: Option<&Asset>| a.map(|a| a.name.clone());
+        assert_eq!(
+            name(select_asset(
+                &assets,
+                "linux",
+                "x86_64",
+                Some("ubuntu-20.04")
+            )),
+            Some("clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-20.04.tar.xz".into())
+        );
+        assert_eq!(
+            name(select_asset(&assets, "linux", "x86_64", Some("fedora-39"))),
+            Some("clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz".into())
+        );
+        assert_eq!(
+            name(select_asset(&assets, "macos", "aarch64", None)),
+            Some("clang+llvm-17.0.6-arm64-apple-darwin22.0.tar.xz".into())
+        );
+        assert_eq!(select_asset(&assets, "windows", "x86_64", None), None);
+
+        let assets = vec![asset("LLVM-19.1.0-Linux-X64.tar.xz")];
+        assert!(select_asset(&assets, "linux", "x86_64", None).is_some());
+    }
+
+    #[test]
+    fn test_verify_unsigned() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let path = tmp_dir.path().join("llvm.tar.xz");
+        fs::write(&path, "abc")?;
+        let archive = Archive {
+            name: "llvm.tar.xz".into(),
+            url: "https://example.com/llvm.tar.xz".into(),
+            size: Some(3),
+            sig_url: None,
+        };
+        assert!(verify(&archive, &path, true).is_err());
+        verify(&archive, &path, false)?;
+        let archive = Archive {
+            size: Some(4),
+            ..archive
+        };
+        assert!(verify(&archive, &path, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256_file() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let path = tmp_dir.path().join("a");
+        fs::write(&path, "abc")?;
+        assert_eq!(
+            sha256_file(&path)?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        Ok(())
+    }
+}
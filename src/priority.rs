@@ -0,0 +1,147 @@
+//! Low-priority builds
+//!
+//! `llvmenv build-entry --nice` (or `rebuild --nice`, or `build.nice = true` in config.toml) runs CMake
+//! and the compilers spawned by it with the lowest CPU priority, and the idle I/O class on Linux,
+//! so that a long build of a toolchain does not make the workstation unusable:
+//!
+//! | OS      | Priority                                           |
+//! |:--------|:---------------------------------------------------|
+//! | Linux   | `nice` 19 and `ionice -c idle`                     |
+//! | macOS   | `nice` 19                                          |
+//! | Windows | `IDLE_PRIORITY_CLASS`, inherited by child processes |
+//!
+//! llvmenv itself runs with the same priority, e.g. while verifying and copying the files of the install.
+//!
+//! The number of jobs detected from the host is also halved to leave CPUs for interactive use,
+//! while `-j` and `build.jobs` are used as is. It does not depend on whether the user is active.
+
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Niceness of builds, the lowest priority
+#[cfg(unix)]
+const NICENESS: libc::c_int = 19;
+
+static NICE: AtomicBool = AtomicBool::new(false);
+
+/// Run the builds with low priority, see [module level doc](index.html)
+pub fn set_nice(nice: bool) {
+    NICE.store(nice, Ordering::SeqCst);
+}
+
+pub fn is_nice() -> bool {
+    NICE.load(Ordering::SeqCst)
+}
+
+/// Lower the priority of llvmenv itself if [nice](fn.set_nice.html), inherited by all commands it runs
+pub fn lower_current_process() {
+    if !is_nice() {
+        return;
+    }
+    #[cfg(unix)]
+    lower();
+    #[cfg(windows)]
+    {
+        const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+        #[link(name = "kernel32")]
+        extern "system" {
+            fn GetCurrentProcess() -> *mut std::ffi::c_void;
+            fn SetPriorityClass(process: *mut std::ffi::c_void, class: u32) -> i32;
+        }
+        unsafe {
+            SetPriorityClass(GetCurrentProcess(), IDLE_PRIORITY_CLASS);
+        }
+    }
+}
+
+/// Set the lowest CPU priority and the idle I/O class of the current process.
+/// Only async-signal-safe calls are made, to be used between fork and exec.
+#[cfg(unix)]
+fn lower() {
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS);
+        #[cfg(target_os = "linux")]
+        {
+            const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+            const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+            const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            );
+        }
+    }
+}
+
+/// Default number of jobs for `jobs` CPUs, halved if [nice](fn.set_nice.html)
+///
+/// ```
+/// # use llvmenv::priority::*;
+/// assert_eq!(default_jobs(8), 8);
+/// set_nice(true);
+/// assert_eq!(default_jobs(8), 4);
+/// assert_eq!(default_jobs(1), 1);
+/// ```
+pub fn default_jobs(jobs: usize) -> usize {
+    if is_nice() {
+        (jobs / 2).max(1)
+    } else {
+        jobs
+    }
+}
+
+/// Lower the priority of commands if [nice](fn.set_nice.html)
+pub trait PriorityExt {
+    fn lower_priority(&mut self) -> &mut Self;
+}
+
+impl PriorityExt for Command {
+    #[cfg(unix)]
+    fn lower_priority(&mut self) -> &mut Self {
+        use std::os::unix::process::CommandExt;
+        if !is_nice() {
+            return self;
+        }
+        // Also if llvmenv itself is not lowered, e.g. by a library user
+        unsafe {
+            self.pre_exec(|| {
+                lower();
+                Ok(())
+            })
+        }
+    }
+
+    #[cfg(windows)]
+    fn lower_priority(&mut self) -> &mut Self {
+        use std::os::windows::process::CommandExt;
+        const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+        if is_nice() {
+            self.creation_flags(IDLE_PRIORITY_CLASS);
+        }
+        self
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn lower_priority(&mut self) -> &mut Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lower_priority() {
+        set_nice(true);
+        let output = Command::new("nice").lower_priority().output().unwrap();
+        set_nice(false);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().trim(),
+            NICENESS.to_string()
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Progress of downloads and builds
+//!
+//! Build and resource subsystems report their progress to [ProgressObserver][ProgressObserver]
+//! registered by [set_observer][set_observer]. By default progress is written into log,
+//! and the llvmenv command uses [TerminalObserver](./struct.TerminalObserver.html) drawing progress bars
+//! if stderr is a terminal.
+//!
+//! ```
+//! use llvmenv::progress::*;
+//!
+//! struct Steps;
+//! impl ProgressObserver for Steps {
+//!     fn on_build_step(&self, step: usize, total: usize) {
+//!         eprint!("\r{}/{}", step, total);
+//!     }
+//! }
+//! set_observer(Steps);
+//! ```
+//!
+//! [ProgressObserver]: ./trait.ProgressObserver.html
+//! [set_observer]: ./fn.set_observer.html
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use crate::error::{format_err, LlvmEnvError, Result};
+use crate::timing::format_duration;
+
+/// Phases of downloading and building entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Download resource (git clone, svn checkout, or tar archive)
+    Download,
+    /// Update downloaded resource
+    Update,
+    /// CMake configure
+    Configure,
+    /// Build (and install if [Install](#variant.Install) is also selected)
+    Build,
+    /// Install into the prefix
+    Install,
+    /// Remove a directory tree, see [tree](../tree/index.html)
+    Remove,
+    /// Copy a directory tree
+    Copy,
+    /// Archive a build by `llvmenv archive`
+    Archive,
+    /// Hash files of a build, see [verify](../verify/index.html)
+    Verify,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Download => "download",
+            Phase::Update => "update",
+            Phase::Configure => "configure",
+            Phase::Build => "build",
+            Phase::Install => "install",
+            Phase::Remove => "remove",
+            Phase::Copy => "copy",
+            Phase::Archive => "archive",
+            Phase::Verify => "verify",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Phase {
+    type Err = LlvmEnvError;
+
+    /// Phases selectable by `build-entry --only` and `--skip`
+    ///
+    /// ```
+    /// # use llvmenv::progress::Phase;
+    /// # use std::str::FromStr;
+    /// assert_eq!(Phase::from_str("configure").unwrap(), Phase::Configure);
+    /// assert!(Phase::from_str("update").is_err());
+    /// ```
+    fn from_str(phase: &str) -> Result<Self> {
+        Ok(match phase {
+            "download" => Phase::Download,
+            "configure" => Phase::Configure,
+            "build" => Phase::Build,
+            "install" => Phase::Install,
+            _ => return Err(format_err!("Unknown phase: {}", phase)),
+        })
+    }
+}
+
+/// Phases run by `build-entry` by default
+pub const ALL_PHASES: [Phase; 4] = [
+    Phase::Download,
+    Phase::Configure,
+    Phase::Build,
+    Phase::Install,
+];
+
+/// Receiver of progress. All methods do nothing by default, except [on_message](#method.on_message).
+pub trait ProgressObserver: Send + Sync {
+    /// `target` is URL for download/update, or the name of entry for configure/build
+    fn on_phase_start(&self, _phase: Phase, _target: &str) {}
+    /// Bytes received while downloading an archive. `total` is the size if known.
+    fn on_bytes(&self, _received: u64, _total: Option<u64>) {}
+    /// Objects received by git, or files checked out by svn. `total` is the number if known.
+    fn on_objects(&self, _received: u64, _total: Option<u64>) {}
+    /// Build step reported by the builder, e.g. `[123/4567]` of ninja
+    fn on_build_step(&self, _step: usize, _total: usize) {}
+    /// Estimate of the remaining time of the build, see [timing](../timing/index.html)
+    fn on_build_remaining(&self, _remaining: Duration) {}
+    /// Files processed while removing, copying, archiving, or verifying a tree. `total` is the number if known.
+    fn on_files(&self, _done: u64, _total: Option<u64>) {}
+    fn on_finish(&self, _phase: Phase, _success: bool) {}
+    /// Jobs finished of a [Scheduler](../jobs/struct.Scheduler.html)
+    fn on_jobs(&self, _done: usize, _total: usize) {}
+    /// Message for the user from a job, e.g. the summary of a build. Written into stderr by default.
+    fn on_message(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Default observer writing progress into log
+pub struct LogObserver;
+
+impl ProgressObserver for LogObserver {
+    fn on_phase_start(&self, phase: Phase, target: &str) {
+        info!("Start {}: {}", phase, target);
+    }
+    fn on_finish(&self, phase: Phase, success: bool) {
+        info!(
+            "Finish {} ({})",
+            phase,
+            if success { "ok" } else { "failed" }
+        );
+    }
+}
+
+/// Observer drawing the progress bar of downloads with bytes, rate, and ETA on the terminal,
+/// of builds with steps and the estimated remaining time, and of the operations on directory trees with files.
+/// Concurrent downloads and jobs have their own bars, distinguished by the thread reporting them,
+/// below the bar of the jobs finished. Messages are printed above the bars.
+/// Phases are also written into log as [LogObserver](./struct.LogObserver.html).
+#[derive(Default)]
+pub struct TerminalObserver {
+    bars: MultiProgress,
+    current: Mutex<HashMap<ThreadId, (ProgressBar, Unit)>>,
+    /// Bar of the jobs of a scheduler, above the others
+    jobs: Mutex<Option<ProgressBar>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Spinner,
+    Bytes,
+    Objects,
+    Files,
+    Steps,
+}
+
+impl TerminalObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the bar of the download in the current thread in `unit`
+    fn update(&self, unit: Unit, position: u64, total: Option<u64>) {
+        let mut current = self.current.lock().unwrap();
+        let (bar, current) = match current.get_mut(&thread::current().id()) {
+            Some(bar) => bar,
+            None => return,
+        };
+        if *current != unit {
+            let template = match (unit, total) {
+                (Unit::Bytes, Some(_)) => {
+                    "{spinner} {msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}"
+                }
+                (Unit::Bytes, None) => "{spinner} {msg} {bytes} {bytes_per_sec}",
+                (Unit::Files, Some(_)) => {
+                    "{spinner} {msg} [{bar:30}] {pos}/{len} files {per_sec} ETA {eta}"
+                }
+                (Unit::Steps, _) => {
+                    "{spinner} {msg} [{bar:30}] {pos}/{len} steps {elapsed} {prefix}"
+                }
+                (_, Some(_)) => {
+                    "{spinner} {msg} [{bar:30}] {pos}/{len} objects {per_sec} ETA {eta}"
+                }
+                (_, None) => "{spinner} {msg} {pos} files",
+            };
+            bar.set_style(style(template));
+            *current = unit;
+        }
+        if let Some(total) = total {
+            bar.set_length(total);
+        }
+        bar.set_position(position);
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("Valid template")
+        .progress_chars("=> ")
+}
+
+impl ProgressObserver for TerminalObserver {
+    fn on_phase_start(&self, phase: Phase, target: &str) {
+        LogObserver.on_phase_start(phase, target);
+        if phase == Phase::Configure {
+            return;
+        }
+        let bar = self.bars.add(ProgressBar::new_spinner());
+        bar.set_style(style("{spinner} {msg} {elapsed}"));
+        bar.set_message(format!("{} {}", phase, target));
+        bar.enable_steady_tick(Duration::from_millis(100));
+        self.current
+            .lock()
+            .unwrap()
+            .insert(thread::current().id(), (bar, Unit::Spinner));
+    }
+    fn on_bytes(&self, received: u64, total: Option<u64>) {
+        self.update(Unit::Bytes, received, total);
+    }
+    fn on_objects(&self, received: u64, total: Option<u64>) {
+        self.update(Unit::Objects, received, total);
+    }
+    fn on_files(&self, done: u64, total: Option<u64>) {
+        self.update(Unit::Files, done, total);
+    }
+    fn on_build_step(&self, step: usize, total: usize) {
+        self.update(Unit::Steps, step as u64, Some(total as u64));
+    }
+    fn on_build_remaining(&self, remaining: Duration) {
+        if let Some((bar, _)) = self.current.lock().unwrap().get(&thread::current().id()) {
+            bar.set_prefix(format!("about {} remaining", format_duration(remaining)));
+        }
+    }
+    fn on_finish(&self, phase: Phase, success: bool) {
+        if let Some((bar, _)) = self.current.lock().unwrap().remove(&thread::current().id()) {
+            bar.finish_and_clear();
+            self.bars.remove(&bar);
+        }
+        LogObserver.on_finish(phase, success);
+    }
+    fn on_jobs(&self, done: usize, total: usize) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if done >= total {
+            if let Some(bar) = jobs.take() {
+                bar.finish_and_clear();
+                self.bars.remove(&bar);
+            }
+            return;
+        }
+        let bar = jobs.get_or_insert_with(|| {
+            let bar = self.bars.insert(0, ProgressBar::new(total as u64));
+            bar.set_style(style("jobs [{bar:30}] {pos}/{len} {elapsed}"));
+            bar
+        });
+        bar.set_position(done as u64);
+    }
+    fn on_message(&self, message: &str) {
+        if self.bars.println(message).is_err() {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// Write `message` for the user through the [observer](fn.observer.html)
+pub fn message(message: &str) {
+    observer().on_message(message);
+}
+
+static OBSERVER: RwLock<Option<Arc<dyn ProgressObserver>>> = RwLock::new(None);
+
+/// Register observer used by the build and resource subsystems
+pub fn set_observer<O: ProgressObserver + 'static>(observer: O) {
+    *OBSERVER.write().unwrap() = Some(Arc::new(observer));
+}
+
+/// Current observer
+pub fn observer() -> Arc<dyn ProgressObserver> {
+    match &*OBSERVER.read().unwrap() {
+        Some(observer) => observer.clone(),
+        None => Arc::new(LogObserver),
+    }
+}
+
+/// Run `f` as `phase`, with notifying its start and finish
+pub(crate) fn run_phase<T, F>(phase: Phase, target: &str, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let observer = observer();
+    observer.on_phase_start(phase, target);
+    let res = f();
+    observer.on_finish(phase, res.is_ok());
+    res
+}
+
+/// Parse build step from a line of ninja (`[12/345] ...`) or make (`[ 12%] ...`) output
+pub(crate) fn parse_build_step(line: &str) -> Option<(usize, usize)> {
+    static NINJA: OnceLock<Regex> = OnceLock::new();
+    static MAKE: OnceLock<Regex> = OnceLock::new();
+    let ninja = NINJA.get_or_init(|| Regex::new(r"^\[(\d+)/(\d+)\]").unwrap());
+    if let Some(cap) = ninja.captures(line) {
+        return Some((cap[1].parse().ok()?, cap[2].parse().ok()?));
+    }
+    let make = MAKE.get_or_init(|| Regex::new(r"^\[\s*(\d+)%\]").unwrap());
+    if let Some(cap) = make.captures(line) {
+        return Some((cap[1].parse().ok()?, 100));
+    }
+    None
+}
+
+/// Parse objects received from a progress line of `git clone --progress`, e.g. `Receiving objects:  12% (34/567)`
+pub(crate) fn parse_git_progress(line: &str) -> Option<(u64, u64)> {
+    static GIT: OnceLock<Regex> = OnceLock::new();
+    let git = GIT.get_or_init(|| Regex::new(r"Receiving objects:\s+\d+% \((\d+)/(\d+)\)").unwrap());
+    let cap = git.captures(line)?;
+    Some((cap[1].parse().ok()?, cap[2].parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_progress() {
+        assert_eq!(
+            parse_git_progress("Receiving objects:  12% (1234/10283), 1.20 MiB | 2.39 MiB/s"),
+            Some((1234, 10283))
+        );
+        assert_eq!(
+            parse_git_progress("remote: Counting objects: 100% (5/5), done."),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_build_step() {
+        assert_eq!(
+            parse_build_step("[12/3456] Building CXX object lib/Support/CMakeFiles/..."),
+            Some((12, 3456))
+        );
+        assert_eq!(
+            parse_build_step("[  7%] Building CXX object lib/Support/CMakeFiles/..."),
+            Some((7, 100))
+        );
+        assert_eq!(parse_build_step("-- Configuring done"), None);
+    }
+}
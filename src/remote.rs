@@ -0,0 +1,306 @@
+//! Share builds between llvmenv installations over SSH
+//!
+//! `llvmenv push ssh://[user@]host[:port] [name]` copies a build into the data directory of llvmenv on the host,
+//! and `llvmenv pull ssh://[user@]host[:port] <name>` copies one from the host.
+//! The transfer uses `rsync` over `ssh`, and llvmenv is also required on the host
+//! to find its data directory (by hidden `llvmenv __remote` commands).
+//!
+//! Since the data directories may differ between the machines,
+//! the old prefix written in text files (e.g. CMake config files) is replaced by the new one after the transfer.
+//! Only whole paths are replaced, i.e. `/x/7.0.0` is not replaced in `/x/7.0.0-debug`.
+//! Binaries are not modified since LLVM uses relative `RPATH`.
+//!
+//! The transfer makes the destination identical to the source, and removes the files only in the destination.
+//! Replacing an existing build is confirmed on a terminal, or requires `--force`.
+
+use log::info;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use crate::build::Build;
+use crate::config::data_dir;
+use crate::error::*;
+use crate::lockdown;
+use crate::setup::confirm;
+
+/// Text files larger than this are not relocated
+const RELOCATE_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Host specified by `ssh://[user@]host[:port]`
+///
+/// ```
+/// # use llvmenv::remote::Remote;
+/// let remote: Remote = "ssh://me@example.com:2222".parse().unwrap();
+/// assert_eq!(remote.dest, "me@example.com");
+/// assert_eq!(remote.port, Some(2222));
+/// assert!("example.com".parse::<Remote>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Remote {
+    pub dest: String,
+    pub port: Option<u16>,
+}
+
+impl std::str::FromStr for Remote {
+    type Err = LlvmEnvError;
+    fn from_str(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("ssh://")
+            .ok_or_else(|| format_err!("Remote must be ssh://[user@]host[:port]: {}", url))?;
+        let rest = rest.trim_end_matches('/');
+        if rest.is_empty() || rest.contains('/') {
+            bail!("Invalid remote: {}", url);
+        }
+        match rest.rfind(':') {
+            Some(pos) => {
+                let port = rest[pos + 1..]
+                    .parse()
+                    .map_err(|_| format_err!("Invalid port: {}", url))?;
+                Ok(Remote {
+                    dest: rest[..pos].into(),
+                    port: Some(port),
+                })
+            }
+            None => Ok(Remote {
+                dest: rest.into(),
+                port: None,
+            }),
+        }
+    }
+}
+
+impl Remote {
+    fn ssh(&self) -> String {
+        match self.port {
+            Some(port) => format!("ssh -p {}", port),
+            None => "ssh".into(),
+        }
+    }
+
+    /// Run llvmenv on the host and returns its stdout
+    fn llvmenv(&self, args: &[&str]) -> Result<String> {
+        check_tool("ssh")?;
+        let mut cmd = Command::new("ssh");
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        cmd.arg(&self.dest)
+            .arg("llvmenv")
+            .args(args.iter().map(|a| shell_quote(a)));
+        info!("{:?}", cmd);
+        let output = cmd.output()?;
+        if !output.status.success() {
+            bail!(
+                "llvmenv on {} failed: {}",
+                self.dest,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn rsync(&self, src: &str, dst: &str) -> Result<()> {
+        check_tool("rsync")?;
+        Command::new("rsync")
+            .args(["-az", "--delete", "--protect-args", "-e"])
+            .arg(self.ssh())
+            .arg(src)
+            .arg(dst)
+            .check_run()?;
+        Ok(())
+    }
+
+    /// Copy the build into the data directory on the host. An existing one is replaced only if confirmed or `force`.
+    pub fn push(&self, build: &Build, force: bool) -> Result<()> {
+        let dir = dir_name(build)?;
+        if self.llvmenv(&["__remote", "exists", &dir])? == "true" {
+            check_replace(&format!("Build '{}' on {}", dir, self.dest), force)?;
+        }
+        let remote_prefix = format!("{}/{}", self.llvmenv(&["__remote", "data-dir"])?, dir);
+        let old_prefix = build.prefix().to_string_lossy().to_string();
+        self.rsync(
+            &format!("{}/", old_prefix),
+            &format!("{}:{}/", self.dest, remote_prefix),
+        )?;
+        self.llvmenv(&["__remote", "relocate", &dir, &old_prefix])?;
+        Ok(())
+    }
+
+    /// Copy the build `name` on the host into the data directory. An existing one is replaced only if confirmed or `force`.
+    pub fn pull(&self, name: &str, force: bool) -> Result<Build> {
+        let remote_prefix = self.llvmenv(&["__remote", "prefix", name])?;
+        let dir = Path::new(&remote_prefix)
+            .file_name()
+            .ok_or_else(|| format_err!("Invalid prefix on {}: {}", self.dest, remote_prefix))?
+            .to_owned();
+        let build = Build::from_path(&data_dir()?.join(dir));
+        let exists = build.exists();
+        if exists {
+            check_replace(&format!("Build '{}'", build.name()), force)?;
+        }
+        // A locked build is updated in place, and locked again
+        lockdown::unlocked(build.prefix(), || {
+            self.rsync(
+                &format!("{}:{}/", self.dest, remote_prefix),
+                &format!("{}/", build.prefix().display()),
+            )?;
+            relocate(build.prefix(), &remote_prefix)
+        })?;
+        if !exists {
+            lockdown::after_install(&build)?;
+        }
+        Ok(build)
+    }
+}
+
+/// Ask whether to replace the existing build, which fails without a terminal unless `force`
+fn check_replace(what: &str, force: bool) -> Result<()> {
+    if force {
+        return Ok(());
+    }
+    if !is_tty() {
+        bail!("{} already exists. Use --force to replace it", what);
+    }
+    let question = format!(
+        "{} already exists. Replace it, removing the files not in the source?",
+        what
+    );
+    if !confirm(&question, false)? {
+        bail!("{} is not replaced", what);
+    }
+    Ok(())
+}
+
+fn dir_name(build: &Build) -> Result<String> {
+    if build.name() == "system" {
+        bail!("system build cannot be transferred");
+    }
+    Ok(build
+        .prefix()
+        .file_name()
+        .ok_or_else(|| format_err!("Invalid prefix: {}", build.prefix().display()))?
+        .to_string_lossy()
+        .to_string())
+}
+
+/// Replace `old_prefix` in text files under `prefix` by `prefix`, and returns the number of modified files
+pub fn relocate(prefix: &Path, old_prefix: &str) -> Result<usize> {
+    let new_prefix = prefix.to_string_lossy();
+    if new_prefix == old_prefix {
+        return Ok(0);
+    }
+    let mut modified = 0;
+    relocate_dir(prefix, old_prefix, &new_prefix, &mut modified)?;
+    info!("Relocated {} files from {}", modified, old_prefix);
+    Ok(modified)
+}
+
+fn relocate_dir(dir: &Path, old: &str, new: &str, modified: &mut usize) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let ty = entry.file_type()?;
+        let path = entry.path();
+        if ty.is_dir() {
+            relocate_dir(&path, old, new, modified)?;
+        } else if ty.is_file() && entry.metadata()?.len() <= RELOCATE_MAX_SIZE {
+            let mut buf = Vec::new();
+            fs::File::open(&path)?.read_to_end(&mut buf)?;
+            if buf.contains(&0) {
+                continue; // binary
+            }
+            let text = match String::from_utf8(buf) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+            if let Some(text) = replace_path(&text, old, new) {
+                fs::write(&path, text)?;
+                *modified += 1;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Characters of file names continuing a path, e.g. `7.0.0` in `7.0.0-debug`
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || "._-+~".contains(c)
+}
+
+/// Replace the whole paths `old` in `text` by `new`. Returns `None` if nothing is replaced.
+fn replace_path(text: &str, old: &str, new: &str) -> Option<String> {
+    let mut replaced = String::with_capacity(text.len());
+    let mut last = 0;
+    for (pos, _) in text.match_indices(old) {
+        if pos < last {
+            continue; // overlapping
+        }
+        let end = pos + old.len();
+        let before = text[..pos].chars().next_back();
+        let after = text[end..].chars().next();
+        if before.map_or(false, |c| is_name_char(c) || c == '/')
+            || after.map_or(false, is_name_char)
+        {
+            continue;
+        }
+        replaced.push_str(&text[last..pos]);
+        replaced.push_str(new);
+        last = end;
+    }
+    if last == 0 {
+        return None;
+    }
+    replaced.push_str(&text[last..]);
+    Some(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote() -> Result<()> {
+        let remote: Remote = "ssh://host".parse()?;
+        assert_eq!(remote.dest, "host");
+        assert_eq!(remote.port, None);
+        assert!("ssh://host/path".parse::<Remote>().is_err());
+        assert!("ssh://host:port".parse::<Remote>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relocate() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path();
+        let old = "/home/old/.local/share/llvmenv/7.0.0";
+        fs::create_dir_all(prefix.join("lib/cmake/llvm"))?;
+        let config = prefix.join("lib/cmake/llvm/LLVMExports.cmake");
+        fs::write(&config, format!("set(LLVM_PREFIX \"{}\")\n", old))?;
+        let other = prefix.join("lib/cmake/llvm/Other.cmake");
+        let other_text = format!("{}-debug\n/opt{}\n", old, old);
+        fs::write(&other, &other_text)?;
+        let binary = prefix.join("lib/libLLVM.so");
+        fs::write(&binary, format!("\0{}", old))?;
+
+        assert_eq!(relocate(prefix, old)?, 1);
+        assert_eq!(
+            fs::read_to_string(&config)?,
+            format!("set(LLVM_PREFIX \"{}\")\n", prefix.display())
+        );
+        assert_eq!(fs::read_to_string(&other)?, other_text);
+        assert_eq!(fs::read(&binary)?, format!("\0{}", old).into_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_path() {
+        let old = "/x/7.0.0";
+        assert_eq!(
+            replace_path("\"/x/7.0.0/lib\";/x/7.0.0 /x/7.0.0:", old, "/y"),
+            Some("\"/y/lib\";/y /y:".into())
+        );
+        assert_eq!(replace_path("/x/7.0.0-debug /x/7.0.01", old, "/y"), None);
+        assert_eq!(replace_path("/opt/x/7.0.0", old, "/y"), None);
+    }
+}
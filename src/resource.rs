@@ -1,24 +1,47 @@
 //! Get remote LLVM/Clang source
+//!
+//! Offline
+//! --------
+//! `llvmenv build-entry --offline` uses the files placed by hand in the download cache
+//! (`${XDG_CACHE_HOME}/llvmenv/download/`) instead of any network access, e.g. on air-gapped CI machines:
+//!
+//! | Resource | File                                                                                  |
+//! |:---------|:--------------------------------------------------------------------------------------|
+//! | Tar      | the file name of the URL, e.g. `llvm-17.0.6.src.tar.xz`                               |
+//! | Git      | `<repository>.bundle` created by `git bundle create`, e.g. `llvm-project.bundle`      |
+//!
+//! See [Resource::seeded_path](enum.Resource.html#method.seeded_path). The download fails naming the expected file
+//! if it is missing. The origin of a clone from the bundle is set to the URL, so that it can be updated online later.
+//! SVN checkouts cannot be seeded, and neither can the sources of [fetcher plugins](../fetcher/index.html).
 
-use failure::{bail, err_msg};
-use log::info;
-use reqwest;
-use std::fs;
+use log::{info, warn};
+use reqwest::{self, header, Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
 use std::path::*;
 use std::process::Command;
+use std::time::Duration;
+use std::{env, fs, thread};
 use tempfile::TempDir;
 use url::Url;
 
+use crate::auth;
 use crate::config::*;
 use crate::error::*;
+use crate::network;
+use crate::progress::{observer, parse_git_progress, run_phase, Phase};
 
 /// Remote LLVM/Clang resource
 #[derive(Debug, PartialEq)]
 pub enum Resource {
     /// Remote Subversion repository
     Svn { url: String },
-    /// Remote Git repository
-    Git { url: String, branch: Option<String> },
+    /// Remote Git repository, shallowly cloned at `tag` if given
+    Git {
+        url: String,
+        branch: Option<String>,
+        tag: Option<String>,
+    },
     /// Tar archive
     Tar { url: String },
 }
@@ -30,26 +53,46 @@ impl Resource {
     /// # use llvmenv::resource::Resource;
     /// // Official SVN repository
     /// let llvm_official_url = "http://llvm.org/svn/llvm-project/llvm/trunk";
-    /// let svn = Resource::from_url(llvm_official_url, None).unwrap();
+    /// let svn = Resource::from_url(llvm_official_url, None, None).unwrap();
     /// assert_eq!(svn, Resource::Svn { url: llvm_official_url.into() });
     ///
     /// // GitHub mirror
     /// let github_mirror = "https://github.com/llvm-mirror/llvm";
-    /// let git = Resource::from_url(github_mirror, None).unwrap();
-    /// assert_eq!(git, Resource::Git { url: github_mirror.into(), branch: None });
+    /// let git = Resource::from_url(github_mirror, None, None).unwrap();
+    /// assert_eq!(git, Resource::Git { url: github_mirror.into(), branch: None, tag: None });
     ///
     /// // GitHub mirror w/branch
     /// let github_mirror = "https://github.com/llvm-mirror/llvm";
     /// let branch = Some("release_70".to_owned());
-    /// let git = Resource::from_url(github_mirror, branch.clone()).unwrap();
-    /// assert_eq!(git, Resource::Git { url: github_mirror.into(), branch  });
+    /// let git = Resource::from_url(github_mirror, branch.clone(), None).unwrap();
+    /// assert_eq!(git, Resource::Git { url: github_mirror.into(), branch, tag: None });
+    ///
+    /// // LLVM monorepo w/tag
+    /// let monorepo = "https://github.com/llvm/llvm-project";
+    /// let tag = Some("llvmorg-17.0.6".to_owned());
+    /// let git = Resource::from_url(monorepo, None, tag.clone()).unwrap();
+    /// assert_eq!(git, Resource::Git { url: monorepo.into(), branch: None, tag });
     ///
     /// // Tar release
     /// let tar_url = "http://releases.llvm.org/6.0.1/llvm-6.0.1.src.tar.xz";
-    /// let tar = Resource::from_url(tar_url, None).unwrap();
+    /// let tar = Resource::from_url(tar_url, None, None).unwrap();
     /// assert_eq!(tar, Resource::Tar { url: tar_url.into() });
+    /// assert!(Resource::from_url(tar_url, None, Some("llvmorg-6.0.1".into())).is_err());
     /// ```
-    pub fn from_url(url_str: &str, branch: Option<String>) -> Result<Self> {
+    pub fn from_url(url_str: &str, branch: Option<String>, tag: Option<String>) -> Result<Self> {
+        if branch.is_some() && tag.is_some() {
+            bail!("Only one of branch or tag is allowed: {}", url_str);
+        }
+        let resource = Self::detect(url_str, branch, tag.clone())?;
+        if let Some(tag) = tag {
+            if !matches!(resource, Resource::Git { .. }) {
+                bail!("Tag '{}' is only for Git repositories: {}", tag, url_str);
+            }
+        }
+        Ok(resource)
+    }
+
+    fn detect(url_str: &str, branch: Option<String>, tag: Option<String>) -> Result<Self> {
         // Check file extension
         if let Ok(filename) = get_filename_from_url(url_str) {
             for ext in &[".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"] {
@@ -73,6 +116,7 @@ impl Resource {
                 return Ok(Resource::Git {
                     url: url_str.into(),
                     branch,
+                    tag,
                 });
             }
         }
@@ -85,6 +129,7 @@ impl Resource {
                 return Ok(Resource::Git {
                     url: url_str.into(),
                     branch,
+                    tag,
                 });
             }
         }
@@ -101,10 +146,15 @@ impl Resource {
                 return Ok(Resource::Git {
                     url: url_str.into(),
                     branch,
+                    tag,
                 });
             }
         }
 
+        if network::is_offline() {
+            bail!("Type of {} cannot be detected offline. Use a URL of a Tar archive or ending with .git", url_str);
+        }
+
         // Try access with git
         //
         // - SVN repository cannot handle git access
@@ -116,20 +166,21 @@ impl Resource {
         // git ls-remote       # This must fail for SVN repo
         // ```
         info!("Try access with git to {}", url_str);
-        let tmp_dir = TempDir::new()?;
-        Command::new("git")
+        check_tool("git")?;
+        let tmp_dir = scratch_dir(env::temp_dir(), PROBE_SPACE)?;
+        network::git()?
             .arg("init")
             .current_dir(tmp_dir.path())
             .silent()
             .check_run()?;
-        Command::new("git")
-            .args(&["remote", "add", "origin"])
+        network::git()?
+            .args(["remote", "add", "origin"])
             .arg(url_str)
             .current_dir(tmp_dir.path())
             .silent()
             .check_run()?;
-        match Command::new("git")
-            .args(&["ls-remote"])
+        match network::git()?
+            .args(["ls-remote"])
             .current_dir(tmp_dir.path())
             .silent()
             .check_run()
@@ -139,6 +190,7 @@ impl Resource {
                 Ok(Resource::Git {
                     url: url_str.into(),
                     branch,
+                    tag,
                 })
             }
             Err(_) => {
@@ -150,101 +202,491 @@ impl Resource {
         }
     }
 
+    /// URL of this resource
+    pub fn url(&self) -> &str {
+        match self {
+            Resource::Svn { url } | Resource::Git { url, .. } | Resource::Tar { url } => url,
+        }
+    }
+
     pub fn download(&self, dest: &Path) -> Result<()> {
-        if !dest.exists() {
+        run_phase(Phase::Download, self.url(), || self.download_to(dest))
+    }
+
+    /// File in the download cache used instead of the network by `--offline`, see [module level doc](index.html#offline)
+    ///
+    /// ```
+    /// # use llvmenv::resource::Resource;
+    /// # use llvmenv::config::download_cache_dir;
+    /// let tar = Resource::Tar { url: "https://example.com/llvm-17.0.6.src.tar.xz".into() };
+    /// assert_eq!(tar.seeded_path().unwrap(), download_cache_dir().unwrap().join("llvm-17.0.6.src.tar.xz"));
+    /// let git = Resource::Git { url: "https://github.com/llvm/llvm-project.git".into(), branch: None, tag: None };
+    /// assert_eq!(git.seeded_path().unwrap(), download_cache_dir().unwrap().join("llvm-project.bundle"));
+    /// ```
+    pub fn seeded_path(&self) -> Result<PathBuf> {
+        let filename = match self {
+            Resource::Tar { url } => get_filename_from_url(url)?,
+            Resource::Git { url, .. } => {
+                let name = get_filename_from_url(url.trim_end_matches('/'))?;
+                format!("{}.bundle", name.trim_end_matches(".git"))
+            }
+            Resource::Svn { url } => bail!("SVN repository cannot be used offline: {}", url),
+        };
+        if filename.is_empty() {
+            bail!("URL has no file name to be seeded: {}", self.url());
+        }
+        Ok(download_cache_dir()?.join(filename))
+    }
+
+    /// Extract or clone the [seeded file](#method.seeded_path) into `dest`
+    fn download_seeded(&self, dest: &Path) -> Result<()> {
+        let seeded = self.seeded_path()?;
+        if !seeded.is_file() {
+            bail!(
+                "{} is required to download {} offline. Place it by hand, or build without --offline",
+                seeded.display(),
+                self.url()
+            );
+        }
+        info!("Use {} offline", seeded.display());
+        match self {
+            Resource::Tar { .. } => {
+                if is_dry_run() {
+                    let dest = quote_if_needed(&dest.display().to_string());
+                    println!("mkdir -p {}", dest);
+                    println!(
+                        "tar -xf {} --strip-components=1 -C {}",
+                        quote_if_needed(&seeded.display().to_string()),
+                        dest
+                    );
+                    return Ok(());
+                }
+                let required = fs::metadata(&seeded)?.len() * EXTRACT_RATIO;
+                let tmp_dir = scratch_dir(download_cache_dir()?, required)?;
+                unpack(&seeded, tmp_dir.path())?;
+                fs::create_dir_all(dest)?;
+                copy_top_dir(tmp_dir.path(), dest, &seeded.display().to_string())?;
+                record_archive(&seeded, self.url(), dest)
+            }
+            Resource::Git { url, branch, tag } => {
+                check_tool("git")?;
+                let mut git = network::git()?;
+                git.arg("clone").arg(&seeded);
+                if let Some(rev) = tag.as_ref().or(branch.as_ref()) {
+                    git.args(["--branch", rev]);
+                }
+                git.arg(dest)
+                    .check_run()
+                    .map_err(|e| download_error(url, e))?;
+                network::git()?
+                    .args(["remote", "set-url", "origin", url])
+                    .current_dir(dest)
+                    .check_run()?;
+                Ok(())
+            }
+            Resource::Svn { .. } => unreachable!("SVN cannot be seeded"),
+        }
+    }
+
+    fn download_to(&self, dest: &Path) -> Result<()> {
+        if network::is_offline() {
+            return self.download_seeded(dest);
+        }
+        if let (true, Resource::Tar { url }) = (is_dry_run(), self) {
+            let archive = download_cache_dir()?.join(get_filename_from_url(url)?);
+            let archive = quote_if_needed(&archive.display().to_string());
+            let dest = quote_if_needed(&dest.display().to_string());
+            println!("curl -fL -o {} {}", archive, quote_if_needed(url));
+            println!("mkdir -p {}", dest);
+            println!("tar -xf {} --strip-components=1 -C {}", archive, dest);
+            return Ok(());
+        }
+        if !dest.exists() && !is_dry_run() {
             fs::create_dir_all(dest)?;
         }
-        if !dest.is_dir() {
+        if dest.exists() && !dest.is_dir() {
             bail!(
                 "Download destination must be a directory: {}",
                 dest.display()
             );
         }
+        if let Some(tool) = self.tool() {
+            check_tool(tool)?;
+        }
         match self {
-            Resource::Svn { url, .. } => Command::new("svn")
-                .args(&["co", url.as_str(), "-r", "HEAD"])
-                .arg(dest)
-                .check_run()?,
-            Resource::Git { url, branch } => {
+            Resource::Svn { url, .. } => {
+                let observer = observer();
+                let mut files = 0;
+                Command::new("svn")
+                    .args(["co", url.as_str(), "-r", "HEAD"])
+                    .arg(dest)
+                    .check_run_progress(|line| {
+                        if !line.starts_with("A ") {
+                            return false;
+                        }
+                        files += 1;
+                        observer.on_objects(files, None);
+                        true
+                    })
+                    .map_err(|e| download_error(url, e))?
+            }
+            Resource::Git { url, branch, tag } => {
                 info!("Git clone {}", url);
-                let mut git = Command::new("git");
+                let mut git = network::git()?;
                 git.arg("clone")
-                   .arg(url.as_str())
-                   .args(&["--depth", "1"])
-                   .arg("--no-single-branch");
+                    .arg("--progress")
+                    .arg(url.as_str())
+                    .args(["--depth", "1"]);
+                if cfg!(windows) {
+                    // Checkouts of LLVM exceed MAX_PATH
+                    git.args(["--config", "core.longpaths=true"]);
+                }
+                if let Some(tag) = tag {
+                    // Only the tagged commit is fetched
+                    git.args(["--branch", tag]);
+                } else {
+                    git.arg("--no-single-branch");
+                }
                 if let Some(branch) = branch {
-                    git.args(&["-b", branch]);
+                    git.args(["-b", branch]);
                 }
                 git.arg(dest);
-                git.check_run()?;
+                git_with_progress(&mut git).map_err(|e| download_error(url, e))?;
             }
             Resource::Tar { url } => {
                 info!("Download Tar file: {}", url);
-                let working = cache_dir()?.join(".tar_download");
-                fs::create_dir_all(&working)?;
-                let filename = get_filename_from_url(url)?;
-                let path = working.join(&filename);
-                let mut req = reqwest::get(url)?;
-                let mut f = fs::File::create(&path)?;
-                req.copy_to(&mut f)?;
-                Command::new("tar")
-                    .arg("xf")
-                    .arg(filename)
-                    .current_dir(&working)
-                    .check_run()?;
-                let d = fs::read_dir(&working)?
-                    .map(|d| d.unwrap())
-                    .filter(|d| d.file_type().unwrap().is_dir())
-                    .nth(0)
-                    .expect("Archive does not contains file");
-                for contents in fs::read_dir(d.path())? {
-                    let path = contents?.path();
-                    if path.is_dir() {
-                        let opt = fs_extra::dir::CopyOptions::new();
-                        fs_extra::dir::copy(path, dest, &opt)?;
-                    } else {
-                        fs::copy(&path, dest.join(path.file_name().unwrap()))?;
-                    }
-                }
+                let required = remote_size(url).unwrap_or(0) * EXTRACT_RATIO;
+                let tmp_dir = scratch_dir(download_cache_dir()?, required)?;
+                let working = tmp_dir.path();
+                let archive = download_resumable(url, &download_cache_dir()?)?;
+                let res = unpack(&archive, working)
+                    .and_then(|_| copy_top_dir(working, dest, url))
+                    .and_then(|_| record_archive(&archive, url, dest));
+                fs::remove_file(&archive)?;
+                res?;
             }
         }
         Ok(())
     }
 
+    /// External tool required to download and update this resource. Tar archives are extracted by llvmenv itself.
+    pub fn tool(&self) -> Option<&'static str> {
+        match self {
+            Resource::Svn { .. } => Some("svn"),
+            Resource::Git { .. } => Some("git"),
+            Resource::Tar { .. } => None,
+        }
+    }
+
     pub fn update(&self, dest: &Path) -> Result<()> {
+        run_phase(Phase::Update, self.url(), || self.update_in(dest))
+    }
+
+    fn update_in(&self, dest: &Path) -> Result<()> {
+        if network::is_offline() && !matches!(self, Resource::Tar { .. }) {
+            bail!("Cannot update {} offline", self.url());
+        }
+        if let Some(tool) = self.tool() {
+            check_tool(tool)?;
+        }
         match self {
             Resource::Svn { .. } => Command::new("svn")
                 .arg("update")
                 .current_dir(dest)
                 .check_run()?,
-            Resource::Git { branch: Some(branch), .. } => {
+            Resource::Git { tag: Some(tag), .. } => {
+                info!("Git checkout is fixed at tag '{}'", tag);
+            }
+            Resource::Git {
+                branch: Some(branch),
+                ..
+            } => {
                 // Checkout branch
-                Command::new("git")
+                network::git()?
                     .arg("checkout")
-                    .args(&["-B", branch])
+                    .args(["-B", branch])
                     .current_dir(dest)
                     .check_run()?;
                 // Merge any fetched commits
-                Command::new("git")
-                    .arg("pull")
-                    .current_dir(dest)
-                    .check_run()?
+                git_with_progress(
+                    network::git()?
+                        .args(["pull", "--progress"])
+                        .current_dir(dest),
+                )?
             }
-            Resource::Git { .. } =>
-                Command::new("git")
-                    .arg("pull")
-                    .current_dir(dest)
-                    .check_run()?,
+            Resource::Git { .. } => git_with_progress(
+                network::git()?
+                    .args(["pull", "--progress"])
+                    .current_dir(dest),
+            )?,
             Resource::Tar { .. } => {}
         }
         Ok(())
     }
 }
 
+/// Record of the archive extracted into a source directory, pinned by [llvmenv.lock](../lock/index.html#pins)
+pub const ARCHIVE_RECORD_FN: &str = ".llvmenv-archive";
+
+/// Record SHA-256 and URL of `archive` extracted into `dest`
+fn record_archive(archive: &Path, url: &str, dest: &Path) -> Result<()> {
+    let sha256 = crate::prebuilt::sha256_file(archive)?;
+    fs::write(
+        dest.join(ARCHIVE_RECORD_FN),
+        format!("{} {}\n", sha256, url),
+    )?;
+    Ok(())
+}
+
+/// SHA-256 and URL of the archive extracted into `dir`, None if it is not recorded
+pub fn archive_record(dir: &Path) -> Option<(String, String)> {
+    let record = fs::read_to_string(dir.join(ARCHIVE_RECORD_FN)).ok()?;
+    let (sha256, url) = record.trim().split_once(' ')?;
+    Some((sha256.to_string(), url.to_string()))
+}
+
+/// Copy the contents of the top directory in `working`, where `archive` is extracted, into `dest`
+fn copy_top_dir(working: &Path, dest: &Path, archive: &str) -> Result<()> {
+    let mut top = None;
+    for entry in fs::read_dir(working)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            top = Some(entry.path());
+            break;
+        }
+    }
+    let top = match top {
+        Some(top) => top,
+        None => bail!("Archive {} does not contain a top directory", archive),
+    };
+    for contents in fs::read_dir(top)? {
+        let path = contents?.path();
+        if path.is_dir() {
+            let opt = fs_extra::dir::CopyOptions::new();
+            fs_extra::dir::copy(path, dest, &opt)?;
+        } else if let Some(name) = path.file_name() {
+            fs::copy(&path, dest.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Run git reporting the objects received to the observer. Lines other than the progress are echoed.
+/// Failure of `git clone` or `svn co` as [DownloadError](../error/enum.LlvmEnvError.html#variant.DownloadError),
+/// except for missing tools and timeouts
+fn download_error(url: &str, e: CommandError) -> LlvmEnvError {
+    match e {
+        CommandError::ErrorCode { .. } | CommandError::TerminatedBySignal { .. } => {
+            LlvmEnvError::DownloadError {
+                url: url.into(),
+                source: Box::new(e.into()),
+            }
+        }
+        e => e.into(),
+    }
+}
+
+fn git_with_progress(git: &mut Command) -> CommandResult {
+    let observer = observer();
+    git.check_run_progress(|line| match parse_git_progress(line) {
+        Some((received, total)) => {
+            observer.on_objects(received, Some(total));
+            true
+        }
+        None => line.contains("% ("),
+    })
+}
+
+/// Reader of the Tar archive decompressed by its extension (`.gz`, `.tgz`, `.xz`, or `.bz2`)
+pub(crate) fn decoder(archive: &Path) -> Result<Box<dyn Read>> {
+    let name = archive.to_string_lossy();
+    let f = io::BufReader::new(fs::File::open(archive)?);
+    Ok(if name.ends_with(".gz") || name.ends_with(".tgz") {
+        Box::new(flate2::read::GzDecoder::new(f))
+    } else if name.ends_with(".xz") {
+        Box::new(xz2::read::XzDecoder::new(f))
+    } else if name.ends_with(".bz2") {
+        Box::new(bzip2::read::BzDecoder::new(f))
+    } else {
+        Box::new(f)
+    })
+}
+
+/// Extract the Tar archive into `dest`, decompressed by its extension (`.gz`, `.tgz`, `.xz`, or `.bz2`).
+/// Only `.Z` (compress) archives are delegated to `tar` command.
+pub fn unpack(archive: &Path, dest: &Path) -> Result<()> {
+    let name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    info!("Extract {}", name);
+    if name.ends_with(".Z") || name.ends_with(".taz") {
+        check_tool("tar")?;
+        Command::new("tar")
+            .arg("xf")
+            .arg(archive)
+            .current_dir(dest)
+            .check_run()?;
+        return Ok(());
+    }
+    tar::Archive::new(decoder(archive)?)
+        .unpack(dest)
+        .map_err(|e| format_err!("Failed to extract {}: {}", archive.display(), e))?;
+    Ok(())
+}
+
+/// Request to `url` with the token stored by `llvmenv auth login` if exists
+fn request(client: &reqwest::Client, method: Method, url: &str) -> reqwest::RequestBuilder {
+    let mut req = client.request(method, url);
+    if let Some(token) = auth::token_for_url(url) {
+        info!("Use the token stored by `llvmenv auth login`");
+        req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    req
+}
+
+/// Size of the file at `url` reported by the server
+fn remote_size(url: &str) -> Option<u64> {
+    let req = request(&network::client().ok()?, Method::HEAD, url);
+    req.send().ok()?.error_for_status().ok()?.content_length()
+}
+
+/// Download `url` into `path`. An interrupted download is retried by `[download]` of config.toml,
+/// and resumed by HTTP range request if the server supports it.
+pub(crate) fn download_file(url: &str, path: &Path) -> Result<()> {
+    let config = load_config()?.download;
+    let client = network::client()?;
+    let mut attempt = 0;
+    loop {
+        let e = match download_range(&client, url, path) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let client_error = match &e {
+            LlvmEnvError::Http(e) => e.status().is_some_and(|status| status.is_client_error()),
+            _ => false,
+        };
+        if client_error || attempt >= config.retries() {
+            return Err(LlvmEnvError::DownloadError {
+                url: url.into(),
+                source: Box::new(e),
+            });
+        }
+        let delay = retry_delay(config.backoff(), attempt);
+        warn!(
+            "Download of {} is interrupted: {}. Retry in {}s",
+            url, e, delay
+        );
+        thread::sleep(Duration::from_secs(delay));
+        attempt += 1;
+    }
+}
+
+/// Download `url` into `dir`, and returns the path of the complete file to be removed by the caller.
+/// It is written into `${hash of url}-${file name}.part`, which is kept if the download fails,
+/// so that the next download of the same URL, e.g. by the next run of llvmenv, resumes it.
+pub(crate) fn download_resumable(url: &str, dir: &Path) -> Result<PathBuf> {
+    let key: String = Sha256::digest(url.as_bytes())
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let path = dir.join(format!("{}-{}", key, get_filename_from_url(url)?));
+    let mut part = path.clone().into_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    fs::create_dir_all(dir)?;
+    download_file(url, &part)?;
+    fs::rename(&part, &path)?;
+    Ok(path)
+}
+
+/// Seconds to wait before the retry after `attempt` retries
+fn retry_delay(backoff: u64, attempt: u32) -> u64 {
+    backoff.saturating_mul(1 << attempt.min(16))
+}
+
+/// Total size in `Content-Range` header, e.g. `bytes */1234`
+fn content_range_total(value: &str) -> Option<u64> {
+    value.rsplit('/').next()?.trim().parse().ok()
+}
+
+/// Download the rest of `url` into `path`, which is appended if it already has some bytes
+fn download_range(client: &reqwest::Client, url: &str, path: &Path) -> Result<()> {
+    let offset = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let mut req = request(client, Method::GET, url);
+    if offset > 0 {
+        req = req.header(header::RANGE, format!("bytes={}-", offset));
+    }
+    let res = req.send()?;
+    if offset > 0 && res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        let total = res
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_range_total);
+        if total == Some(offset) {
+            return Ok(());
+        }
+        fs::remove_file(path)?;
+        bail!("Cannot resume the download of {}", url);
+    }
+    let mut res = res.error_for_status()?;
+    let (mut f, mut received) = if offset > 0 && res.status() == StatusCode::PARTIAL_CONTENT {
+        info!("Resume the download from {} bytes", offset);
+        (fs::OpenOptions::new().append(true).open(path)?, offset)
+    } else {
+        (fs::File::create(path)?, 0)
+    };
+    let total = res.content_length().map(|len| len + received);
+    let observer = observer();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = res.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        f.write_all(&buf[..n])?;
+        received += n as u64;
+        observer.on_bytes(received, total);
+    }
+    if let Some(total) = total {
+        if received < total {
+            bail!("Connection closed at {} of {} bytes", received, total);
+        }
+    }
+    Ok(())
+}
+
+/// Free space required for probing git repository
+const PROBE_SPACE: u64 = 1024 * 1024;
+/// Expected ratio of the extracted size to the archive size, including the archive itself
+pub(crate) const EXTRACT_RATIO: u64 = 12;
+
+/// Create a temporal directory in `[paths].tmp` of config.toml (or `default` if unset),
+/// which must have at least `required` bytes free
+pub(crate) fn scratch_dir(default: PathBuf, required: u64) -> Result<TempDir> {
+    let dir = load_config()?.tmp_dir()?.unwrap_or(default);
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+    }
+    let available = fs2::available_space(&dir)?;
+    if available < required {
+        bail!(
+            "Not enough space in {}: {} MB required, {} MB available. Set [paths].tmp in {} to use another directory.",
+            dir.display(),
+            required / 1024 / 1024,
+            available / 1024 / 1024,
+            CONFIG_TOML
+        );
+    }
+    Ok(TempDir::new_in(dir)?)
+}
+
 fn get_filename_from_url(url_str: &str) -> Result<String> {
     let url = ::url::Url::parse(url_str)?;
-    let seg = url.path_segments().ok_or(err_msg("URL parse failed"))?;
-    let filename = seg.last().ok_or(err_msg("URL is invalid"))?;
+    let mut seg = url.path_segments().ok_or(err_msg("URL parse failed"))?;
+    let filename = seg.next_back().ok_or(err_msg("URL is invalid"))?;
     Ok(filename.to_string())
 }
 
@@ -252,12 +694,71 @@ fn get_filename_from_url(url_str: &str) -> Result<String> {
 mod tests {
     use super::*;
 
+    #[cfg(unix)]
+    #[test]
+    fn test_git_tag_download() -> Result<()> {
+        let repo = TempDir::new()?;
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(["-c", "user.name=a", "-c", "user.email=a@b"])
+                .args(args)
+                .current_dir(repo.path())
+                .silent()
+                .check_run()
+        };
+        git(&["init", "-q"])?;
+        fs::write(repo.path().join("CMakeLists.txt"), "v1")?;
+        git(&["add", "."])?;
+        git(&["commit", "-q", "-m", "v1"])?;
+        git(&["tag", "v1"])?;
+        fs::write(repo.path().join("CMakeLists.txt"), "v2")?;
+        git(&["commit", "-q", "-am", "v2"])?;
+
+        let url = format!("file://{}", repo.path().display());
+        let resource = Resource::from_url(&url, None, Some("v1".into()))?;
+        let dest = TempDir::new()?;
+        resource.download(dest.path())?;
+        assert_eq!(
+            fs::read_to_string(dest.path().join("CMakeLists.txt"))?,
+            "v1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry() {
+        assert_eq!(retry_delay(1, 0), 1);
+        assert_eq!(retry_delay(2, 3), 16);
+        assert_eq!(content_range_total("bytes */1234"), Some(1234));
+        assert_eq!(content_range_total("bytes 0-99/*"), None);
+    }
+
+    #[test]
+    fn test_download_error() {
+        let url = "https://example.com/llvm.git";
+        let e = download_error(
+            url,
+            CommandError::ErrorCode {
+                errno: 128,
+                cmd: "git".into(),
+            },
+        );
+        assert!(matches!(&e, LlvmEnvError::DownloadError { url: u, .. } if u == url));
+        assert_eq!(e.exit_code(), 3);
+        let timeout = CommandError::Timeout {
+            timeout: Duration::from_secs(1),
+            cmd: "git".into(),
+        };
+        assert!(download_error(url, timeout).is_timeout());
+    }
+
     // Test donwloading this repo
     #[test]
     fn test_git_donwload() -> Result<()> {
         let git = Resource::Git {
             url: "http://github.com/termoshtt/llvmenv".into(),
             branch: None,
+            tag: None,
         };
         let tmp_dir = TempDir::new()?;
         git.download(tmp_dir.path())?;
@@ -281,10 +782,175 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_download_resumable() -> Result<()> {
+        use std::io::{BufRead, BufReader};
+        use std::net::TcpListener;
+
+        // Server with range requests, accepting only a request resuming the half of `content`
+        let content = b"0123456789";
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/llvm.tar.xz", listener.local_addr()?);
+        thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut range = None;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                let lower = line.to_lowercase();
+                if let Some(value) = lower.strip_prefix("range: bytes=") {
+                    range = value.trim().trim_end_matches('-').parse::<usize>().ok();
+                }
+                line.clear();
+            }
+            let offset = range.unwrap_or(0);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                offset,
+                content.len() - 1,
+                content.len(),
+                content.len() - offset
+            );
+            let _ = stream.write_all(&content[offset..]);
+        });
+
+        let tmp_dir = TempDir::new()?;
+        let entries = || fs::read_dir(tmp_dir.path()).unwrap().count();
+        let part = tmp_dir.path().join(format!(
+            "{}-llvm.tar.xz.part",
+            Sha256::digest(url.as_bytes())
+                .iter()
+                .take(8)
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>()
+        ));
+        fs::write(&part, &content[..5])?;
+        let path = download_resumable(&url, tmp_dir.path())?;
+        assert_eq!(fs::read(&path)?, content);
+        assert!(!part.exists());
+        assert_eq!(entries(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpack() -> Result<()> {
+        let tmp_dir = TempDir::new()?;
+        let mut data = Vec::new();
+        {
+            let mut builder = tar::Builder::new(&mut data);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(5);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, "llvm-7.0.0.src/CMakeLists.txt", &b"hello"[..])?;
+            builder.finish()?;
+        }
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&data)?;
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 1);
+        xz.write_all(&data)?;
+        let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        bz.write_all(&data)?;
+        for (name, bytes) in &[
+            ("a.tar", data.clone()),
+            ("a.tar.gz", gz.finish()?),
+            ("a.tar.xz", xz.finish()?),
+            ("a.tar.bz2", bz.finish()?),
+        ] {
+            let archive = tmp_dir.path().join(name);
+            fs::write(&archive, bytes)?;
+            let dest = tmp_dir.path().join(format!("{}.d", name));
+            fs::create_dir(&dest)?;
+            unpack(&archive, &dest)?;
+            assert_eq!(
+                fs::read_to_string(dest.join("llvm-7.0.0.src/CMakeLists.txt"))?,
+                "hello"
+            );
+        }
+        let broken = tmp_dir.path().join("broken.tar.gz");
+        fs::write(&broken, "not an archive")?;
+        assert!(unpack(&broken, tmp_dir.path()).is_err());
+
+        let working = tmp_dir.path().join("a.tar.d");
+        let dest = tmp_dir.path().join("llvm");
+        fs::create_dir(&dest)?;
+        copy_top_dir(&working, &dest, "a.tar")?;
+        assert_eq!(fs::read_to_string(dest.join("CMakeLists.txt"))?, "hello");
+        assert_eq!(archive_record(&dest), None);
+        record_archive(
+            &tmp_dir.path().join("a.tar"),
+            "https://example.com/a.tar",
+            &dest,
+        )?;
+        let (sha256, url) = archive_record(&dest).unwrap();
+        assert_eq!(sha256.len(), 64);
+        assert_eq!(url, "https://example.com/a.tar");
+        let empty = tmp_dir.path().join("empty");
+        fs::create_dir(&empty)?;
+        let e = copy_top_dir(&empty, &dest, "empty.tar").unwrap_err();
+        assert!(e.to_string().contains("empty.tar"));
+        Ok(())
+    }
+
     #[test]
     fn test_get_filename_from_url() {
         let url = "http://releases.llvm.org/6.0.1/llvm-6.0.1.src.tar.xz";
         assert_eq!(get_filename_from_url(url).unwrap(), "llvm-6.0.1.src.tar.xz");
     }
 
+    /// `scheme://host/dirs/` of URLs, which are detected only by the last segment or the host
+    const BASE: &str =
+        "https?://[a-z]{1,8}(\\.[a-z]{2,5}){1,2}(:[0-9]{2,4})?(/[a-zA-Z0-9_.-]{1,8}){0,3}/";
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(256))]
+
+        /// Detection by the extension does not access the network. Tags are only for Git.
+        #[test]
+        fn test_tar_url(
+            base in BASE,
+            name in "[a-z0-9_.-]{1,10}",
+            ext in proptest::sample::select(vec![".tar.gz", ".tar.xz", ".tar.bz2", ".tar.Z", ".tgz", ".taz"]),
+        ) {
+            let url = format!("{}{}{}", base, name, ext);
+            let tar = Resource::from_url(&url, None, None).unwrap();
+            let is_tar = matches!(tar, Resource::Tar { .. });
+            proptest::prop_assert!(is_tar, "Unexpected resource: {:?}", tar);
+            proptest::prop_assert_eq!(tar.url(), url.as_str());
+            proptest::prop_assert_eq!(get_filename_from_url(&url).unwrap(), format!("{}{}", name, ext));
+            proptest::prop_assert!(Resource::from_url(&url, None, Some("v1".into())).is_err());
+        }
+
+        #[test]
+        fn test_git_url(base in BASE, name in "[a-z0-9_-]{1,10}", branch in proptest::option::of("[a-z0-9/_-]{1,10}")) {
+            let url = format!("{}{}.git", base, name);
+            match Resource::from_url(&url, branch.clone(), None).unwrap() {
+                Resource::Git { url: u, branch: b, tag: None } => {
+                    proptest::prop_assert_eq!(u, url.clone());
+                    proptest::prop_assert_eq!(b, branch);
+                }
+                r => proptest::prop_assert!(false, "Unexpected resource: {:?}", r),
+            }
+            proptest::prop_assert!(Resource::from_url(&url, Some("main".into()), Some("v1".into())).is_err());
+        }
+
+        #[test]
+        fn test_git_service(
+            host in proptest::sample::select(vec!["github.com", "gitlab.com"]),
+            path in "(/[a-zA-Z0-9_-]{1,8}){1,3}",
+        ) {
+            proptest::prop_assume!(!path.ends_with("trunk"));
+            let url = format!("https://{}{}", host, path);
+            let git = Resource::from_url(&url, None, None).unwrap();
+            let is_git = matches!(git, Resource::Git { .. });
+            proptest::prop_assert!(is_git, "Unexpected resource: {:?}", git);
+        }
+
+        /// Arbitrary strings, e.g. of broken entries, are rejected without panics
+        #[test]
+        fn test_filename_any(s in "\\PC*") {
+            let _ = get_filename_from_url(&s);
+        }
+    }
 }
@@ -0,0 +1,412 @@
+//! Serve downloads and archives over HTTP
+//!
+//! `llvmenv serve --addr 0.0.0.0:8080` serves
+//!
+//! - `/download/...`: files in the download cache
+//! - `/archive/${name}.tar.xz`: builds archived by `llvmenv archive`
+//!
+//! with an index page at `/`, so that a machine in an office or lab can be a mirror for others.
+//! Only `GET` and `HEAD` are supported, and the server is not intended to be exposed to the internet.
+//! Still, slow or oversized requests are cut off, and at most `MAX_CONNECTIONS` are served at once.
+
+use log::{info, warn};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{data_dir, download_cache_dir};
+use crate::error::Result;
+
+/// Timeout of reading the request from a client
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Limit of the request line and the headers
+const MAX_HEADER_BYTES: u64 = 8192;
+
+/// Connections served at once, and the others are responded with 503
+const MAX_CONNECTIONS: usize = 32;
+
+/// URL prefixes and directories served under them
+#[derive(Debug, Clone)]
+pub struct Server {
+    roots: Vec<(String, PathBuf)>,
+}
+
+impl Server {
+    pub fn new(roots: Vec<(String, PathBuf)>) -> Self {
+        Server { roots }
+    }
+
+    /// Download cache and data directory of llvmenv
+    pub fn default_roots() -> Result<Self> {
+        Ok(Self::new(vec![
+            ("download".into(), download_cache_dir()?),
+            ("archive".into(), data_dir()?),
+        ]))
+    }
+
+    /// Files published under each root. Only `*.tar.xz` are published from the data directory.
+    pub fn files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        for (name, dir) in &self.roots {
+            let mut found = Vec::new();
+            list_files(dir, dir, &mut found);
+            found.sort();
+            files.extend(
+                found
+                    .into_iter()
+                    .filter(|f| name != "archive" || f.ends_with(".tar.xz"))
+                    .map(|f| format!("/{}/{}", name, f)),
+            );
+        }
+        files
+    }
+
+    /// Local path for the (percent-encoded) path of URL, None if not published
+    pub fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let url_path = percent_decode(url_path.split('?').next()?)?;
+        let url_path = url_path.trim_start_matches('/');
+        let (name, rest) = url_path.split_at(url_path.find('/')?);
+        let rest = Path::new(&rest[1..]);
+        if rest
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        let (_, dir) = self.roots.iter().find(|(n, _)| n == name)?;
+        if name == "archive" && !rest.to_string_lossy().ends_with(".tar.xz") {
+            return None;
+        }
+        let path = dir.join(rest);
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    fn index(&self) -> String {
+        let items: String = self
+            .files()
+            .iter()
+            .map(|f| {
+                format!(
+                    "<li><a href=\"{}\">{}</a></li>\n",
+                    html_escape(&percent_encode(f)),
+                    html_escape(f)
+                )
+            })
+            .collect();
+        format!(
+            "<!DOCTYPE html>\n<html><head><title>llvmenv mirror</title></head><body>\n<h1>llvmenv mirror</h1>\n<ul>\n{}</ul>\n</body></html>\n",
+            items
+        )
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?.take(MAX_HEADER_BYTES));
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip headers
+        let mut line = String::new();
+        let mut complete = false;
+        while reader.read_line(&mut line)? > 0 {
+            if line.len() <= 2 {
+                complete = true;
+                break;
+            }
+            line.clear();
+        }
+        let mut stream = stream;
+        if !complete {
+            return respond(
+                &mut stream,
+                "431 Request Header Fields Too Large",
+                "text/plain",
+                b"Request Header Fields Too Large\n",
+            );
+        }
+        let mut words = request.split_whitespace();
+        let (method, path) = match (words.next(), words.next()) {
+            (Some(method), Some(path)) => (method, path),
+            _ => {
+                return respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    b"Bad Request\n",
+                )
+            }
+        };
+        info!("{} {}", method, path);
+        if method != "GET" && method != "HEAD" {
+            return respond(
+                &mut stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Method Not Allowed\n",
+            );
+        }
+        let head = method == "HEAD";
+        if path == "/" || path == "/index.html" {
+            let index = self.index();
+            let body = if head { &b""[..] } else { index.as_bytes() };
+            return respond_len(&mut stream, "200 OK", "text/html", index.len() as u64, body);
+        }
+        match self.resolve(path) {
+            Some(file) => {
+                let len = fs::metadata(&file)?.len();
+                write_header(&mut stream, "200 OK", "application/octet-stream", len)?;
+                if !head {
+                    io::copy(&mut fs::File::open(&file)?, &mut stream)?;
+                }
+                Ok(())
+            }
+            None => respond(&mut stream, "404 Not Found", "text/plain", b"Not Found\n"),
+        }
+    }
+
+    /// Serve until the process is killed
+    pub fn serve<A: ToSocketAddrs>(self, addr: A) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        eprintln!("Serving on http://{}", listener.local_addr()?);
+        self.serve_on(listener);
+        Ok(())
+    }
+
+    /// Serve in a background thread, returning the address, e.g. of port 0 for fixtures of tests
+    pub fn spawn<A: ToSocketAddrs>(self, addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        thread::spawn(move || self.serve_on(listener));
+        Ok(addr)
+    }
+
+    fn serve_on(self, listener: TcpListener) {
+        let server = Arc::new(self);
+        let active = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Connection failed: {}", e);
+                    continue;
+                }
+            };
+            if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                active.fetch_sub(1, Ordering::SeqCst);
+                let _ = respond(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    "text/plain",
+                    b"Service Unavailable\n",
+                );
+                continue;
+            }
+            let server = server.clone();
+            let active = active.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    warn!("Failed to respond: {}", e);
+                }
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+/// Decode `%XX` in the path of URL, None if invalid or not UTF-8
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    if decoded.contains(&0) {
+        return None;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Encode the path for URL except unreserved characters and `/`
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Escape for text and attribute values of HTML
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Relative paths of files under `dir` (recursively)
+fn list_files(base: &Path, dir: &Path, files: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            // Builds themselves are not published
+            if dir == base && path.join("bin").is_dir() {
+                continue;
+            }
+            list_files(base, &path, files);
+        } else if let Ok(rel) = path.strip_prefix(base) {
+            files.push(rel.to_string_lossy().replace('\\', "/"));
+        }
+    }
+}
+
+fn write_header(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    len: u64,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, len
+    )
+}
+
+fn respond_len(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    len: u64,
+    body: &[u8],
+) -> io::Result<()> {
+    write_header(stream, status, content_type, len)?;
+    stream.write_all(body)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    respond_len(stream, status, content_type, body.len() as u64, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn server(tmp: &Path) -> Result<Server> {
+        fs::create_dir_all(tmp.join("download/abc"))?;
+        fs::write(tmp.join("download/abc/llvm.tar.xz"), "llvm")?;
+        fs::create_dir_all(tmp.join("data/7.0.0/bin"))?;
+        fs::write(tmp.join("data/7.0.0.tar.xz"), "archive")?;
+        Ok(Server::new(vec![
+            ("download".into(), tmp.join("download")),
+            ("archive".into(), tmp.join("data")),
+        ]))
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let server = server(tmp_dir.path())?;
+        assert_eq!(
+            server.files(),
+            vec!["/download/abc/llvm.tar.xz", "/archive/7.0.0.tar.xz"]
+        );
+        assert!(server.resolve("/archive/7.0.0.tar.xz").is_some());
+        assert!(server.resolve("/download/abc/llvm.tar.xz").is_some());
+        assert!(server.resolve("/archive/7.0.0/bin").is_none());
+        assert!(server.resolve("/download/../data/7.0.0.tar.xz").is_none());
+        assert!(server.resolve("/unknown/a").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let server = server(tmp_dir.path())?;
+        fs::write(tmp_dir.path().join("download/abc/<a> b.tar.xz"), "")?;
+        assert!(server.resolve("/download/abc/%3Ca%3E%20b.tar.xz").is_some());
+        assert!(server
+            .resolve("/download/%2e%2e/data/7.0.0.tar.xz")
+            .is_none());
+        assert!(server.resolve("/download/abc/%zz").is_none());
+        assert!(server.index().contains(
+            "<a href=\"/download/abc/%3Ca%3E%20b.tar.xz\">/download/abc/&lt;a&gt; b.tar.xz</a>"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let server = server(tmp_dir.path())?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        thread::spawn(move || server.serve_on(listener));
+
+        let get = |path: &str| -> Result<String> {
+            let mut stream = TcpStream::connect(addr)?;
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+            let mut res = String::new();
+            stream.read_to_string(&mut res)?;
+            Ok(res)
+        };
+        let res = get("/archive/7.0.0.tar.xz")?;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.ends_with("\r\n\r\narchive"));
+        assert!(get("/")?.contains("<a href=\"/archive/7.0.0.tar.xz\">"));
+        assert!(get("/archive/none.tar.xz")?.starts_with("HTTP/1.1 404"));
+
+        // Headers not ending in the limit, which are read by the server up to it
+        let mut stream = TcpStream::connect(addr)?;
+        let request = "GET / HTTP/1.1\r\nX: ";
+        write!(
+            stream,
+            "{}{}",
+            request,
+            "a".repeat(MAX_HEADER_BYTES as usize - request.len())
+        )?;
+        let mut res = String::new();
+        stream.read_to_string(&mut res)?;
+        assert!(res.starts_with("HTTP/1.1 431"));
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Guided setup on the first run
+//!
+//! If `entry.toml` does not exist, llvmenv asks on the terminal whether to set up before running the command,
+//! and `llvmenv init` (or `llvmenv init --defaults` without questions, e.g. for provisioning) runs it directly:
+//!
+//! 1. creates `entry.toml` (built-in entries are available without it) and `config.toml`
+//!    (`--force` replaces the existing `entry.toml` after backing it up, see [backup_config](../config/fn.backup_config.html))
+//! 2. uses Ninja by default if found (`build.builder`, see [config](../config/index.html))
+//! 3. fetches the index of LLVM releases from GitHub
+//! 4. shows the line to be added into the rc file of the current shell, and an `llvmenv install` to start with
+
+use log::info;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+
+use crate::config::{self, config_dir, CONFIG_TOML, ENTRY_TOML};
+use crate::error::*;
+use crate::github;
+use crate::shell::Shell;
+use crate::version::{Pre, Version};
+
+/// Whether llvmenv has been set up, i.e. `entry.toml` exists
+pub fn is_initialized() -> bool {
+    config::config_dir_path()
+        .map(|dir| dir.join(ENTRY_TOML).exists())
+        .unwrap_or(false)
+}
+
+/// Ask a yes/no question, `default` if the answer is empty
+pub(crate) fn confirm(question: &str, default: bool) -> Result<bool> {
+    eprint!("{} [{}] ", question, if default { "Y/n" } else { "y/N" });
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_ascii_lowercase().as_str() {
+        "" => default,
+        answer => answer.starts_with('y'),
+    })
+}
+
+/// Ask whether to set up if not initialized yet. Returns true if set up.
+pub fn offer() -> Result<bool> {
+    if is_initialized() || !is_tty() {
+        return Ok(false);
+    }
+    eprintln!("llvmenv is not set up yet ({} is not found).", ENTRY_TOML);
+    if !confirm("Set up now?", true)? {
+        eprintln!("Run `llvmenv init` to set up later.");
+        return Ok(false);
+    }
+    run(false, false)?;
+    Ok(true)
+}
+
+/// Line to be added into the rc file of `shell`, e.g. `/bin/zsh`
+pub fn shell_hint(shell: &str) -> Option<(&'static str, &'static str)> {
+    Some(Shell::from_path(shell)?.rc_line())
+}
+
+/// Run the setup. Questions are skipped and answered by default if `defaults`.
+/// The existing entry.toml is backed up and replaced by the template if `force`.
+pub fn run(defaults: bool, force: bool) -> Result<()> {
+    let interactive = !defaults && is_tty();
+    let dir = config_dir()?;
+    if dir.join(ENTRY_TOML).exists() && !force {
+        eprintln!(
+            "llvmenv is already set up in {}. Use --force to reset entry.toml.",
+            dir.display()
+        );
+    } else {
+        config::init_config(force)?;
+        eprintln!("Created {}", dir.join(ENTRY_TOML).display());
+    }
+    let config_toml = dir.join(CONFIG_TOML);
+    if !config_toml.exists() {
+        fs::write(
+            &config_toml,
+            "# Settings of llvmenv, managed by `llvmenv config set <key> <value>`\n",
+        )?;
+        eprintln!("Created {}", config_toml.display());
+    }
+
+    if config::config_get("build.builder")?.is_none() && find_tool("ninja").is_some() {
+        let use_ninja = !interactive || confirm("Ninja is found. Use it to build entries?", true)?;
+        if use_ninja {
+            config::config_set("build.builder", "Ninja")?;
+            eprintln!("Set build.builder = Ninja");
+        }
+    }
+
+    let latest = match github::Client::new().and_then(|c| c.releases(github::LLVM_REPO)) {
+        Ok(releases) => releases
+            .iter()
+            .filter(|r| !r.prerelease)
+            .filter_map(|r| Version::from_name(&r.tag_name))
+            .filter(|v| v.pre == Pre::Release)
+            .max()
+            .map(|v| v.to_string()),
+        Err(e) => {
+            info!("Cannot fetch releases of LLVM: {}", e);
+            None
+        }
+    };
+
+    eprintln!();
+    if let Some((rc, line)) = env::var("SHELL").ok().as_deref().and_then(shell_hint) {
+        eprintln!("Add the following line into {}:", rc);
+        eprintln!();
+        eprintln!("    {}", line);
+        eprintln!();
+    }
+    match latest {
+        Some(version) => {
+            eprintln!("To start with the latest release {}:", version);
+            eprintln!();
+            eprintln!(
+                "    llvmenv install {} && llvmenv global {}",
+                version, version
+            );
+        }
+        None => {
+            eprintln!("To start, install a prebuilt release or build an entry:");
+            eprintln!();
+            eprintln!("    llvmenv releases");
+            eprintln!("    llvmenv entries");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_hint() {
+        assert_eq!(
+            shell_hint("/usr/bin/zsh").unwrap().1,
+            "source <(llvmenv zsh)"
+        );
+        assert_eq!(shell_hint("/bin/bash").unwrap().0, "~/.bashrc");
+        assert_eq!(
+            shell_hint("/usr/bin/fish").unwrap().1,
+            "llvmenv init fish | source"
+        );
+        assert_eq!(shell_hint("/bin/csh"), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Data and cache directories shared by users
+//!
+//! The data and cache directories may be shared by several users, e.g. `XDG_DATA_HOME=/opt` for a team,
+//! or builds installed by root and used by the others. `[shared]` in config.toml makes the files created by
+//! llvmenv and the build commands accessible for a group:
+//!
+//! ```toml
+//! [shared]
+//! group = "llvm"
+//! umask = "002"
+//! ```
+//!
+//! - `umask` is set to llvmenv on startup, and inherited by git, cmake, and the compilers
+//! - the data and cache directories are owned by `group` with the setgid bit when llvmenv creates them,
+//!   so that the files created in them belong to the group (Unix)
+//!
+//! Users without the write permission of the data directory can use the builds in it, and the commands
+//! installing, removing, or modifying builds fail before doing anything with the owner of the directory.
+//! `llvmenv doctor` reports the directories and their entries not matching the group and the umask,
+//! and those not writable in a writable directory, e.g. a build tree left by root.
+
+use log::warn;
+use std::fs;
+use std::path::Path;
+
+use crate::config::load_config;
+use crate::error::*;
+use crate::lockdown;
+
+/// Mode of the umask in octal, e.g. `002`
+///
+/// ```
+/// # use llvmenv::shared::parse_umask;
+/// assert_eq!(parse_umask("002").unwrap(), 0o002);
+/// assert_eq!(parse_umask("0027").unwrap(), 0o027);
+/// assert!(parse_umask("800").is_err());
+/// assert!(parse_umask("1777").is_err());
+/// ```
+pub fn parse_umask(umask: &str) -> Result<u32> {
+    match u32::from_str_radix(umask, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format_err!(
+            "Invalid umask: {} (use an octal mode, e.g. 002)",
+            umask
+        )),
+    }
+}
+
+/// Set `shared.umask` to the process, called on startup.
+/// An invalid umask is warned and ignored, so that the commands not creating files still work.
+pub fn init() {
+    #[cfg(unix)]
+    {
+        // Errors of config.toml are reported by the commands reading it
+        let config = match load_config() {
+            Ok(config) => config,
+            Err(_) => return,
+        };
+        if let Some(umask) = &config.shared.umask {
+            match parse_umask(umask) {
+                Ok(umask) => unsafe {
+                    libc::umask(umask as libc::mode_t);
+                },
+                Err(e) => warn!("{} in shared.umask, ignored", e),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn current_umask() -> u32 {
+    // umask cannot be read without setting it
+    unsafe {
+        let umask = libc::umask(0o022);
+        libc::umask(umask);
+        umask as u32
+    }
+}
+
+/// Group ID of the name or the number
+#[cfg(unix)]
+fn group_id(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let name =
+        std::ffi::CString::new(group).map_err(|_| format_err!("Invalid group: {}", group))?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        bail!("Unknown group: {} (shared.group)", group);
+    }
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// Name of the user, or the ID if unknown
+#[cfg(unix)]
+fn user_name(uid: u32) -> String {
+    let entry = unsafe { libc::getpwuid(uid) };
+    if entry.is_null() {
+        return format!("uid {}", uid);
+    }
+    unsafe { std::ffi::CStr::from_ptr((*entry).pw_name) }
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Give a directory just created to `shared.group` with the setgid bit
+pub fn share_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let group = match load_config()?.shared.group {
+            Some(group) => group_id(&group)?,
+            None => return Ok(()),
+        };
+        std::os::unix::fs::chown(dir, None, Some(group))?;
+        let mut perms = fs::metadata(dir)?.permissions();
+        perms.set_mode(perms.mode() | 0o2000);
+        fs::set_permissions(dir, perms)?;
+    }
+    Ok(())
+}
+
+/// The current user can create and remove files in `dir`
+pub fn is_writable(dir: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        match std::ffi::CString::new(dir.as_os_str().as_bytes()) {
+            Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+            Err(_) => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        fs::metadata(dir)
+            .map(|meta| !meta.permissions().readonly())
+            .unwrap_or(false)
+    }
+}
+
+fn owner(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(meta) = fs::metadata(path) {
+            return user_name(meta.uid());
+        }
+    }
+    "another user".to_string()
+}
+
+/// Error if `dir` exists but is not writable by the current user, before modifying builds in it
+pub fn require_writable(dir: &Path) -> Result<()> {
+    if dir.exists() && !is_writable(dir) {
+        bail!(
+            "{} is shared by {} and read-only for you. The builds in it can be used, but not installed, removed, or modified.",
+            dir.display(),
+            owner(dir)
+        );
+    }
+    Ok(())
+}
+
+/// Problem of an entry with `mode` and `gid` in a shared directory for `group` and `umask`
+#[cfg(unix)]
+fn mismatch(mode: u32, gid: u32, is_dir: bool, group: u32, umask: u32) -> Option<&'static str> {
+    if gid != group {
+        Some("not owned by the group")
+    } else if umask & 0o020 == 0 && mode & 0o020 == 0 {
+        Some("not writable by the group")
+    } else if is_dir && mode & 0o2000 == 0 {
+        Some("without the setgid bit")
+    } else {
+        None
+    }
+}
+
+/// Entries of `dir` not matching `shared.group` and `shared.umask`, or not writable in a writable `dir`,
+/// with the reasons. Only `dir` and its entries are checked, not the whole trees.
+/// The prefixes [locked](../lockdown/index.html) read-only are not reported.
+pub fn check_dir(dir: &Path) -> Result<Vec<(String, String)>> {
+    let mut found = Vec::new();
+    if !dir.is_dir() {
+        return Ok(found);
+    }
+    let mut paths = vec![dir.to_path_buf()];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !lockdown::is_locked(&path) {
+            paths.push(path);
+        }
+    }
+    let writable = is_writable(dir);
+    for path in paths.iter().skip(1).filter(|path| path.is_dir()) {
+        if writable && !is_writable(path) {
+            found.push((
+                path.display().to_string(),
+                format!("not writable for you (owned by {})", owner(path)),
+            ));
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let config = load_config()?.shared;
+        if let Some(group) = &config.group {
+            let gid = group_id(group)?;
+            let umask = match &config.umask {
+                Some(umask) => parse_umask(umask)?,
+                None => current_umask(),
+            };
+            for path in &paths {
+                let meta = fs::symlink_metadata(path)?;
+                if meta.file_type().is_symlink() {
+                    continue;
+                }
+                if let Some(reason) = mismatch(meta.mode(), meta.gid(), meta.is_dir(), gid, umask) {
+                    found.push((
+                        path.display().to_string(),
+                        format!("{} (shared.group = {})", reason, group),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mismatch() {
+        assert_eq!(mismatch(0o2775, 100, true, 100, 0o002), None);
+        assert_eq!(
+            mismatch(0o2775, 0, true, 100, 0o002),
+            Some("not owned by the group")
+        );
+        assert_eq!(
+            mismatch(0o2755, 100, true, 100, 0o002),
+            Some("not writable by the group")
+        );
+        // Not expected to be writable by the group
+        assert_eq!(mismatch(0o644, 100, false, 100, 0o022), None);
+        assert_eq!(
+            mismatch(0o775, 100, true, 100, 0o002),
+            Some("without the setgid bit")
+        );
+    }
+
+    #[test]
+    fn test_require_writable() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        require_writable(tmp_dir.path())?;
+        require_writable(&tmp_dir.path().join("not-exist"))?;
+        assert!(check_dir(tmp_dir.path())?.is_empty());
+        let prefix = tmp_dir.path().join("17.0.6");
+        fs::create_dir(&prefix)?;
+        lockdown::lock(&prefix)?;
+        assert!(check_dir(tmp_dir.path())?.is_empty());
+        lockdown::unlock(&prefix)?;
+        Ok(())
+    }
+}
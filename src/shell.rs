@@ -0,0 +1,107 @@
+//! Shell integration switching builds per directory
+//!
+//! `llvmenv init <shell>` prints the hook for the shell, which updates `PATH` (and `LLVM_SYS_<version>_PREFIX`
+//! if `LLVMENV_RUST_BINDING` is set) for the current build before each prompt:
+//!
+//! | Shell      | Line in the rc file                                          |
+//! |:-----------|:-------------------------------------------------------------|
+//! | bash       | `eval "$(llvmenv init bash)"` in `~/.bashrc`                 |
+//! | fish       | `llvmenv init fish \| source` in `~/.config/fish/config.fish` |
+//! | zsh        | `source <(llvmenv zsh)` in `~/.zshrc`                        |
+//! | PowerShell | `Invoke-Expression (& llvmenv init powershell \| Out-String)` in `$PROFILE` |
+//!
+//! `llvmenv zsh` is the same as `llvmenv init zsh`.
+
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::*;
+
+/// Names of shells accepted by `llvmenv init`
+pub const SHELLS: [&str; 4] = ["bash", "fish", "zsh", "powershell"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Fish,
+    Zsh,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = LlvmEnvError;
+
+    /// Shell of the name, or the file name of the executable
+    ///
+    /// ```
+    /// # use llvmenv::shell::Shell;
+    /// # use std::str::FromStr;
+    /// assert_eq!(Shell::from_str("fish").unwrap(), Shell::Fish);
+    /// assert_eq!(Shell::from_str("pwsh").unwrap(), Shell::PowerShell);
+    /// assert!(Shell::from_str("csh").is_err());
+    /// ```
+    fn from_str(shell: &str) -> Result<Self> {
+        Ok(match shell.trim_end_matches(".exe") {
+            "bash" => Shell::Bash,
+            "fish" => Shell::Fish,
+            "zsh" => Shell::Zsh,
+            "powershell" | "pwsh" => Shell::PowerShell,
+            _ => {
+                return Err(format_err!(
+                    "Unsupported shell: {} (use one of {})",
+                    shell,
+                    SHELLS.join(", ")
+                ))
+            }
+        })
+    }
+}
+
+impl Shell {
+    /// Shell of the executable, e.g. `$SHELL`
+    pub fn from_path(shell: &str) -> Option<Self> {
+        Path::new(shell).file_name()?.to_str()?.parse().ok()
+    }
+
+    /// Script of the hook printed by `llvmenv init <shell>`
+    pub fn hook(self) -> &'static str {
+        match self {
+            Shell::Bash => include_str!("../llvmenv.bash"),
+            Shell::Fish => include_str!("../llvmenv.fish"),
+            Shell::Zsh => include_str!("../llvmenv.zsh"),
+            Shell::PowerShell => include_str!("../llvmenv.ps1"),
+        }
+    }
+
+    /// Rc file and the line loading the hook, see [module level doc](index.html)
+    pub fn rc_line(self) -> (&'static str, &'static str) {
+        match self {
+            Shell::Bash => ("~/.bashrc", r#"eval "$(llvmenv init bash)""#),
+            Shell::Fish => ("~/.config/fish/config.fish", "llvmenv init fish | source"),
+            Shell::Zsh => ("~/.zshrc", "source <(llvmenv zsh)"),
+            Shell::PowerShell => (
+                "$PROFILE",
+                "Invoke-Expression (& llvmenv init powershell | Out-String)",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hooks() {
+        for name in SHELLS.iter() {
+            let shell = Shell::from_str(name).unwrap();
+            assert!(shell.hook().contains("LLVMENV_RUST_BINDING"));
+            assert!(shell.hook().contains("llvmenv_cd_source"));
+        }
+        assert_eq!(Shell::from_path("/usr/local/bin/fish"), Some(Shell::Fish));
+        assert_eq!(
+            Shell::from_path("C:\\Program Files\\PowerShell\\7\\pwsh.exe").is_some(),
+            cfg!(windows)
+        );
+    }
+}
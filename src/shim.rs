@@ -0,0 +1,263 @@
+//! Shims dispatching to the current build
+//!
+//! `llvmenv rehash` writes a small script into `${data_dir}/shims` for each executable in `bin/` of the builds,
+//! e.g. `clang`, `clang++`, `llvm-config`, and `opt`. With the directory in `PATH`, they run the tool of the build
+//! selected at invocation time (see [seek_build](../build/fn.seek_build.html)), also in shells without the
+//! [hook](../shell/index.html), editors, and build systems:
+//!
+//! ```shell
+//! export PATH="$HOME/.local/share/llvmenv/shims:$PATH"
+//! ```
+//!
+//! A shim runs `llvmenv __shim <tool> <args>...`, which executes the tool in the current build,
+//! or the next one in `PATH` if the build does not have it, e.g. the system build.
+//! The executions are recorded in the [audit log](../audit/index.html) if enabled.
+//! Once the shims exist, they are regenerated after builds are installed or uninstalled.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audit;
+use crate::build::{self, Build};
+use crate::config::data_dir;
+use crate::error::*;
+use crate::shared;
+
+/// Directory of the shims in the data directory
+pub const SHIMS_DIR: &str = "shims";
+
+/// Hidden subcommand run by the shims
+pub const SHIM_COMMAND: &str = "__shim";
+
+pub fn shim_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join(SHIMS_DIR))
+}
+
+/// Name of the tool of an executable in `bin/`, None if it is not executable
+fn tool_name(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(path).ok()?.permissions().mode() & 0o111 == 0 {
+            return None;
+        }
+    }
+    let name = path.file_name()?.to_str()?;
+    if cfg!(windows) {
+        name.strip_suffix(".exe").map(|name| name.to_string())
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Tools in `bin/` of the `builds`
+fn tools(builds: &[Build]) -> Result<BTreeSet<String>> {
+    let mut tools = BTreeSet::new();
+    for build in builds.iter().filter(|build| build.name() != "system") {
+        let bin = build.prefix().join("bin");
+        if !bin.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&bin)? {
+            tools.extend(tool_name(&entry?.path()));
+        }
+    }
+    Ok(tools)
+}
+
+/// Script of the shim of `tool` running `llvmenv`
+///
+/// ```
+/// # use llvmenv::shim::script;
+/// # use std::path::Path;
+/// # if cfg!(unix) {
+/// let script = script(Path::new("/home/user/.cargo/bin/llvmenv"), "clang++");
+/// assert!(script.ends_with("exec '/home/user/.cargo/bin/llvmenv' __shim 'clang++' \"$@\"\n"));
+/// # }
+/// ```
+pub fn script(llvmenv: &Path, tool: &str) -> String {
+    let llvmenv = llvmenv.display().to_string();
+    if cfg!(windows) {
+        format!("@\"{}\" {} {} %*\r\n", llvmenv, SHIM_COMMAND, tool)
+    } else {
+        format!(
+            "#!/bin/sh\n# Shim of llvmenv, regenerated by `llvmenv rehash`\nexec {} {} {} \"$@\"\n",
+            shell_quote(&llvmenv),
+            SHIM_COMMAND,
+            shell_quote(tool)
+        )
+    }
+}
+
+fn shim_path(dir: &Path, tool: &str) -> PathBuf {
+    if cfg!(windows) {
+        dir.join(format!("{}.cmd", tool))
+    } else {
+        dir.join(tool)
+    }
+}
+
+/// Write the shims of `tools` into `dir`, and remove the others
+fn rehash_in(dir: &Path, llvmenv: &Path, tools: &BTreeSet<String>) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let shims: BTreeSet<PathBuf> = tools.iter().map(|tool| shim_path(dir, tool)).collect();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !shims.contains(&path) {
+            fs::remove_file(&path)?;
+        }
+    }
+    for tool in tools {
+        let path = shim_path(dir, tool);
+        fs::write(&path, script(llvmenv, tool))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(())
+}
+
+/// Regenerate the shims for the builds, and returns the tools
+pub fn rehash() -> Result<BTreeSet<String>> {
+    shared::require_writable(&data_dir()?)?;
+    let tools = tools(&build::builds()?)?;
+    rehash_in(&shim_dir()?, &env::current_exe()?, &tools)?;
+    Ok(tools)
+}
+
+/// [Rehash](fn.rehash.html) if the shims have been generated and writable
+pub fn refresh() -> Result<()> {
+    let dir = shim_dir()?;
+    if dir.is_dir() && shared::is_writable(&dir) {
+        rehash()?;
+    }
+    Ok(())
+}
+
+/// Executable of `tool` in `build`, or in `PATH` except the shims
+fn resolve(tool: &str, build: &Build) -> Result<PathBuf> {
+    let exe = if cfg!(windows) {
+        format!("{}.exe", tool)
+    } else {
+        tool.to_string()
+    };
+    if build.name() != "system" {
+        let path = build.prefix().join("bin").join(&exe);
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    let paths = env::var_os("PATH").unwrap_or_default();
+    find_in_path(&exe, &paths, &shim_dir()?).ok_or_else(|| {
+        format_err!(
+            "'{}' is found neither in the build '{}' nor in PATH",
+            tool,
+            build.name()
+        )
+    })
+}
+
+/// `exe` in the directories of `paths` except `shims`, which may be given through symbolic links or relative paths
+fn find_in_path(exe: &str, paths: &OsStr, shims: &Path) -> Option<PathBuf> {
+    let canonical = |dir: &Path| fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let shims = canonical(shims);
+    env::split_paths(paths)
+        .filter(|dir| canonical(dir) != shims)
+        .map(|dir| dir.join(exe))
+        .find(|path| path.is_file())
+}
+
+/// Run `tool` of the current build with `args` for the shim, and returns its exit code
+pub fn exec(tool: &str, args: &[OsString]) -> Result<i32> {
+    let build = build::seek_build()?;
+    let path = resolve(tool, &build)?;
+    audit::log_tool(tool, &build, args);
+    let mut command = Command::new(&path);
+    command.args(args);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt as _;
+        // Returns only on failure
+        let e = command.exec();
+        bail!("Cannot execute {}: {}", path.display(), e);
+    }
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .map_err(|e| format_err!("Cannot execute {}: {}", path.display(), e))?;
+        Ok(status.code().unwrap_or(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rehash() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let dir = tmp_dir.path().join(SHIMS_DIR);
+        let llvmenv = Path::new("/usr/local/bin/llvmenv");
+        let tools: BTreeSet<String> = ["clang", "opt"].iter().map(|s| s.to_string()).collect();
+        rehash_in(&dir, llvmenv, &tools)?;
+        let clang = shim_path(&dir, "clang");
+        assert_eq!(fs::read_to_string(&clang)?, script(llvmenv, "clang"));
+
+        let tools: BTreeSet<String> = ["clang"].iter().map(|s| s.to_string()).collect();
+        rehash_in(&dir, llvmenv, &tools)?;
+        assert!(clang.exists());
+        assert!(!shim_path(&dir, "opt").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_in_path() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let shims = tmp_dir.path().join(SHIMS_DIR);
+        let bin = tmp_dir.path().join("bin");
+        let link = tmp_dir.path().join("link");
+        for dir in &[&shims, &bin] {
+            fs::create_dir_all(dir)?;
+            fs::write(dir.join("clang"), "")?;
+        }
+        std::os::unix::fs::symlink(&shims, &link)?;
+        let paths = env::join_paths([&link, &shims.join("."), &bin])?;
+        assert_eq!(
+            find_in_path("clang", &paths, &shims),
+            Some(bin.join("clang"))
+        );
+        let paths = env::join_paths([&link])?;
+        assert_eq!(find_in_path("clang", &paths, &shims), None);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tools() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let bin = tmp_dir.path().join("17.0.6/bin");
+        fs::create_dir_all(&bin)?;
+        for name in &["clang", "llvm-config", "README"] {
+            fs::write(bin.join(name), "")?;
+        }
+        for name in &["clang", "llvm-config"] {
+            fs::set_permissions(bin.join(name), fs::Permissions::from_mode(0o755))?;
+        }
+        let build = Build::from_path(&tmp_dir.path().join("17.0.6"));
+        let tools: Vec<String> = tools(&[build])?.into_iter().collect();
+        assert_eq!(tools, vec!["clang", "llvm-config"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+//! Snapshots of the builds for rollback
+//!
+//! With `snapshot.enabled = true` in config.toml, the data directory where builds are installed is snapshotted
+//! before operations removing or replacing builds, i.e. `uninstall`, `gc --yes`, and `build-entry` or `install`
+//! over an existing build. `llvmenv snapshot list` shows the snapshots, and `llvmenv snapshot restore <name>`
+//! rolls the builds back to one of them:
+//!
+//! | Filesystem | Snapshot                                                          | Restore                                      |
+//! |:-----------|:------------------------------------------------------------------|:---------------------------------------------|
+//! | btrfs      | read-only subvolume in `.llvmenv-snapshots` next to the data directory | the current one is kept as `<time>-before-restore`, and replaced by a snapshot of it |
+//! | ZFS        | `<dataset>@llvmenv-<name>`                                        | `zfs rollback -r`, destroying later snapshots |
+//! | APFS       | local snapshot of the volume by `tmutil localsnapshot`            | copied back from the mounted snapshot by rsync, as root |
+//!
+//! The data directory has to be a btrfs subvolume (`btrfs subvolume create`) or the mount point of a ZFS dataset,
+//! so that the snapshots do not include other files. Snapshots are named `<time>-<operation>`,
+//! e.g. `20190115T093000Z-uninstall`, except on APFS where the date of the volume snapshot is used.
+//! `llvmenv snapshot create` takes one by hand. Old snapshots are not removed by llvmenv.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::audit::format_utc;
+use crate::config::{data_dir, load_config};
+use crate::error::*;
+use crate::progress;
+
+/// Directory of btrfs snapshots, next to the data directory to be in the same filesystem
+const BTRFS_SNAPSHOTS: &str = ".llvmenv-snapshots";
+
+/// Prefix of ZFS snapshots made by llvmenv
+const ZFS_PREFIX: &str = "llvmenv-";
+
+/// Prefix of APFS local snapshots made by `tmutil`
+const TMUTIL_PREFIX: &str = "com.apple.TimeMachine.";
+
+/// Inode of the root directory of a btrfs subvolume
+#[cfg(unix)]
+const BTRFS_SUBVOLUME_INODE: u64 = 256;
+
+/// Snapshot mechanism of the filesystem of the data directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    Btrfs,
+    Zfs { dataset: String },
+    Apfs,
+}
+
+/// Type of the filesystem of `path`, e.g. `btrfs`
+#[cfg(target_os = "linux")]
+fn fs_type(path: &Path) -> Option<&'static str> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    const BTRFS_SUPER_MAGIC: u32 = 0x9123_683E;
+    const ZFS_SUPER_MAGIC: u32 = 0x2FC1_2FC1;
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+    match st.f_type as u32 {
+        BTRFS_SUPER_MAGIC => Some("btrfs"),
+        ZFS_SUPER_MAGIC => Some("zfs"),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn fs_type(path: &Path) -> Option<&'static str> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+    match unsafe { CStr::from_ptr(st.f_fstypename.as_ptr()) }
+        .to_str()
+        .ok()?
+    {
+        "apfs" => Some("apfs"),
+        "zfs" => Some("zfs"),
+        _ => None,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn fs_type(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+/// Stdout of `command`, error with its stderr if failed
+fn output(command: &mut Command) -> Result<String> {
+    let output = command
+        .output()
+        .map_err(|e| format_err!("Cannot run {:?}: {}", command, e))?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Dataset mounted at `dir` in `zfs list -H -o name,mountpoint <dir>`
+fn parse_zfs_dataset(out: &str, dir: &Path) -> Option<String> {
+    let mut fields = out.lines().next()?.split('\t');
+    let (name, mountpoint) = (fields.next()?, fields.next()?);
+    if Path::new(mountpoint) == dir {
+        Some(name.to_string())
+    } else {
+        None
+    }
+}
+
+/// Names of the snapshots of llvmenv in `zfs list -H -t snapshot -o name`, oldest first
+///
+/// ```
+/// # use llvmenv::snapshot::parse_zfs_snapshots;
+/// let out = "tank/llvmenv@daily\ntank/llvmenv@llvmenv-20190115T093000Z-gc\n";
+/// assert_eq!(parse_zfs_snapshots(out, "tank/llvmenv"), vec!["20190115T093000Z-gc"]);
+/// ```
+pub fn parse_zfs_snapshots(out: &str, dataset: &str) -> Vec<String> {
+    let prefix = format!("{}@{}", dataset, ZFS_PREFIX);
+    out.lines()
+        .filter_map(|line| line.trim().strip_prefix(&prefix))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Dates of the local snapshots in `tmutil listlocalsnapshots`, or `Created local snapshot with date: ...`
+///
+/// ```
+/// # use llvmenv::snapshot::parse_tmutil;
+/// let out = "Snapshots for disk /:\ncom.apple.TimeMachine.2019-01-15-093000.local\n";
+/// assert_eq!(parse_tmutil(out), vec!["2019-01-15-093000"]);
+/// assert_eq!(parse_tmutil("Created local snapshot with date: 2019-01-15-093000\n"), vec!["2019-01-15-093000"]);
+/// ```
+pub fn parse_tmutil(out: &str) -> Vec<String> {
+    out.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if let Some(date) = line.strip_prefix("Created local snapshot with date:") {
+                return Some(date.trim());
+            }
+            line.strip_prefix(TMUTIL_PREFIX)?.strip_suffix(".local")
+        })
+        .map(|date| date.to_string())
+        .collect()
+}
+
+/// Name of a snapshot taken now before `operation`, e.g. `20190115T093000Z-uninstall`
+fn snapshot_name(operation: &str) -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    format!("{}-{}", format_utc(secs).replace(['-', ':'], ""), operation)
+}
+
+fn btrfs_snapshots(dir: &Path) -> Result<PathBuf> {
+    let parent = dir
+        .parent()
+        .ok_or_else(|| format_err!("No parent directory of {}", dir.display()))?;
+    Ok(parent.join(BTRFS_SNAPSHOTS))
+}
+
+impl Backend {
+    /// Backend for the data directory `dir`, None if its filesystem has no snapshots
+    pub fn detect(dir: &Path) -> Result<Option<Self>> {
+        Ok(match fs_type(dir) {
+            Some("btrfs") => {
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::MetadataExt;
+                    if fs::metadata(dir)?.ino() != BTRFS_SUBVOLUME_INODE {
+                        bail!(
+                            "{} is not a btrfs subvolume. Move it aside, and create it by `btrfs subvolume create` to take snapshots.",
+                            dir.display()
+                        );
+                    }
+                }
+                Some(Backend::Btrfs)
+            }
+            Some("zfs") => {
+                let out = output(
+                    Command::new("zfs")
+                        .args(["list", "-H", "-o", "name,mountpoint"])
+                        .arg(dir),
+                )?;
+                match parse_zfs_dataset(&out, dir) {
+                    Some(dataset) => Some(Backend::Zfs { dataset }),
+                    None => bail!(
+                        "{} is not the mount point of a ZFS dataset. Create a dataset for it to take snapshots.",
+                        dir.display()
+                    ),
+                }
+            }
+            Some("apfs") => Some(Backend::Apfs),
+            _ => None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Btrfs => "btrfs",
+            Backend::Zfs { .. } => "ZFS",
+            Backend::Apfs => "APFS",
+        }
+    }
+
+    /// Take a snapshot of `dir` before `operation`, and returns its name
+    pub fn create(&self, dir: &Path, operation: &str) -> Result<String> {
+        let name = snapshot_name(operation);
+        match self {
+            Backend::Btrfs => {
+                let snapshots = btrfs_snapshots(dir)?;
+                fs::create_dir_all(&snapshots)?;
+                Command::new("btrfs")
+                    .args(["subvolume", "snapshot", "-r"])
+                    .arg(dir)
+                    .arg(snapshots.join(&name))
+                    .silent()
+                    .check_run()?;
+            }
+            Backend::Zfs { dataset } => {
+                Command::new("zfs")
+                    .arg("snapshot")
+                    .arg(format!("{}@{}{}", dataset, ZFS_PREFIX, name))
+                    .check_run()?;
+            }
+            Backend::Apfs => {
+                let out = output(Command::new("tmutil").arg("localsnapshot"))?;
+                return parse_tmutil(&out).pop().ok_or_else(|| {
+                    format_err!("Unexpected output of tmutil localsnapshot: {}", out.trim())
+                });
+            }
+        }
+        Ok(name)
+    }
+
+    /// Names of the snapshots of `dir`, oldest first
+    pub fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        Ok(match self {
+            Backend::Btrfs => {
+                let snapshots = btrfs_snapshots(dir)?;
+                if !snapshots.exists() {
+                    return Ok(Vec::new());
+                }
+                let mut names: Vec<String> = fs::read_dir(&snapshots)?
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect();
+                names.sort();
+                names
+            }
+            Backend::Zfs { dataset } => {
+                let out = output(
+                    Command::new("zfs")
+                        .args([
+                            "list", "-H", "-t", "snapshot", "-o", "name", "-s", "creation", "-d",
+                            "1",
+                        ])
+                        .arg(dataset),
+                )?;
+                parse_zfs_snapshots(&out, dataset)
+            }
+            Backend::Apfs => parse_tmutil(&output(
+                Command::new("tmutil").arg("listlocalsnapshots").arg(dir),
+            )?),
+        })
+    }
+
+    /// Roll `dir` back to the snapshot `name`, see [module level doc](index.html)
+    pub fn restore(&self, dir: &Path, name: &str) -> Result<()> {
+        if !self.list(dir)?.iter().any(|n| n == name) {
+            bail!(
+                "Snapshot '{}' is not found. See `llvmenv snapshot list`.",
+                name
+            );
+        }
+        match self {
+            Backend::Btrfs => {
+                let snapshots = btrfs_snapshots(dir)?;
+                let current = snapshots.join(snapshot_name("before-restore"));
+                Command::new("mv").arg(dir).arg(&current).check_run()?;
+                Command::new("btrfs")
+                    .args(["subvolume", "snapshot"])
+                    .arg(snapshots.join(name))
+                    .arg(dir)
+                    .silent()
+                    .check_run()?;
+                progress::message(&format!(
+                    "The builds before the restore are kept in {}",
+                    current.display()
+                ));
+            }
+            Backend::Zfs { dataset } => {
+                Command::new("zfs")
+                    .args(["rollback", "-r"])
+                    .arg(format!("{}@{}{}", dataset, ZFS_PREFIX, name))
+                    .check_run()?;
+            }
+            Backend::Apfs => {
+                let volume = output(Command::new("df").arg(dir))?
+                    .lines()
+                    .nth(1)
+                    .and_then(|line| line.split_whitespace().last())
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format_err!("Cannot find the volume of {}", dir.display()))?;
+                let relative = dir.strip_prefix(&volume).unwrap_or(dir);
+                let mount = tempfile::TempDir::new()?;
+                Command::new("mount_apfs")
+                    .args(["-o", "rdonly,nobrowse", "-s"])
+                    .arg(format!("{}{}.local", TMUTIL_PREFIX, name))
+                    .arg(&volume)
+                    .arg(mount.path())
+                    .check_run()?;
+                let res = Command::new("rsync")
+                    .args(["-a", "--delete"])
+                    .arg(format!("{}/", mount.path().join(relative).display()))
+                    .arg(format!("{}/", dir.display()))
+                    .check_run();
+                Command::new("umount").arg(mount.path()).check_run()?;
+                res?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Backend for the data directory, error if its filesystem has no snapshots
+pub fn backend() -> Result<(PathBuf, Backend)> {
+    let dir = data_dir()?;
+    match Backend::detect(&dir)? {
+        Some(backend) => Ok((dir, backend)),
+        None => bail!(
+            "Snapshots need btrfs, ZFS, or APFS, but {} is not on them",
+            dir.display()
+        ),
+    }
+}
+
+/// Take a snapshot before `operation` if `snapshot.enabled`, see [module level doc](index.html)
+pub fn before(operation: &str) -> Result<Option<String>> {
+    if !load_config()?.snapshot.enabled {
+        return Ok(None);
+    }
+    let (dir, backend) = backend()?;
+    let name = backend.create(&dir, operation)?;
+    progress::message(&format!(
+        "Snapshot '{}' of the builds ({}), `llvmenv snapshot restore {}` to undo {}",
+        name,
+        backend.name(),
+        name,
+        operation
+    ));
+    Ok(Some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zfs_dataset() {
+        let dir = Path::new("/home/user/.local/share/llvmenv");
+        let out = "tank/home/llvmenv\t/home/user/.local/share/llvmenv\n";
+        assert_eq!(
+            parse_zfs_dataset(out, dir),
+            Some("tank/home/llvmenv".to_string())
+        );
+        // Snapshots of the whole home are too much
+        assert_eq!(parse_zfs_dataset("tank/home\t/home\n", dir), None);
+    }
+
+    #[test]
+    fn test_snapshot_name() {
+        let name = snapshot_name("uninstall");
+        assert!(name.ends_with("Z-uninstall"), "{}", name);
+        assert!(!name.contains(':'), "{}", name);
+    }
+}
@@ -0,0 +1,125 @@
+//! Export and import the whole state of llvmenv
+//!
+//! `llvmenv state export state.tar` creates a tar archive containing
+//!
+//! - `state/config/`: files in `$XDG_CONFIG_HOME/llvmenv`, e.g. entry.toml, config.toml, and the global `.llvmenv`
+//! - `state/metadata/${build}.toml`: metadata of builds, e.g. tags
+//! - `llvmenv/${build}/`: builds themselves, only if `--builds` is specified
+//!
+//! and `llvmenv state import state.tar` restores them on another machine.
+
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+use crate::build::{self, Build};
+use crate::config::*;
+use crate::error::*;
+use crate::resource::unpack;
+use crate::tree;
+
+const STATE_DIR: &str = "state";
+
+/// Export config, entries, and metadata of builds (and builds themselves if `with_builds`) into `archive`
+pub fn export(archive: &Path, with_builds: bool) -> Result<()> {
+    let staging = TempDir::new()?;
+    let state = staging.path().join(STATE_DIR);
+    let config = state.join("config");
+    let metadata = state.join("metadata");
+    fs::create_dir_all(&config)?;
+    fs::create_dir_all(&metadata)?;
+
+    for path in fs::read_dir(config_dir()?)? {
+        let path = path?.path();
+        if path.is_file() {
+            info!("Export {}", path.display());
+            fs::copy(&path, config.join(path.file_name().unwrap()))?;
+        }
+    }
+    let builds: Vec<Build> = build::builds()?
+        .into_iter()
+        .filter(|b| b.name() != "system")
+        .collect();
+    for b in &builds {
+        let toml = toml::to_string(&b.metadata()?)?;
+        fs::write(metadata.join(format!("{}.toml", b.name())), toml)?;
+    }
+
+    let mut tar = tar::Builder::new(fs::File::create(archive)?);
+    tar.follow_symlinks(false);
+    tar.append_dir_all(STATE_DIR, &state)?;
+    if with_builds {
+        for b in &builds {
+            info!("Export build {}", b.name());
+            tar.append_dir_all(Path::new(APP_NAME).join(b.name()), b.prefix())?;
+        }
+    }
+    tar.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// Import an archive created by [export](./fn.export.html).
+/// Existing config files and builds are kept unless `force`.
+pub fn import(archive: &Path, force: bool) -> Result<()> {
+    if !archive.exists() {
+        bail!("Archive does not found: {}", archive.display());
+    }
+    // Extract in the data directory to move builds without copying
+    let staging = TempDir::new_in(data_dir()?)?;
+    unpack(archive, staging.path())?;
+    let state = staging.path().join(STATE_DIR);
+    if !state.is_dir() {
+        bail!("Not a llvmenv state archive: {}", archive.display());
+    }
+
+    let config = config_dir()?;
+    for path in fs::read_dir(state.join("config"))? {
+        let path = path?.path();
+        let dest = config.join(path.file_name().unwrap());
+        if dest.exists() && !force {
+            warn!("Skip existing {}", dest.display());
+            continue;
+        }
+        info!("Import {}", dest.display());
+        fs::copy(&path, &dest)?;
+    }
+
+    let builds = staging.path().join(APP_NAME);
+    if builds.is_dir() {
+        let data = data_dir()?;
+        for path in fs::read_dir(&builds)? {
+            let path = path?.path();
+            let dest = data.join(path.file_name().unwrap());
+            if dest.exists() {
+                if !force {
+                    warn!("Skip existing build {}", dest.display());
+                    continue;
+                }
+                tree::remove(&dest)?;
+            }
+            info!("Import build {}", dest.display());
+            tree::move_dir(&path, &dest)?;
+        }
+    }
+
+    for path in fs::read_dir(state.join("metadata"))? {
+        let path = path?.path();
+        let name = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let b = Build::from_name(&name)?;
+        if !b.exists() {
+            warn!("Build '{}' does not exist, metadata is not imported", name);
+            continue;
+        }
+        if b.metadata()? != Default::default() && !force {
+            warn!("Skip existing metadata of '{}'", name);
+            continue;
+        }
+        let metadata = toml::from_str(&fs::read_to_string(&path)?)?;
+        b.save_metadata(&metadata)?;
+    }
+    Ok(())
+}
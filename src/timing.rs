@@ -0,0 +1,210 @@
+//! Estimate of the remaining build time
+//!
+//! The time and the number of steps (e.g. `[123/4567]` of ninja) of each build are recorded
+//! for the entry in `${cache_dir}/build-times.toml`, keeping the last `KEEP` builds.
+//! When the next build reports its first step, the time per step of the previous builds of the entry
+//! (or of all entries if it has never been built) gives the estimate of the whole build:
+//!
+//! ```text
+//! Estimated build time of 'llvm-17': about 1h40m for 4567 steps
+//! ```
+//!
+//! During the build, the estimate is refined by the time per step observed so far, which is weighted more
+//! as the build proceeds, and the progress bar shows e.g. `about 25m remaining`.
+//! The time per step is scaled by the number of parallel jobs, assuming it is inversely proportional to them.
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::cache_dir;
+use crate::error::*;
+
+/// History of the build times in the cache directory
+const TIMINGS_FN: &str = "build-times.toml";
+
+/// Number of builds kept for each entry
+const KEEP: usize = 5;
+
+/// Steps to be observed before estimating without history
+const MIN_OBSERVED_STEPS: usize = 10;
+
+/// Time and steps of a build
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub seconds: u64,
+    /// Total steps reported by the builder
+    pub steps: usize,
+    /// Number of parallel jobs
+    pub jobs: usize,
+}
+
+impl Record {
+    /// Seconds for each step with `jobs`
+    fn seconds_per_step(&self, jobs: usize) -> Option<f64> {
+        if self.steps == 0 {
+            return None;
+        }
+        Some(self.seconds as f64 / self.steps as f64 * self.jobs.max(1) as f64 / jobs.max(1) as f64)
+    }
+}
+
+/// Records of the builds for each entry
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct History {
+    entries: BTreeMap<String, Vec<Record>>,
+}
+
+impl History {
+    fn path() -> Result<PathBuf> {
+        Ok(cache_dir()?.join(TIMINGS_FN))
+    }
+
+    /// Load the history, empty if it does not exist or is broken
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        let entries = match fs::read_to_string(&path) {
+            Ok(toml_str) => toml::from_str(&toml_str).unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(History { entries })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(Self::path()?, toml::to_string(&self.entries)?)?;
+        Ok(())
+    }
+
+    /// Add `record` of the build of `name`, dropping the oldest ones
+    pub fn add(&mut self, name: &str, record: Record) {
+        let records = self.entries.entry(name.to_string()).or_default();
+        records.push(record);
+        if records.len() > KEEP {
+            records.drain(..records.len() - KEEP);
+        }
+    }
+
+    /// Average seconds for each step with `jobs` in the builds of `name`, or of all entries if it has no records
+    ///
+    /// ```
+    /// # use llvmenv::timing::{History, Record};
+    /// let mut history = History::default();
+    /// assert_eq!(history.seconds_per_step("llvm-17", 8), None);
+    /// history.add("llvm-16", Record { seconds: 300, steps: 100, jobs: 8 });
+    /// assert_eq!(history.seconds_per_step("llvm-17", 8), Some(3.0));
+    /// history.add("llvm-17", Record { seconds: 400, steps: 100, jobs: 8 });
+    /// assert_eq!(history.seconds_per_step("llvm-17", 8), Some(4.0));
+    /// // Twice faster with twice jobs
+    /// assert_eq!(history.seconds_per_step("llvm-17", 16), Some(2.0));
+    /// ```
+    pub fn seconds_per_step(&self, name: &str, jobs: usize) -> Option<f64> {
+        let records: Vec<&Record> = match self.entries.get(name) {
+            Some(records) if !records.is_empty() => records.iter().collect(),
+            _ => self.entries.values().flatten().collect(),
+        };
+        let rates: Vec<f64> = records
+            .iter()
+            .filter_map(|r| r.seconds_per_step(jobs))
+            .collect();
+        if rates.is_empty() {
+            return None;
+        }
+        Some(rates.iter().sum::<f64>() / rates.len() as f64)
+    }
+}
+
+/// Remaining time of a build at `step` of `total` after `elapsed`, with `prior` seconds per step from the history
+///
+/// ```
+/// # use llvmenv::timing::remaining;
+/// # use std::time::Duration;
+/// // Only the history at the start
+/// assert_eq!(remaining(Some(2.0), 0, 100, Duration::from_secs(0)), Some(Duration::from_secs(200)));
+/// // Half of the observed rate (4 s/step) at the middle
+/// assert_eq!(remaining(Some(2.0), 50, 100, Duration::from_secs(200)), Some(Duration::from_secs(150)));
+/// // Too few steps to estimate without history
+/// assert_eq!(remaining(None, 5, 100, Duration::from_secs(10)), None);
+/// assert_eq!(remaining(None, 50, 100, Duration::from_secs(100)), Some(Duration::from_secs(100)));
+/// ```
+pub fn remaining(
+    prior: Option<f64>,
+    step: usize,
+    total: usize,
+    elapsed: Duration,
+) -> Option<Duration> {
+    if total == 0 || step > total {
+        return None;
+    }
+    let observed = if step > 0 {
+        Some(elapsed.as_secs_f64() / step as f64)
+    } else {
+        None
+    };
+    let progress = step as f64 / total as f64;
+    let rate = match (prior, observed) {
+        (Some(prior), Some(observed)) => prior * (1.0 - progress) + observed * progress,
+        (Some(prior), None) => prior,
+        (None, Some(observed)) if step >= MIN_OBSERVED_STEPS.min(total) => observed,
+        _ => return None,
+    };
+    Some(Duration::from_secs(
+        (rate * (total - step) as f64).round() as u64
+    ))
+}
+
+/// Rough representation of a duration, e.g. `1h40m`, `25m`, or `40s`
+///
+/// ```
+/// # use llvmenv::timing::format_duration;
+/// # use std::time::Duration;
+/// assert_eq!(format_duration(Duration::from_secs(6000)), "1h40m");
+/// assert_eq!(format_duration(Duration::from_secs(1530)), "25m");
+/// assert_eq!(format_duration(Duration::from_secs(40)), "40s");
+/// ```
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_roundtrip() {
+        let mut history = History::default();
+        for seconds in 0..7 {
+            history.add(
+                "llvm-17",
+                Record {
+                    seconds,
+                    steps: 10,
+                    jobs: 4,
+                },
+            );
+        }
+        history.add(
+            "my llvm",
+            Record {
+                seconds: 1,
+                steps: 0,
+                jobs: 1,
+            },
+        );
+        assert_eq!(history.entries["llvm-17"].len(), KEEP);
+        assert_eq!(history.entries["llvm-17"][0].seconds, 2);
+        let toml_str = toml::to_string(&history.entries).unwrap();
+        let entries: BTreeMap<String, Vec<Record>> = toml::from_str(&toml_str).unwrap();
+        assert_eq!(entries, history.entries);
+        // without steps
+        assert_eq!(history.seconds_per_step("my llvm", 4), None);
+    }
+}
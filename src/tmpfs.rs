@@ -0,0 +1,287 @@
+//! Build trees on a RAM-backed filesystem
+//!
+//! `build_in = "tmpfs"` of an entry places its build tree on tmpfs, which speeds up builds on machines
+//! with slow disks:
+//!
+//! ```toml
+//! [llvm-17]
+//! url      = "https://github.com/llvm/llvm-project"
+//! tag      = "llvmorg-17.0.6"
+//! build_in = "tmpfs"
+//! ```
+//!
+//! The tree is created in `/dev/shm/llvmenv-<uid>` on Linux, or in `$LLVMENV_TMPFS/llvmenv-<uid>` (e.g. a ramdisk
+//! mounted by hand on macOS). It is placed there only if the filesystem has free space for the estimated size of the tree
+//! ([estimated_size](fn.estimated_size.html)), and the available memory keeps `RESERVED_MEMORY` for the compilers
+//! in addition. Otherwise the build falls back to the build directory on disk with a message.
+//!
+//! The choice is kept while the tree exists, and `build-entry --clean` makes it again.
+//! The tree except the build logs is removed after a successful install to release the memory,
+//! unless `keep_tmpfs = true` in the entry keeps it for incremental `llvmenv rebuild`.
+//! `llvmenv uninstall --purge-cache` and `llvmenv gc` remove the trees there as well as on disk.
+//! Since tmpfs is shared by the users, the directory is created only accessible by the user, and an existing one
+//! is used only if it is a directory (not a symbolic link) owned by the user with mode 0700.
+//! Trees on tmpfs are lost on reboot, which only costs a rebuild since the installed build is on disk.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::entry::BuildType;
+use crate::error::*;
+use crate::hostinfo;
+use crate::progress;
+use crate::tree;
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// Memory left for the compilers and the linkers besides the build tree
+const RESERVED_MEMORY: u64 = 4 * GB;
+
+/// Rough size of a build tree of LLVM with the default projects
+pub fn estimated_size(build_type: BuildType) -> u64 {
+    match build_type {
+        BuildType::Debug => 50 * GB,
+        BuildType::RelWithDebInfo => 30 * GB,
+        BuildType::Release | BuildType::MinSizeRel => 6 * GB,
+    }
+}
+
+/// Directory of the build trees on tmpfs, None if not available on this host
+pub fn root() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("LLVMENV_TMPFS").filter(|dir| !dir.is_empty()) {
+        return Some(user_dir(Path::new(&dir)));
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let shm = Path::new("/dev/shm");
+        if shm.is_dir() {
+            return Some(user_dir(shm));
+        }
+    }
+    None
+}
+
+/// Directory of the user in the shared tmpfs mounted at `mount`
+fn user_dir(mount: &Path) -> PathBuf {
+    #[cfg(unix)]
+    let name = format!("llvmenv-{}", unsafe { libc::getuid() });
+    #[cfg(not(unix))]
+    let name = "llvmenv".to_string();
+    mount.join(name)
+}
+
+/// Reason not to place a tree of `required` bytes on tmpfs, with `free` space of it and the `available` memory.
+/// Unknown ones are not checked.
+///
+/// ```
+/// # use llvmenv::tmpfs::check;
+/// const GB: u64 = 1024 * 1024 * 1024;
+/// assert_eq!(check(6 * GB, Some(16 * GB), Some(24 * GB)), None);
+/// assert!(check(6 * GB, Some(4 * GB), Some(24 * GB)).unwrap().contains("free"));
+/// // The compilers need memory as well
+/// assert!(check(6 * GB, Some(16 * GB), Some(8 * GB)).unwrap().contains("memory"));
+/// assert_eq!(check(6 * GB, None, None), None);
+/// ```
+pub fn check(required: u64, free: Option<u64>, available: Option<u64>) -> Option<String> {
+    let gb = |bytes: u64| format!("{:.1} GB", bytes as f64 / GB as f64);
+    match (free, available) {
+        (Some(free), _) if free < required => {
+            Some(format!("tmpfs has {} free for {}", gb(free), gb(required)))
+        }
+        (_, Some(available)) if available < required.saturating_add(RESERVED_MEMORY) => {
+            Some(format!(
+                "{} of memory is available for {} and {} for the compilers",
+                gb(available),
+                gb(required),
+                gb(RESERVED_MEMORY)
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Tree of `name` on tmpfs if it exists or fits there, None to build in `disk`, see [module level doc](index.html)
+pub fn build_dir(name: &str, build_type: BuildType, disk: &Path) -> Result<Option<PathBuf>> {
+    let required = estimated_size(build_type);
+    build_dir_in(root().as_deref(), name, disk, |root| {
+        check(
+            required,
+            fs2::available_space(root).ok(),
+            hostinfo::available_memory(),
+        )
+    })
+}
+
+/// [build_dir](fn.build_dir.html) in `root`, with `fits` giving the reason not to place a tree there
+fn build_dir_in<F>(root: Option<&Path>, name: &str, disk: &Path, fits: F) -> Result<Option<PathBuf>>
+where
+    F: FnOnce(&Path) -> Option<String>,
+{
+    let root = match root {
+        Some(root) => root,
+        None => {
+            if !disk.exists() {
+                progress::message(&format!(
+                    "Build '{}' on disk since tmpfs is not available",
+                    name
+                ));
+            }
+            return Ok(None);
+        }
+    };
+    let dir = root.join(name);
+    if dir.exists() {
+        check_root(root)?;
+        return Ok(Some(dir));
+    }
+    // Keep the tree on disk made by a previous fallback
+    if disk.exists() {
+        return Ok(None);
+    }
+    create_root(root)?;
+    if let Some(reason) = fits(root) {
+        progress::message(&format!("Build '{}' on disk since {}", name, reason));
+        return Ok(None);
+    }
+    fs::create_dir_all(&dir)?;
+    progress::message(&format!("Build '{}' on tmpfs in {}", name, dir.display()));
+    Ok(Some(dir))
+}
+
+/// Remove the contents of the tree `dir` except the files named in `keep`, e.g. the build logs
+pub fn release(dir: &Path, keep: &[&str]) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if keep.iter().any(|name| entry.file_name() == *name) {
+            continue;
+        }
+        tree::remove(&entry.path())?;
+    }
+    Ok(())
+}
+
+/// Error if `root` is not a directory only accessible by the user, e.g. made by another user on shared tmpfs
+fn check_root(root: &Path) -> Result<()> {
+    let meta = fs::symlink_metadata(root)?;
+    if !meta.is_dir() {
+        bail!(
+            "{} is not a directory, refused to build in it",
+            root.display()
+        );
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let uid = unsafe { libc::getuid() };
+        if meta.uid() != uid {
+            bail!(
+                "{} is not owned by you (uid {}), refused to build in it",
+                root.display(),
+                uid
+            );
+        }
+        if meta.mode() & 0o777 != 0o700 {
+            bail!(
+                "{} has mode {:o} instead of 700, refused to build in it",
+                root.display(),
+                meta.mode() & 0o777
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Create `root` only accessible by the user, since tmpfs is shared
+fn create_root(root: &Path) -> Result<()> {
+    if fs::symlink_metadata(root).is_ok() {
+        return check_root(root);
+    }
+    fs::create_dir_all(root)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(root, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_dir() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let root = tmp_dir.path().join("tmpfs");
+        let disk = tmp_dir.path().join("disk");
+
+        let full = |_: &Path| Some("full".to_string());
+        let fits = |_: &Path| None;
+
+        assert_eq!(
+            build_dir_in(Some(&root), "llvm-17", &disk.join("llvm-17"), full)?,
+            None
+        );
+        assert!(!root.join("llvm-17").exists());
+
+        let dir = build_dir_in(Some(&root), "llvm-17", &disk.join("llvm-17"), fits)?;
+        assert_eq!(dir, Some(root.join("llvm-17")));
+        assert!(root.join("llvm-17").is_dir());
+        // Kept while it exists
+        assert_eq!(
+            build_dir_in(Some(&root), "llvm-17", &disk.join("llvm-17"), full)?,
+            dir
+        );
+
+        // Kept on disk
+        fs::create_dir_all(disk.join("llvm-16"))?;
+        assert_eq!(
+            build_dir_in(Some(&root), "llvm-16", &disk.join("llvm-16"), fits)?,
+            None
+        );
+        assert_eq!(
+            build_dir_in(None, "llvm-16", &disk.join("llvm-16"), fits)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_release() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let dir = tmp_dir.path().join("llvm-17");
+        fs::create_dir_all(dir.join("lib"))?;
+        fs::write(dir.join("lib/libLLVM.a"), "")?;
+        fs::write(dir.join("CMakeCache.txt"), "")?;
+        fs::write(dir.join("build.log"), "")?;
+        release(&dir, &["build.log"])?;
+        let names: Vec<_> = fs::read_dir(&dir)?
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["build.log"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_root() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp_dir = tempfile::TempDir::new()?;
+        let root = tmp_dir.path().join("tmpfs");
+        let disk = tmp_dir.path().join("disk");
+        create_root(&root)?;
+        check_root(&root)?;
+
+        fs::create_dir(root.join("llvm-17"))?;
+        fs::set_permissions(&root, fs::Permissions::from_mode(0o755))?;
+        assert!(build_dir_in(Some(&root), "llvm-17", &disk.join("llvm-17"), |_| None).is_err());
+        assert!(create_root(&root).is_err());
+
+        let link = tmp_dir.path().join("link");
+        fs::set_permissions(&root, fs::Permissions::from_mode(0o700))?;
+        std::os::unix::fs::symlink(&root, &link)?;
+        assert!(create_root(&link).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! Bazel C/C++ toolchain
+//!
+//! `llvmenv bazel [name] -o toolchain/` writes `BUILD.bazel` and `cc_toolchain_config.bzl`
+//! into `toolchain/` which define `cc_toolchain` using clang, lld, and binutils of the build
+//! by their absolute paths. `target_cpu` is derived from the host triple of clang (e.g. `k8` for x86_64 Linux),
+//! and C++ programs link libc++ of the build if it is installed, or libstdc++ of the system otherwise.
+//! Register it in `WORKSPACE`:
+//!
+//! ```text
+//! register_toolchains("//toolchain:llvmenv_toolchain")
+//! ```
+//!
+//! and build with `--incompatible_enable_cc_toolchain_resolution`.
+
+use log::info;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{clang_include_dirs, tool_path};
+use crate::build::Build;
+use crate::error::Result;
+
+pub const BUILD_FN: &str = "BUILD.bazel";
+pub const CONFIG_FN: &str = "cc_toolchain_config.bzl";
+
+/// Tools in `tool_paths` of Bazel, with LLVM and GNU names
+const TOOLS: [(&str, &str, &str); 9] = [
+    ("gcc", "clang", "cc"),
+    ("cpp", "clang-cpp", "cpp"),
+    ("ld", "ld.lld", "ld"),
+    ("ar", "llvm-ar", "ar"),
+    ("nm", "llvm-nm", "nm"),
+    ("objcopy", "llvm-objcopy", "objcopy"),
+    ("objdump", "llvm-objdump", "objdump"),
+    ("strip", "llvm-strip", "strip"),
+    ("gcov", "llvm-cov", "gcov"),
+];
+
+/// Write toolchain definition of `build` into `out` directory
+pub fn generate(build: &Build, out: &Path) -> Result<()> {
+    fs::create_dir_all(out)?;
+    info!("Write Bazel toolchain into {}", out.display());
+    fs::write(out.join(BUILD_FN), build_file(build))?;
+    fs::write(out.join(CONFIG_FN), config_file(build))?;
+    Ok(())
+}
+
+fn identifier(build: &Build) -> String {
+    format!("llvmenv-{}", build.name())
+}
+
+/// Content of `BUILD.bazel`
+pub fn build_file(build: &Build) -> String {
+    format!(
+        r#"# Generated by llvmenv for '{name}'
+load(":{config}", "cc_toolchain_config")
+
+package(default_visibility = ["//visibility:public"])
+
+filegroup(name = "empty")
+
+cc_toolchain_config(name = "llvmenv_config")
+
+cc_toolchain(
+    name = "llvmenv_cc_toolchain",
+    toolchain_identifier = "{id}",
+    toolchain_config = ":llvmenv_config",
+    all_files = ":empty",
+    compiler_files = ":empty",
+    dwp_files = ":empty",
+    linker_files = ":empty",
+    objcopy_files = ":empty",
+    strip_files = ":empty",
+    supports_param_files = 0,
+)
+
+toolchain(
+    name = "llvmenv_toolchain",
+    toolchain = ":llvmenv_cc_toolchain",
+    toolchain_type = "@bazel_tools//tools/cpp:toolchain_type",
+)
+"#,
+        name = build.name(),
+        config = CONFIG_FN,
+        id = identifier(build),
+    )
+}
+
+/// Triple of the host by `clang -dumpmachine` of the build, or of llvmenv itself
+fn host_triple(build: &Build) -> String {
+    Command::new(build.prefix().join("bin/clang"))
+        .arg("-dumpmachine")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|triple| !triple.is_empty())
+        .unwrap_or_else(|| format!("{}-unknown-{}", env::consts::ARCH, env::consts::OS))
+}
+
+/// `target_cpu` of Bazel for the triple
+///
+/// ```
+/// # use llvmenv::toolchain::bazel::target_cpu;
+/// assert_eq!(target_cpu("x86_64-pc-linux-gnu"), "k8");
+/// assert_eq!(target_cpu("aarch64-unknown-linux-gnu"), "aarch64");
+/// assert_eq!(target_cpu("arm64-apple-darwin23.4.0"), "darwin_arm64");
+/// assert_eq!(target_cpu("x86_64-apple-darwin"), "darwin_x86_64");
+/// assert_eq!(target_cpu("x86_64-pc-windows-msvc"), "x64_windows");
+/// assert_eq!(target_cpu("powerpc64le-unknown-linux-gnu"), "ppc");
+/// assert_eq!(target_cpu("riscv64-unknown-linux-gnu"), "riscv64");
+/// ```
+pub fn target_cpu(triple: &str) -> String {
+    let arch = triple.split('-').next().unwrap_or("");
+    let darwin = triple.contains("-apple-");
+    let windows = triple.contains("-windows");
+    match arch {
+        "x86_64" | "amd64" if darwin => "darwin_x86_64",
+        "x86_64" | "amd64" if windows => "x64_windows",
+        "x86_64" | "amd64" => "k8",
+        "aarch64" | "arm64" if darwin => "darwin_arm64",
+        "aarch64" | "arm64" if windows => "arm64_windows",
+        "aarch64" | "arm64" => "aarch64",
+        "i386" | "i486" | "i586" | "i686" => "piii",
+        "powerpc64le" | "ppc64le" => "ppc",
+        arch if arch.starts_with("arm") => "arm",
+        arch => arch,
+    }
+    .to_string()
+}
+
+/// Directory of libc++ in the build, `lib` or the per-target one, e.g. `lib/x86_64-unknown-linux-gnu`
+fn libcxx_dir(build: &Build) -> Option<PathBuf> {
+    ["lib/libc++.*", "lib/*/libc++.*"]
+        .iter()
+        .filter_map(|pattern| glob::glob(&build.prefix().join(pattern).to_string_lossy()).ok())
+        .flat_map(|paths| paths.filter_map(|path| path.ok()))
+        .min()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+}
+
+/// String literal of Starlark
+fn string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Content of `cc_toolchain_config.bzl`
+pub fn config_file(build: &Build) -> String {
+    let tool_paths: String = TOOLS
+        .iter()
+        .map(|(name, llvm, gnu)| {
+            format!(
+                "        tool_path(name = \"{}\", path = {}),\n",
+                name,
+                string(&tool_path(build, llvm, gnu).display().to_string())
+            )
+        })
+        .collect();
+    let mut includes = clang_include_dirs(build);
+    includes.push("/usr/include".into());
+    includes.push("/usr/local/include".into());
+    let mut link_flags = Vec::new();
+    if build.prefix().join("bin/ld.lld").is_file() {
+        link_flags.push("-fuse-ld=lld".to_string());
+    }
+    let mut compile_flags = Vec::new();
+    match libcxx_dir(build) {
+        Some(dir) => {
+            let headers = build.prefix().join("include/c++/v1");
+            includes.insert(0, headers);
+            compile_flags.push("-stdlib=libc++".to_string());
+            link_flags.push("-stdlib=libc++".to_string());
+            link_flags.push(format!("-L{}", dir.display()));
+            link_flags.push(format!("-Wl,-rpath,{}", dir.display()));
+        }
+        None => link_flags.push("-lstdc++".to_string()),
+    }
+    link_flags.push("-lm".to_string());
+    let flags = |flags: &[String]| {
+        flags
+            .iter()
+            .map(|f| string(f))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let includes: String = includes
+        .iter()
+        .map(|dir| format!("            {},\n", string(&dir.display().to_string())))
+        .collect();
+    format!(
+        r#"# Generated by llvmenv for '{name}'
+load("@bazel_tools//tools/build_defs/cc:action_names.bzl", "ACTION_NAMES")
+load(
+    "@bazel_tools//tools/cpp:cc_toolchain_config_lib.bzl",
+    "feature",
+    "flag_group",
+    "flag_set",
+    "tool_path",
+)
+
+def _impl(ctx):
+    tool_paths = [
+{tool_paths}    ]
+    default_linker_flags = feature(
+        name = "default_linker_flags",
+        enabled = True,
+        flag_sets = [
+            flag_set(
+                actions = [
+                    ACTION_NAMES.cpp_link_executable,
+                    ACTION_NAMES.cpp_link_dynamic_library,
+                    ACTION_NAMES.cpp_link_nodeps_dynamic_library,
+                ],
+                flag_groups = [flag_group(flags = [{link_flags}])],
+            ),
+        ],
+    )
+    default_compile_flags = feature(
+        name = "default_compile_flags",
+        enabled = True,
+        flag_sets = [
+            flag_set(
+                actions = [
+                    ACTION_NAMES.cpp_compile,
+                    ACTION_NAMES.cpp_header_parsing,
+                    ACTION_NAMES.cpp_module_compile,
+                ],
+                flag_groups = [flag_group(flags = [{compile_flags}])],
+            ),
+        ],
+    )
+    return cc_common.create_cc_toolchain_config_info(
+        ctx = ctx,
+        features = [default_compile_flags, default_linker_flags],
+        cxx_builtin_include_directories = [
+{includes}        ],
+        toolchain_identifier = "{id}",
+        host_system_name = "local",
+        target_system_name = "local",
+        target_cpu = "{cpu}",
+        target_libc = "unknown",
+        compiler = "clang",
+        abi_version = "unknown",
+        abi_libc_version = "unknown",
+        tool_paths = tool_paths,
+    )
+
+cc_toolchain_config = rule(
+    implementation = _impl,
+    attrs = {{}},
+    provides = [CcToolchainConfigInfo],
+)
+"#,
+        name = build.name(),
+        id = identifier(build),
+        tool_paths = tool_paths,
+        includes = includes,
+        link_flags = flags(&link_flags),
+        compile_flags = flags(&compile_flags),
+        cpu = target_cpu(&host_triple(build)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("my-llvm");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::create_dir_all(prefix.join("lib/clang/7.0.0/include"))?;
+        fs::write(prefix.join("bin/clang"), "")?;
+        let out = tmp_dir.path().join("toolchain");
+        generate(&Build::from_path(&prefix), &out)?;
+
+        let config = fs::read_to_string(out.join(CONFIG_FN))?;
+        let clang = format!("path = \"{}\"", prefix.join("bin/clang").display());
+        assert!(config.contains(&clang));
+        let include = prefix.join("lib/clang/7.0.0/include");
+        assert!(config.contains(&include.display().to_string()));
+        assert!(config.contains("\"-lstdc++\", \"-lm\""));
+        assert!(config.contains(&format!(
+            "target_cpu = \"{}\"",
+            target_cpu(&format!(
+                "{}-unknown-{}",
+                env::consts::ARCH,
+                env::consts::OS
+            ))
+        )));
+        let build = fs::read_to_string(out.join(BUILD_FN))?;
+        assert!(build.contains("toolchain_identifier = \"llvmenv-my-llvm\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_libcxx() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("my-llvm");
+        let lib = prefix.join("lib/x86_64-unknown-linux-gnu");
+        fs::create_dir_all(&lib)?;
+        fs::write(lib.join("libc++.so"), "")?;
+        fs::create_dir_all(prefix.join("include/c++/v1"))?;
+        let config = config_file(&Build::from_path(&prefix));
+        assert!(!config.contains("-lstdc++"));
+        assert!(config.contains(&format!("\"-L{}\"", lib.display())));
+        assert!(config.contains(&format!("flags = [{}]", string("-stdlib=libc++"))));
+        assert!(config.contains(&prefix.join("include/c++/v1").display().to_string()));
+        Ok(())
+    }
+
+    proptest::proptest! {
+        /// Starlark shares the escapes of `\\` and `"` with JSON
+        #[test]
+        fn test_string(s in "[^\\x00-\\x1f]*") {
+            proptest::prop_assert_eq!(serde_json::from_str::<String>(&string(&s)).unwrap(), s);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Conan package of a build
+//!
+//! `llvmenv conan [name] -o llvm-conan/` writes `conanfile.py` packaging headers, libraries,
+//! and CMake config files of the build. Register it into the local cache of Conan by
+//!
+//! ```shell
+//! $ conan export-pkg llvm-conan/
+//! ```
+//!
+//! and the projects can depend on `llvm/${version}@llvmenv`.
+
+use log::info;
+use std::fs;
+use std::path::Path;
+
+use super::package_version;
+use crate::build::Build;
+use crate::error::Result;
+
+pub const CONANFILE_FN: &str = "conanfile.py";
+
+/// Write `conanfile.py` of `build` into `out` directory
+pub fn generate(build: &Build, out: &Path) -> Result<()> {
+    fs::create_dir_all(out)?;
+    info!("Write Conan recipe into {}", out.display());
+    fs::write(out.join(CONANFILE_FN), conanfile(build))?;
+    Ok(())
+}
+
+/// String literal of Python
+fn python_str(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Content of `conanfile.py`
+pub fn conanfile(build: &Build) -> String {
+    format!(
+        r#"# Generated by llvmenv for '{name}'
+import os
+
+from conan import ConanFile
+from conan.tools.files import copy
+
+PREFIX = {prefix}
+
+
+class LlvmenvConan(ConanFile):
+    name = "llvm"
+    version = "{version}"
+    user = "llvmenv"
+    settings = "os", "arch", "compiler", "build_type"
+    package_type = "library"
+
+    def package(self):
+        for sub in ["include", "lib"]:
+            copy(self, "*", os.path.join(PREFIX, sub), os.path.join(self.package_folder, sub))
+
+    def package_info(self):
+        # Use LLVMConfig.cmake and ClangConfig.cmake installed by LLVM
+        self.cpp_info.set_property("cmake_find_mode", "none")
+        self.cpp_info.builddirs = ["lib/cmake/llvm", "lib/cmake/clang"]
+        self.cpp_info.includedirs = ["include"]
+        self.cpp_info.libdirs = ["lib"]
+"#,
+        name = build.name(),
+        prefix = python_str(&build.prefix().display().to_string()),
+        version = package_version(build),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("my-llvm");
+        fs::create_dir_all(prefix.join("bin"))?;
+        let out = tmp_dir.path().join("conan");
+        generate(&Build::from_path(&prefix), &out)?;
+        let conanfile = fs::read_to_string(out.join(CONANFILE_FN))?;
+        assert!(conanfile.contains(&format!("PREFIX = \"{}\"", prefix.display())));
+        assert!(conanfile.contains("version = \"my-llvm\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_python_str() {
+        assert_eq!(python_str(r#"C:\a "b""#), r#""C:\\a \"b\"""#);
+        // Not `\u{e9}` of Rust
+        assert_eq!(python_str("/home/jos\u{e9}"), "\"/home/jos\u{e9}\"");
+        assert_eq!(python_str("a\u{1b}"), r#""a\u001b""#);
+    }
+}
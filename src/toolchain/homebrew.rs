@@ -0,0 +1,163 @@
+//! Homebrew formula of an archived build
+//!
+//! `llvmenv archive [name]` creates `${data_dir}/${name}.tar.xz`. After uploading it,
+//! `llvmenv homebrew [name] --url <URL>` writes `Formula/llvmenv-${name}.rb` with the checksum
+//! of the archive, the platform where it was built, and the tags of the build.
+//! Commit it into your tap, and install by `brew install <tap>/llvmenv-${name}`.
+//! The formula is keg-only to avoid conflicts with the LLVM of Homebrew.
+
+use log::info;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::build::Build;
+use crate::config::data_dir;
+use crate::error::{bail, Result};
+
+/// Archive created by `llvmenv archive`
+pub fn default_archive(build: &Build) -> Result<PathBuf> {
+    Ok(data_dir()?.join(format!("{}.tar.xz", build.name())))
+}
+
+/// Name of formula, e.g. `llvmenv-7-0-0` for the build `7.0.0`
+pub fn formula_name(build: &Build) -> String {
+    let name: String = build
+        .name()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("llvmenv-{}", name.trim_matches('-'))
+}
+
+/// Ruby class name of formula, in the same manner as Homebrew
+fn class_name(formula: &str) -> String {
+    formula
+        .split('-')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Content of formula for `build` whose archive is served at `url` with `sha256`
+pub fn formula(build: &Build, url: &str, sha256: &str) -> Result<String> {
+    let formula = formula_name(build);
+    let version = match build.version() {
+        Ok((major, minor, patch)) => format!("{}.{}.{}", major, minor, patch),
+        Err(_) => build.name().into(),
+    };
+    let arch = match env::consts::ARCH {
+        "aarch64" => ":arm64",
+        _ => ":x86_64",
+    };
+    let mut tags = String::new();
+    for (k, v) in build.metadata()?.tags {
+        tags += &format!("# tag: {}={}\n", k, v);
+    }
+    Ok(format!(
+        r##"# Generated by llvmenv for '{name}'
+# built on: {arch_raw}-{os}
+{tags}class {class} < Formula
+  desc "LLVM/Clang built by llvmenv ({name})"
+  homepage "https://llvm.org/"
+  url "{url}"
+  sha256 "{sha256}"
+  version "{version}"
+
+  keg_only "it conflicts with LLVM of Homebrew"
+
+  depends_on arch: {arch}
+  depends_on :{os}
+
+  def install
+    prefix.install Dir["*"]
+  end
+
+  test do
+    system "#{{bin}}/clang", "--version"
+  end
+end
+"##,
+        name = build.name(),
+        arch_raw = env::consts::ARCH,
+        os = if env::consts::OS == "macos" {
+            "macos"
+        } else {
+            "linux"
+        },
+        tags = tags,
+        class = class_name(&formula),
+        url = url,
+        sha256 = sha256,
+        version = version,
+        arch = arch,
+    ))
+}
+
+/// Write formula into `out` directory, and returns its path
+pub fn generate(build: &Build, url: &str, archive: &Path, out: &Path) -> Result<PathBuf> {
+    if !archive.exists() {
+        bail!(
+            "Archive does not found: {} (create it by `llvmenv archive`)",
+            archive.display()
+        );
+    }
+    let content = formula(build, url, &sha256(archive)?)?;
+    fs::create_dir_all(out)?;
+    let path = out.join(format!("{}.rb", formula_name(build)));
+    info!("Write formula: {}", path.display());
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formula_name() {
+        let build = Build::from_path(Path::new("/opt/llvmenv/7.0.0"));
+        assert_eq!(formula_name(&build), "llvmenv-7-0-0");
+        assert_eq!(class_name("llvmenv-7-0-0"), "Llvmenv700");
+        assert_eq!(class_name("llvmenv-my-llvm"), "LlvmenvMyLlvm");
+    }
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let build = Build::from_path(&tmp_dir.path().join("my-llvm"));
+        let archive = tmp_dir.path().join("my-llvm.tar.xz");
+        let out = tmp_dir.path().join("Formula");
+        assert!(generate(&build, "https://example.com/my-llvm.tar.xz", &archive, &out).is_err());
+
+        fs::write(&archive, "")?;
+        let path = generate(&build, "https://example.com/my-llvm.tar.xz", &archive, &out)?;
+        assert_eq!(path, out.join("llvmenv-my-llvm.rb"));
+        let formula = fs::read_to_string(&path)?;
+        assert!(formula.contains("class LlvmenvMyLlvm < Formula\n"));
+        // sha256 of the empty file
+        assert!(formula.contains(
+            "sha256 \"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\""
+        ));
+        Ok(())
+    }
+}
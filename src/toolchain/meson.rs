@@ -0,0 +1,170 @@
+//! Meson native and cross files
+//!
+//! `llvmenv meson [name]` prints a [native file](https://mesonbuild.com/Native-environments.html)
+//! using clang, lld, and `llvm-config` of the build,
+//!
+//! ```shell
+//! $ llvmenv meson 7.0.0 > llvm.ini
+//! $ meson setup --native-file llvm.ini builddir
+//! ```
+//!
+//! and `--cross <triple>` prints a [cross file](https://mesonbuild.com/Cross-compilation.html)
+//! with `--target=<triple>` and `[host_machine]` section.
+
+use std::fmt::Write;
+
+use super::tool_path;
+use crate::build::Build;
+use crate::error::{format_err, Result};
+
+/// OS names in target triple, `android` before `linux` for `*-linux-android`
+const SYSTEMS: [&str; 9] = [
+    "android", "linux", "windows", "darwin", "macos", "ios", "freebsd", "netbsd", "openbsd",
+];
+
+/// `[host_machine]` of Meson derived from target triple
+#[derive(Debug, PartialEq)]
+pub struct HostMachine {
+    pub system: String,
+    pub cpu_family: String,
+    pub cpu: String,
+    pub endian: &'static str,
+}
+
+impl HostMachine {
+    /// Parse target triple, e.g. `aarch64-linux-gnu` or `x86_64-pc-windows-msvc`
+    pub fn from_triple(triple: &str) -> Result<Self> {
+        let mut parts = triple.split('-');
+        let cpu = parts
+            .next()
+            .filter(|cpu| !cpu.is_empty())
+            .ok_or_else(|| format_err!("Invalid target triple: {}", triple))?;
+        let (cpu_family, endian) = match cpu {
+            "x86_64" | "amd64" => ("x86_64", "little"),
+            "i386" | "i486" | "i586" | "i686" => ("x86", "little"),
+            "aarch64" | "arm64" => ("aarch64", "little"),
+            "aarch64_be" => ("aarch64", "big"),
+            "riscv32" => ("riscv32", "little"),
+            "riscv64" => ("riscv64", "little"),
+            "powerpc64le" | "ppc64le" => ("ppc64", "little"),
+            "powerpc64" | "ppc64" => ("ppc64", "big"),
+            "powerpc" | "ppc" => ("ppc", "big"),
+            "mips" | "mips64" => (cpu, "big"),
+            "mipsel" => ("mips", "little"),
+            "mips64el" => ("mips64", "little"),
+            "s390x" => ("s390x", "big"),
+            "wasm32" | "wasm64" => (cpu, "little"),
+            _ if cpu.starts_with("armeb") || cpu.starts_with("thumbeb") => ("arm", "big"),
+            _ if cpu.starts_with("arm") || cpu.starts_with("thumb") => ("arm", "little"),
+            _ => return Err(format_err!("Unsupported CPU of target triple: {}", triple)),
+        };
+        let rest: Vec<&str> = parts.collect();
+        let system = SYSTEMS
+            .iter()
+            .find(|os| rest.iter().any(|part| part.starts_with(*os)))
+            .map(|os| match *os {
+                "macos" | "ios" => "darwin",
+                os => os,
+            })
+            .unwrap_or("none");
+        Ok(HostMachine {
+            system: system.into(),
+            cpu_family: cpu_family.into(),
+            cpu: cpu.into(),
+            endian,
+        })
+    }
+}
+
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Native file of `build`, or cross file to `triple` if given
+pub fn generate(build: &Build, cross: Option<&str>) -> Result<String> {
+    let host = match cross {
+        Some(triple) => Some((triple, HostMachine::from_triple(triple)?)),
+        None => None,
+    };
+    let compiler = |tool: &str, fallback: &str| {
+        let path = quote(&tool_path(build, tool, fallback).display().to_string());
+        match &host {
+            Some((triple, _)) => format!("[{}, {}]", path, quote(&format!("--target={}", triple))),
+            None => path,
+        }
+    };
+    let tool =
+        |tool: &str, fallback: &str| quote(&tool_path(build, tool, fallback).display().to_string());
+
+    let mut s = String::new();
+    writeln!(s, "# Generated by llvmenv for '{}'", build.name())?;
+    writeln!(s, "[binaries]")?;
+    writeln!(s, "c = {}", compiler("clang", "cc"))?;
+    writeln!(s, "cpp = {}", compiler("clang++", "c++"))?;
+    if build.prefix().join("bin/ld.lld").is_file() {
+        writeln!(s, "c_ld = 'lld'")?;
+        writeln!(s, "cpp_ld = 'lld'")?;
+    }
+    writeln!(s, "ar = {}", tool("llvm-ar", "ar"))?;
+    writeln!(s, "nm = {}", tool("llvm-nm", "nm"))?;
+    writeln!(s, "strip = {}", tool("llvm-strip", "strip"))?;
+    writeln!(s, "objcopy = {}", tool("llvm-objcopy", "objcopy"))?;
+    writeln!(s, "llvm-config = {}", tool("llvm-config", "llvm-config"))?;
+    if let Some((_, host)) = &host {
+        writeln!(s)?;
+        writeln!(s, "[host_machine]")?;
+        writeln!(s, "system = '{}'", host.system)?;
+        writeln!(s, "cpu_family = '{}'", host.cpu_family)?;
+        writeln!(s, "cpu = '{}'", host.cpu)?;
+        writeln!(s, "endian = '{}'", host.endian)?;
+    }
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_host_machine() -> Result<()> {
+        let host = HostMachine::from_triple("aarch64-linux-gnu")?;
+        assert_eq!(host.system, "linux");
+        assert_eq!(host.cpu_family, "aarch64");
+        assert_eq!(host.endian, "little");
+        let host = HostMachine::from_triple("x86_64-pc-windows-msvc")?;
+        assert_eq!(host.system, "windows");
+        assert_eq!(host.cpu_family, "x86_64");
+        let host = HostMachine::from_triple("armv7-unknown-linux-gnueabihf")?;
+        assert_eq!(host.cpu_family, "arm");
+        assert_eq!(
+            HostMachine::from_triple("thumbv7em-none-eabi")?.system,
+            "none"
+        );
+        assert_eq!(
+            HostMachine::from_triple("aarch64-linux-android")?.system,
+            "android"
+        );
+        assert!(HostMachine::from_triple("unknown-linux-gnu").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("my-llvm");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::write(prefix.join("bin/clang"), "")?;
+        let build = Build::from_path(&prefix);
+
+        let native = generate(&build, None)?;
+        let clang = format!("c = '{}'", prefix.join("bin/clang").display());
+        assert!(native.contains(&clang));
+        assert!(!native.contains("[host_machine]"));
+
+        let cross = generate(&build, Some("aarch64-linux-gnu"))?;
+        assert!(cross.contains("'--target=aarch64-linux-gnu']"));
+        assert!(cross.contains("cpu_family = 'aarch64'"));
+        Ok(())
+    }
+}
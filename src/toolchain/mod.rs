@@ -0,0 +1,39 @@
+//! Generate toolchain definitions of other build systems and package managers
+//! pointing at a build of llvmenv
+
+use std::path::{Path, PathBuf};
+
+use crate::build::Build;
+
+pub mod bazel;
+pub mod conan;
+pub mod homebrew;
+pub mod meson;
+pub mod modulefile;
+pub mod spack;
+pub mod vcpkg;
+
+/// Path of `tool` in the prefix of `build`, or `fallback` found in $PATH if it does not exist
+fn tool_path(build: &Build, tool: &str, fallback: &str) -> PathBuf {
+    let path = build.prefix().join("bin").join(tool);
+    if path.is_file() {
+        return path;
+    }
+    crate::error::find_tool(fallback).unwrap_or_else(|| Path::new("/usr/bin").join(fallback))
+}
+
+/// Resource directories of clang, e.g. `${prefix}/lib/clang/7.0.0/include`
+fn clang_include_dirs(build: &Build) -> Vec<PathBuf> {
+    let pattern = build.prefix().join("lib/clang/*/include");
+    glob::glob(&pattern.to_string_lossy())
+        .map(|paths| paths.filter_map(|path| path.ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Version of package, `${major}.${minor}.${patch}` of clang or the name of build if unknown
+fn package_version(build: &Build) -> String {
+    match build.version() {
+        Ok((major, minor, patch)) => format!("{}.{}.{}", major, minor, patch),
+        Err(_) => build.name().into(),
+    }
+}
@@ -0,0 +1,164 @@
+//! Environment Modules / Lmod modulefile
+//!
+//! `llvmenv modulefile [name]` prints a Tcl modulefile (or Lua one for Lmod by `--lua`)
+//! setting `PATH`, `LD_LIBRARY_PATH`, `MANPATH`, and `CPATH` for the build.
+//! `llvmenv modulefile --all -o modulefiles/` regenerates `modulefiles/llvmenv/${name}`
+//! for all builds, which can be loaded by
+//!
+//! ```shell
+//! $ module use modulefiles/
+//! $ module load llvmenv/7.0.0
+//! ```
+
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::build::{self, Build};
+use crate::config::APP_NAME;
+use crate::error::Result;
+
+/// Environment variables and sub-directories of prefix prepended to them
+const PATHS: [(&str, &str); 4] = [
+    ("PATH", "bin"),
+    ("LD_LIBRARY_PATH", "lib"),
+    ("MANPATH", "share/man"),
+    ("CPATH", "include"),
+];
+
+/// Language of modulefile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Tcl, for both Environment Modules and Lmod
+    Tcl,
+    /// Lua, only for Lmod
+    Lua,
+}
+
+impl Format {
+    fn file_name(self, name: &str) -> String {
+        match self {
+            Format::Tcl => name.into(),
+            Format::Lua => format!("{}.lua", name),
+        }
+    }
+}
+
+/// Double-quoted word of Tcl, without the command and variable substitutions
+fn tcl_str(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' | '"' | '$' | '[' | ']' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// String literal of Lua
+fn lua_str(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\{:03}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Content of modulefile for `build`
+pub fn generate(build: &Build, format: Format) -> String {
+    let prefix = build.prefix().display().to_string();
+    let mut lines = Vec::new();
+    match format {
+        Format::Tcl => {
+            lines.push("#%Module1.0".to_string());
+            lines.push(format!("## Generated by llvmenv for '{}'", build.name()));
+            lines.push(format!(
+                "module-whatis \"LLVM/Clang built by llvmenv ({})\"",
+                build.name()
+            ));
+            lines.push(format!("conflict {}", APP_NAME));
+            lines.push(format!("set prefix {}", tcl_str(&prefix)));
+            for (var, sub) in &PATHS {
+                lines.push(format!("prepend-path {} $prefix/{}", var, sub));
+            }
+        }
+        Format::Lua => {
+            lines.push(format!("-- Generated by llvmenv for '{}'", build.name()));
+            lines.push(format!(
+                "whatis(\"LLVM/Clang built by llvmenv ({})\")",
+                build.name()
+            ));
+            lines.push(format!("family(\"{}\")", APP_NAME));
+            lines.push(format!("local prefix = {}", lua_str(&prefix)));
+            for (var, sub) in &PATHS {
+                lines.push(format!(
+                    "prepend_path(\"{}\", pathJoin(prefix, \"{}\"))",
+                    var, sub
+                ));
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Write modulefiles of all builds except system into `out/llvmenv/`, and returns their paths
+pub fn generate_all(out: &Path, format: Format) -> Result<Vec<PathBuf>> {
+    let dir = out.join(APP_NAME);
+    fs::create_dir_all(&dir)?;
+    let mut paths = Vec::new();
+    for b in build::builds()? {
+        if b.name() == "system" {
+            continue;
+        }
+        let path = dir.join(format.file_name(b.name()));
+        info!("Write modulefile: {}", path.display());
+        fs::write(&path, generate(&b, format))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let build = Build::from_path(Path::new("/opt/llvmenv/7.0.0"));
+        let tcl = generate(&build, Format::Tcl);
+        assert!(tcl.starts_with("#%Module1.0\n"));
+        assert!(tcl.contains("set prefix \"/opt/llvmenv/7.0.0\"\n"));
+        assert!(tcl.contains("prepend-path LD_LIBRARY_PATH $prefix/lib\n"));
+        let lua = generate(&build, Format::Lua);
+        assert!(lua.contains("local prefix = \"/opt/llvmenv/7.0.0\"\n"));
+        assert!(lua.contains("prepend_path(\"CPATH\", pathJoin(prefix, \"include\"))\n"));
+    }
+
+    #[test]
+    fn test_quote() {
+        let path = "/home/jos\u{e9}/{a}/$b[c]\\\"d\"";
+        assert_eq!(
+            tcl_str(path),
+            "\"/home/jos\u{e9}/{a}/\\$b\\[c\\]\\\\\\\"d\\\"\""
+        );
+        assert_eq!(lua_str(path), "\"/home/jos\u{e9}/{a}/$b[c]\\\\\\\"d\\\"\"");
+        assert_eq!(lua_str("a\u{1}"), r#""a\001""#);
+    }
+}
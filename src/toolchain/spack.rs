@@ -0,0 +1,217 @@
+//! Register builds as external packages of Spack
+//!
+//! `llvmenv spack-register [name..]` appends builds (all builds if no names are given)
+//! into `packages.yaml` of Spack (`~/.spack/packages.yaml` by default):
+//!
+//! ```yaml
+//! packages:
+//!   llvm:
+//!     externals:
+//!     - spec: llvm@7.0.0+clang+lld
+//!       prefix: /home/user/.local/share/llvmenv/7.0.0
+//! ```
+//!
+//! The version is taken from `clang --version`, and variants from installed executables.
+//! Existing `packages.yaml` is assumed to be indented by two spaces as Spack writes it,
+//! and builds whose prefix is already registered are skipped.
+
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::build::Build;
+use crate::error::{err_msg, Result};
+
+/// Variants of llvm package in Spack and executables indicating them
+const VARIANTS: [(&str, &str); 3] = [("clang", "clang"), ("lld", "ld.lld"), ("lldb", "lldb")];
+
+/// `packages.yaml` in the user scope of Spack
+pub fn default_packages_yaml() -> Result<PathBuf> {
+    Ok(dirs::home_dir()
+        .ok_or_else(|| err_msg("Home directory is not found"))?
+        .join(".spack/packages.yaml"))
+}
+
+/// Spec string of `build`, e.g. `llvm@7.0.0+clang+lld`
+pub fn spec(build: &Build) -> String {
+    let mut spec = "llvm".to_string();
+    if let Ok((major, minor, patch)) = build.version() {
+        spec += &format!("@{}.{}.{}", major, minor, patch);
+    }
+    let bin = build.prefix().join("bin");
+    for (variant, exe) in &VARIANTS {
+        spec += if bin.join(exe).is_file() { "+" } else { "~" };
+        spec += variant;
+    }
+    spec
+}
+
+/// Scalar of YAML, double-quoted unless it consists of safe characters, e.g. a prefix containing spaces
+fn yaml_scalar(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/._-+@~".contains(c));
+    if plain {
+        s.into()
+    } else {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Append `externals` (pairs of spec and prefix) into the content of packages.yaml
+pub fn add_externals(yaml: &str, externals: &[(String, PathBuf)]) -> String {
+    let externals: Vec<&(String, PathBuf)> = externals
+        .iter()
+        .filter(|(_, prefix)| {
+            let prefix = prefix.display().to_string();
+            let registered = [
+                format!("prefix: {}", prefix),
+                format!("prefix: {}", yaml_scalar(&prefix)),
+            ];
+            !yaml
+                .lines()
+                .any(|line| registered.iter().any(|r| line.trim() == r))
+        })
+        .collect();
+    if externals.is_empty() {
+        return yaml.into();
+    }
+    let items: Vec<String> = externals
+        .iter()
+        .map(|(spec, prefix)| {
+            format!(
+                "    - spec: {}\n      prefix: {}",
+                spec,
+                yaml_scalar(&prefix.display().to_string())
+            )
+        })
+        .collect();
+    let items = items.join("\n");
+
+    let mut lines: Vec<String> = yaml.lines().map(|l| l.to_string()).collect();
+    let packages = lines.iter().position(|l| l.trim_end() == "packages:");
+    let packages = match packages {
+        Some(i) => i,
+        None => {
+            lines.push("packages:".into());
+            lines.len() - 1
+        }
+    };
+    // the block of packages ends at the next line without indent
+    let end = lines[packages + 1..]
+        .iter()
+        .position(|l| !l.is_empty() && !l.starts_with(' '))
+        .map(|i| packages + 1 + i)
+        .unwrap_or_else(|| lines.len());
+    let llvm = lines[packages + 1..end]
+        .iter()
+        .position(|l| l.trim_end() == "  llvm:")
+        .map(|i| packages + 1 + i);
+    match llvm {
+        None => lines.insert(packages + 1, format!("  llvm:\n    externals:\n{}", items)),
+        Some(llvm) => {
+            let block_end = lines[llvm + 1..end]
+                .iter()
+                .position(|l| !l.is_empty() && !l.starts_with("   "))
+                .map(|i| llvm + 1 + i)
+                .unwrap_or(end);
+            match lines[llvm + 1..block_end]
+                .iter()
+                .position(|l| l.trim_end() == "    externals:")
+            {
+                Some(i) => lines.insert(llvm + 2 + i, items),
+                None => lines.insert(llvm + 1, format!("    externals:\n{}", items)),
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Register `builds` into `packages_yaml`
+pub fn register(builds: &[Build], packages_yaml: &Path) -> Result<()> {
+    let current = if packages_yaml.exists() {
+        fs::read_to_string(packages_yaml)?
+    } else {
+        String::new()
+    };
+    let externals: Vec<(String, PathBuf)> = builds
+        .iter()
+        .map(|b| (spec(b), b.prefix().to_owned()))
+        .collect();
+    if let Some(dir) = packages_yaml.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(packages_yaml, add_externals(&current, &externals))?;
+    info!("Register builds into {}", packages_yaml.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_externals() {
+        let ext = vec![(
+            "llvm@7.0.0+clang".to_string(),
+            PathBuf::from("/opt/llvmenv/7.0.0"),
+        )];
+        let item = "    - spec: llvm@7.0.0+clang\n      prefix: /opt/llvmenv/7.0.0\n";
+
+        let yaml = add_externals("", &ext);
+        assert_eq!(
+            yaml,
+            format!("packages:\n  llvm:\n    externals:\n{}", item)
+        );
+        // already registered
+        assert_eq!(add_externals(&yaml, &ext), yaml);
+
+        let yaml = "packages:\n  cmake:\n    buildable: false\n";
+        assert_eq!(
+            add_externals(yaml, &ext),
+            format!(
+                "packages:\n  llvm:\n    externals:\n{}  cmake:\n    buildable: false\n",
+                item
+            )
+        );
+
+        let yaml = "packages:\n  llvm:\n    buildable: false\n    externals:\n    - spec: llvm@6.0.0\n      prefix: /usr\n";
+        assert_eq!(
+            add_externals(yaml, &ext),
+            format!(
+                "packages:\n  llvm:\n    buildable: false\n    externals:\n{}    - spec: llvm@6.0.0\n      prefix: /usr\n",
+                item
+            )
+        );
+
+        let yaml = "packages:\n  llvm:\n    buildable: false\n";
+        assert_eq!(
+            add_externals(yaml, &ext),
+            format!(
+                "packages:\n  llvm:\n    externals:\n{}    buildable: false\n",
+                item
+            )
+        );
+
+        let ext = vec![(
+            "llvm@7.0.0".to_string(),
+            PathBuf::from("/Users/Jane Doe/llvm \"7\""),
+        )];
+        let yaml = add_externals("", &ext);
+        assert!(yaml.ends_with("      prefix: \"/Users/Jane Doe/llvm \\\"7\\\"\"\n"));
+        assert_eq!(add_externals(&yaml, &ext), yaml);
+    }
+
+    proptest::proptest! {
+        /// Double-quoted scalars without control characters are also JSON strings
+        #[test]
+        fn test_yaml_scalar(s in "[^\\x00-\\x1f]*") {
+            let quoted = yaml_scalar(&s);
+            if quoted.starts_with('"') {
+                proptest::prop_assert_eq!(serde_json::from_str::<String>(&quoted).unwrap(), s);
+            } else {
+                proptest::prop_assert_eq!(quoted, s);
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! vcpkg overlay port of a build
+//!
+//! `llvmenv vcpkg [name] -o ports/` writes `ports/llvm/{vcpkg.json,portfile.cmake}`
+//! installing headers, libraries, and CMake config files of the build without compiling.
+//! Use it as an overlay port:
+//!
+//! ```shell
+//! $ vcpkg install llvm --overlay-ports=ports/
+//! ```
+
+use log::info;
+use std::fs;
+use std::path::Path;
+
+use super::package_version;
+use crate::build::Build;
+use crate::error::Result;
+
+pub const PORT_NAME: &str = "llvm";
+
+/// Write overlay port of `build` into `out/llvm`
+pub fn generate(build: &Build, out: &Path) -> Result<()> {
+    let port = out.join(PORT_NAME);
+    fs::create_dir_all(&port)?;
+    info!("Write vcpkg overlay port into {}", port.display());
+    fs::write(port.join("vcpkg.json"), manifest(build))?;
+    fs::write(port.join("portfile.cmake"), portfile(build))?;
+    Ok(())
+}
+
+/// Content of `vcpkg.json`
+pub fn manifest(build: &Build) -> String {
+    format!(
+        r#"{{
+  "name": "{port}",
+  "version-string": "{version}",
+  "description": "LLVM/Clang built by llvmenv ('{name}')"
+}}
+"#,
+        port = PORT_NAME,
+        version = package_version(build),
+        name = build.name(),
+    )
+}
+
+/// Escape for quoted arguments of CMake, which expand variable references and escape sequences.
+/// `;` is escaped not to split the path as a list.
+fn cmake_escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' | '"' | '$' | ';' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Content of `portfile.cmake`
+pub fn portfile(build: &Build) -> String {
+    // CMake accepts `/` as separator also on Windows
+    let prefix = cmake_escape(&build.prefix().display().to_string().replace('\\', "/"));
+    format!(
+        r#"# Generated by llvmenv for '{name}'
+set(VCPKG_BUILD_TYPE release)
+set(VCPKG_POLICY_SKIP_ARCHITECTURE_CHECK enabled)
+set(VCPKG_POLICY_SKIP_COPYRIGHT_CHECK enabled)
+set(VCPKG_POLICY_MISMATCHED_NUMBER_OF_BINARIES enabled)
+
+set(LLVMENV_PREFIX "{prefix}")
+file(INSTALL "${{LLVMENV_PREFIX}}/include/" DESTINATION "${{CURRENT_PACKAGES_DIR}}/include")
+file(INSTALL "${{LLVMENV_PREFIX}}/lib/" DESTINATION "${{CURRENT_PACKAGES_DIR}}/lib" PATTERN "cmake" EXCLUDE)
+foreach(pkg llvm clang lld)
+  if(EXISTS "${{LLVMENV_PREFIX}}/lib/cmake/${{pkg}}")
+    file(INSTALL "${{LLVMENV_PREFIX}}/lib/cmake/${{pkg}}/" DESTINATION "${{CURRENT_PACKAGES_DIR}}/share/${{pkg}}")
+  endif()
+endforeach()
+"#,
+        name = build.name(),
+        prefix = prefix,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let tmp_dir = tempfile::TempDir::new()?;
+        let prefix = tmp_dir.path().join("my-llvm");
+        fs::create_dir_all(prefix.join("bin"))?;
+        let out = tmp_dir.path().join("ports");
+        generate(&Build::from_path(&prefix), &out)?;
+        let portfile = fs::read_to_string(out.join("llvm/portfile.cmake"))?;
+        assert!(portfile.contains(&format!("set(LLVMENV_PREFIX \"{}\")", prefix.display())));
+        let manifest = fs::read_to_string(out.join("llvm/vcpkg.json"))?;
+        assert!(manifest.contains("\"version-string\": \"my-llvm\""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmake_escape() {
+        assert_eq!(
+            cmake_escape("/Users/Jane Doe/${x}"),
+            r"/Users/Jane Doe/\${x}"
+        );
+        assert_eq!(cmake_escape(r#"a"b"#), r#"a\"b"#);
+        assert_eq!(cmake_escape(r"a\b;c"), r"a\\b\;c");
+    }
+}
@@ -0,0 +1,319 @@
+//! Parallel operations on large directory trees
+//!
+//! A build of LLVM has tens of thousands of files in several GB. [remove](fn.remove.html) and [copy](fn.copy.html)
+//! traverse the tree and process the files by `TREE_JOBS` threads, with reporting the number of files done
+//! to the [observer](../progress/index.html). They are used by `llvmenv uninstall`, `gc`, `install`, `import`,
+//! and `archive`, and by `build-entry --clean` and `--discard`.
+//!
+//! Ctrl-C stops them safely, instead of leaving a half-processed tree in place:
+//!
+//! - remove first renames the tree into a hidden sibling `.${name}.removing-${pid}`,
+//!   so that a partially removed build is never used. `llvmenv gc` removes the rest.
+//! - copy writes into a hidden sibling `.${name}.copying-${pid}`, which is renamed into the destination
+//!   only after all files are copied, and removed if stopped. `llvmenv archive` writes its archive in the same way.
+
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::*;
+use crate::interrupt::{self, interrupted};
+use crate::lockdown;
+use crate::progress::{observer, run_phase, Phase};
+
+/// Number of threads processing a tree
+const TREE_JOBS: usize = 8;
+
+/// Run `f` with Ctrl-C stopping it at [check_interrupted](fn.check_interrupted.html), instead of killing the process,
+/// see [interrupt](../interrupt/index.html)
+pub(crate) fn interruptible<T, F: FnOnce() -> Result<T>>(f: F) -> Result<T> {
+    interrupt::interruptible(f)
+}
+
+/// Error if Ctrl-C is pressed while [interruptible](fn.interruptible.html)
+pub(crate) fn check_interrupted() -> Result<()> {
+    if interrupted() {
+        return Err(LlvmEnvError::Interrupted);
+    }
+    Ok(())
+}
+
+/// Entries of a tree listed by [walk](fn.walk.html), sorted by path
+#[derive(Debug, Default)]
+pub struct Tree {
+    /// Directories including the root, parents before their children
+    pub dirs: Vec<PathBuf>,
+    /// Files and symbolic links
+    pub files: Vec<PathBuf>,
+    /// Total size of files in bytes
+    pub bytes: u64,
+}
+
+/// Subdirectories, files, and the size of the files in `dir`
+fn read_dir(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>, u64)> {
+    let (mut dirs, mut files, mut bytes) = (Vec::new(), Vec::new(), 0);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        } else {
+            bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            files.push(entry.path());
+        }
+    }
+    Ok((dirs, files, bytes))
+}
+
+/// List the tree at `root` by `TREE_JOBS` threads. Symbolic links are not followed.
+pub fn walk(root: &Path) -> Result<Tree> {
+    // Directories to be read, and the number of directories being read
+    let queue = Mutex::new((vec![root.to_path_buf()], 0));
+    let cond = Condvar::new();
+    let tree = Mutex::new(Tree::default());
+    let error = Mutex::new(None);
+    thread::scope(|s| {
+        for _ in 0..TREE_JOBS {
+            s.spawn(|| loop {
+                let dir = {
+                    let mut queue = queue.lock().unwrap();
+                    loop {
+                        if interrupted() || error.lock().unwrap().is_some() {
+                            cond.notify_all();
+                            return;
+                        }
+                        if let Some(dir) = queue.0.pop() {
+                            queue.1 += 1;
+                            break dir;
+                        }
+                        if queue.1 == 0 {
+                            cond.notify_all();
+                            return;
+                        }
+                        queue = cond.wait(queue).unwrap();
+                    }
+                };
+                let res = read_dir(&dir);
+                let mut queue = queue.lock().unwrap();
+                queue.1 -= 1;
+                match res {
+                    Ok((dirs, files, bytes)) => {
+                        queue.0.extend(dirs);
+                        let mut tree = tree.lock().unwrap();
+                        tree.dirs.push(dir);
+                        tree.files.extend(files);
+                        tree.bytes += bytes;
+                    }
+                    Err(e) => {
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+                cond.notify_all();
+            });
+        }
+    });
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+    check_interrupted()?;
+    let mut tree = tree.into_inner().unwrap();
+    tree.dirs
+        .sort_by(|a, b| (a.components().count(), a).cmp(&(b.components().count(), b)));
+    tree.files.sort();
+    Ok(tree)
+}
+
+/// Run `f` for each of `items` by `TREE_JOBS` threads, with reporting the progress from the current thread.
+/// The first error stops the others.
+pub(crate) fn for_each<T, F>(items: &[T], f: F) -> Result<()>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<()> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let done = AtomicU64::new(0);
+    let running = AtomicUsize::new(TREE_JOBS);
+    let error = Mutex::new(None);
+    let observer = observer();
+    let total = items.len() as u64;
+    thread::scope(|s| {
+        for _ in 0..TREE_JOBS {
+            s.spawn(|| {
+                while !interrupted() && error.lock().unwrap().is_none() {
+                    let item = match items.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        Some(item) => item,
+                        None => break,
+                    };
+                    if let Err(e) = f(item) {
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                    done.fetch_add(1, Ordering::SeqCst);
+                }
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        while running.load(Ordering::SeqCst) > 0 {
+            observer.on_files(done.load(Ordering::SeqCst), Some(total));
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+    observer.on_files(done.load(Ordering::SeqCst), Some(total));
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+    check_interrupted()
+}
+
+/// Hidden sibling of `path` used while it is processed, e.g. `.17.0.6.removing-1234`
+pub(crate) fn hidden_sibling(path: &Path, what: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}-{}", name, what, process::id()))
+}
+
+/// Remove the file or directory tree at `path`, see [module level doc](index.html)
+pub fn remove(path: &Path) -> Result<()> {
+    if !fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_file(path)?;
+        return Ok(());
+    }
+    let trash = hidden_sibling(path, "removing");
+    let target = match fs::rename(path, &trash) {
+        Ok(()) => trash,
+        Err(e) => {
+            warn!(
+                "Cannot move {} aside ({}), remove it in place",
+                path.display(),
+                e
+            );
+            path.to_path_buf()
+        }
+    };
+    info!("Remove {}", path.display());
+    let res = interruptible(|| {
+        run_phase(Phase::Remove, &path.display().to_string(), || {
+            if lockdown::is_locked(&target) {
+                lockdown::unlock(&target)?;
+            }
+            let tree = walk(&target)?;
+            for_each(&tree.files, |file| Ok(fs::remove_file(file)?))?;
+            for dir in tree.dirs.iter().rev() {
+                fs::remove_dir(dir)?;
+            }
+            Ok(())
+        })
+    });
+    if res.is_err() && target.exists() {
+        warn!(
+            "The rest of {} is left in {}. Run `llvmenv gc` to remove it.",
+            path.display(),
+            target.display()
+        );
+    }
+    res
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dest: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(src)?, dest)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(src: &Path, dest: &Path) -> Result<()> {
+    fs::copy(src, dest)?;
+    Ok(())
+}
+
+/// Copy the directory tree at `src` into `dest`, which must not exist, see [module level doc](index.html).
+/// Symbolic links are copied as links.
+pub fn copy(src: &Path, dest: &Path) -> Result<()> {
+    if dest.exists() {
+        bail!("Destination already exists: {}", dest.display());
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = hidden_sibling(dest, "copying");
+    info!("Copy {} to {}", src.display(), dest.display());
+    let dest_of = |path: &Path| tmp.join(path.strip_prefix(src).unwrap());
+    let res = interruptible(|| {
+        run_phase(Phase::Copy, &dest.display().to_string(), || {
+            let tree = walk(src)?;
+            for dir in &tree.dirs {
+                fs::create_dir_all(dest_of(dir))?;
+            }
+            for_each(&tree.files, |file| {
+                if fs::symlink_metadata(file)?.file_type().is_symlink() {
+                    copy_symlink(file, &dest_of(file))
+                } else {
+                    fs::copy(file, dest_of(file))?;
+                    Ok(())
+                }
+            })?;
+            // After the files, since they cannot be created in read-only directories
+            for dir in &tree.dirs {
+                fs::set_permissions(dest_of(dir), fs::metadata(dir)?.permissions())?;
+            }
+            fs::rename(&tmp, dest)?;
+            Ok(())
+        })
+    });
+    if res.is_err() && tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    res
+}
+
+/// Move the directory tree at `src` into `dest`, copying if `dest` is on another file system
+pub fn move_dir(src: &Path, dest: &Path) -> Result<()> {
+    if fs::rename(src, dest).is_ok() {
+        return Ok(());
+    }
+    copy(src, dest)?;
+    remove(src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy_remove() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let src = tmp.path().join("src");
+        fs::create_dir_all(src.join("bin"))?;
+        fs::create_dir_all(src.join("lib/clang/17/include"))?;
+        fs::write(src.join("bin/clang-17"), "clang")?;
+        fs::write(src.join("lib/clang/17/include/stddef.h"), "")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("clang-17", src.join("bin/clang"))?;
+
+        let tree = walk(&src)?;
+        assert_eq!(tree.dirs[0], src);
+        assert_eq!(tree.dirs.len(), 6);
+        assert_eq!(tree.bytes, 5 + if cfg!(unix) { 8 } else { 0 });
+
+        let dest = tmp.path().join("dest");
+        copy(&src, &dest)?;
+        assert_eq!(fs::read_to_string(dest.join("bin/clang-17"))?, "clang");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dest.join("bin/clang"))?,
+            Path::new("clang-17")
+        );
+        assert!(copy(&src, &dest).is_err());
+
+        remove(&dest)?;
+        remove(&src)?;
+        assert_eq!(fs::read_dir(tmp.path())?.count(), 0);
+        Ok(())
+    }
+}
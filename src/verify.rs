@@ -0,0 +1,282 @@
+//! Verification of installed builds
+//!
+//! When a build is installed by `llvmenv build-entry` or `llvmenv install`, the size and SHA-256 of its files
+//! are recorded into `.llvmenv-manifest` in the prefix (`llvmenv verify --record [name]` records it for older builds).
+//! `llvmenv verify [name]` checks the build against the manifest:
+//!
+//! - every file is checked to exist with the recorded size, which reads only the file system metadata
+//! - contents are hashed for a sample of files, bounded by `SAMPLE_BYTES` (256 MB) to take a couple of seconds
+//!   even for huge prefixes. The sample is deterministic for each build (ordered by the hash of the build name
+//!   and the path), so that repeated checks are comparable.
+//!
+//! `llvmenv verify --deep [name]` hashes all files instead.
+
+use log::info;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::build::Build;
+use crate::error::*;
+use crate::lockdown;
+use crate::prebuilt::sha256_file;
+use crate::progress::{run_phase, Phase};
+use crate::tree;
+
+/// Manifest of files in the prefix
+pub const MANIFEST_FN: &str = ".llvmenv-manifest";
+
+/// Bytes hashed by the quick verification
+const SAMPLE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// File recorded in the manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// Path relative to the prefix, separated by `/`
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Result of [verify](fn.verify.html)
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of files in the manifest
+    pub files: usize,
+    /// Number of files whose contents are hashed
+    pub hashed: usize,
+    /// Bytes hashed
+    pub hashed_bytes: u64,
+    /// Files in the manifest which no longer exist
+    pub missing: Vec<String>,
+    /// Files whose size or content differs from the manifest
+    pub modified: Vec<String>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Parse `${sha256} ${size} ${path}` lines of the manifest
+///
+/// ```
+/// # use llvmenv::verify::parse_manifest;
+/// let entries = parse_manifest("e3b0c442 0 lib/clang/17/include/stddef.h\n").unwrap();
+/// assert_eq!(entries[0].path, "lib/clang/17/include/stddef.h");
+/// assert_eq!(entries[0].size, 0);
+/// assert!(parse_manifest("e3b0c442 zero bin/clang\n").is_err());
+/// ```
+pub fn parse_manifest(manifest: &str) -> Result<Vec<ManifestEntry>> {
+    manifest
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut words = line.splitn(3, ' ');
+            match (words.next(), words.next().map(str::parse), words.next()) {
+                (Some(sha256), Some(Ok(size)), Some(path)) => Ok(ManifestEntry {
+                    path: path.into(),
+                    size,
+                    sha256: sha256.into(),
+                }),
+                _ => Err(format_err!("Invalid line of manifest: {}", line)),
+            }
+        })
+        .collect()
+}
+
+/// Size and SHA-256 of a file, or of the target of a symbolic link
+fn hash(path: &Path) -> Result<(u64, String)> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)?.to_string_lossy().to_string();
+        let sha256 = Sha256::digest(target.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        return Ok((target.len() as u64, sha256));
+    }
+    Ok((meta.len(), sha256_file(path)?))
+}
+
+/// Size as recorded in the manifest, i.e. of the target path for symbolic links
+fn size(path: &Path) -> io::Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.file_type().is_symlink() {
+        return Ok(fs::read_link(path)?.to_string_lossy().len() as u64);
+    }
+    Ok(meta.len())
+}
+
+/// Record the manifest of `build`, see [module level doc](index.html). Returns the number of files.
+pub fn record(build: &Build) -> Result<usize> {
+    if build.name() == "system" {
+        bail!("Cannot record manifest for system build");
+    }
+    let prefix = build.prefix();
+    let entries = run_phase(Phase::Verify, build.name(), || {
+        let files: Vec<_> = tree::walk(prefix)?
+            .files
+            .into_iter()
+            .filter_map(|file| {
+                let path = file
+                    .strip_prefix(prefix)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                // Metadata and the manifest itself are updated after installation
+                if path.starts_with(".llvmenv") {
+                    None
+                } else {
+                    Some((file, path))
+                }
+            })
+            .collect();
+        let entries = Mutex::new(Vec::with_capacity(files.len()));
+        tree::for_each(&files, |(file, path)| {
+            let (size, sha256) = hash(file)?;
+            entries.lock().unwrap().push(ManifestEntry {
+                path: path.clone(),
+                size,
+                sha256,
+            });
+            Ok(())
+        })?;
+        Ok(entries.into_inner().unwrap())
+    })?;
+    let mut entries = entries;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut manifest = String::new();
+    for e in &entries {
+        writeln!(manifest, "{} {} {}", e.sha256, e.size, e.path)?;
+    }
+    lockdown::write(prefix, MANIFEST_FN, manifest.as_bytes())?;
+    info!("Recorded {} files of '{}'", entries.len(), build.name());
+    Ok(entries.len())
+}
+
+/// Files to be hashed: all if `deep`, or the deterministic sample of `seed` up to `budget` bytes
+fn sample<'a>(
+    entries: &'a [ManifestEntry],
+    seed: &str,
+    budget: u64,
+    deep: bool,
+) -> Vec<&'a ManifestEntry> {
+    if deep {
+        return entries.iter().collect();
+    }
+    let mut keyed: Vec<_> = entries
+        .iter()
+        .map(|e| {
+            (
+                Sha256::digest(format!("{}\0{}", seed, e.path).as_bytes()),
+                e,
+            )
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    let mut bytes = 0;
+    keyed
+        .into_iter()
+        .map(|(_, e)| e)
+        .take_while(|e| {
+            let within = bytes < budget;
+            bytes += e.size;
+            within
+        })
+        .collect()
+}
+
+/// Check `build` against its manifest, see [module level doc](index.html)
+pub fn verify(build: &Build, deep: bool) -> Result<Report> {
+    let path = build.prefix().join(MANIFEST_FN);
+    let manifest = match fs::read_to_string(&path) {
+        Ok(manifest) => manifest,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => bail!(
+            "Build '{}' has no manifest. Record it by `llvmenv verify --record {}`",
+            build.name(),
+            build.name()
+        ),
+        Err(e) => return Err(e.into()),
+    };
+    let entries = parse_manifest(&manifest)?;
+    let mut report = Report {
+        files: entries.len(),
+        ..Default::default()
+    };
+    for e in &entries {
+        match size(&build.prefix().join(&e.path)) {
+            Ok(size) if size == e.size => {}
+            Ok(_) => report.modified.push(e.path.clone()),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                report.missing.push(e.path.clone())
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let targets: Vec<_> = sample(&entries, build.name(), SAMPLE_BYTES, deep)
+        .into_iter()
+        .filter(|e| !report.missing.contains(&e.path) && !report.modified.contains(&e.path))
+        .collect();
+    let modified = Mutex::new(Vec::new());
+    run_phase(Phase::Verify, build.name(), || {
+        tree::for_each(&targets, |e| {
+            if hash(&build.prefix().join(&e.path))?.1 != e.sha256 {
+                modified.lock().unwrap().push(e.path.clone());
+            }
+            Ok(())
+        })
+    })?;
+    report.hashed = targets.len();
+    report.hashed_bytes = targets.iter().map(|e| e.size).sum();
+    report.modified.extend(modified.into_inner().unwrap());
+    report.modified.sort();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sample() {
+        let entries: Vec<_> = (0..100)
+            .map(|i| ManifestEntry {
+                path: format!("lib/lib{}.a", i),
+                size: 10,
+                sha256: String::new(),
+            })
+            .collect();
+        let a = sample(&entries, "17.0.6", 100, false);
+        assert_eq!(a.len(), 10);
+        assert_eq!(a, sample(&entries, "17.0.6", 100, false));
+        assert_ne!(a, sample(&entries, "18.1.8", 100, false));
+        assert_eq!(sample(&entries, "17.0.6", 0, false).len(), 0);
+        assert_eq!(sample(&entries, "17.0.6", 100, true).len(), 100);
+    }
+
+    #[test]
+    fn test_record_verify() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let prefix = tmp.path().join("17.0.6");
+        fs::create_dir_all(prefix.join("bin"))?;
+        fs::write(prefix.join("bin/clang-17"), "clang")?;
+        fs::write(prefix.join("bin/llvm-config"), "llvm-config")?;
+        let build = Build::from_path(&prefix);
+        assert_eq!(record(&build)?, 2);
+        assert!(verify(&build, true)?.is_ok());
+
+        fs::write(prefix.join("bin/clang-17"), "CLANG")?;
+        fs::remove_file(prefix.join("bin/llvm-config"))?;
+        let report = verify(&build, false)?;
+        assert_eq!(report.missing, vec!["bin/llvm-config"]);
+        assert_eq!(report.modified, vec!["bin/clang-17"]);
+        assert_eq!(report.hashed, 1);
+        Ok(())
+    }
+}
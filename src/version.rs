@@ -0,0 +1,352 @@
+//! Versions of LLVM
+//!
+//! [Version](./struct.Version.html) parses versions of LLVM in release tags, entry and build names,
+//! and the output of `clang --version`, e.g. `17.0.6`, `llvmorg-18.1.0-rc1`, `19.0.0git`, and `17.0.6+asan`.
+//! They are ordered as development snapshots (`git`) < release candidates (`rc`) < releases,
+//! and the suffix after `+` only breaks ties.
+//!
+//! [VersionReq](./struct.VersionReq.html) matches versions, e.g. `llvmenv install 17` installs the latest 17.x.y:
+//!
+//! | Requirement | Matches                    |
+//! |:------------|:---------------------------|
+//! | `17`        | 17.x.y                     |
+//! | `17.0`      | 17.0.x                     |
+//! | `17.0.6`    | 17.0.6 (including rc)      |
+//! | `>=17`      | 17.0.0 or later            |
+//! | `<18.1`     | earlier than 18.1.0        |
+
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::error::{format_err, LlvmEnvError, Result};
+
+/// Pre-release of a version, ordered as `Git < Rc < Release`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pre {
+    /// Development snapshot, e.g. `19.0.0git`
+    Git,
+    /// Release candidate, e.g. `18.1.0-rc1`
+    Rc(u32),
+    Release,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub pre: Pre,
+    /// Suffix of entry or build names after `+`, e.g. `asan` of `17.0.6+asan`
+    pub suffix: Option<String>,
+}
+
+const PRE: &str = r"(?:-?rc(\d+)|(git))?";
+
+fn pre(rc: Option<regex::Match>, git: Option<regex::Match>) -> Pre {
+    match (rc, git) {
+        (Some(n), _) => Pre::Rc(n.as_str().parse().unwrap_or(0)),
+        (None, Some(_)) => Pre::Git,
+        (None, None) => Pre::Release,
+    }
+}
+
+impl Version {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+            pre: Pre::Release,
+            suffix: None,
+        }
+    }
+
+    /// First `major.minor.patch` in a text, e.g. the output of `clang --version`
+    ///
+    /// ```
+    /// # use llvmenv::version::{Version, Pre};
+    /// let v = Version::find("clang version 19.0.0git (https://github.com/llvm/llvm-project abc)").unwrap();
+    /// assert_eq!((v.major, v.minor, v.patch, v.pre), (19, 0, 0, Pre::Git));
+    /// assert!(Version::find("clang version unknown").is_none());
+    /// ```
+    pub fn find(text: &str) -> Option<Self> {
+        static FIND: OnceLock<Regex> = OnceLock::new();
+        let re = FIND.get_or_init(|| Regex::new(&format!(r"(\d+)\.(\d+)\.(\d+){}", PRE)).unwrap());
+        let cap = re.captures(text)?;
+        Some(Version {
+            major: cap[1].parse().ok()?,
+            minor: cap[2].parse().ok()?,
+            patch: cap[3].parse().ok()?,
+            pre: pre(cap.get(4), cap.get(5)),
+            suffix: None,
+        })
+    }
+
+    /// Version of `name` of an entry, build, or release tag, None if it is not a version
+    pub fn from_name(name: &str) -> Option<Self> {
+        name.parse().ok()
+    }
+
+    fn triple(&self) -> (u32, u32, u32) {
+        (self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = LlvmEnvError;
+
+    /// Parse a version, with optional `llvmorg-` prefix of release tags
+    ///
+    /// ```
+    /// # use llvmenv::version::{Version, Pre};
+    /// let v: Version = "llvmorg-18.1.0-rc1".parse().unwrap();
+    /// assert_eq!((v.major, v.minor, v.patch, v.pre), (18, 1, 0, Pre::Rc(1)));
+    /// let v: Version = "17.0.6+asan".parse().unwrap();
+    /// assert_eq!(v.suffix.as_deref(), Some("asan"));
+    /// assert_eq!("3.9".parse::<Version>().unwrap(), Version::new(3, 9, 0));
+    /// assert!("llvm-mirror".parse::<Version>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        static PARSE: OnceLock<Regex> = OnceLock::new();
+        let re = PARSE.get_or_init(|| {
+            Regex::new(&format!(
+                r"^(?:llvmorg-)?(\d+)(?:\.(\d+))?(?:\.(\d+))?{}(?:\+(.+))?$",
+                PRE
+            ))
+            .unwrap()
+        });
+        let cap = re
+            .captures(s)
+            .ok_or_else(|| format_err!("Invalid version: {}", s))?;
+        let num = |i: usize| -> Result<u32> {
+            match cap.get(i) {
+                Some(m) => m
+                    .as_str()
+                    .parse()
+                    .map_err(|e| format_err!("Invalid version {}: {}", s, e)),
+                None => Ok(0),
+            }
+        };
+        Ok(Version {
+            major: num(1)?,
+            minor: num(2)?,
+            patch: num(3)?,
+            pre: pre(cap.get(4), cap.get(5)),
+            suffix: cap.get(6).map(|m| m.as_str().to_string()),
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        match self.pre {
+            Pre::Git => write!(f, "git")?,
+            Pre::Rc(n) => write!(f, "-rc{}", n)?,
+            Pre::Release => {}
+        }
+        if let Some(suffix) = &self.suffix {
+            write!(f, "+{}", suffix)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+/// Requirement of versions, see [module level doc](index.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionReq {
+    op: Op,
+    /// major, minor, and patch as given
+    parts: Vec<u32>,
+}
+
+impl FromStr for VersionReq {
+    type Err = LlvmEnvError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (op, rest) = [
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+            ("=", Op::Eq),
+        ]
+        .iter()
+        .find(|(prefix, _)| s.starts_with(prefix))
+        .map(|(prefix, op)| (*op, s[prefix.len()..].trim()))
+        .unwrap_or((Op::Eq, s));
+        let parts = rest
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| format_err!("Invalid version requirement: {}", s))?;
+        if parts.is_empty() || parts.len() > 3 {
+            return Err(format_err!("Invalid version requirement: {}", s));
+        }
+        Ok(VersionReq { op, parts })
+    }
+}
+
+impl VersionReq {
+    /// Whether `version` satisfies the requirement, compared up to the precision of the requirement
+    ///
+    /// ```
+    /// # use llvmenv::version::{Version, VersionReq};
+    /// let req: VersionReq = "17".parse().unwrap();
+    /// assert!(req.matches(&"17.0.6".parse().unwrap()));
+    /// assert!(!req.matches(&"18.1.0".parse().unwrap()));
+    /// let req: VersionReq = "<18.1".parse().unwrap();
+    /// assert!(req.matches(&"18.0.9".parse().unwrap()));
+    /// assert!(!req.matches(&"18.1.0".parse().unwrap()));
+    /// ```
+    pub fn matches(&self, version: &Version) -> bool {
+        let (major, minor, patch) = version.triple();
+        let ord = [major, minor, patch][..self.parts.len()].cmp(&self.parts[..]);
+        match self.op {
+            Op::Eq => ord == Ordering::Equal,
+            Op::Ge => ord != Ordering::Less,
+            Op::Gt => ord == Ordering::Greater,
+            Op::Le => ord != Ordering::Greater,
+            Op::Lt => ord == Ordering::Less,
+        }
+    }
+
+    /// Whether this requires exactly one `major.minor.patch`
+    pub fn is_exact(&self) -> bool {
+        self.op == Op::Eq && self.parts.len() == 3
+    }
+}
+
+/// Order of entry and build names: names which are not versions first in alphabetical order,
+/// then versions from the latest
+///
+/// ```
+/// # use llvmenv::version::cmp_names;
+/// let mut names = vec!["7.0.0", "llvm-mirror", "10.0.1", "17.0.6+asan", "17.0.6", "dev"];
+/// names.sort_by(|a, b| cmp_names(a, b));
+/// assert_eq!(names, vec!["dev", "llvm-mirror", "17.0.6+asan", "17.0.6", "10.0.1", "7.0.0"]);
+/// ```
+pub fn cmp_names(a: &str, b: &str) -> Ordering {
+    match (Version::from_name(a), Version::from_name(b)) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (None, None) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() -> Result<()> {
+        let mut versions: Vec<Version> =
+            ["18.1.0", "18.1.0-rc2", "18.1.0git", "18.1.0-rc10", "9.0.1"]
+                .iter()
+                .map(|v| v.parse())
+                .collect::<Result<_>>()?;
+        versions.sort();
+        let names: Vec<String> = versions.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            names,
+            vec!["9.0.1", "18.1.0git", "18.1.0-rc2", "18.1.0-rc10", "18.1.0"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_req() -> Result<()> {
+        let v: Version = "17.0.6".parse()?;
+        for req in &[
+            "17", "17.0", "17.0.6", ">=17", ">16", "<=17.0", "<17.1", "=17.0.6",
+        ] {
+            assert!(req.parse::<VersionReq>()?.matches(&v), "{}", req);
+        }
+        for req in &["18", "17.1", ">17", "<17.0.6", ">=17.0.7"] {
+            assert!(!req.parse::<VersionReq>()?.matches(&v), "{}", req);
+        }
+        assert!("17.0.6".parse::<VersionReq>()?.is_exact());
+        assert!(!">=17.0.6".parse::<VersionReq>()?.is_exact());
+        assert!("latest".parse::<VersionReq>().is_err());
+        Ok(())
+    }
+
+    fn version() -> impl proptest::strategy::Strategy<Value = Version> {
+        use proptest::prelude::*;
+        let pre = prop_oneof![
+            Just(Pre::Git),
+            (0u32..20).prop_map(Pre::Rc),
+            Just(Pre::Release)
+        ];
+        (
+            0u32..100,
+            0u32..10,
+            0u32..10,
+            pre,
+            proptest::option::of("[a-z0-9_-]{1,8}"),
+        )
+            .prop_map(|(major, minor, patch, pre, suffix)| Version {
+                major,
+                minor,
+                patch,
+                pre,
+                suffix,
+            })
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(256))]
+
+        /// Names of builds printed from versions are parsed back
+        #[test]
+        fn test_display_parse(v in version()) {
+            proptest::prop_assert_eq!(v.to_string().parse::<Version>().unwrap(), v);
+        }
+
+        /// Pre-releases and suffixes are ordered only within the same `major.minor.patch`
+        #[test]
+        fn test_order_triple(a in version(), b in version()) {
+            if a.triple() != b.triple() {
+                proptest::prop_assert_eq!(a.cmp(&b), a.triple().cmp(&b.triple()));
+            }
+        }
+
+        /// Requirements compare up to their precision, ignoring pre-releases
+        #[test]
+        fn test_req_precision(v in version(), n in 1usize..=3) {
+            let parts = [v.major, v.minor, v.patch][..n].iter().map(|p| p.to_string()).collect::<Vec<_>>().join(".");
+            for (req, expected) in [("", true), (">=", true), ("<=", true), (">", false), ("<", false)] {
+                let req: VersionReq = format!("{}{}", req, parts).parse().unwrap();
+                proptest::prop_assert_eq!(req.matches(&v), expected, "{:?}", req);
+            }
+        }
+
+        /// Arbitrary names, e.g. with too large numbers, are rejected without panics
+        #[test]
+        fn test_parse_any(s in "\\PC*") {
+            let _ = s.parse::<Version>();
+            let _ = s.parse::<VersionReq>();
+            let _ = Version::find(&s);
+        }
+
+        /// Sorting names of entries and builds is consistent
+        #[test]
+        fn test_cmp_names(a in "(llvmorg-)?[0-9]{1,3}(\\.[0-9]{1,2}){0,2}(-rc[0-9]|git)?|[a-z0-9.+-]{1,10}", b in "[a-z0-9.+-]{1,10}") {
+            proptest::prop_assert_eq!(cmp_names(&a, &b), cmp_names(&b, &a).reverse());
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Configure, build, and install by stubs of cmake and ninja
+#![cfg(unix)]
+
+mod support;
+
+use std::fs;
+use std::io::Write;
+use std::process::Command;
+
+use support::*;
+
+/// Sandbox with a local entry `local`
+fn local_entry(option: &str) -> Sandbox {
+    let sandbox = Sandbox::new();
+    let src = sandbox.path().join("llvm-project");
+    fs::create_dir_all(src.join("llvm")).unwrap();
+    fs::write(src.join("llvm/CMakeLists.txt"), "project(LLVM)\n").unwrap();
+    write_local_entry(&sandbox, option);
+    sandbox
+}
+
+/// Write the entry `local` of the sources in the sandbox with `LLVM_ENABLE_ASSERTIONS = option`
+fn write_local_entry(sandbox: &Sandbox, option: &str) {
+    sandbox.write_entries(&format!(
+        "[local]\npath = \"{}\"\n\n[local.option]\nLLVM_ENABLE_ASSERTIONS = \"{}\"\n",
+        sandbox.path().join("llvm-project").display(),
+        option
+    ));
+}
+
+/// Sandbox with an entry `corp` fetched by a stub plugin `llvmenv-fetch-corp` recording its runs
+fn plugin_entry() -> Sandbox {
+    let sandbox = Sandbox::new();
+    write_stub(
+        &sandbox.path().join("bin/llvmenv-fetch-corp"),
+        "#!/bin/sh\necho \"llvmenv-fetch-corp\" >> \"$LLVMENV_FAKE_LOG\"\n",
+    );
+    sandbox.write_entries("[corp]\nurl = \"corp://example.com/llvm-project.tar.xz\"\n");
+    sandbox
+}
+
+fn plugin_runs(sandbox: &Sandbox) -> usize {
+    sandbox
+        .invocations()
+        .iter()
+        .filter(|cmd| *cmd == "llvmenv-fetch-corp")
+        .count()
+}
+
+fn configures(sandbox: &Sandbox) -> usize {
+    sandbox
+        .invocations()
+        .iter()
+        .filter(|cmd| cmd.starts_with("cmake -G"))
+        .count()
+}
+
+#[test]
+fn test_build_entry() {
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local", "-j", "2"]);
+
+    let invocations = sandbox.invocations();
+    let configure = invocations
+        .iter()
+        .find(|cmd| cmd.starts_with("cmake -G Ninja"))
+        .unwrap();
+    assert!(configure.contains("-DLLVM_ENABLE_ASSERTIONS=ON"));
+    assert!(invocations
+        .iter()
+        .any(|cmd| cmd.starts_with("cmake --build") && cmd.contains("--target install -- -j 2")));
+
+    assert!(sandbox.data_dir().join("local/bin/clang").exists());
+    assert!(sandbox.run(&["builds"]).contains("local"));
+    sandbox.run(&["verify", "local", "--deep"]);
+    assert!(sandbox
+        .run(&["build-log", "local"])
+        .contains("Linking CXX executable bin/clang"));
+
+    // Output of the compilers in a non-UTF-8 locale
+    let log = std::path::PathBuf::from(sandbox.run(&["builddir", "local"]).trim())
+        .join("llvmenv-build.log");
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&log)
+        .unwrap()
+        .write_all(b"caf\xe9\n")
+        .unwrap();
+    assert_eq!(
+        sandbox.run(&["build-log", "local", "--tail", "1"]),
+        "caf\u{fffd}\n"
+    );
+}
+
+#[test]
+fn test_build_root() {
+    let sandbox = local_entry("ON");
+    let root = sandbox.path().join("b");
+    sandbox.run(&["config", "set", "paths.build", &root.display().to_string()]);
+    sandbox.run(&["build-entry", "local"]);
+    assert!(root.join("llvmenv/local").is_dir());
+
+    // Only the trees of llvmenv are collected
+    fs::create_dir_all(root.join("other")).unwrap();
+    fs::create_dir_all(root.join("llvmenv/other")).unwrap();
+    sandbox.run(&["gc", "--yes"]);
+    assert!(root.join("other").is_dir());
+    assert!(!root.join("llvmenv/other").exists());
+}
+
+#[test]
+fn test_exec() {
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local"]);
+    let prefix = sandbox.data_dir().join("local");
+    let stdout = sandbox.run(&[
+        "exec",
+        "local",
+        "--",
+        "sh",
+        "-c",
+        "echo $LLVM_SYS_170_PREFIX; clang",
+    ]);
+    assert_eq!(
+        stdout,
+        format!("{}\nclang version 17.0.6\n", prefix.display())
+    );
+
+    let output = sandbox
+        .llvmenv(&["exec", "local", "--", "sh", "-c", "exit 3"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+
+    let export = sandbox.run(&["llvm-sys-env", "local", "--export"]);
+    assert_eq!(
+        export,
+        format!("export LLVM_SYS_170_PREFIX='{}'\n", prefix.display())
+    );
+}
+
+#[test]
+fn test_shim_non_utf8() {
+    use std::os::unix::ffi::OsStrExt;
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local"]);
+    sandbox.run(&["global", "local"]);
+    let output = sandbox
+        .llvmenv(&["__shim", "clang"])
+        .arg(std::ffi::OsStr::from_bytes(b"caf\xe9.c"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"clang version 17.0.6\n");
+}
+
+#[test]
+fn test_non_utf8_args() {
+    use std::os::unix::ffi::OsStrExt;
+    let sandbox = local_entry("ON");
+    sandbox.run(&["config", "set", "audit.enabled", "true"]);
+    let output = sandbox.run_err(
+        sandbox
+            .llvmenv(&["source"])
+            .arg(std::ffi::OsStr::from_bytes(b"caf\xe9")),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(sandbox
+        .run(&["audit-log", "show"])
+        .contains("source caf\u{fffd}"));
+}
+
+#[test]
+fn test_rebuild() {
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local"]);
+    assert_eq!(configures(&sandbox), 1);
+
+    sandbox.clear_invocations();
+    sandbox.run(&["rebuild", "local"]);
+    assert_eq!(configures(&sandbox), 0);
+    assert!(sandbox
+        .invocations()
+        .iter()
+        .any(|cmd| cmd.starts_with("cmake --build")));
+
+    // Only the option of the entry is changed
+    write_local_entry(&sandbox, "OFF");
+    sandbox.clear_invocations();
+    sandbox.run(&["rebuild", "local"]);
+    assert_eq!(configures(&sandbox), 1);
+}
+
+#[test]
+fn test_up_to_date_phases() {
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local"]);
+
+    sandbox.clear_invocations();
+    sandbox.run(&["build-entry", "local", "--skip", "download"]);
+    assert!(sandbox.invocations().is_empty());
+
+    // Run as requested without installing
+    sandbox.run(&["build-entry", "local", "--only", "configure"]);
+    assert_eq!(configures(&sandbox), 1);
+}
+
+#[test]
+fn test_components() {
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local"]);
+    let stale = sandbox.data_dir().join("local/bin/clang-tidy");
+    fs::write(&stale, "").unwrap();
+
+    let entry = |components: &str| {
+        format!(
+            "[local]\npath = \"{}\"\nset.compilers = [\"clang\"]\ncomponents = [{}]\n",
+            sandbox.path().join("llvm-project").display(),
+            components
+        )
+    };
+    sandbox.write_entries(&entry("\"compilers\""));
+    sandbox.clear_invocations();
+    sandbox.run(&["build-entry", "local"]);
+    let invocations = sandbox.invocations();
+    assert!(invocations
+        .iter()
+        .any(|cmd| cmd.starts_with("cmake -G")
+            && cmd.contains("-DLLVM_DISTRIBUTION_COMPONENTS=clang")));
+    assert!(invocations
+        .iter()
+        .any(|cmd| cmd.contains("--target install-distribution")));
+    assert!(sandbox.data_dir().join("local/bin/clang").exists());
+    assert!(!stale.exists());
+
+    // Components selected in the previous configure are removed from the cache
+    sandbox.write_entries(&entry(""));
+    sandbox.clear_invocations();
+    sandbox.run(&["build-entry", "local"]);
+    assert!(sandbox
+        .invocations()
+        .iter()
+        .any(|cmd| cmd.starts_with("cmake -G") && cmd.contains("-ULLVM_DISTRIBUTION_COMPONENTS")));
+}
+
+#[test]
+fn test_lld_of_build() {
+    if ["ld.lld", "mold"].iter().any(|tool| {
+        Command::new("which")
+            .arg(tool)
+            .output()
+            .unwrap()
+            .status
+            .success()
+    }) {
+        // Found before the builds
+        return;
+    }
+    let sandbox = local_entry("ON");
+    sandbox.run(&["build-entry", "local"]);
+    let lld = sandbox.data_dir().join("local/bin/ld.lld");
+    write_stub(&lld, "#!/bin/sh\n");
+
+    sandbox.clear_invocations();
+    sandbox.run(&["build-entry", "local", "--force"]);
+    let invocations = sandbox.invocations();
+    let configure = invocations
+        .iter()
+        .find(|cmd| cmd.starts_with("cmake -G"))
+        .unwrap();
+    // Not the path, which GCC before 12 rejects
+    assert!(configure.ends_with("-DLLVM_USE_LINKER=lld"));
+    let link = sandbox
+        .cache_dir()
+        .join("build/local/.llvmenv-linker/ld.lld");
+    assert_eq!(fs::read_link(link).unwrap(), lld);
+}
+
+#[test]
+fn test_build_failure() {
+    let sandbox = local_entry("ON");
+    let error = "/src/a.cpp:1:2: error: use of undeclared identifier 'y'";
+    let output = sandbox.run_err(
+        sandbox
+            .llvmenv(&["build-entry", "local"])
+            .env("LLVMENV_FAKE_FAIL", error),
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Errors: 1"), "{}", stderr);
+    assert!(stderr.contains("llvmenv report local"), "{}", stderr);
+    assert!(!sandbox.data_dir().join("local").exists());
+
+    let errors = sandbox.run(&["build-log", "local", "--errors-only"]);
+    assert_eq!(errors.lines().next(), Some(error));
+    assert!(sandbox.run(&["report", "local"]).contains(error));
+}
+
+#[test]
+fn test_dry_run() {
+    let sandbox = local_entry("ON");
+    let script = sandbox.run(&["--dry-run", "build-entry", "local", "-j", "2"]);
+    let lines: Vec<&str> = script.lines().collect();
+    assert!(lines[0].starts_with("mkdir -p "));
+    assert!(lines[1].contains("&& cmake -G Ninja "));
+    assert!(lines[1].contains("-DLLVM_ENABLE_ASSERTIONS=ON"));
+    assert!(
+        lines[2].starts_with("cmake --build ") && lines[2].ends_with("--target install -- -j 2")
+    );
+    // Only the versions are checked
+    assert!(sandbox
+        .invocations()
+        .iter()
+        .all(|cmd| cmd.ends_with(" --version")));
+    assert!(!sandbox.data_dir().join("local").exists());
+
+    // Not supported by the commands removing builds
+    sandbox.run(&["build-entry", "local"]);
+    sandbox.run_err(&mut sandbox.llvmenv(&["--dry-run", "uninstall", "local"]));
+    assert!(sandbox.data_dir().join("local").exists());
+}
+
+#[test]
+fn test_dry_run_plugin() {
+    let sandbox = plugin_entry();
+    let script = sandbox.run(&["--dry-run", "build-entry", "corp"]);
+    let src = sandbox.cache_dir().join("src/corp");
+    assert!(script.contains(&format!("mkdir -p {}\n", src.display())));
+    assert!(script.contains(r#""action":"download""#));
+    let plugin = sandbox.path().join("bin/llvmenv-fetch-corp");
+    assert!(script.contains(&format!(" | {}\n", plugin.display())));
+    assert_eq!(plugin_runs(&sandbox), 0);
+    assert!(!src.exists());
+}
+
+#[test]
+fn test_offline_plugin() {
+    let sandbox = plugin_entry();
+    let output = sandbox.run_err(&mut sandbox.llvmenv(&["build-entry", "corp", "--offline"]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("disabled by --offline"), "{}", stderr);
+    assert_eq!(plugin_runs(&sandbox), 0);
+}
+
+/// Stub of ccache whose hits increase by 4 and misses by 1 for each `--print-stats`
+const CCACHE_STUB: &str = r#"#!/bin/sh
+echo "ccache $*" >> "$LLVMENV_FAKE_LOG"
+if [ "$1" = "--print-stats" ]; then
+  echo x >> "$(dirname "$0")/ccache-calls"
+  n=$(wc -l < "$(dirname "$0")/ccache-calls")
+  printf 'direct_cache_hit\t%d\ncache_miss\t%d\n' $((n * 4)) "$n"
+fi
+echo "ccache version 4.9"
+"#;
+
+#[test]
+fn test_group_cache_savings() {
+    let sandbox = Sandbox::new();
+    write_stub(&sandbox.path().join("bin/ccache"), CCACHE_STUB);
+    let mut toml = String::new();
+    for name in &["llvm-16", "llvm-17"] {
+        let src = sandbox.path().join(name);
+        fs::create_dir_all(src.join("llvm")).unwrap();
+        fs::write(src.join("llvm/CMakeLists.txt"), "project(LLVM)\n").unwrap();
+        toml += &format!(
+            "[{}]\npath = \"{}\"\ngroup = \"all\"\nccache = true\n\n",
+            name,
+            src.display()
+        );
+    }
+    sandbox.write_entries(&toml);
+
+    let output = sandbox
+        .llvmenv(&["build-entry", "--group", "all"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains(
+            "Compiler cache (ccache): 4 of 5 compile jobs hit the cache while building 'llvm-17'"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Compiler cache (ccache): 8 of 10 compile jobs hit the cache during the build of 2 entries"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_build_estimate() {
+    let sandbox = local_entry("ON");
+    let first = sandbox.llvmenv(&["build-entry", "local"]).output().unwrap();
+    assert!(!String::from_utf8_lossy(&first.stderr).contains("Estimated build time"));
+    let history = fs::read_to_string(sandbox.cache_dir().join("build-times.toml")).unwrap();
+    assert!(
+        history.contains("[[local]]") && history.contains("steps = 2"),
+        "{}",
+        history
+    );
+
+    let second = sandbox
+        .llvmenv(&["build-entry", "local", "--force"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(
+        stderr.contains("Estimated build time of 'local': about 0s for 2 steps"),
+        "{}",
+        stderr
+    );
+}
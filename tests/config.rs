@@ -0,0 +1,79 @@
+//! Backup and restore of entry.toml and config.toml
+#![cfg(unix)]
+
+mod support;
+
+use std::fs;
+
+use support::*;
+
+fn backups(sandbox: &Sandbox) -> Vec<String> {
+    sandbox
+        .run(&["config", "restore", "--list"])
+        .lines()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[test]
+fn test_backup_restore() {
+    let sandbox = Sandbox::new();
+    let entry = sandbox.config_dir().join("entry.toml");
+    let config = sandbox.config_dir().join("config.toml");
+    sandbox.write_entries("[a]\npath = \"/src/a\"\n");
+    sandbox.run(&["config", "set", "build.jobs", "8"]);
+    let config_text = fs::read_to_string(&config).unwrap();
+    sandbox.run(&["config", "backup"]);
+    let first = backups(&sandbox);
+    assert_eq!(first.len(), 1);
+
+    sandbox.write_entries("[b]\npath = \"/src/b\"\n");
+    sandbox.run(&["config", "unset", "build.jobs"]);
+    sandbox.run(&["config", "restore"]);
+    assert_eq!(
+        fs::read_to_string(&entry).unwrap(),
+        "[a]\npath = \"/src/a\"\n"
+    );
+    assert_eq!(fs::read_to_string(&config).unwrap(), config_text);
+
+    // The setting replaced by the restore is backed up, also within the same second
+    let all = backups(&sandbox);
+    assert_eq!(all.len(), 2);
+    let replaced = all.iter().find(|name| !first.contains(name)).unwrap();
+    sandbox.run(&["config", "restore", replaced]);
+    assert_eq!(
+        fs::read_to_string(&entry).unwrap(),
+        "[b]\npath = \"/src/b\"\n"
+    );
+    sandbox.run_err(&mut sandbox.llvmenv(&["config", "get", "build.jobs"]));
+}
+
+#[test]
+fn test_backup_not_overwritten() {
+    let sandbox = Sandbox::new();
+    sandbox.write_entries("[a]\npath = \"/src/a\"\n");
+    sandbox.run(&["config", "backup"]);
+    sandbox.write_entries("[b]\npath = \"/src/b\"\n");
+    sandbox.run(&["config", "backup"]);
+    let names = backups(&sandbox);
+    assert_eq!(names.len(), 2);
+    let backup = |name: &str| {
+        fs::read_to_string(
+            sandbox
+                .config_dir()
+                .join("backups")
+                .join(name)
+                .join("entry.toml"),
+        )
+        .unwrap()
+    };
+    let mut contents: Vec<String> = names.iter().map(|name| backup(name)).collect();
+    contents.sort();
+    assert_eq!(
+        contents,
+        vec!["[a]\npath = \"/src/a\"\n", "[b]\npath = \"/src/b\"\n"]
+    );
+
+    sandbox.run_err(&mut sandbox.llvmenv(&["config", "restore", "unknown"]));
+    assert_eq!(backups(&sandbox), names);
+}
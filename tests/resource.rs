@@ -0,0 +1,186 @@
+//! Downloads of sources from fixtures instead of GitHub
+#![cfg(unix)]
+
+mod support;
+
+use std::fs;
+use std::path::PathBuf;
+
+use support::*;
+
+#[test]
+fn test_tar_from_server() {
+    let sandbox = Sandbox::new();
+    let fixtures = sandbox.path().join("fixtures");
+    fs::create_dir_all(&fixtures).unwrap();
+    let tarball = tarball(&[("llvm-17.0.6.src/CMakeLists.txt", "project(LLVM)\n")]);
+    fs::write(fixtures.join("llvm-17.0.6.src.tar.gz"), tarball).unwrap();
+    let base = serve(&fixtures);
+    sandbox.write_entries(&format!(
+        "[fixture]\nurl = \"{}/llvm-17.0.6.src.tar.gz\"\n\n[missing]\nurl = \"{}/llvm-18.1.8.src.tar.gz\"\n",
+        base, base
+    ));
+
+    sandbox.run(&["build-entry", "fixture", "--only", "download"]);
+    let src = PathBuf::from(sandbox.run(&["source", "fixture"]).trim());
+    assert_eq!(
+        fs::read_to_string(src.join("CMakeLists.txt")).unwrap(),
+        "project(LLVM)\n"
+    );
+    assert!(sandbox.invocations().is_empty());
+
+    let output =
+        sandbox.run_err(&mut sandbox.llvmenv(&["build-entry", "missing", "--only", "download"]));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("404"));
+}
+
+#[test]
+fn test_git_from_repo() {
+    let sandbox = Sandbox::new();
+    let repo = sandbox.path().join("llvm-project.git");
+    git_repo(&repo, &[("llvm/CMakeLists.txt", "project(LLVM)\n")]);
+    sandbox.write_entries(&format!("[fixture]\nurl = \"file://{}\"\n", repo.display()));
+
+    sandbox.run(&["build-entry", "fixture", "--only", "download"]);
+    let src = PathBuf::from(sandbox.run(&["source", "fixture"]).trim());
+    assert!(src.join("llvm/CMakeLists.txt").exists());
+
+    git_commit(&repo, &[("llvm/README.txt", "updated\n")]);
+    sandbox.run(&["build-entry", "fixture", "--only", "download", "--update"]);
+    assert!(src.join("llvm/README.txt").exists());
+}
+
+#[test]
+fn test_tar_through_proxy() {
+    let sandbox = Sandbox::new();
+    let fixtures = sandbox.path().join("fixtures");
+    fs::create_dir_all(&fixtures).unwrap();
+    let tarball = tarball(&[("llvm-17.0.6.src/CMakeLists.txt", "project(LLVM)\n")]);
+    fs::write(fixtures.join("llvm-17.0.6.src.tar.gz"), tarball).unwrap();
+    sandbox.write_entries(&format!(
+        "[fixture]\nurl = \"{}/llvm-17.0.6.src.tar.gz\"\n",
+        serve(&fixtures)
+    ));
+    sandbox.run(&["config", "set", "download.retries", "0"]);
+
+    // Nothing listens on the port of the proxy
+    let proxied = || {
+        let mut cmd = sandbox.llvmenv(&["build-entry", "fixture", "--only", "download"]);
+        cmd.env("http_proxy", "http://127.0.0.1:9")
+            .env_remove("NO_PROXY");
+        cmd
+    };
+    sandbox.run_err(&mut proxied());
+
+    sandbox.run(&["config", "set", "network.no_proxy", "localhost,127.0.0.1"]);
+    let output = proxied().output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_tar_from_mirrors() {
+    let sandbox = Sandbox::new();
+    let fixtures = sandbox.path().join("fixtures");
+    fs::create_dir_all(&fixtures).unwrap();
+    let tarball = tarball(&[("llvm-17.0.6.src/CMakeLists.txt", "project(LLVM)\n")]);
+    fs::write(fixtures.join("llvm-17.0.6.src.tar.gz"), tarball).unwrap();
+    let base = serve(&fixtures);
+    let good = format!("{}/llvm-17.0.6.src.tar.gz", base);
+    sandbox.write_entries(&format!(
+        "[fixture]\nurl = [\"{}/missing.tar.gz\", \"{}\"]\n",
+        base, good
+    ));
+
+    sandbox.run(&["build-entry", "fixture", "--only", "download"]);
+    let src = PathBuf::from(sandbox.run(&["source", "fixture"]).trim());
+    assert!(src.join("CMakeLists.txt").exists());
+    let mirrors = fs::read_to_string(sandbox.cache_dir().join("mirrors.toml")).unwrap();
+    assert_eq!(mirrors.trim(), format!("fixture = \"{}\"", good));
+}
+
+#[test]
+fn test_offline() {
+    let sandbox = Sandbox::new();
+    sandbox.write_entries(
+        "[tar]\nurl = \"https://example.invalid/llvm-17.0.6.src.tar.gz\"\n\n\
+         [git]\nurl = \"https://example.invalid/llvm/llvm-project.git\"\ntag = \"llvmorg-17.0.6\"\n",
+    );
+    let download = sandbox.cache_dir().join("download");
+    let output = sandbox.run_err(&mut sandbox.llvmenv(&[
+        "build-entry",
+        "tar",
+        "--only",
+        "download",
+        "--offline",
+    ]));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            &download
+                .join("llvm-17.0.6.src.tar.gz")
+                .display()
+                .to_string()
+        ),
+        "{}",
+        stderr
+    );
+
+    let tarball = tarball(&[("llvm-17.0.6.src/CMakeLists.txt", "project(LLVM)\n")]);
+    fs::write(download.join("llvm-17.0.6.src.tar.gz"), tarball).unwrap();
+    sandbox.run(&["build-entry", "tar", "--only", "download", "--offline"]);
+    let src = PathBuf::from(sandbox.run(&["source", "tar"]).trim());
+    assert_eq!(
+        fs::read_to_string(src.join("CMakeLists.txt")).unwrap(),
+        "project(LLVM)\n"
+    );
+
+    let repo = sandbox.path().join("llvm-project");
+    git_repo(&repo, &[("llvm/CMakeLists.txt", "project(LLVM)\n")]);
+    let bundle = download.join("llvm-project.bundle");
+    git(&repo, &["tag", "llvmorg-17.0.6"]);
+    git(
+        &repo,
+        &["bundle", "create", bundle.to_str().unwrap(), "--all"],
+    );
+    sandbox.run(&["build-entry", "git", "--only", "download", "--offline"]);
+    let src = PathBuf::from(sandbox.run(&["source", "git"]).trim());
+    assert!(src.join("llvm/CMakeLists.txt").exists());
+}
+
+#[test]
+fn test_sync_pin() {
+    let sandbox = Sandbox::new();
+    let repo = sandbox.path().join("llvm-project.git");
+    git_repo(&repo, &[("llvm/CMakeLists.txt", "project(LLVM)\n")]);
+    sandbox.write_entries(&format!("[fixture]\nurl = \"file://{}\"\n", repo.display()));
+    let lockfile = sandbox.path().join("home/llvmenv.lock");
+    fs::write(&lockfile, "name = \"fixture\"\n").unwrap();
+
+    sandbox.run(&["sync", "--pin"]);
+    let lock = fs::read_to_string(&lockfile).unwrap();
+    assert!(lock.starts_with("name = \"fixture\"\n"));
+    assert!(lock.contains("[[pin]]\npath = \".\"\n"));
+    let src = PathBuf::from(sandbox.run(&["source", "fixture"]).trim());
+    let head = || {
+        let output = std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(&src)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    };
+    let pinned = head();
+    assert!(lock.contains(&format!("revision = \"{}\"", pinned)));
+
+    // A fresh checkout follows the branch, and is moved back to the pinned commit
+    git_commit(&repo, &[("llvm/README.txt", "updated\n")]);
+    fs::remove_dir_all(sandbox.data_dir().join("fixture")).unwrap();
+    fs::remove_dir_all(&src).unwrap();
+    sandbox.run(&["sync"]);
+    assert_eq!(head(), pinned);
+    assert!(!src.join("llvm/README.txt").exists());
+}
@@ -0,0 +1,230 @@
+//! Fixtures of integration tests
+//!
+//! - [Sandbox] runs the `llvmenv` binary with the XDG directories and `$HOME` in a temporary directory,
+//!   and with stubs of `cmake` and `ninja` in front of `$PATH`. The stubs record their invocations,
+//!   write `CMakeCache.txt` on configure, and install a fake `clang` into `CMAKE_INSTALL_PREFIX`.
+//!   `LLVMENV_FAKE_FAIL=<line>` makes `cmake --build` print the line and fail.
+//! - [serve] serves fixture files over HTTP by `llvmenv serve` on a local port.
+//! - [tarball] and [git_repo] create sources to be downloaded.
+//!
+//! The stubs are shell scripts, so that the tests using them are only for Unix.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use llvmenv::serve::Server;
+use tempfile::TempDir;
+
+const CMAKE_STUB: &str = r#"#!/bin/sh
+echo "cmake $*" >> "$LLVMENV_FAKE_LOG"
+if [ "$1" = "--version" ]; then
+  echo "cmake version 3.28.0"
+  exit 0
+fi
+if [ "$1" = "--build" ]; then
+  if [ -n "$LLVMENV_FAKE_FAIL" ]; then
+    echo "$LLVMENV_FAKE_FAIL"
+    echo "FAILED: lib/a.cpp.o"
+    exit 1
+  fi
+  echo "[1/2] Building CXX object lib/a.cpp.o"
+  echo "[2/2] Linking CXX executable bin/clang"
+  case " $* " in
+  *" install "*|*" install-distribution "*)
+    prefix=$(sed -n 's/^CMAKE_INSTALL_PREFIX:[A-Z]*=//p' "$2/CMakeCache.txt")
+    mkdir -p "$prefix/bin"
+    printf '#!/bin/sh\necho "clang version 17.0.6"\n' > "$prefix/bin/clang"
+    printf '#!/bin/sh\necho "17.0.6"\n' > "$prefix/bin/llvm-config"
+    chmod +x "$prefix/bin/clang" "$prefix/bin/llvm-config"
+    ;;
+  esac
+  exit 0
+fi
+# Configure in the build directory, i.e. the current directory
+: > CMakeCache.txt
+while [ $# -gt 0 ]; do
+  case "$1" in
+  -G) echo "CMAKE_GENERATOR:INTERNAL=$2" >> CMakeCache.txt; shift ;;
+  -D*) kv="${1#-D}"; echo "${kv%%=*}:STRING=${kv#*=}" >> CMakeCache.txt ;;
+  esac
+  shift
+done
+echo "-- Configuring done"
+"#;
+
+const NINJA_STUB: &str = r#"#!/bin/sh
+echo "ninja $*" >> "$LLVMENV_FAKE_LOG"
+if [ "$1" = "--version" ]; then
+  echo "1.11.1"
+fi
+"#;
+
+/// Executable `script` at `path`, e.g. a stub of another tool in `bin` of the sandbox
+pub fn write_stub(path: &Path, script: &str) {
+    fs::write(path, script).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+/// Isolated environment running `llvmenv`, see [module level doc](index.html)
+pub struct Sandbox {
+    root: TempDir,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        let root = TempDir::new().unwrap();
+        for dir in &["config", "data", "cache", "home", "bin"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        write_stub(&root.path().join("bin/cmake"), CMAKE_STUB);
+        write_stub(&root.path().join("bin/ninja"), NINJA_STUB);
+        Sandbox { root }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// `${XDG_CONFIG_HOME}/llvmenv`
+    pub fn config_dir(&self) -> PathBuf {
+        self.path().join("config/llvmenv")
+    }
+
+    /// `${XDG_DATA_HOME}/llvmenv`, where builds are installed
+    pub fn data_dir(&self) -> PathBuf {
+        self.path().join("data/llvmenv")
+    }
+
+    /// `${XDG_CACHE_HOME}/llvmenv`, where sources are downloaded and built
+    pub fn cache_dir(&self) -> PathBuf {
+        self.path().join("cache/llvmenv")
+    }
+
+    /// Write `entry.toml`
+    pub fn write_entries(&self, toml: &str) {
+        fs::create_dir_all(self.config_dir()).unwrap();
+        fs::write(self.config_dir().join("entry.toml"), toml).unwrap();
+    }
+
+    /// `llvmenv` with `args` in the sandbox
+    pub fn llvmenv(&self, args: &[&str]) -> Command {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![self.path().join("bin")];
+        paths.extend(std::env::split_paths(&path));
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_llvmenv"));
+        cmd.args(args)
+            .current_dir(self.path().join("home"))
+            .env("PATH", std::env::join_paths(paths).unwrap())
+            .env("HOME", self.path().join("home"))
+            .env("XDG_CONFIG_HOME", self.path().join("config"))
+            .env("XDG_DATA_HOME", self.path().join("data"))
+            .env("XDG_CACHE_HOME", self.path().join("cache"))
+            .env("LLVMENV_FAKE_LOG", self.path().join("invocations.log"))
+            .env("NO_PROXY", "127.0.0.1")
+            .env_remove("LLVMENV_OUTPUT")
+            .env_remove("LLVMENV_FAKE_FAIL");
+        cmd
+    }
+
+    /// Run `llvmenv` and return stdout, panicking with stderr if it fails
+    pub fn run(&self, args: &[&str]) -> String {
+        let output = self.llvmenv(args).output().unwrap();
+        assert!(
+            output.status.success(),
+            "llvmenv {} failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Run `llvmenv` expected to fail
+    pub fn run_err(&self, cmd: &mut Command) -> Output {
+        let output = cmd.output().unwrap();
+        assert!(!output.status.success(), "llvmenv succeeded unexpectedly");
+        output
+    }
+
+    /// Invocations of the stubs, e.g. `cmake --build /path/to/build --target install -- -j 4`
+    pub fn invocations(&self) -> Vec<String> {
+        fs::read_to_string(self.path().join("invocations.log"))
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    pub fn clear_invocations(&self) {
+        let _ = fs::remove_file(self.path().join("invocations.log"));
+    }
+}
+
+/// Serve files in `dir` over HTTP on a local port, returning the base URL
+pub fn serve(dir: &Path) -> String {
+    let addr = Server::new(vec![("fixtures".into(), dir.to_path_buf())])
+        .spawn("127.0.0.1:0")
+        .unwrap();
+    format!("http://{}/fixtures", addr)
+}
+
+/// `.tar.gz` archive of `files`, pairs of the path and the contents
+pub fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+    let gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    let mut builder = tar::Builder::new(gz);
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, contents.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+/// Run git with `args` in `dir`, panicking if it fails
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "user.name=llvmenv",
+            "-c",
+            "user.email=llvmenv@example.com",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {} failed", args.join(" "));
+}
+
+/// Git repository at `dir` with a commit of `files`
+pub fn git_repo(dir: &Path, files: &[(&str, &str)]) {
+    fs::create_dir_all(dir).unwrap();
+    git(dir, &["init", "-q", "-b", "main"]);
+    git_commit(dir, files);
+}
+
+/// Commit `files` into the repository at `dir`
+pub fn git_commit(dir: &Path, files: &[(&str, &str)]) {
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+    git(dir, &["add", "-A"]);
+    git(dir, &["commit", "-q", "-m", "fixture"]);
+}